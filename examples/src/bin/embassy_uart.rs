@@ -0,0 +1,124 @@
+//! Uncompress the packets received on a DMA UART from an embassy task and
+//! send the uncompressed data on the same UART, the decoder state being a
+//! `static`.
+//!
+//! The sender flushes its encoder at the end of each packet (see
+//! `HeatshrinkEncoder::flush`) and the line going idle ends a packet: the
+//! decoder then drops the padding of the flush with `sync`, and keeps its
+//! window for the following packets. While the DMA receives or sends, the
+//! executor runs the other tasks.
+
+#![no_std]
+#![no_main]
+
+use embassy_executor::Spawner;
+use embassy_stm32::mode::Async;
+use embassy_stm32::usart::{self, Config, Uart, UartRx, UartTx};
+use embassy_stm32::{bind_interrupts, peripherals};
+use heatshrink::decoder::HeatshrinkDecoder;
+use heatshrink::{HSError, HSpollRes, HSsinkRes, Params};
+use panic_halt as _;
+use static_cell::ConstStaticCell;
+
+bind_interrupts!(struct Irqs {
+    USART2 => usart::InterruptHandler<peripherals::USART2>;
+});
+
+/// 1 KiB window and back-references of up to 16 bytes (`-w 10 -l 4`)
+const PARAMS: Params = match Params::new(10, 4) {
+    Ok(params) => params,
+    Err(_) => panic!("invalid parameters"),
+};
+
+/// Decoder with a 1 KiB window and a 64 bytes input buffer
+type Decoder = HeatshrinkDecoder<1024, 64>;
+
+/// The decoder state, built at compile time: it is placed in RAM by the
+/// linker, instead of being built on the stack and moved into the task
+static DECODER: ConstStaticCell<Decoder> =
+    ConstStaticCell::new(match Decoder::with_params(PARAMS) {
+        Ok(dec) => dec,
+        Err(_) => panic!("window too large"),
+    });
+
+/// Buffer the DMA receives the packets in
+static RX_BUFFER: ConstStaticCell<[u8; 256]> = ConstStaticCell::new([0; 256]);
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    let p = embassy_stm32::init(Default::default());
+
+    // USART2 on PA2 (TX) and PA3 (RX), with the DMA1 streams 6 (TX) and 5
+    // (RX)
+    let uart = Uart::new(
+        p.USART2,
+        p.PA3,
+        p.PA2,
+        Irqs,
+        p.DMA1_CH6,
+        p.DMA1_CH5,
+        Config::default(),
+    )
+    .unwrap();
+    let (tx, rx) = uart.split();
+
+    spawner
+        .spawn(uncompress(tx, rx, DECODER.take(), RX_BUFFER.take()))
+        .unwrap();
+}
+
+/// Uncompress the packets received, sending the uncompressed data back
+#[embassy_executor::task]
+async fn uncompress(
+    mut tx: UartTx<'static, Async>,
+    mut rx: UartRx<'static, Async>,
+    dec: &'static mut Decoder,
+    rx_buffer: &'static mut [u8; 256],
+) {
+    loop {
+        let result = match rx.read_until_idle(rx_buffer).await {
+            Ok(size) => uncompress_packet(dec, &rx_buffer[..size], &mut tx).await,
+            Err(_) => Err(HSError::Truncated),
+        };
+
+        // a lost or corrupted packet breaks the stream: start over, the
+        // sender resets its encoder on its side
+        if result.is_err() {
+            dec.reset();
+        }
+    }
+}
+
+/// Uncompress a packet, sending the uncompressed data as it is produced
+async fn uncompress_packet(
+    dec: &mut Decoder,
+    mut packet: &[u8],
+    tx: &mut UartTx<'static, Async>,
+) -> Result<(), HSError> {
+    let mut output = [0u8; 64];
+
+    while !packet.is_empty() {
+        match dec.sink(packet) {
+            (HSsinkRes::SinkOK, size) => packet = &packet[size..],
+            (HSsinkRes::SinkFull, _) => {}
+            (_, _) => return Err(HSError::Internal),
+        }
+
+        loop {
+            let (res, size) = dec.poll(&mut output);
+            if size > 0 {
+                tx.write(&output[..size])
+                    .await
+                    .map_err(|_| HSError::Internal)?;
+            }
+            match res {
+                HSpollRes::PollMore => {}
+                HSpollRes::PollEmpty => break,
+                HSpollRes::PollErrorBackref => return Err(HSError::InvalidBackref),
+                _ => return Err(HSError::Internal),
+            }
+        }
+    }
+
+    dec.sync()
+}
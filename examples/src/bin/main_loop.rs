@@ -0,0 +1,87 @@
+//! Compress the samples of a sensor from a bare-metal main loop and stream
+//! the compressed data over ITM, the encoder state being a `static`.
+//!
+//! Each iteration sinks one sample and polls the encoder with a bounded
+//! budget of steps of work, so that the compression (including the indexing
+//! and shift of a filled window) is spread over the iterations instead of
+//! delaying the loop. Every `FLUSH_PERIOD` samples the data is flushed to a
+//! byte boundary, so that the host (e.g. reading the SWO output with
+//! `itmdump`) can uncompress it up to there.
+
+#![no_std]
+#![no_main]
+
+use cortex_m::peripheral::syst::SystClkSource;
+use cortex_m::peripheral::{ITM, SYST};
+use cortex_m_rt::entry;
+use heatshrink::encoder::HeatshrinkEncoder;
+use heatshrink::{HSfinishRes, HSpollRes, Params};
+use panic_halt as _;
+use static_cell::ConstStaticCell;
+
+/// 1 KiB window and back-references of up to 16 bytes (`-w 10 -l 4`)
+const PARAMS: Params = match Params::new(10, 4) {
+    Ok(params) => params,
+    Err(_) => panic!("invalid parameters"),
+};
+
+/// The encoder state, built at compile time: it is placed in RAM by the
+/// linker, instead of being built on the stack and moved
+static ENCODER: ConstStaticCell<HeatshrinkEncoder<1024>> =
+    ConstStaticCell::new(match HeatshrinkEncoder::with_params(PARAMS) {
+        Ok(enc) => enc,
+        Err(_) => panic!("window too large"),
+    });
+
+/// Steps of work done by the encoder per iteration
+const POLL_BUDGET: usize = 8;
+
+/// Number of samples between two flushes
+const FLUSH_PERIOD: u32 = 256;
+
+/// Poll the encoder, at most budget steps of work, and write the
+/// compressed data to the stimulus port 0 of the ITM
+fn poll(enc: &mut HeatshrinkEncoder<1024>, itm: &mut ITM, budget: usize) -> HSpollRes {
+    let mut output = [0u8; 16];
+    let (res, size) = enc.poll_with_budget(&mut output, budget);
+
+    cortex_m::itm::write_all(&mut itm.stim[0], &output[..size]);
+    res
+}
+
+/// Poll the encoder until all the data sunk so far is compressed
+fn drain(enc: &mut HeatshrinkEncoder<1024>, itm: &mut ITM) {
+    while poll(enc, itm, usize::MAX) == HSpollRes::PollMore {}
+}
+
+#[entry]
+fn main() -> ! {
+    let mut core = cortex_m::Peripherals::take().unwrap();
+    let enc = ENCODER.take();
+    let mut samples: u32 = 0;
+
+    core.SYST.set_clock_source(SystClkSource::Core);
+    core.SYST.set_reload(0x00ff_ffff);
+    core.SYST.clear_current();
+    core.SYST.enable_counter();
+
+    loop {
+        // a slowly changing reading, standing for an ADC sample
+        let sample = ((SYST::get_current() >> 14) as u16).to_le_bytes();
+
+        // the window is only full every 512 samples: it is then compressed
+        // at once, the worst case of an iteration
+        if enc.sink_capacity() < sample.len() {
+            drain(enc, &mut core.ITM);
+        }
+        enc.sink(&sample);
+        poll(enc, &mut core.ITM, POLL_BUDGET);
+
+        samples = samples.wrapping_add(1);
+        if samples % FLUSH_PERIOD == 0 {
+            while enc.flush() == HSfinishRes::FinishMore {
+                drain(enc, &mut core.ITM);
+            }
+        }
+    }
+}
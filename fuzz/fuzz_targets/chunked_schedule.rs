@@ -0,0 +1,89 @@
+#![no_main]
+
+use heatshrink::decoder::HeatshrinkDecoder;
+use heatshrink::encoder::{self, HeatshrinkEncoder};
+use heatshrink::*;
+use libfuzzer_sys::fuzz_target;
+
+/// Size of the next chunk of the schedule, from 1 byte (the suspend/resume
+/// paths of the bit readers and writers) to 64 bytes
+fn next_size(schedule: &[u8], index: &mut usize) -> usize {
+    let size = schedule
+        .get(*index % schedule.len().max(1))
+        .map_or(64, |size| usize::from(size % 64) + 1);
+    *index += 1;
+    size
+}
+
+fuzz_target!(|input: (u8, Vec<u8>, Vec<u8>, Vec<u8>)| {
+    let (params, sink_sizes, poll_sizes, data) = input;
+    let window_bits = HEATSHRINK_MIN_WINDOWS_BITS + params % 8;
+    let lookahead_bits = HEATSHRINK_MIN_LOOKAHEAD_BITS + (params >> 4) % (window_bits - 3);
+    let Ok(params) = Params::new(window_bits, lookahead_bits) else {
+        return;
+    };
+    let (mut sink_index, mut poll_index) = (0, 0);
+
+    // compress with the schedule, the output must not depend on it
+    let mut enc = HeatshrinkEncoder::<2048>::with_params(params).unwrap();
+    let mut compressed = vec![0; encoder::encode_bound(data.len()) + 64];
+    let mut input = &data[..];
+    let mut compressed_size = 0;
+    while !input.is_empty() {
+        let size = next_size(&sink_sizes, &mut sink_index).min(input.len());
+        let (res, sunk) = enc.sink(&input[..size]);
+        assert_ne!(res, HSsinkRes::SinkErrorMisuse);
+        input = &input[sunk..];
+        loop {
+            let size = next_size(&poll_sizes, &mut poll_index);
+            let end = (compressed_size + size).min(compressed.len());
+            let (res, polled) = enc.poll(&mut compressed[compressed_size..end]);
+            compressed_size += polled;
+            if res == HSpollRes::PollEmpty {
+                break;
+            }
+            assert_eq!(res, HSpollRes::PollMore);
+        }
+    }
+    while enc.finish() == HSfinishRes::FinishMore {
+        let size = next_size(&poll_sizes, &mut poll_index);
+        let end = (compressed_size + size).min(compressed.len());
+        compressed_size += enc.poll(&mut compressed[compressed_size..end]).1;
+    }
+    let compressed = &compressed[..compressed_size];
+
+    let mut expected = vec![0; encoder::encode_bound(data.len())];
+    let mut enc = HeatshrinkEncoder::<2048>::with_params(params).unwrap();
+    assert_eq!(
+        encoder::encode_with(&mut enc, &data, &mut expected),
+        Ok(compressed)
+    );
+
+    // uncompress with the schedule
+    let mut dec = HeatshrinkDecoder::<2048>::with_params(params).unwrap();
+    let mut output = vec![0; data.len() + 64];
+    let mut input = compressed;
+    let mut output_size = 0;
+    loop {
+        let size = next_size(&sink_sizes, &mut sink_index).min(input.len());
+        let (res, sunk) = dec.sink(&input[..size]);
+        assert_ne!(res, HSsinkRes::SinkErrorMisuse);
+        input = &input[sunk..];
+        loop {
+            let size = next_size(&poll_sizes, &mut poll_index);
+            let end = (output_size + size).min(output.len());
+            let (res, polled) = dec.poll(&mut output[output_size..end]);
+            output_size += polled;
+            assert!(output_size <= data.len());
+            if res == HSpollRes::PollEmpty {
+                break;
+            }
+            assert_eq!(res, HSpollRes::PollMore);
+        }
+        if input.is_empty() {
+            break;
+        }
+    }
+    assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+    assert_eq!(&output[..output_size], &data[..]);
+});
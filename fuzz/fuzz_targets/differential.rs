@@ -0,0 +1,40 @@
+#![no_main]
+
+use heatshrink::decoder::HeatshrinkDecoder;
+use heatshrink::encoder::{self, HeatshrinkEncoder};
+use heatshrink::*;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u8, Vec<u8>)| {
+    let (params, data) = input;
+    let window_bits = HEATSHRINK_MIN_WINDOWS_BITS + params % 8;
+    let lookahead_bits = HEATSHRINK_MIN_LOOKAHEAD_BITS + (params >> 4) % (window_bits - 3);
+    let Ok(params) = Params::new(window_bits, lookahead_bits) else {
+        return;
+    };
+
+    // the default level is the exhaustive search of the C library, so the
+    // compressed streams are the same
+    let mut enc = HeatshrinkEncoder::<2048>::with_params(params).unwrap();
+    let mut compressed = vec![0; encoder::encode_bound(data.len())];
+    let compressed = encoder::encode_with(&mut enc, &data, &mut compressed).unwrap();
+    assert_eq!(
+        compressed,
+        &heatshrink_c_sys::encode(window_bits, lookahead_bits, &data, 4096)[..]
+    );
+
+    // the data as a compressed stream: when the Rust decoder accepts it, the
+    // C decoder must produce the same output (the C decoder doesn't reject
+    // back-references before the start of the stream)
+    let mut dec = HeatshrinkDecoder::<2048>::with_params(params).unwrap();
+    let mut output = Vec::new();
+    if dec
+        .sink_all(&data, &mut |chunk| output.extend_from_slice(chunk))
+        .is_ok()
+    {
+        assert_eq!(
+            output,
+            heatshrink_c_sys::decode(256, window_bits, lookahead_bits, &data).output
+        );
+    }
+});
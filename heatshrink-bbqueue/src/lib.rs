@@ -0,0 +1,231 @@
+//! Compression and decompression between the grants of [bbqueue] queues,
+//! e.g. from the queue filled by a DMA UART to the one drained by a radio,
+//! without copying the data through intermediate buffers.
+//!
+//! Each call of `encode_step` or `decode_step` does one cycle: it takes the
+//! read grant of the input queue and a write grant of the output queue,
+//! moves as much data as possible between them with
+//! `heatshrink::encoder::encode_step` or `heatshrink::decoder::decode_step`,
+//! then releases the bytes consumed and commits the bytes written. An empty
+//! input queue or a full output queue is not an error, the call then moves
+//! nothing.
+//!
+//! ```ignore
+//! static RX: BBBuffer<256> = BBBuffer::new();
+//! static TX: BBBuffer<256> = BBBuffer::new();
+//!
+//! let (rx_producer, mut rx_consumer) = RX.try_split().unwrap();
+//! let (mut tx_producer, tx_consumer) = TX.try_split().unwrap();
+//! loop {
+//!     heatshrink_bbqueue::encode_step(&mut enc, &mut rx_consumer, &mut tx_producer, 64)?;
+//! }
+//! ```
+//!
+//! [bbqueue]: https://docs.rs/bbqueue
+#![no_std]
+
+use bbqueue::{Consumer, Producer};
+use heatshrink::{decoder, encoder, HSError};
+
+/// Error of a step between two queues
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The encoder or decoder failed, nothing was released nor committed
+    Codec(HSError),
+    /// A grant of one of the queues is already in progress
+    GrantInProgress,
+}
+
+impl From<HSError> for Error {
+    fn from(error: HSError) -> Self {
+        Error::Codec(error)
+    }
+}
+
+/// Compress the data of the input queue to the output queue, writing up to
+/// max_output bytes. Return the number of bytes released from the input
+/// queue and committed to the output queue.
+///
+/// Once all the data was sunk and the input queue is empty, call
+/// `HeatshrinkEncoder::finish` and keep calling this function until it
+/// returns `FinishDone`, to end the compressed stream.
+pub fn encode_step<const WINDOW_SIZE: usize, const N: usize, const M: usize>(
+    enc: &mut encoder::HeatshrinkEncoder<WINDOW_SIZE>,
+    input: &mut Consumer<'_, N>,
+    output: &mut Producer<'_, M>,
+    max_output: usize,
+) -> Result<(usize, usize), Error> {
+    step(input, output, max_output, |src, dst| {
+        encoder::encode_step(enc, src, dst)
+    })
+}
+
+/// Uncompress the data of the input queue to the output queue, writing up
+/// to max_output bytes. Return the number of bytes released from the input
+/// queue and committed to the output queue.
+///
+/// Once all the compressed data was sunk, `HeatshrinkDecoder::finish` checks
+/// the stream is complete.
+pub fn decode_step<
+    const WINDOW_SIZE: usize,
+    const INPUT_SIZE: usize,
+    const N: usize,
+    const M: usize,
+>(
+    dec: &mut decoder::HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    input: &mut Consumer<'_, N>,
+    output: &mut Producer<'_, M>,
+    max_output: usize,
+) -> Result<(usize, usize), Error> {
+    step(input, output, max_output, |src, dst| {
+        decoder::decode_step(dec, src, dst)
+    })
+}
+
+/// Grant, step, then release and commit. The grants dropped on an error
+/// release and commit nothing.
+fn step<const N: usize, const M: usize>(
+    input: &mut Consumer<'_, N>,
+    output: &mut Producer<'_, M>,
+    max_output: usize,
+    codec: impl FnOnce(&[u8], &mut [u8]) -> Result<(usize, usize), HSError>,
+) -> Result<(usize, usize), Error> {
+    let mut write = match output.grant_max_remaining(max_output) {
+        Ok(write) => write,
+        Err(bbqueue::Error::InsufficientSize) => return Ok((0, 0)),
+        Err(_) => return Err(Error::GrantInProgress),
+    };
+
+    // the data sunk before is polled even when the input queue is empty
+    let (consumed, written) = match input.read() {
+        Ok(read) => {
+            let (consumed, written) = codec(read.buf(), write.buf())?;
+            read.release(consumed);
+            (consumed, written)
+        }
+        Err(bbqueue::Error::InsufficientSize) => codec(&[], write.buf())?,
+        Err(_) => return Err(Error::GrantInProgress),
+    };
+    write.commit(written);
+
+    Ok((consumed, written))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::{decode_step, encode_step, Error};
+    use bbqueue::BBBuffer;
+    use heatshrink::{decoder, encoder, HSfinishRes};
+    use std::vec::Vec;
+
+    #[test]
+    fn round_trip() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+
+        // raw data -> encoder -> compressed data -> decoder -> output,
+        // through queues smaller than the data
+        let raw: BBBuffer<256> = BBBuffer::new();
+        let compressed: BBBuffer<64> = BBBuffer::new();
+        let output: BBBuffer<128> = BBBuffer::new();
+        let (mut raw_producer, mut raw_consumer) = raw.try_split().unwrap();
+        let (mut compressed_producer, mut compressed_consumer) = compressed.try_split().unwrap();
+        let (mut output_producer, mut output_consumer) = output.try_split().unwrap();
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+
+        let mut fed = 0;
+        let mut finished = false;
+        let mut uncompressed = Vec::new();
+        loop {
+            // the producer, e.g. a DMA transfer, fills the raw queue
+            if let Ok(mut write) = raw_producer.grant_max_remaining(src.len() - fed) {
+                let size = write.len();
+                write.copy_from_slice(&src[fed..fed + size]);
+                write.commit(size);
+                fed += size;
+            }
+            if fed == src.len() && raw_consumer.read().is_err() {
+                finished = enc.finish() == HSfinishRes::FinishDone;
+            }
+
+            let (_, encoded) =
+                encode_step(&mut enc, &mut raw_consumer, &mut compressed_producer, 16).unwrap();
+            let (_, written) =
+                decode_step(&mut dec, &mut compressed_consumer, &mut output_producer, 32).unwrap();
+
+            // the consumer, e.g. a radio, drains the output queue
+            if let Ok(read) = output_consumer.read() {
+                uncompressed.extend_from_slice(&read);
+                let size = read.len();
+                read.release(size);
+            }
+
+            if finished
+                && encoded == 0
+                && written == 0
+                && compressed_consumer.read().is_err()
+                && output_consumer.read().is_err()
+            {
+                break;
+            }
+        }
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(uncompressed, src);
+        assert!(enc.total_out() < src.len() as u64);
+    }
+
+    #[test]
+    fn grants() {
+        let input: BBBuffer<64> = BBBuffer::new();
+        let output: BBBuffer<64> = BBBuffer::new();
+        let (mut input_producer, mut input_consumer) = input.try_split().unwrap();
+        let (mut output_producer, mut output_consumer) = output.try_split().unwrap();
+        let mut enc = encoder::HeatshrinkEncoder::new();
+
+        // nothing to move
+        assert_eq!(
+            encode_step(&mut enc, &mut input_consumer, &mut output_producer, 64),
+            Ok((0, 0))
+        );
+
+        let mut write = input_producer.grant_exact(8).unwrap();
+        write.copy_from_slice(b"abababab");
+        write.commit(8);
+
+        // the output queue is full
+        output_producer.grant_exact(64).unwrap().commit(64);
+        assert_eq!(
+            encode_step(&mut enc, &mut input_consumer, &mut output_producer, 64),
+            Ok((0, 0))
+        );
+        output_consumer.read().unwrap().release(64);
+
+        // a grant of the input queue is in progress
+        let read = input_consumer.read().unwrap();
+        assert_eq!(
+            encode_step(&mut enc, &mut input_consumer, &mut output_producer, 64),
+            Err(Error::GrantInProgress)
+        );
+        read.release(0);
+
+        // the input is sunk, its output pending until the encoder finishes
+        assert_eq!(
+            encode_step(&mut enc, &mut input_consumer, &mut output_producer, 64),
+            Ok((8, 0))
+        );
+        assert_eq!(enc.finish(), HSfinishRes::FinishMore);
+        let (_, written) =
+            encode_step(&mut enc, &mut input_consumer, &mut output_producer, 64).unwrap();
+        assert_eq!(enc.finish(), HSfinishRes::FinishDone);
+
+        let mut out = [0u8; 64];
+        let read = output_consumer.read().unwrap();
+        assert_eq!(read.len(), written);
+        assert_eq!(decoder::decode(&read, &mut out), Ok(&b"abababab"[..]));
+    }
+}
@@ -0,0 +1,290 @@
+//! .hsa archives packing several files compressed on their own.
+//!
+//! An archive is made of a header (the magic, the version, the window and
+//! lookahead sizes) followed by an entry per file until the end of the
+//! archive: the length of its path (16-bit little endian), its path in
+//! UTF-8, its original and compressed sizes (64-bit little endian) and its
+//! compressed stream.
+
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path};
+
+use super::{decode, encode, report, HEATSHRINK_APP_WINDOW_SIZE};
+
+/// Magic bytes starting a .hsa archive
+const ARCHIVE_MAGIC: [u8; 4] = *b"HSAR";
+
+/// Version of the archive layout
+const ARCHIVE_VERSION: u8 = 1;
+
+/// Size of the archive header: the magic, the version, the window and
+/// lookahead sizes
+const ARCHIVE_HEADER_SIZE: usize = 7;
+
+/// Pack the files into the archive, each one compressed with params and
+/// level
+pub fn archive(
+    archive_name: &str,
+    files: &[String],
+    params: heatshrink::Params,
+    level: u8,
+    verbose: bool,
+) -> io::Result<()> {
+    let mut output_file = BufWriter::new(File::create(archive_name)?);
+    // kept on the heap as the largest window and its index take ~1 MiB
+    let mut enc =
+        heatshrink::encoder::HeatshrinkEncoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            params,
+        )
+        .map_err(io::Error::other)?;
+    enc.set_level(level);
+
+    output_file.write_all(&ARCHIVE_MAGIC)?;
+    output_file.write_all(&[
+        ARCHIVE_VERSION,
+        params.window_bits(),
+        params.lookahead_bits(),
+    ])?;
+
+    for file_name in files {
+        let path = file_name.as_bytes();
+        let path_len = u16::try_from(path.len()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{file_name}: path is too long for an archive"),
+            )
+        })?;
+        check_path(file_name)?;
+
+        // compressed in memory, as its size precedes the compressed stream
+        let mut compressed = Vec::new();
+        enc.reset();
+        let (input_size, output_size) = File::open(file_name)
+            .and_then(|file| encode(&mut enc, &mut BufReader::new(file), &mut compressed))
+            .map_err(|error| file_error(file_name, error))?;
+
+        output_file.write_all(&path_len.to_le_bytes())?;
+        output_file.write_all(path)?;
+        output_file.write_all(&input_size.to_le_bytes())?;
+        output_file.write_all(&output_size.to_le_bytes())?;
+        output_file.write_all(&compressed)?;
+
+        if verbose {
+            report(
+                false,
+                file_name,
+                input_size,
+                output_size,
+                params.window_bits(),
+                params.lookahead_bits(),
+            );
+        }
+    }
+    output_file.flush()
+}
+
+/// Whether the data starts with the magic bytes of an archive
+pub fn is_archive(src: &[u8]) -> bool {
+    src.starts_with(&ARCHIVE_MAGIC)
+}
+
+/// Error of a malformed archive
+fn corrupted(message: impl Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// The error prefixed with the name of the file it is about
+fn file_error(file_name: impl Display, error: io::Error) -> io::Error {
+    io::Error::new(error.kind(), format!("{file_name}: {error}"))
+}
+
+/// A read cut short by the end of the archive is reported as truncated
+fn truncated(error: io::Error) -> io::Error {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => corrupted("archive is truncated"),
+        _ => error,
+    }
+}
+
+/// Read the archive header, returning the parameters of its entries
+fn read_header(input_file: &mut dyn Read) -> io::Result<heatshrink::Params> {
+    let mut header = [0u8; ARCHIVE_HEADER_SIZE];
+    input_file.read_exact(&mut header).map_err(truncated)?;
+    let [m0, m1, m2, m3, version, window_bits, lookahead_bits] = header;
+    if [m0, m1, m2, m3] != ARCHIVE_MAGIC || version != ARCHIVE_VERSION {
+        return Err(corrupted("not a heatshrink archive"));
+    }
+    heatshrink::Params::new(window_bits, lookahead_bits)
+        .map_err(|_| corrupted("archive header has unsupported parameters"))
+}
+
+/// Read the description of the next entry: its path and its original and
+/// compressed sizes, the compressed stream following. None at the end of
+/// the archive
+fn read_entry(input_file: &mut dyn Read) -> io::Result<Option<(String, u64, u64)>> {
+    let mut path_len = [0u8; 2];
+    match input_file.read(&mut path_len[..1])? {
+        // end of the archive
+        0 => return Ok(None),
+        _ => input_file
+            .read_exact(&mut path_len[1..])
+            .map_err(truncated)?,
+    }
+    let mut path = vec![0u8; u16::from_le_bytes(path_len).into()];
+    let mut original_size = [0u8; 8];
+    let mut compressed_size = [0u8; 8];
+    input_file
+        .read_exact(&mut path)
+        .and_then(|_| input_file.read_exact(&mut original_size))
+        .and_then(|_| input_file.read_exact(&mut compressed_size))
+        .map_err(truncated)?;
+    let file_name =
+        String::from_utf8(path).map_err(|_| corrupted("archive entry path is not UTF-8"))?;
+    let original_size = u64::from_le_bytes(original_size);
+    let compressed_size = u64::from_le_bytes(compressed_size);
+
+    Ok(Some((file_name, original_size, compressed_size)))
+}
+
+/// Create the file of an entry in the directory, refusing to overwrite an
+/// existing file unless forced. Symbolic links are never followed, as one
+/// already in the directory would redirect the entry outside of it: a link
+/// to a directory is refused, and one in place of the file replaced
+fn create_entry(directory: &Path, file_name: &str, force: bool) -> io::Result<File> {
+    let output_name = directory.join(file_name);
+    let mut parent = directory.to_path_buf();
+    for component in Path::new(file_name)
+        .parent()
+        .into_iter()
+        .flat_map(Path::components)
+    {
+        parent.push(component);
+        match std::fs::symlink_metadata(&parent) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is a symbolic link", parent.display()),
+                ))
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                std::fs::create_dir(&parent).map_err(|error| file_error(parent.display(), error))?
+            }
+            Err(error) => return Err(file_error(parent.display(), error)),
+        }
+    }
+
+    if std::fs::symlink_metadata(&output_name).is_ok() {
+        if !force {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} already exists, use -f to overwrite it",
+                    output_name.display()
+                ),
+            ));
+        }
+        std::fs::remove_file(&output_name)
+            .map_err(|error| file_error(output_name.display(), error))?;
+    }
+    File::options()
+        .write(true)
+        .create_new(true)
+        .open(&output_name)
+        .map_err(|error| file_error(output_name.display(), error))
+}
+
+/// Unpack the files of the archive into the directory, refusing to
+/// overwrite existing files unless forced
+pub fn extract(archive_name: &str, directory: &Path, force: bool, verbose: bool) -> io::Result<()> {
+    let mut input_file = BufReader::new(File::open(archive_name)?);
+
+    let params = read_header(&mut input_file)?;
+    let mut dec =
+        heatshrink::decoder::HeatshrinkDecoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            params,
+        )
+        .map_err(io::Error::other)?;
+    std::fs::create_dir_all(directory).map_err(|error| file_error(directory.display(), error))?;
+
+    while let Some((file_name, original_size, compressed_size)) = read_entry(&mut input_file)? {
+        check_path(&file_name)?;
+        let mut output_file = BufWriter::new(create_entry(directory, &file_name, force)?);
+        let mut compressed = (&mut input_file).take(compressed_size);
+        dec.reset();
+        let result = decode(&mut dec, &mut compressed, &mut output_file)
+            .and_then(|sizes| {
+                output_file.flush()?;
+                Ok(sizes)
+            })
+            .and_then(|(input_size, output_size)| {
+                if input_size != compressed_size {
+                    return Err(corrupted("archive is truncated"));
+                }
+                if output_size != original_size {
+                    return Err(corrupted(
+                        "uncompressed data doesn't match the size in the archive",
+                    ));
+                }
+                Ok((input_size, output_size))
+            });
+        drop(output_file);
+        // like decode, the partial output is removed
+        let (input_size, output_size) = result.map_err(|error| {
+            let _ = std::fs::remove_file(directory.join(&file_name));
+            file_error(&file_name, error)
+        })?;
+
+        if verbose {
+            report(
+                false,
+                &file_name,
+                input_size,
+                output_size,
+                params.window_bits(),
+                params.lookahead_bits(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print the parameters of the archive read from input_file and the path
+/// and sizes of its entries, without uncompressing them
+pub fn list(archive_name: &str, input_file: &mut dyn Read) -> io::Result<()> {
+    let params = read_header(input_file)?;
+    println!(
+        "{archive_name}: archive (-w {} -l {})",
+        params.window_bits(),
+        params.lookahead_bits()
+    );
+
+    while let Some((file_name, original_size, compressed_size)) = read_entry(input_file)? {
+        let skipped = io::copy(
+            &mut (&mut *input_file).take(compressed_size),
+            &mut io::sink(),
+        )?;
+        if skipped != compressed_size {
+            return Err(corrupted("archive is truncated"));
+        }
+        println!("  {file_name} \t{original_size} -> {compressed_size}");
+    }
+    Ok(())
+}
+
+/// refuse the paths that would escape the directory the archive is
+/// extracted to (absolute or going through a parent directory)
+fn check_path(file_name: &str) -> io::Result<()> {
+    match Path::new(file_name)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        true => Ok(()),
+        false => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{file_name}: path must be relative and within its directory"),
+        )),
+    }
+}
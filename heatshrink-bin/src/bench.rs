@@ -0,0 +1,83 @@
+//! Compression ratio and speed of the encoder and decoder on files, the
+//! data being compressed and decompressed in memory.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use super::{decode, encode, report, HEATSHRINK_APP_WINDOW_SIZE};
+
+/// Compress and decompress each file (stdin if none) iterations times,
+/// printing the compression ratio and the speeds of the fastest runs
+pub fn bench(files: &[String], params: heatshrink::Params, level: u8, iterations: u32) {
+    // kept on the heap as the largest window and its index take ~1 MiB
+    let mut enc =
+        heatshrink::encoder::HeatshrinkEncoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            params,
+        )
+        .unwrap();
+    enc.set_level(level);
+    let mut dec =
+        heatshrink::decoder::HeatshrinkDecoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            params,
+        )
+        .unwrap();
+
+    // stdin if no file name was provided
+    let files: Vec<Option<&str>> = match files.is_empty() {
+        true => vec![None],
+        false => files.iter().map(|file| Some(file.as_str())).collect(),
+    };
+
+    for file in files {
+        let mut data = Vec::new();
+        match file {
+            None => io::stdin().read_to_end(&mut data),
+            Some(filename) => {
+                File::open(filename).and_then(|mut input| input.read_to_end(&mut data))
+            }
+        }
+        .unwrap();
+
+        let mut compressed = Vec::new();
+        let mut uncompressed = Vec::new();
+        let mut encode_time = Duration::MAX;
+        let mut decode_time = Duration::MAX;
+        for _ in 0..iterations {
+            compressed.clear();
+            enc.reset();
+            let start = Instant::now();
+            encode(&mut enc, &mut data.as_slice(), &mut compressed).unwrap();
+            encode_time = encode_time.min(start.elapsed());
+
+            uncompressed.clear();
+            dec.reset();
+            let start = Instant::now();
+            decode(&mut dec, &mut compressed.as_slice(), &mut uncompressed).unwrap();
+            decode_time = decode_time.min(start.elapsed());
+        }
+
+        let file_name = file.unwrap_or("-");
+        if uncompressed != data {
+            panic!("Uncompressed data doesn't match the original: {file_name}");
+        }
+        report(
+            false,
+            file_name,
+            data.len() as u64,
+            compressed.len() as u64,
+            params.window_bits(),
+            params.lookahead_bits(),
+        );
+        println!(
+            "  encode {:.2} MB/s, decode {:.2} MB/s",
+            throughput(data.len(), encode_time),
+            throughput(data.len(), decode_time)
+        );
+    }
+}
+
+/// Speed in MB/s of processing size bytes in time
+fn throughput(size: usize, time: Duration) -> f64 {
+    size as f64 / time.as_secs_f64().max(f64::MIN_POSITIVE) / 1e6
+}
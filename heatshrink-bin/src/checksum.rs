@@ -0,0 +1,164 @@
+//! Checksum trailer of the compressed data, as written by heatshrink::crc:
+//! the checksum of the uncompressed data, little-endian, following the
+//! compressed stream.
+//!
+//! The trailer doesn't announce itself, so it is only written and checked
+//! when selected with --crc.
+
+use clap::ValueEnum;
+use heatshrink::crc::{Checksum, Crc16, Crc32};
+use std::io::{self, Read, Write};
+
+/// Checksum of the uncompressed data
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ChecksumKind {
+    /// CRC-32 (IEEE 802.3), a 4-byte trailer
+    Crc32,
+    /// CRC-16/CCITT-FALSE, a 2-byte trailer
+    Crc16,
+}
+
+impl ChecksumKind {
+    /// Size in bytes of the trailer
+    pub fn size(self) -> usize {
+        match self {
+            ChecksumKind::Crc32 => Crc32::SIZE,
+            ChecksumKind::Crc16 => Crc16::SIZE,
+        }
+    }
+}
+
+/// Checksum of the data seen so far
+enum ChecksumState {
+    Crc32(Crc32),
+    Crc16(Crc16),
+}
+
+impl ChecksumState {
+    fn new(kind: ChecksumKind) -> Self {
+        match kind {
+            ChecksumKind::Crc32 => ChecksumState::Crc32(Crc32::default()),
+            ChecksumKind::Crc16 => ChecksumState::Crc16(Crc16::default()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ChecksumState::Crc32(checksum) => checksum.update(data),
+            ChecksumState::Crc16(checksum) => checksum.update(data),
+        }
+    }
+
+    /// The trailer bytes of the checksum
+    fn trailer(&self) -> Vec<u8> {
+        let (value, size) = match self {
+            ChecksumState::Crc32(checksum) => (checksum.value(), Crc32::SIZE),
+            ChecksumState::Crc16(checksum) => (checksum.value(), Crc16::SIZE),
+        };
+        value.to_le_bytes()[..size].to_vec()
+    }
+}
+
+/// Reader adding the data read to a checksum
+pub struct ChecksumReader<'a> {
+    input: &'a mut dyn Read,
+    checksum: ChecksumState,
+}
+
+impl<'a> ChecksumReader<'a> {
+    pub fn new(input: &'a mut dyn Read, kind: ChecksumKind) -> Self {
+        ChecksumReader {
+            input,
+            checksum: ChecksumState::new(kind),
+        }
+    }
+
+    /// The trailer of the data read so far
+    pub fn trailer(&self) -> Vec<u8> {
+        self.checksum.trailer()
+    }
+}
+
+impl Read for ChecksumReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.input.read(buf)?;
+        self.checksum.update(&buf[..size]);
+        Ok(size)
+    }
+}
+
+/// Writer adding the data written to a checksum
+pub struct ChecksumWriter<'a> {
+    output: &'a mut dyn Write,
+    checksum: ChecksumState,
+}
+
+impl<'a> ChecksumWriter<'a> {
+    pub fn new(output: &'a mut dyn Write, kind: ChecksumKind) -> Self {
+        ChecksumWriter {
+            output,
+            checksum: ChecksumState::new(kind),
+        }
+    }
+
+    /// The trailer of the data written so far
+    pub fn trailer(&self) -> Vec<u8> {
+        self.checksum.trailer()
+    }
+}
+
+impl Write for ChecksumWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.output.write(buf)?;
+        self.checksum.update(&buf[..size]);
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Reader holding back the trailer ending its input: the last bytes read
+/// are only returned once more data follows them
+pub struct TrailerReader<'a> {
+    input: &'a mut dyn Read,
+    held: Vec<u8>,
+    trailer_size: usize,
+}
+
+impl<'a> TrailerReader<'a> {
+    pub fn new(input: &'a mut dyn Read, kind: ChecksumKind) -> Self {
+        TrailerReader {
+            input,
+            held: Vec::new(),
+            trailer_size: kind.size(),
+        }
+    }
+
+    /// The trailer, once all the input was read: shorter than expected if
+    /// the input is
+    pub fn trailer(&self) -> &[u8] {
+        &self.held
+    }
+}
+
+impl Read for TrailerReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.held.len() <= self.trailer_size && !buf.is_empty() {
+            let mut chunk = [0u8; 4096];
+            let chunk_size = self.input.read(&mut chunk)?;
+            if chunk_size == 0 {
+                return Ok(0);
+            }
+            self.held.extend_from_slice(&chunk[..chunk_size]);
+        }
+
+        let size = buf
+            .len()
+            .min(self.held.len().saturating_sub(self.trailer_size));
+        buf[..size].copy_from_slice(&self.held[..size]);
+        self.held.drain(..size);
+        Ok(size)
+    }
+}
@@ -0,0 +1,92 @@
+//! Description of compressed files and archives, read from their headers
+//! without uncompressing them.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+
+use super::archive;
+
+/// Print the format, the parameters and the metadata of each file (stdin
+/// if none): a .hsa archive, a container or a raw stream
+pub fn inspect(files: &[String]) {
+    // stdin if no file name was provided
+    let files: Vec<Option<&str>> = match files.is_empty() {
+        true => vec![None],
+        false => files.iter().map(|file| Some(file.as_str())).collect(),
+    };
+
+    for file in files {
+        let file_name = file.unwrap_or("-");
+        let mut input_file: Box<dyn Read> = match file {
+            None => Box::new(BufReader::new(io::stdin())),
+            Some(filename) => Box::new(BufReader::new(File::open(filename).unwrap())),
+        };
+
+        // the archives and the containers both start with 4 magic bytes
+        let mut buffer = Vec::new();
+        (&mut input_file)
+            .take(heatshrink::container::MAGIC.len() as u64)
+            .read_to_end(&mut buffer)
+            .unwrap();
+        if archive::is_archive(&buffer) {
+            if let Err(error) =
+                archive::list(file_name, &mut io::Cursor::new(buffer).chain(input_file))
+            {
+                super::exit_with_error(format_args!("{file_name}: {error}"));
+            }
+            continue;
+        }
+
+        match heatshrink::container::Format::detect(&buffer) {
+            heatshrink::container::Format::Container => {
+                let (header, metadata, header_size) = loop {
+                    match heatshrink::container::read_header_with_metadata(&buffer) {
+                        Err(heatshrink::HSError::Truncated) => {
+                            let mut chunk = [0u8; 256];
+                            let chunk_size = input_file.read(&mut chunk).unwrap();
+                            if chunk_size == 0 {
+                                panic!("Compressed data is too short for a container header");
+                            }
+                            buffer.extend_from_slice(&chunk[..chunk_size]);
+                        }
+                        result => {
+                            break result.expect(
+                                "Error in heatshrink::container::read_header_with_metadata()",
+                            )
+                        }
+                    }
+                };
+                let size =
+                    buffer.len() as u64 + io::copy(&mut input_file, &mut io::sink()).unwrap();
+
+                println!(
+                    "{file_name}: container (-w {} -l {})",
+                    header.params.window_bits(),
+                    header.params.lookahead_bits()
+                );
+                match header.original_len {
+                    Some(len) => println!("  size: {len} -> {size}"),
+                    None => println!("  size: unknown -> {size}"),
+                }
+                println!("  header: {header_size} bytes");
+                let metadata = metadata.unwrap_or_default();
+                if let Some(name) = metadata.name {
+                    println!("  name: {name}");
+                }
+                if let Some(mtime) = metadata.mtime {
+                    println!("  modified: {mtime} (seconds since the Unix epoch)");
+                }
+                if let Some(comment) = metadata.comment {
+                    println!("  comment: {comment}");
+                }
+            }
+            heatshrink::container::Format::Raw => {
+                let size =
+                    buffer.len() as u64 + io::copy(&mut input_file, &mut io::sink()).unwrap();
+                println!(
+                    "{file_name}: raw stream of {size} bytes, its parameters are not recorded"
+                );
+            }
+        }
+    }
+}
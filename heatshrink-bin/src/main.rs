@@ -1,33 +1,80 @@
-use clap::{ArgGroup, Parser};
+use checksum::{ChecksumKind, ChecksumReader, ChecksumWriter, TrailerReader};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use std::fs::File;
-use std::io;
+use std::io::{self, IsTerminal};
 use std::io::{BufReader, BufWriter};
 use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use verify::{CompareWriter, CopyReader, SharedBuffer};
+
+mod archive;
+mod bench;
+mod checksum;
+mod inspect;
+mod verify;
 
 const HEATSHRINK_APP_BUFFER_SIZE: usize = 64 * 1024;
 
+/// Suffix appended to the name of the compressed files by default
+const HEATSHRINK_APP_SUFFIX: &str = ".hs";
+
+/// Window capacity allowing to use any window size selected with -w
+const HEATSHRINK_APP_WINDOW_SIZE: usize = 1 << heatshrink::HEATSHRINK_MAX_WINDOWS_BITS;
+
+/// Format of the compressed data
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum StreamFormat {
+    /// The bare compressed stream, as written by the C implementation
+    Raw,
+    /// The compressed stream preceded by a container header
+    Container,
+    /// A container if the data starts with the magic bytes of its header,
+    /// else a raw stream (raw when compressing)
+    Auto,
+}
+
 #[derive(Parser)] // requires `derive` feature
 #[clap(author, version, about, long_about = None)]
-#[clap(group(ArgGroup::new("command").required(true).args(&["encode", "decode"])))]
 struct Cli {
-    #[clap(short = 'e', long = "encode", help = "Compress data")]
-    encode: bool,
-
-    #[clap(short = 'd', long = "decode", help = "Decompress data")]
-    decode: bool,
+    #[clap(subcommand)]
+    command: Command,
 
     #[clap(
         short = 'v',
         long = "verbose",
+        global = true,
         help = "Print input & output sizes, compression ratio, etc"
     )]
     verbose: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress data
+    Encode(EncodeArgs),
+    /// Decompress data
+    Decode(DecodeArgs),
+    /// Check that compressed data decompresses without error, without writing it
+    Test(TestArgs),
+    /// Measure the compression ratio and the compression and decompression speeds
+    Bench(BenchArgs),
+    /// Print the format, parameters and metadata of compressed data or archives
+    Inspect(InspectArgs),
+    /// Pack files into a .hsa archive, each one compressed on its own
+    Archive(ArchiveArgs),
+    /// Unpack the files of a .hsa archive
+    Extract(ExtractArgs),
+}
 
+/// Window and lookahead sizes
+#[derive(Args)]
+struct ParamsArgs {
     #[clap(
         short = 'w',
         long = "window",
         help = "Base-2 log of LZSS sliding window size",
-        default_value_t = 8
+        default_value_t = heatshrink::HEATSHRINK_WINDOWS_BITS
     )]
     size: u8,
 
@@ -35,20 +82,354 @@ struct Cli {
         short = 'l',
         long = "length",
         help = "Number of bits used for back-reference lengths",
-        default_value_t = 4
+        default_value_t = heatshrink::HEATSHRINK_LOOKAHEAD_BITS
     )]
     bits: u8,
+}
+
+impl ParamsArgs {
+    /// The parameters selected, exiting with a usage error if invalid
+    fn params(&self) -> heatshrink::Params {
+        match heatshrink::Params::new(self.size, self.bits) {
+            Ok(params) => params,
+            Err(error) => Cli::command()
+                .error(clap::error::ErrorKind::ValueValidation, error)
+                .exit(),
+        }
+    }
+}
+
+/// Compression level
+#[derive(Args)]
+struct LevelArgs {
+    #[clap(
+        long = "level",
+        help = "Compression level, from 1 (fastest) to 9 (best ratio on text)",
+        default_value_t = heatshrink::encoder::DEFAULT_LEVEL,
+        value_parser = clap::value_parser!(u8).range(1..=9)
+    )]
+    level: u8,
+}
+
+/// Format and encoding of the compressed data
+#[derive(Args)]
+struct FormatArgs {
+    #[clap(
+        long = "format",
+        value_enum,
+        default_value_t = StreamFormat::Raw,
+        help = "Format of the compressed data (-w and -l are read from the container header when decompressing)"
+    )]
+    format: StreamFormat,
+
+    #[clap(
+        long = "container",
+        conflicts_with = "format",
+        help = "Prefix the compressed data with a header giving its parameters and length (same as --format container)"
+    )]
+    container: bool,
+
+    #[clap(
+        long = "armor",
+        help = "Write the compressed data as base64 text wrapped in lines (read it back when decompressing)"
+    )]
+    armor: bool,
+
+    #[clap(
+        long = "crc",
+        value_enum,
+        value_name = "CHECKSUM",
+        help = "Append the checksum of the uncompressed data to the compressed stream (check it when decompressing)"
+    )]
+    crc: Option<ChecksumKind>,
+}
+
+impl FormatArgs {
+    /// The format selected
+    fn format(&self) -> StreamFormat {
+        if self.container {
+            StreamFormat::Container
+        } else {
+            self.format
+        }
+    }
+}
+
+/// Output and input files handling, following the gzip conventions
+#[derive(Args)]
+struct OutputArgs {
+    #[clap(
+        short = 'c',
+        long = "stdout",
+        conflicts_with = "output_file",
+        help = "Write to stdout, keeping the input file"
+    )]
+    stdout: bool,
+
+    #[clap(
+        short = 'k',
+        long = "keep",
+        help = "Keep the input file once its output file is written"
+    )]
+    keep: bool,
+
+    #[clap(
+        short = 'f',
+        long = "force",
+        help = "Overwrite existing output files, and write compressed data to or read it from a terminal"
+    )]
+    force: bool,
+
+    #[clap(
+        short = 'S',
+        long = "suffix",
+        value_name = "SUFFIX",
+        default_value = HEATSHRINK_APP_SUFFIX,
+        value_parser = parse_suffix,
+        help = "Suffix appended to the name of the compressed files, and stripped when decompressing"
+    )]
+    suffix: String,
+}
+
+/// Accept the non-empty suffixes without path separator
+fn parse_suffix(suffix: &str) -> Result<String, String> {
+    if suffix.is_empty() || suffix.contains(std::path::is_separator) {
+        return Err(format!("invalid suffix: {suffix:?}"));
+    }
+    Ok(suffix.to_string())
+}
+
+#[derive(Args)]
+struct EncodeArgs {
+    #[clap(flatten)]
+    params: ParamsArgs,
+
+    #[clap(flatten)]
+    level: LevelArgs,
+
+    #[clap(flatten)]
+    format: FormatArgs,
+
+    #[clap(
+        short = 'N',
+        long = "name",
+        help = "Record the name and modification time of the input file in the container header (like gzip -N)"
+    )]
+    name: bool,
+
+    #[clap(
+        long = "comment",
+        value_name = "TEXT",
+        help = "Record a comment in the container header"
+    )]
+    comment: Option<String>,
+
+    #[clap(
+        long = "verify",
+        help = "Decompress the compressed output and compare it with the input before declaring success"
+    )]
+    verify: bool,
+
+    #[clap(flatten)]
+    output: OutputArgs,
 
     /// some regular input. It will default to stdin if unspecified.
-    #[clap(group = "input")]
     input_file: Option<String>,
 
-    /// some regular output. It will default to stdout if unspecified.
-    #[clap(group = "output")]
+    /// the compressed output. It will default to the input file name with
+    /// the suffix appended (the input file being removed), or to stdout if
+    /// no input file is given.
+    output_file: Option<String>,
+}
+
+#[derive(Args)]
+struct DecodeArgs {
+    #[clap(flatten)]
+    params: ParamsArgs,
+
+    #[clap(flatten)]
+    format: FormatArgs,
+
+    #[clap(
+        short = 'N',
+        long = "name",
+        help = "Restore the name and modification time recorded in the container header (like gzip -N)"
+    )]
+    name: bool,
+
+    #[clap(
+        short = 't',
+        long = "test",
+        conflicts_with_all = ["output_file", "name"],
+        help = "Check that the compressed data decompresses without error, without writing it (same as the test subcommand)"
+    )]
+    test: bool,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+
+    /// some compressed input. It will default to stdin if unspecified.
+    input_file: Option<String>,
+
+    /// the uncompressed output. It will default to the input file name
+    /// without its suffix (the input file being removed), or to stdout if
+    /// no input file is given.
     output_file: Option<String>,
 }
 
-fn report(use_stderr: bool, file_name: &String, input_len: usize, output_len: usize) {
+#[derive(Args)]
+struct TestArgs {
+    #[clap(flatten)]
+    params: ParamsArgs,
+
+    #[clap(flatten)]
+    format: FormatArgs,
+
+    /// compressed files to check. It will default to stdin if unspecified.
+    files: Vec<String>,
+}
+
+#[derive(Args)]
+struct BenchArgs {
+    #[clap(flatten)]
+    params: ParamsArgs,
+
+    #[clap(flatten)]
+    level: LevelArgs,
+
+    #[clap(
+        short = 'n',
+        long = "iterations",
+        help = "Number of times each file is compressed and decompressed, the fastest run being reported",
+        default_value_t = 5,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    iterations: u32,
+
+    /// files to compress. It will default to stdin if unspecified.
+    files: Vec<String>,
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    /// compressed files or archives. It will default to stdin if unspecified.
+    files: Vec<String>,
+}
+
+#[derive(Args)]
+struct ArchiveArgs {
+    #[clap(flatten)]
+    params: ParamsArgs,
+
+    #[clap(flatten)]
+    level: LevelArgs,
+
+    /// the .hsa archive to write.
+    archive: String,
+
+    /// the files to pack.
+    #[clap(required = true)]
+    files: Vec<String>,
+}
+
+#[derive(Args)]
+struct ExtractArgs {
+    /// the .hsa archive to read.
+    archive: String,
+
+    #[clap(short = 'f', long = "force", help = "Overwrite existing files")]
+    force: bool,
+
+    /// the directory to unpack the files into, the current one by default.
+    directory: Option<String>,
+}
+
+/// Output of the compressed data, written as base64 text with --armor
+struct CompressedOutput {
+    armor: Option<heatshrink::armor::ArmorWriter>,
+    output: Box<dyn Write>,
+}
+
+impl CompressedOutput {
+    /// Write the end of the base64 text, if any, and flush the output:
+    /// unlike dropping it, the write errors are returned
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(mut armor) = self.armor.take() {
+            let mut result = Ok(());
+            armor.finish(&mut |data| {
+                if result.is_ok() {
+                    result = self.output.write_all(data);
+                }
+            });
+            result?;
+        }
+        self.output.flush()
+    }
+}
+
+impl Write for CompressedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(ref mut armor) = self.armor else {
+            return self.output.write(buf);
+        };
+        let mut result = Ok(());
+        armor.sink_all(buf, &mut |data| {
+            if result.is_ok() {
+                result = self.output.write_all(data);
+            }
+        });
+        result.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Input reading the data back from base64 text
+struct ArmoredInput {
+    armor: heatshrink::armor::ArmorReader,
+    input: Box<dyn Read>,
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl Read for ArmoredInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.offset == self.data.len() {
+            let mut text = [0u8; 4096];
+            let text_size = self.input.read(&mut text)?;
+            self.data.clear();
+            self.offset = 0;
+
+            if text_size == 0 {
+                self.armor
+                    .finish()
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                return Ok(0);
+            }
+            self.armor
+                .sink_all(&text[..text_size], &mut |data| {
+                    self.data.extend_from_slice(data)
+                })
+                .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+        }
+
+        let size = buf.len().min(self.data.len() - self.offset);
+        buf[..size].copy_from_slice(&self.data[self.offset..self.offset + size]);
+        self.offset += size;
+        Ok(size)
+    }
+}
+
+fn report(
+    use_stderr: bool,
+    file_name: &str,
+    input_len: u64,
+    output_len: u64,
+    window_bits: u8,
+    lookahead_bits: u8,
+) {
     if use_stderr {
         eprintln!(
             "{0:} {1:.2}% \t{2:} -> {3:} (-w {4:} -l {5:})",
@@ -56,8 +437,8 @@ fn report(use_stderr: bool, file_name: &String, input_len: usize, output_len: us
             100.0 - (100.0 * output_len as f32) / input_len as f32,
             input_len,
             output_len,
-            heatshrink::HEATSHRINK_WINDOWS_BITS,
-            heatshrink::HEATSHRINK_LOOKAHEAD_BITS
+            window_bits,
+            lookahead_bits
         );
     } else {
         println!(
@@ -66,249 +447,681 @@ fn report(use_stderr: bool, file_name: &String, input_len: usize, output_len: us
             100.0 - (100.0 * output_len as f32) / input_len as f32,
             input_len,
             output_len,
-            heatshrink::HEATSHRINK_WINDOWS_BITS,
-            heatshrink::HEATSHRINK_LOOKAHEAD_BITS
+            window_bits,
+            lookahead_bits
         );
     }
 }
 
-fn encode(input_file: &mut Box<dyn Read>, output_file: &mut Box<dyn Write>) -> (usize, usize) {
+/// Compress the input to the output, failing with the first read or write
+/// error
+fn encode<const WINDOW_SIZE: usize>(
+    enc: &mut heatshrink::encoder::HeatshrinkEncoder<WINDOW_SIZE>,
+    input_file: &mut dyn Read,
+    output_file: &mut dyn Write,
+) -> io::Result<(u64, u64)> {
     let mut input_buffer = [0u8; HEATSHRINK_APP_BUFFER_SIZE];
-    let mut output_buffer = [0u8; HEATSHRINK_APP_BUFFER_SIZE];
-    let mut encoding_is_complete = false;
-    let mut total_input_byte_size = 0;
-    let mut total_output_byte_size = 0;
-
-    let mut enc: heatshrink::encoder::HeatshrinkEncoder = Default::default();
-
-    let mut output_bytes_processed = 0;
+    let mut result = Ok(());
+    let mut write_output = |data: &[u8]| {
+        if result.is_ok() {
+            result = output_file.write_all(data);
+        }
+    };
 
     loop {
-        let input_bytes_read = input_file.read(&mut input_buffer[0..]).unwrap();
+        let input_bytes_read = input_file.read(&mut input_buffer)?;
 
-        total_input_byte_size += input_bytes_read;
+        if input_bytes_read == 0 {
+            break;
+        }
 
-        let mut input_bytes_processed = 0;
+        enc.sink_all(&input_buffer[..input_bytes_read], &mut write_output)
+            .map_err(io::Error::other)?;
+    }
 
-        loop {
-            if input_bytes_read > 0 {
-                match enc.sink(&input_buffer[input_bytes_processed..input_bytes_read]) {
-                    (heatshrink::HSsinkRes::SinkOK, segment_input_size) => {
-                        // Data has been added to the encoder.
-                        // Let's try to process/poll it
-                        input_bytes_processed += segment_input_size;
-                    }
-                    (heatshrink::HSsinkRes::SinkFull, _) => {
-                        // Hum ... no data was added to the encoder because
-                        // the internal buffer was already full.
-                        panic!("Input buffer is full and unprocessed");
-                    }
-                    (heatshrink::HSsinkRes::SinkErrorMisuse, _) => {
-                        panic!("Error in HeatshrinkEncoder::sink()");
-                    }
-                }
-            }
+    // flush the end of the compressed stream
+    enc.finish();
+    enc.poll_all(&mut write_output).map_err(io::Error::other)?;
+    result?;
 
-            loop {
-                // process the current input buffer
-                match enc.poll(&mut output_buffer[output_bytes_processed..]) {
-                    (heatshrink::HSpollRes::PollMore, segment_output_size) => {
-                        output_bytes_processed += segment_output_size;
-                        let mut buf_begin = 0;
-                        while buf_begin != output_bytes_processed {
-                            let bytes_written = output_file
-                                .write(&output_buffer[buf_begin..output_bytes_processed])
-                                .unwrap();
-                            buf_begin += bytes_written;
-                        }
-                        total_output_byte_size += output_bytes_processed;
-                        output_bytes_processed = 0;
-                        // Some more data is avaialble in input_buffer.
-                        // Let's loop.
-                    }
-                    (heatshrink::HSpollRes::PollEmpty, segment_output_size) => {
-                        output_bytes_processed += segment_output_size;
-                        // The input_buffer is consumed.
-                        // Exit the loop.
-                        break;
-                    }
-                    (heatshrink::HSpollRes::PollErrorMisuse, _) => {
-                        panic!("Error in HeatshrinkEncoder::poll()");
-                    }
-                }
-            }
+    Ok((enc.total_in(), enc.total_out()))
+}
 
-            if input_bytes_read == 0 {
-                if output_bytes_processed != 0 {
-                    let mut buf_begin = 0;
-                    while buf_begin != output_bytes_processed {
-                        let bytes_written = output_file
-                            .write(&output_buffer[buf_begin..output_bytes_processed])
-                            .unwrap();
-                        buf_begin += bytes_written;
-                    }
-                    total_output_byte_size += output_bytes_processed;
-                    output_bytes_processed = 0;
-                }
-                if let heatshrink::HSfinishRes::FinishDone = enc.finish() {
-                    encoding_is_complete = true;
-                    break;
-                }
-            }
+/// Error of corrupted compressed data
+fn invalid_data(error: heatshrink::HSError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
 
-            if input_bytes_read == input_bytes_processed {
-                break;
-            }
+/// Decompress the input to the output, failing with an `InvalidData` error
+/// if the compressed data is corrupted or truncated
+fn decode<const WINDOW_SIZE: usize>(
+    dec: &mut heatshrink::decoder::HeatshrinkDecoder<WINDOW_SIZE>,
+    input_file: &mut dyn Read,
+    output_file: &mut dyn Write,
+) -> io::Result<(u64, u64)> {
+    let mut input_buffer = [0u8; HEATSHRINK_APP_BUFFER_SIZE];
+    let mut result = Ok(());
+    let mut write_output = |data: &[u8]| {
+        if result.is_ok() {
+            result = output_file.write_all(data);
         }
+    };
+
+    loop {
+        let input_bytes_read = input_file.read(&mut input_buffer)?;
 
-        if encoding_is_complete {
+        if input_bytes_read == 0 {
             break;
         }
+
+        dec.sink_all(&input_buffer[..input_bytes_read], &mut write_output)
+            .map_err(invalid_data)?;
     }
 
-    (total_input_byte_size, total_output_byte_size)
-}
+    if let heatshrink::HSfinishRes::FinishMore = dec.finish() {
+        return Err(invalid_data(heatshrink::HSError::Truncated));
+    }
+    result?;
 
-fn decode(input_file: &mut Box<dyn Read>, output_file: &mut Box<dyn Write>) -> (usize, usize) {
-    let mut input_buffer = [0u8; HEATSHRINK_APP_BUFFER_SIZE];
-    let mut output_buffer = [0u8; HEATSHRINK_APP_BUFFER_SIZE];
-    let mut total_input_byte_size = 0;
-    let mut total_output_byte_size = 0;
+    Ok((dec.total_in(), dec.total_out()))
+}
 
-    let mut dec: heatshrink::decoder::HeatshrinkDecoder = Default::default();
+/// Compressed data opened for decompression
+struct CompressedInput {
+    /// The compressed stream, past the container header if any
+    reader: Box<dyn Read>,
+    /// Parameters of the compressed stream
+    params: heatshrink::Params,
+    /// The container header, if any
+    header: Option<heatshrink::container::Header>,
+    /// Size of the container header and of its metadata section
+    header_size: usize,
+    /// Original file name recorded in the metadata section
+    name: Option<String>,
+    /// Modification time recorded in the metadata section
+    mtime: Option<u64>,
+    /// Comment recorded in the metadata section
+    comment: Option<String>,
+    /// Checksum trailer ending the compressed stream, if any
+    checksum: Option<ChecksumKind>,
+}
 
-    let mut output_bytes_processed = 0;
+/// Open the compressed input (stdin if no file name is given) and read its
+/// container header, if any
+fn open_compressed(
+    input_file: Option<&str>,
+    params: &ParamsArgs,
+    format: &FormatArgs,
+) -> io::Result<CompressedInput> {
+    let reader: Box<dyn Read> = match input_file {
+        // if no file name was provided use stdin instead
+        None => Box::new(BufReader::new(io::stdin())),
+        Some(filename) => Box::new(BufReader::new(File::open(filename)?)),
+    };
+    read_compressed(reader, params.params(), format)
+}
 
-    loop {
-        let input_bytes_read = input_file.read(&mut input_buffer).unwrap();
+/// Read the container header of the compressed data, if any
+fn read_compressed(
+    reader: Box<dyn Read>,
+    params: heatshrink::Params,
+    format: &FormatArgs,
+) -> io::Result<CompressedInput> {
+    let mut input = CompressedInput {
+        reader,
+        params,
+        header: None,
+        header_size: 0,
+        name: None,
+        mtime: None,
+        comment: None,
+        checksum: format.crc,
+    };
+    if format.armor {
+        input.reader = Box::new(ArmoredInput {
+            armor: heatshrink::armor::ArmorReader::new(),
+            input: input.reader,
+            data: Vec::new(),
+            offset: 0,
+        });
+    }
 
-        total_input_byte_size += input_bytes_read;
+    let mut format = format.format();
+    if format == StreamFormat::Auto {
+        // the first bytes tell whether there is a container header, they
+        // are then read again
+        let mut magic = Vec::new();
+        (&mut input.reader)
+            .take(heatshrink::container::MAGIC.len() as u64)
+            .read_to_end(&mut magic)?;
+        format = match heatshrink::container::Format::detect(&magic) {
+            heatshrink::container::Format::Container => StreamFormat::Container,
+            heatshrink::container::Format::Raw => StreamFormat::Raw,
+        };
+        input.reader = Box::new(io::Cursor::new(magic).chain(input.reader));
+    }
 
-        if input_bytes_read == 0 {
-            match dec.finish() {
-                heatshrink::HSfinishRes::FinishDone => {
-                    if output_bytes_processed != 0 {
-                        let mut buf_begin = 0;
-                        while buf_begin != output_bytes_processed {
-                            let bytes_written = output_file
-                                .write(&output_buffer[buf_begin..output_bytes_processed])
-                                .unwrap();
-                            buf_begin += bytes_written;
-                        }
-                        total_output_byte_size += output_bytes_processed;
+    // the container header gives the parameters of the compressed data
+    if format == StreamFormat::Container {
+        // the metadata section makes the header of variable size: the bytes
+        // read past it are the start of the compressed stream
+        let mut buffer = Vec::new();
+        let (header, metadata, size) = loop {
+            match heatshrink::container::read_header_with_metadata(&buffer) {
+                Err(heatshrink::HSError::Truncated) => {
+                    let mut chunk = [0u8; 256];
+                    let chunk_size = input.reader.read(&mut chunk)?;
+                    if chunk_size == 0 {
+                        return Err(invalid_data(heatshrink::HSError::Truncated));
                     }
-                    // the input input_buffer if empty now.
-                    break;
-                }
-                heatshrink::HSfinishRes::FinishMore => {
-                    // More data to be processed ?
+                    buffer.extend_from_slice(&chunk[..chunk_size]);
                 }
+                result => break result.map_err(invalid_data)?,
             }
+        };
+        input.params = header.params;
+        input.header = Some(header);
+        input.header_size = size;
+        if let Some(metadata) = metadata {
+            input.name = metadata.name.map(str::to_string);
+            input.mtime = metadata.mtime;
+            input.comment = metadata.comment.map(str::to_string);
         }
+        let stream = buffer.split_off(size);
+        input.reader = Box::new(io::Cursor::new(stream).chain(input.reader));
+    }
 
-        let mut input_bytes_processed = 0;
+    Ok(input)
+}
 
-        while input_bytes_processed < input_bytes_read {
-            match dec.sink(&input_buffer[input_bytes_processed..input_bytes_read]) {
-                (heatshrink::HSsinkRes::SinkOK, segment_input_size) => {
-                    // Data has been added to the decoder.
-                    // Let's try to process/poll it
-                    input_bytes_processed += segment_input_size;
-                }
-                (heatshrink::HSsinkRes::SinkFull, _) => {
-                    // Hum ... no data was added to the decoder because
-                    // the internal buffer was already full.
-                    panic!("Input buffer is full and unprocessed");
-                }
-                (heatshrink::HSsinkRes::SinkErrorMisuse, _) => {
-                    // We should abort/assert/return
-                    panic!("Error in HeatshrinkDecoder::sink()");
-                }
+/// Decompress the input to the output, checking the length recorded in the
+/// container header and the checksum trailer, and return the compressed
+/// (header and trailer included) and uncompressed sizes
+fn decode_input(
+    input: &mut CompressedInput,
+    output_file: &mut dyn Write,
+) -> io::Result<(u64, u64)> {
+    let mut dec =
+        heatshrink::decoder::HeatshrinkDecoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            input.params,
+        )
+        .unwrap();
+    let (input_size, output_size) = match input.checksum {
+        None => decode(&mut dec, &mut input.reader, output_file)?,
+        Some(kind) => {
+            let mut input_file = TrailerReader::new(&mut input.reader, kind);
+            let mut output_file = ChecksumWriter::new(output_file, kind);
+            let (input_size, output_size) = decode(&mut dec, &mut input_file, &mut output_file)?;
+            if input_file.trailer().len() < kind.size() {
+                return Err(invalid_data(heatshrink::HSError::Truncated));
             }
+            if input_file.trailer() != output_file.trailer() {
+                return Err(invalid_data(heatshrink::HSError::InvalidChecksum));
+            }
+            (input_size + kind.size() as u64, output_size)
+        }
+    };
+    if let Some(len) = input.header.and_then(|header| header.original_len) {
+        if len > output_size {
+            return Err(invalid_data(heatshrink::HSError::TooShort));
+        }
+        if len < output_size {
+            return Err(invalid_data(heatshrink::HSError::TooLong));
+        }
+    }
 
-            loop {
-                // process the current input buffer
-                match dec.poll(&mut output_buffer[output_bytes_processed..]) {
-                    (heatshrink::HSpollRes::PollMore, segment_output_size) => {
-                        output_bytes_processed += segment_output_size;
-                        let mut buf_begin = 0;
-                        while buf_begin != output_bytes_processed {
-                            let bytes_written = output_file
-                                .write(&output_buffer[buf_begin..output_bytes_processed])
-                                .unwrap();
-                            buf_begin += bytes_written;
-                        }
-                        total_output_byte_size += output_bytes_processed;
-                        output_bytes_processed = 0;
-                        // Some more data is avaialble in input_buffer.
-                        // Let's loop.
-                    }
-                    (heatshrink::HSpollRes::PollEmpty, segment_output_size) => {
-                        output_bytes_processed += segment_output_size;
-                        // The input_buffer is consumed.
-                        // Exit the loop.
-                        break;
-                    }
-                    (heatshrink::HSpollRes::PollErrorMisuse, _) => {
-                        // We should abort/assert/return
-                        panic!("Error in HeatshrinkDecoder::poll()");
-                    }
-                }
+    Ok((input_size + input.header_size as u64, output_size))
+}
+
+/// Open the file to read back, or its copy kept in memory for stdin and
+/// stdout
+fn open_or_copy(filename: Option<&Path>, copy: Vec<u8>) -> io::Result<Box<dyn Read>> {
+    Ok(match filename {
+        None => Box::new(io::Cursor::new(copy)),
+        Some(filename) => Box::new(BufReader::new(File::open(filename)?)),
+    })
+}
+
+/// Decompress the compressed data written and compare it with the input,
+/// failing with an `InvalidData` error if it differs
+fn verify_output(
+    compressed: Box<dyn Read>,
+    source: &mut dyn Read,
+    params: heatshrink::Params,
+    format: &FormatArgs,
+) -> io::Result<()> {
+    let mut output = CompareWriter::new(source);
+    let mut input = read_compressed(compressed, params, format)?;
+    decode_input(&mut input, &mut output)?;
+    output.finish()
+}
+
+/// Print the error and exit with a failure status
+fn exit_with_error(message: impl std::fmt::Display) -> ! {
+    eprintln!("heatshrink: {message}");
+    std::process::exit(1)
+}
+
+/// Create the output file (stdout if no file name is given), refusing to
+/// overwrite an existing file unless forced
+fn create_output(output_name: Option<&Path>, force: bool) -> Box<dyn Write> {
+    match output_name {
+        // if no file name was provided use stdout instead
+        None => Box::new(BufWriter::new(io::stdout())),
+        Some(filename) => {
+            if !force && filename.exists() {
+                exit_with_error(format_args!(
+                    "{} already exists, use -f to overwrite it",
+                    filename.display()
+                ));
             }
+            let file = File::create(filename).unwrap_or_else(|error| {
+                exit_with_error(format_args!("{}: {error}", filename.display()))
+            });
+            Box::new(BufWriter::new(file))
         }
     }
-    (total_input_byte_size, total_output_byte_size)
 }
 
-fn main() {
-    // parse the command line parameters
-    let args = Cli::parse();
+/// Remove the output file left incomplete or not trusted: only a regular
+/// file, not e.g. a device written to
+fn remove_output(output_name: Option<&Path>) {
+    if let Some(filename) = output_name {
+        if std::fs::metadata(filename).is_ok_and(|metadata| metadata.is_file()) {
+            let _ = std::fs::remove_file(filename);
+        }
+    }
+}
 
-    if args.size != heatshrink::HEATSHRINK_WINDOWS_BITS {
-        panic!(
-            "For now only the default value [{0:}] is supported for window size",
-            heatshrink::HEATSHRINK_WINDOWS_BITS
-        );
+/// Remove the input file once its output file is written, unless kept
+fn remove_input(input_file: Option<&str>, output: &OutputArgs) {
+    if let Some(filename) = input_file.filter(|_| !output.keep) {
+        if let Err(error) = std::fs::remove_file(filename) {
+            exit_with_error(format_args!("{filename}: {error}"));
+        }
     }
+}
 
-    if args.bits != heatshrink::HEATSHRINK_LOOKAHEAD_BITS {
-        panic!(
-            "For now only the default value [{0:}] is supported for back-reference length",
-            heatshrink::HEATSHRINK_LOOKAHEAD_BITS
-        );
+fn encode_command(args: &EncodeArgs, verbose: bool) {
+    let params = args.params.params();
+    let format = args.format.format();
+    if (args.name || args.comment.is_some()) && format != StreamFormat::Container {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "-N and --comment need the container format",
+            )
+            .exit();
+    }
+
+    // like gzip, a file is compressed next to it and removed, and stdin to
+    // stdout
+    let derived_name = match (&args.input_file, &args.output_file) {
+        (Some(filename), None) if !args.output.stdout => {
+            // compressing twice is most likely a mistake
+            if filename.ends_with(&args.output.suffix) && !args.output.force {
+                exit_with_error(format_args!(
+                    "{filename} already has the {} suffix, use -f to compress it",
+                    args.output.suffix
+                ));
+            }
+            Some(PathBuf::from(format!("{filename}{}", args.output.suffix)))
+        }
+        _ => None,
+    };
+    let output_name = derived_name
+        .clone()
+        .or(args.output_file.as_ref().map(PathBuf::from));
+    if output_name.is_none()
+        && !args.format.armor
+        && !args.output.force
+        && io::stdout().is_terminal()
+    {
+        exit_with_error("compressed data not written to a terminal, use -f to force it");
     }
 
     // Open input file for read
     let mut input_file: Box<dyn Read> = match args.input_file {
         // if no file name was provided use stdin instead
         None => Box::new(BufReader::new(io::stdin())),
-        Some(ref filename) => Box::new(BufReader::new(File::open(filename).unwrap())),
+        Some(ref filename) => {
+            let file = File::open(filename)
+                .unwrap_or_else(|error| exit_with_error(format_args!("{filename}: {error}")));
+            Box::new(BufReader::new(file))
+        }
     };
     // Open output file for write
-    let mut output_file: Box<dyn Write> = match args.output_file {
-        // if no file name was provided use stdin instead
-        None => Box::new(BufWriter::new(io::stdout())),
-        Some(ref filename) => Box::new(BufWriter::new(File::create(filename).unwrap())),
+    let mut output = create_output(output_name.as_deref(), args.output.force);
+    // to verify the output, stdin is copied as it is read, and the data for
+    // stdout only written there once verified
+    let source_copy = SharedBuffer::default();
+    let output_copy = SharedBuffer::default();
+    if args.verify {
+        if args.input_file.is_none() {
+            input_file = Box::new(CopyReader::new(input_file, source_copy.clone()));
+        }
+        if output_name.is_none() {
+            output = Box::new(output_copy.clone());
+        }
+    }
+    let mut output_file = CompressedOutput {
+        armor: args
+            .format
+            .armor
+            .then(heatshrink::armor::ArmorWriter::default),
+        output,
     };
 
-    // Process the file
-    let (input_size, output_size) = if args.encode {
-        encode(&mut input_file, &mut output_file)
+    // kept on the heap as the largest window and its index take ~1 MiB
+    let mut enc =
+        heatshrink::encoder::HeatshrinkEncoder::<HEATSHRINK_APP_WINDOW_SIZE>::boxed_with_params(
+            params,
+        )
+        .unwrap();
+    enc.set_level(args.level.level);
+    let mut header = Vec::new();
+    if format == StreamFormat::Container {
+        // the length of the input is only known for regular files
+        let file_metadata = args
+            .input_file
+            .as_ref()
+            .and_then(|filename| std::fs::metadata(filename).ok())
+            .filter(|metadata| metadata.is_file());
+        let container_header = heatshrink::container::Header::new(
+            params,
+            file_metadata.as_ref().map(|metadata| metadata.len()),
+        );
+        let mut buffer = vec![0u8; heatshrink::container::HEADER_SIZE];
+        let header_size = if args.name || args.comment.is_some() {
+            let mut metadata = heatshrink::container::Metadata::new();
+            if args.name {
+                metadata.name = args
+                    .input_file
+                    .as_ref()
+                    .and_then(|filename| Path::new(filename).file_name())
+                    .and_then(|name| name.to_str());
+                metadata.mtime = file_metadata
+                    .and_then(|metadata| metadata.modified().ok())
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|mtime| mtime.as_secs());
+            }
+            metadata.comment = args.comment.as_deref();
+            buffer.resize(heatshrink::container::HEADER_SIZE + metadata.size(), 0);
+            heatshrink::container::write_header_with_metadata(
+                &container_header,
+                &metadata,
+                &mut buffer,
+            )
+            .expect("Error in heatshrink::container::write_header_with_metadata()")
+        } else {
+            heatshrink::container::write_header(&container_header, &mut buffer)
+                .expect("Error in heatshrink::container::write_header()")
+        };
+        buffer.truncate(header_size);
+        header = buffer;
+    }
+    let result = output_file
+        .write_all(&header)
+        .and_then(|_| match args.format.crc {
+            None => encode(&mut enc, &mut input_file, &mut output_file),
+            Some(kind) => {
+                let mut input_file = ChecksumReader::new(&mut input_file, kind);
+                let (input_size, output_size) =
+                    encode(&mut enc, &mut input_file, &mut output_file)?;
+                output_file.write_all(&input_file.trailer())?;
+                Ok((input_size, output_size + kind.size() as u64))
+            }
+        })
+        .and_then(|sizes| {
+            output_file.finish()?;
+            Ok(sizes)
+        });
+
+    // like gzip, the input is kept and the incomplete output removed
+    let input_name = args.input_file.as_deref().unwrap_or("-");
+    let (input_size, output_size) = result.unwrap_or_else(|error| {
+        remove_output(output_name.as_deref());
+        exit_with_error(format_args!("{input_name}: {error}"));
+    });
+    if args.verify {
+        // read back as written: auto is raw when compressing
+        let format = FormatArgs {
+            format: match format {
+                StreamFormat::Container => StreamFormat::Container,
+                _ => StreamFormat::Raw,
+            },
+            container: false,
+            ..args.format
+        };
+        let compressed_data = output_copy.take();
+        let result =
+            open_or_copy(output_name.as_deref(), compressed_data.clone()).and_then(|compressed| {
+                let input_path = args.input_file.as_deref().map(Path::new);
+                let mut source = open_or_copy(input_path, source_copy.take())?;
+                verify_output(compressed, &mut source, params, &format)
+            });
+        match result {
+            Ok(()) if output_name.is_none() => {
+                let mut stdout = io::stdout().lock();
+                if let Err(error) = stdout
+                    .write_all(&compressed_data)
+                    .and_then(|_| stdout.flush())
+                {
+                    exit_with_error(format_args!("{input_name}: {error}"));
+                }
+            }
+            Ok(()) => {}
+            Err(error) => {
+                // the input is kept and the output not trusted
+                remove_output(output_name.as_deref());
+                exit_with_error(format_args!("{input_name}: verification failed: {error}"));
+            }
+        }
+    }
+    if derived_name.is_some() {
+        remove_input(args.input_file.as_deref(), &args.output);
+    }
+
+    // Output log if requested
+    if verbose {
+        report(
+            output_name.is_none(),
+            input_name,
+            input_size,
+            output_size + header.len() as u64,
+            params.window_bits(),
+            params.lookahead_bits(),
+        );
+    }
+}
+
+fn decode_command(args: &DecodeArgs, verbose: bool) {
+    if args.name && args.format.format() == StreamFormat::Raw {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                "-N needs the container format",
+            )
+            .exit();
+    }
+    if args.input_file.is_none()
+        && !args.format.armor
+        && !args.output.force
+        && io::stdin().is_terminal()
+    {
+        exit_with_error("compressed data not read from a terminal, use -f to force it");
+    }
+    if args.test {
+        test_files(&[args.input_file.as_deref()], &args.params, &args.format);
+        return;
+    }
+    let input_name = args.input_file.as_deref().unwrap_or("-");
+    let mut input = open_compressed(args.input_file.as_deref(), &args.params, &args.format)
+        .unwrap_or_else(|error| exit_with_error(format_args!("{input_name}: {error}")));
+
+    // like gunzip, a file is uncompressed next to it and removed, and stdin
+    // to stdout. With -N, under the name recorded, restored next to the
+    // compressed file without the directory it was recorded with
+    let derived_name = if args.output_file.is_some() || args.output.stdout {
+        None
     } else {
-        decode(&mut input_file, &mut output_file)
+        let recorded_name = input
+            .name
+            .as_ref()
+            .filter(|_| args.name)
+            .and_then(|name| Path::new(name).file_name())
+            .map(|name| match args.input_file {
+                Some(ref filename) => Path::new(filename).with_file_name(name),
+                None => PathBuf::from(name),
+            });
+        match (recorded_name, &args.input_file) {
+            (Some(name), _) => Some(name),
+            (None, Some(filename)) => match filename.strip_suffix(&args.output.suffix) {
+                Some(stem) if !stem.is_empty() => Some(PathBuf::from(stem)),
+                _ => exit_with_error(format_args!(
+                    "{filename} has no {} suffix, give the output file name or use -c",
+                    args.output.suffix
+                )),
+            },
+            (None, None) => None,
+        }
     };
+    let mut mtime = None;
+    if args.name {
+        mtime = input.mtime;
+        if let Some(comment) = input.comment.as_ref().filter(|_| verbose) {
+            eprintln!("{comment}");
+        }
+    }
+    let output_name = derived_name
+        .clone()
+        .or(args.output_file.as_ref().map(PathBuf::from));
+
+    // Open output file for write
+    let mut output_file = create_output(output_name.as_deref(), args.output.force);
+    let result = decode_input(&mut input, &mut output_file).and_then(|sizes| {
+        output_file.flush()?;
+        Ok(sizes)
+    });
+    drop(output_file);
+    let (input_size, output_size) = match result {
+        Ok(sizes) => sizes,
+        Err(error) => {
+            // like gunzip, the input is kept and the partial output removed
+            remove_output(output_name.as_deref());
+            exit_with_error(format_args!("{input_name}: {error}"));
+        }
+    };
+
+    // restore the modification time once all the data is written: the
+    // uncompressed file is kept if it cannot be, e.g. on a filesystem
+    // without modification times
+    if let (Some(mtime), Some(ref filename)) = (mtime, output_name.as_ref()) {
+        let result = File::options()
+            .write(true)
+            .open(filename)
+            .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime)));
+        if let Err(error) = result {
+            eprintln!(
+                "heatshrink: {}: modification time not restored: {error}",
+                filename.display()
+            );
+        }
+    }
+    if derived_name.is_some() {
+        remove_input(args.input_file.as_deref(), &args.output);
+    }
 
     // Output log if requested
-    if args.verbose {
-        let file_name = match args.input_file {
-            None => "-".to_string(),
-            Some(ref filename) => filename.to_string(),
-        };
+    if verbose {
         report(
-            args.output_file.is_none(),
-            &file_name,
+            output_name.is_none(),
+            input_name,
             input_size,
             output_size,
+            input.params.window_bits(),
+            input.params.lookahead_bits(),
         );
     }
 }
+
+/// Decompress each file (stdin for None) without writing it, reporting
+/// whether it is OK or corrupted, and exit with a failure status if any
+/// is not OK
+fn test_files(files: &[Option<&str>], params: &ParamsArgs, format: &FormatArgs) {
+    let mut failed = false;
+
+    for file in files {
+        let file_name = file.unwrap_or("-");
+        match open_compressed(*file, params, format)
+            .and_then(|mut input| decode_input(&mut input, &mut io::sink()))
+        {
+            Ok(_) => println!("{file_name}: OK"),
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => {
+                println!("{file_name}: corrupted ({error})");
+                failed = true;
+            }
+            Err(error) => {
+                eprintln!("heatshrink: {file_name}: {error}");
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+fn test_command(args: &TestArgs) {
+    // stdin if no file name was provided
+    let files: Vec<Option<&str>> = match args.files.is_empty() {
+        true => vec![None],
+        false => args.files.iter().map(|file| Some(file.as_str())).collect(),
+    };
+
+    test_files(&files, &args.params, &args.format);
+}
+
+fn main() {
+    // parse the command line parameters
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Encode(ref args) => encode_command(args, cli.verbose),
+        Command::Decode(ref args) => decode_command(args, cli.verbose),
+        Command::Test(ref args) => test_command(args),
+        Command::Bench(ref args) => bench::bench(
+            &args.files,
+            args.params.params(),
+            args.level.level,
+            args.iterations,
+        ),
+        Command::Inspect(ref args) => inspect::inspect(&args.files),
+        Command::Archive(ref args) => {
+            let result = archive::archive(
+                &args.archive,
+                &args.files,
+                args.params.params(),
+                args.level.level,
+                cli.verbose,
+            );
+            // like encode, the incomplete archive is removed
+            if let Err(error) = result {
+                remove_output(Some(Path::new(&args.archive)));
+                exit_with_error(format_args!("{}: {error}", args.archive));
+            }
+        }
+        Command::Extract(ref args) => {
+            let result = archive::extract(
+                &args.archive,
+                Path::new(args.directory.as_deref().unwrap_or(".")),
+                args.force,
+                cli.verbose,
+            );
+            if let Err(error) = result {
+                exit_with_error(format_args!("{}: {error}", args.archive));
+            }
+        }
+    }
+}
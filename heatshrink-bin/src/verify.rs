@@ -0,0 +1,108 @@
+//! Adapters checking that the compressed output decompresses back to the
+//! input, for --verify.
+
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Buffer shared by the writer filling it and its reader once written, to
+/// keep a copy of stdin or of the data for stdout
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    /// The data written so far, leaving the buffer empty
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reader keeping a copy of the data read
+pub struct CopyReader {
+    input: Box<dyn Read>,
+    copy: SharedBuffer,
+}
+
+impl CopyReader {
+    pub fn new(input: Box<dyn Read>, copy: SharedBuffer) -> Self {
+        CopyReader { input, copy }
+    }
+}
+
+impl Read for CopyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.input.read(buf)?;
+        self.copy.write_all(&buf[..size])?;
+        Ok(size)
+    }
+}
+
+/// Writer comparing the data written with the data of a reader, failing
+/// with an `InvalidData` error at the first difference
+pub struct CompareWriter<'a> {
+    expected: &'a mut dyn Read,
+    offset: u64,
+}
+
+impl<'a> CompareWriter<'a> {
+    pub fn new(expected: &'a mut dyn Read) -> Self {
+        CompareWriter {
+            expected,
+            offset: 0,
+        }
+    }
+
+    /// Check that all the data of the reader was written
+    pub fn finish(self) -> io::Result<()> {
+        let mut byte = [0u8; 1];
+        match self.expected.read(&mut byte)? {
+            0 => Ok(()),
+            _ => Err(differs(self.offset)),
+        }
+    }
+}
+
+/// Error of data differing from the expected one from offset
+fn differs(offset: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("uncompressed data differs from the input at byte {offset}"),
+    )
+}
+
+impl Write for CompareWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut expected = vec![0u8; buf.len()];
+        let mut size = 0;
+        while size < buf.len() {
+            match self.expected.read(&mut expected[size..])? {
+                0 => break,
+                read => size += read,
+            }
+        }
+
+        match buf.iter().zip(&expected[..size]).position(|(a, b)| a != b) {
+            Some(position) => Err(differs(self.offset + position as u64)),
+            None if size < buf.len() => Err(differs(self.offset + size as u64)),
+            None => {
+                self.offset += size as u64;
+                Ok(size)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! End-to-end tests of the command line tool, run on files in a temporary
+//! directory: the round trips, and the gzip-like handling of the input and
+//! output files.
+
+use std::fs;
+
+use assert_cmd::cargo::cargo_bin_cmd;
+use assert_cmd::Command;
+use tempfile::TempDir;
+
+/// Text compressing well, long enough to fill several windows
+fn text() -> Vec<u8> {
+    (0..200)
+        .map(|line| format!("line {line}: the quick brown fox jumps over the lazy dog\n"))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// The tool, run in the directory
+fn heatshrink(dir: &TempDir) -> Command {
+    let mut cmd = cargo_bin_cmd!("heatshrink");
+    cmd.current_dir(dir.path());
+    cmd
+}
+
+/// The standard error of a failed run
+fn stderr(cmd: &mut Command) -> String {
+    let assert = cmd.assert().failure();
+    String::from_utf8_lossy(&assert.get_output().stderr).into_owned()
+}
+
+/// Write the file in the directory
+fn write(dir: &TempDir, name: &str, data: &[u8]) {
+    fs::write(dir.path().join(name), data).unwrap();
+}
+
+/// Read the file of the directory
+fn read(dir: &TempDir, name: &str) -> Vec<u8> {
+    fs::read(dir.path().join(name)).unwrap()
+}
+
+/// Whether the file is in the directory
+fn exists(dir: &TempDir, name: &str) -> bool {
+    dir.path().join(name).exists()
+}
+
+#[test]
+fn round_trip() {
+    let dir = TempDir::new().unwrap();
+    let data = text();
+    write(&dir, "input.txt", &data);
+
+    heatshrink(&dir)
+        .args(["encode", "input.txt"])
+        .assert()
+        .success();
+    assert!(!exists(&dir, "input.txt"));
+    assert!(read(&dir, "input.txt.hs").len() < data.len());
+
+    heatshrink(&dir)
+        .args(["decode", "input.txt.hs"])
+        .assert()
+        .success();
+    assert!(!exists(&dir, "input.txt.hs"));
+    assert_eq!(read(&dir, "input.txt"), data);
+}
+
+#[test]
+fn round_trip_formats() {
+    let formats: [&[&str]; 4] = [
+        &["-w", "8", "-l", "4"],
+        &["--container"],
+        &["--armor"],
+        &["--crc", "crc32"],
+    ];
+    for format in formats {
+        let dir = TempDir::new().unwrap();
+        let data = text();
+        write(&dir, "input.txt", &data);
+
+        heatshrink(&dir)
+            .arg("encode")
+            .args(format)
+            .args(["input.txt", "compressed"])
+            .assert()
+            .success();
+        heatshrink(&dir)
+            .arg("decode")
+            .args(format)
+            .args(["compressed", "output.txt"])
+            .assert()
+            .success();
+        assert_eq!(read(&dir, "output.txt"), data, "{format:?}");
+    }
+}
+
+#[test]
+fn round_trip_stdio() {
+    let dir = TempDir::new().unwrap();
+    let data = text();
+
+    let compressed = heatshrink(&dir)
+        .args(["encode", "-f"])
+        .write_stdin(data.clone())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    heatshrink(&dir)
+        .args(["decode", "-f"])
+        .write_stdin(compressed)
+        .assert()
+        .success()
+        .stdout(data);
+}
+
+#[test]
+fn no_overwrite() {
+    let dir = TempDir::new().unwrap();
+    write(&dir, "input.txt", &text());
+    write(&dir, "input.txt.hs", b"kept");
+
+    let message = stderr(heatshrink(&dir).args(["encode", "input.txt"]));
+    assert!(message.contains("already exists, use -f"), "{message}");
+    assert_eq!(read(&dir, "input.txt.hs"), b"kept");
+    assert_eq!(read(&dir, "input.txt"), text());
+
+    heatshrink(&dir)
+        .args(["encode", "-f", "input.txt"])
+        .assert()
+        .success();
+    assert_ne!(read(&dir, "input.txt.hs"), b"kept");
+
+    write(&dir, "input.txt", b"kept");
+    let message = stderr(heatshrink(&dir).args(["decode", "input.txt.hs"]));
+    assert!(message.contains("already exists, use -f"), "{message}");
+    assert_eq!(read(&dir, "input.txt"), b"kept");
+
+    heatshrink(&dir)
+        .args(["decode", "-f", "input.txt.hs"])
+        .assert()
+        .success();
+    assert_eq!(read(&dir, "input.txt"), text());
+}
+
+#[test]
+fn keep_input() {
+    let dir = TempDir::new().unwrap();
+    let data = text();
+    write(&dir, "input.txt", &data);
+
+    heatshrink(&dir)
+        .args(["encode", "-k", "input.txt"])
+        .assert()
+        .success();
+    assert_eq!(read(&dir, "input.txt"), data);
+
+    fs::rename(
+        dir.path().join("input.txt"),
+        dir.path().join("original.txt"),
+    )
+    .unwrap();
+    heatshrink(&dir)
+        .args(["decode", "-k", "input.txt.hs"])
+        .assert()
+        .success();
+    assert!(exists(&dir, "input.txt.hs"));
+    assert_eq!(read(&dir, "input.txt"), data);
+}
+
+#[test]
+fn test_corrupted() {
+    let dir = TempDir::new().unwrap();
+    write(&dir, "input.txt", &text());
+    heatshrink(&dir)
+        .args(["encode", "--crc", "crc32", "input.txt"])
+        .assert()
+        .success();
+
+    let output = heatshrink(&dir)
+        .args(["decode", "-t", "--crc", "crc32", "input.txt.hs"])
+        .assert()
+        .success();
+    assert_eq!(output.get_output().stdout, b"input.txt.hs: OK\n");
+
+    let mut compressed = read(&dir, "input.txt.hs");
+    let middle = compressed.len() / 2;
+    compressed[middle] ^= 0x10;
+    write(&dir, "input.txt.hs", &compressed);
+    let output = heatshrink(&dir)
+        .args(["decode", "-t", "--crc", "crc32", "input.txt.hs"])
+        .assert()
+        .failure();
+    let stdout = String::from_utf8_lossy(&output.get_output().stdout);
+    assert!(stdout.starts_with("input.txt.hs: corrupted"), "{stdout}");
+    // nothing is written or removed
+    assert!(!exists(&dir, "input.txt"));
+    assert_eq!(read(&dir, "input.txt.hs"), compressed);
+}
+
+#[test]
+fn missing_input() {
+    let dir = TempDir::new().unwrap();
+
+    let message = stderr(heatshrink(&dir).args(["encode", "missing.txt"]));
+    assert!(
+        message.starts_with("heatshrink: missing.txt: "),
+        "{message}"
+    );
+    assert!(!exists(&dir, "missing.txt.hs"));
+}
+
+#[test]
+fn extract_no_overwrite() {
+    let dir = TempDir::new().unwrap();
+    write(&dir, "a.txt", &text());
+    heatshrink(&dir)
+        .args(["archive", "files.hsa", "a.txt"])
+        .assert()
+        .success();
+    fs::create_dir(dir.path().join("out")).unwrap();
+    write(&dir, "out/a.txt", b"kept");
+
+    let message = stderr(heatshrink(&dir).args(["extract", "files.hsa", "out"]));
+    assert!(message.contains("already exists, use -f"), "{message}");
+    assert_eq!(read(&dir, "out/a.txt"), b"kept");
+
+    heatshrink(&dir)
+        .args(["extract", "-f", "files.hsa", "out"])
+        .assert()
+        .success();
+    assert_eq!(read(&dir, "out/a.txt"), text());
+}
+
+#[cfg(unix)]
+#[test]
+fn extract_no_symlink() {
+    let dir = TempDir::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    fs::create_dir(dir.path().join("sub")).unwrap();
+    write(&dir, "sub/a.txt", &text());
+    heatshrink(&dir)
+        .args(["archive", "files.hsa", "sub/a.txt"])
+        .assert()
+        .success();
+
+    // a link in place of a directory of the entry is refused
+    fs::create_dir(dir.path().join("out")).unwrap();
+    std::os::unix::fs::symlink(outside.path(), dir.path().join("out/sub")).unwrap();
+    let message = stderr(heatshrink(&dir).args(["extract", "-f", "files.hsa", "out"]));
+    assert!(message.contains("is a symbolic link"), "{message}");
+    assert!(!outside.path().join("a.txt").exists());
+
+    // and one in place of the file replaced, not followed
+    fs::remove_file(dir.path().join("out/sub")).unwrap();
+    fs::create_dir(dir.path().join("out/sub")).unwrap();
+    let target = outside.path().join("target.txt");
+    fs::write(&target, b"kept").unwrap();
+    std::os::unix::fs::symlink(&target, dir.path().join("out/sub/a.txt")).unwrap();
+    heatshrink(&dir)
+        .args(["extract", "-f", "files.hsa", "out"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(&target).unwrap(), b"kept");
+    assert_eq!(read(&dir, "out/sub/a.txt"), text());
+}
@@ -0,0 +1,63 @@
+use std::env;
+use std::path::PathBuf;
+
+/// Functions of the C API, renamed with an `upstream_` prefix so that they
+/// don't clash with the ones exported by heatshrink-lib with its `ffi`
+/// feature
+const FUNCTIONS: [&str; 12] = [
+    "heatshrink_encoder_alloc",
+    "heatshrink_encoder_free",
+    "heatshrink_encoder_reset",
+    "heatshrink_encoder_sink",
+    "heatshrink_encoder_poll",
+    "heatshrink_encoder_finish",
+    "heatshrink_decoder_alloc",
+    "heatshrink_decoder_free",
+    "heatshrink_decoder_reset",
+    "heatshrink_decoder_sink",
+    "heatshrink_decoder_poll",
+    "heatshrink_decoder_finish",
+];
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(heatshrink_c)");
+    println!("cargo::rerun-if-env-changed=HEATSHRINK_C_DIR");
+
+    // checkout of https://github.com/atomicobject/heatshrink (v0.4.1)
+    let dir = match env::var_os("HEATSHRINK_C_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("heatshrink"),
+    };
+    println!("cargo::rerun-if-changed={}", dir.display());
+
+    let sources = [
+        dir.join("heatshrink_encoder.c"),
+        dir.join("heatshrink_decoder.c"),
+    ];
+    if !sources.iter().all(|source| source.is_file()) {
+        // the bindings and the tests are then empty, so that the workspace
+        // builds without the C sources
+        if env::var_os("HEATSHRINK_C_DIR").is_some() {
+            panic!("no heatshrink C sources in {}", dir.display());
+        }
+        println!(
+            "cargo::warning=no heatshrink C sources in {}, the compatibility tests are skipped",
+            dir.display()
+        );
+        return;
+    }
+
+    let mut build = cc::Build::new();
+    build
+        .files(&sources)
+        .include(&dir)
+        .define("HEATSHRINK_DYNAMIC_ALLOC", "1")
+        .define("HEATSHRINK_USE_INDEX", "1")
+        .warnings(false);
+    for function in FUNCTIONS {
+        build.define(function, format!("upstream_{function}").as_str());
+    }
+    build.compile("heatshrink");
+
+    println!("cargo::rustc-cfg=heatshrink_c");
+}
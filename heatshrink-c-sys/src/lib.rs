@@ -0,0 +1,199 @@
+//! Bindings of the reference heatshrink C library (dynamic allocation API),
+//! to check that heatshrink-lib stays compatible with it.
+//!
+//! The C sources are built from a checkout of
+//! <https://github.com/atomicobject/heatshrink> in the `heatshrink`
+//! directory of the crate, or in `HEATSHRINK_C_DIR`. Without them the crate
+//! is empty.
+
+#![cfg(heatshrink_c)]
+#![allow(non_camel_case_types)]
+
+use std::os::raw::c_int;
+
+/// Opaque encoder state of the C library
+#[repr(C)]
+pub struct heatshrink_encoder {
+    _private: [u8; 0],
+}
+
+/// Opaque decoder state of the C library
+#[repr(C)]
+pub struct heatshrink_decoder {
+    _private: [u8; 0],
+}
+
+pub const HSER_SINK_OK: c_int = 0;
+pub const HSER_SINK_ERROR_NULL: c_int = -1;
+pub const HSER_SINK_ERROR_MISUSE: c_int = -2;
+
+pub const HSER_POLL_EMPTY: c_int = 0;
+pub const HSER_POLL_MORE: c_int = 1;
+pub const HSER_POLL_ERROR_NULL: c_int = -1;
+pub const HSER_POLL_ERROR_MISUSE: c_int = -2;
+
+pub const HSER_FINISH_DONE: c_int = 0;
+pub const HSER_FINISH_MORE: c_int = 1;
+pub const HSER_FINISH_ERROR_NULL: c_int = -1;
+
+pub const HSDR_SINK_OK: c_int = 0;
+pub const HSDR_SINK_FULL: c_int = 1;
+pub const HSDR_SINK_ERROR_NULL: c_int = -1;
+
+pub const HSDR_POLL_EMPTY: c_int = 0;
+pub const HSDR_POLL_MORE: c_int = 1;
+pub const HSDR_POLL_ERROR_NULL: c_int = -1;
+pub const HSDR_POLL_ERROR_UNKNOWN: c_int = -2;
+
+pub const HSDR_FINISH_DONE: c_int = 0;
+pub const HSDR_FINISH_MORE: c_int = 1;
+pub const HSDR_FINISH_ERROR_NULL: c_int = -1;
+
+extern "C" {
+    #[link_name = "upstream_heatshrink_encoder_alloc"]
+    pub fn heatshrink_encoder_alloc(window_sz2: u8, lookahead_sz2: u8) -> *mut heatshrink_encoder;
+    #[link_name = "upstream_heatshrink_encoder_free"]
+    pub fn heatshrink_encoder_free(hse: *mut heatshrink_encoder);
+    #[link_name = "upstream_heatshrink_encoder_reset"]
+    pub fn heatshrink_encoder_reset(hse: *mut heatshrink_encoder);
+    #[link_name = "upstream_heatshrink_encoder_sink"]
+    pub fn heatshrink_encoder_sink(
+        hse: *mut heatshrink_encoder,
+        in_buf: *mut u8,
+        size: usize,
+        input_size: *mut usize,
+    ) -> c_int;
+    #[link_name = "upstream_heatshrink_encoder_poll"]
+    pub fn heatshrink_encoder_poll(
+        hse: *mut heatshrink_encoder,
+        out_buf: *mut u8,
+        out_buf_size: usize,
+        output_size: *mut usize,
+    ) -> c_int;
+    #[link_name = "upstream_heatshrink_encoder_finish"]
+    pub fn heatshrink_encoder_finish(hse: *mut heatshrink_encoder) -> c_int;
+
+    #[link_name = "upstream_heatshrink_decoder_alloc"]
+    pub fn heatshrink_decoder_alloc(
+        input_buffer_size: u16,
+        expansion_buffer_sz2: u8,
+        lookahead_sz2: u8,
+    ) -> *mut heatshrink_decoder;
+    #[link_name = "upstream_heatshrink_decoder_free"]
+    pub fn heatshrink_decoder_free(hsd: *mut heatshrink_decoder);
+    #[link_name = "upstream_heatshrink_decoder_reset"]
+    pub fn heatshrink_decoder_reset(hsd: *mut heatshrink_decoder);
+    #[link_name = "upstream_heatshrink_decoder_sink"]
+    pub fn heatshrink_decoder_sink(
+        hsd: *mut heatshrink_decoder,
+        in_buf: *mut u8,
+        size: usize,
+        input_size: *mut usize,
+    ) -> c_int;
+    #[link_name = "upstream_heatshrink_decoder_poll"]
+    pub fn heatshrink_decoder_poll(
+        hsd: *mut heatshrink_decoder,
+        out_buf: *mut u8,
+        out_buf_size: usize,
+        output_size: *mut usize,
+    ) -> c_int;
+    #[link_name = "upstream_heatshrink_decoder_finish"]
+    pub fn heatshrink_decoder_finish(hsd: *mut heatshrink_decoder) -> c_int;
+}
+
+/// Output of `decode`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// Uncompressed data produced from the input
+    pub output: Vec<u8>,
+    /// Whether `heatshrink_decoder_finish` reported the end of the stream
+    pub done: bool,
+}
+
+/// Compress data with the C library, sinking it size bytes at a time
+pub fn encode(window_sz2: u8, lookahead_sz2: u8, data: &[u8], size: usize) -> Vec<u8> {
+    let mut input = data.to_vec();
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 256];
+
+    // SAFETY: the encoder is checked to be allocated, and the buffers given
+    // to it are valid for the sizes given
+    unsafe {
+        let hse = heatshrink_encoder_alloc(window_sz2, lookahead_sz2);
+        assert!(!hse.is_null(), "invalid parameters");
+
+        let mut poll = |output: &mut Vec<u8>| loop {
+            let mut output_size = 0;
+            let res =
+                heatshrink_encoder_poll(hse, buffer.as_mut_ptr(), buffer.len(), &mut output_size);
+            assert!(res >= 0, "poll error {res}");
+            output.extend_from_slice(&buffer[..output_size]);
+            if res != HSER_POLL_MORE {
+                break;
+            }
+        };
+        for chunk in input.chunks_mut(size.max(1)) {
+            let mut sunk = 0;
+            while sunk < chunk.len() {
+                let mut input_size = 0;
+                let res = heatshrink_encoder_sink(
+                    hse,
+                    chunk[sunk..].as_mut_ptr(),
+                    chunk.len() - sunk,
+                    &mut input_size,
+                );
+                assert_eq!(res, HSER_SINK_OK);
+                sunk += input_size;
+                poll(&mut output);
+            }
+        }
+        while heatshrink_encoder_finish(hse) == HSER_FINISH_MORE {
+            poll(&mut output);
+        }
+        heatshrink_encoder_free(hse);
+    }
+    output
+}
+
+/// Uncompress data with the C library, with an input buffer of
+/// input_buffer_size bytes
+pub fn decode(input_buffer_size: u16, window_sz2: u8, lookahead_sz2: u8, data: &[u8]) -> Decoded {
+    let mut input = data.to_vec();
+    let mut output = Vec::new();
+    let mut buffer = [0u8; 256];
+    let done;
+
+    // SAFETY: the decoder is checked to be allocated, and the buffers given
+    // to it are valid for the sizes given
+    unsafe {
+        let hsd = heatshrink_decoder_alloc(input_buffer_size, window_sz2, lookahead_sz2);
+        assert!(!hsd.is_null(), "invalid parameters");
+
+        let mut poll = |output: &mut Vec<u8>| loop {
+            let mut output_size = 0;
+            let res =
+                heatshrink_decoder_poll(hsd, buffer.as_mut_ptr(), buffer.len(), &mut output_size);
+            assert!(res >= 0, "poll error {res}");
+            output.extend_from_slice(&buffer[..output_size]);
+            if res != HSDR_POLL_MORE {
+                break;
+            }
+        };
+        let mut sunk = 0;
+        while sunk < input.len() {
+            let mut input_size = 0;
+            let res = heatshrink_decoder_sink(
+                hsd,
+                input[sunk..].as_mut_ptr(),
+                input.len() - sunk,
+                &mut input_size,
+            );
+            assert!(res >= 0, "sink error {res}");
+            sunk += input_size;
+            poll(&mut output);
+        }
+        done = heatshrink_decoder_finish(hsd) == HSDR_FINISH_DONE;
+        heatshrink_decoder_free(hsd);
+    }
+    Decoded { output, done }
+}
@@ -0,0 +1,212 @@
+//! Byte-for-byte compatibility of heatshrink-lib with the reference C
+//! library, for a corpus of files and every window and lookahead
+//! combination: the compressed streams must be the same, and both decoders
+//! must produce the same output from the same (complete, truncated or
+//! corrupted) streams.
+//!
+//! Skipped (empty) without the C sources, see the crate documentation.
+
+#![cfg(heatshrink_c)]
+
+use heatshrink::decoder::HeatshrinkDecoder;
+use heatshrink::encoder::HeatshrinkEncoder;
+use heatshrink::{
+    HSError, HSfinishRes, Params, HEATSHRINK_MAX_WINDOWS_BITS, HEATSHRINK_MIN_LOOKAHEAD_BITS,
+    HEATSHRINK_MIN_WINDOWS_BITS,
+};
+
+/// Window capacity of the instances, large enough for every parameter set
+const WINDOW_SIZE: usize = 1 << HEATSHRINK_MAX_WINDOWS_BITS;
+
+/// Input buffer size of the decoders
+const INPUT_SIZE: usize = 256;
+
+/// Largest size of the files of the corpus
+const FILE_SIZE: usize = 16 * 1024;
+
+/// Pseudo-random bytes from a linear congruential generator
+fn noise(seed: u32, size: usize) -> Vec<u8> {
+    (0..size)
+        .scan(seed, |seed, _| {
+            *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            Some((*seed >> 16) as u8)
+        })
+        .collect()
+}
+
+/// The corpus: text, source code, a binary-like file, runs and noise, and
+/// the corner cases of the empty and single byte inputs
+fn corpus() -> Vec<(&'static str, Vec<u8>)> {
+    let file = |data: &[u8]| data[..data.len().min(FILE_SIZE)].to_vec();
+    let binary: Vec<u8> = noise(7, 4096)
+        .chunks(4)
+        .flat_map(|word| [word[0] & 0x3f, 0, 0x9f, 0xe5])
+        .chain([0; 1024])
+        .collect();
+
+    vec![
+        ("empty", Vec::new()),
+        ("byte", vec![0x2a]),
+        ("README.md", file(include_bytes!("../../README.md"))),
+        ("LICENSE", file(include_bytes!("../../LICENSE"))),
+        (
+            "decoder.rs",
+            file(include_bytes!("../../heatshrink-lib/src/decoder.rs")),
+        ),
+        ("binary", binary),
+        ("zeros", vec![0; 4096]),
+        ("noise", noise(42, 4096)),
+    ]
+}
+
+/// Every valid (window_bits, lookahead_bits) combination
+fn all_params() -> impl Iterator<Item = (u8, u8)> {
+    (HEATSHRINK_MIN_WINDOWS_BITS..=HEATSHRINK_MAX_WINDOWS_BITS).flat_map(|window_bits| {
+        (HEATSHRINK_MIN_LOOKAHEAD_BITS..window_bits)
+            .map(move |lookahead_bits| (window_bits, lookahead_bits))
+    })
+}
+
+/// A few combinations, for the slower checks
+fn some_params() -> impl Iterator<Item = (u8, u8)> {
+    [
+        (4, 3),
+        (5, 4),
+        (8, 4),
+        (8, 7),
+        (10, 5),
+        (11, 4),
+        (13, 12),
+        (15, 8),
+    ]
+    .into_iter()
+}
+
+/// Compress data with heatshrink-lib, sinking it size bytes at a time
+fn encode(window_bits: u8, lookahead_bits: u8, data: &[u8], size: usize) -> Vec<u8> {
+    let params = Params::new(window_bits, lookahead_bits).unwrap();
+    let mut enc = HeatshrinkEncoder::<WINDOW_SIZE>::boxed_with_params(params).unwrap();
+    let mut output = Vec::new();
+
+    for chunk in data.chunks(size) {
+        enc.sink_all(chunk, &mut |data| output.extend_from_slice(data))
+            .unwrap();
+    }
+    while enc.finish() == HSfinishRes::FinishMore {
+        enc.poll_all(&mut |data| output.extend_from_slice(data))
+            .unwrap();
+    }
+    output
+}
+
+/// Uncompress data with heatshrink-lib, returning the output produced until
+/// the first error, and the error or whether the stream is complete
+fn decode(
+    window_bits: u8,
+    lookahead_bits: u8,
+    data: &[u8],
+) -> (Vec<u8>, Result<HSfinishRes, HSError>) {
+    let params = Params::new(window_bits, lookahead_bits).unwrap();
+    let mut dec = HeatshrinkDecoder::<WINDOW_SIZE, INPUT_SIZE>::boxed_with_params(params).unwrap();
+    let mut output = Vec::new();
+
+    let result = dec
+        .sink_all(data, &mut |data| output.extend_from_slice(data))
+        .map(|()| dec.finish());
+    (output, result)
+}
+
+#[test]
+fn encode_matches_upstream() {
+    for (name, data) in corpus() {
+        for (window_bits, lookahead_bits) in all_params() {
+            let expected = heatshrink_c_sys::encode(window_bits, lookahead_bits, &data, 4096);
+
+            for size in [1, 7, 4096] {
+                assert!(
+                    encode(window_bits, lookahead_bits, &data, size) == expected,
+                    "{name} compressed with -w {window_bits} -l {lookahead_bits} in chunks of {size}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn decode_matches_upstream() {
+    for (name, data) in corpus() {
+        for (window_bits, lookahead_bits) in all_params() {
+            let compressed = heatshrink_c_sys::encode(window_bits, lookahead_bits, &data, 4096);
+
+            assert!(
+                decode(window_bits, lookahead_bits, &compressed)
+                    == (data.clone(), Ok(HSfinishRes::FinishDone)),
+                "{name} uncompressed with -w {window_bits} -l {lookahead_bits}"
+            );
+            for input_buffer_size in [1, 256] {
+                let decoded = heatshrink_c_sys::decode(
+                    input_buffer_size,
+                    window_bits,
+                    lookahead_bits,
+                    &encode(window_bits, lookahead_bits, &data, 4096),
+                );
+                assert!(
+                    decoded.done && decoded.output == data,
+                    "{name} uncompressed by the C library with -w {window_bits} -l {lookahead_bits}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn truncated_streams() {
+    for (name, data) in corpus() {
+        for (window_bits, lookahead_bits) in some_params() {
+            let compressed = encode(window_bits, lookahead_bits, &data, 4096);
+            let cuts = (0..16.min(compressed.len()))
+                .chain((1..8).map(|eighth| compressed.len() * eighth / 8));
+
+            // the C decoder can't tell a truncated stream from a complete
+            // one, only the output produced so far is compared
+            for cut in cuts {
+                let (output, _) = decode(window_bits, lookahead_bits, &compressed[..cut]);
+                let expected =
+                    heatshrink_c_sys::decode(256, window_bits, lookahead_bits, &compressed[..cut]);
+                assert!(
+                    output == expected.output,
+                    "{name} with -w {window_bits} -l {lookahead_bits} cut at {cut}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn corrupted_streams() {
+    for (name, data) in corpus() {
+        for (window_bits, lookahead_bits) in some_params() {
+            let compressed = encode(window_bits, lookahead_bits, &data, 4096);
+
+            // heatshrink-lib rejects the back-references before the start
+            // of the stream, which the C decoder reads from its zeroed
+            // window: the outputs are compared when the stream is accepted
+            for index in (0..compressed.len()).step_by(compressed.len() / 32 + 1) {
+                for mask in [0x01, 0x80, 0xff] {
+                    let mut corrupted = compressed.clone();
+                    corrupted[index] ^= mask;
+
+                    let (output, result) = decode(window_bits, lookahead_bits, &corrupted);
+                    if result.is_ok() {
+                        let expected =
+                            heatshrink_c_sys::decode(256, window_bits, lookahead_bits, &corrupted);
+                        assert!(
+                            output == expected.output,
+                            "{name} with -w {window_bits} -l {lookahead_bits} corrupted at {index}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
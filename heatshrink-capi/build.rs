@@ -0,0 +1,17 @@
+use std::env;
+use std::path::PathBuf;
+
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let ffi = crate_dir.join("../heatshrink-lib/src/ffi.rs");
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+
+    println!("cargo::rerun-if-changed=cbindgen.toml");
+    println!("cargo::rerun-if-changed={}", ffi.display());
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(ffi)
+        .generate()
+        .expect("Unable to generate the C header")
+        .write_to_file(crate_dir.join("include/heatshrink.h"));
+}
@@ -0,0 +1,186 @@
+#ifndef HEATSHRINK_H
+#define HEATSHRINK_H
+
+/* Generated by cbindgen from heatshrink-lib/src/ffi.rs, do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Result of `heatshrink_encoder_sink`
+typedef enum {
+  // data sunk into the input buffer
+  HSER_SINK_OK = 0,
+  // NULL argument
+  HSER_SINK_ERROR_NULL = -1,
+  // the encoder must be polled or was finished
+  HSER_SINK_ERROR_MISUSE = -2,
+} HSE_sink_res;
+
+// Result of `heatshrink_encoder_poll`
+typedef enum {
+  // input exhausted
+  HSER_POLL_EMPTY = 0,
+  // poll again for more output
+  HSER_POLL_MORE = 1,
+  // NULL argument
+  HSER_POLL_ERROR_NULL = -1,
+  // empty output buffer
+  HSER_POLL_ERROR_MISUSE = -2,
+} HSE_poll_res;
+
+// Result of `heatshrink_encoder_finish`
+typedef enum {
+  // encoding is complete
+  HSER_FINISH_DONE = 0,
+  // more output remains, poll it
+  HSER_FINISH_MORE = 1,
+  // NULL argument
+  HSER_FINISH_ERROR_NULL = -1,
+} HSE_finish_res;
+
+// Result of `heatshrink_decoder_sink`
+typedef enum {
+  // data sunk, ready to poll
+  HSDR_SINK_OK = 0,
+  // out of space in the input buffer, poll first
+  HSDR_SINK_FULL = 1,
+  // NULL argument
+  HSDR_SINK_ERROR_NULL = -1,
+} HSD_sink_res;
+
+// Result of `heatshrink_decoder_poll`
+typedef enum {
+  // input exhausted
+  HSDR_POLL_EMPTY = 0,
+  // more data remaining, call again with a fresh output buffer
+  HSDR_POLL_MORE = 1,
+  // NULL argument
+  HSDR_POLL_ERROR_NULL = -1,
+  // invalid compressed stream or empty output buffer
+  HSDR_POLL_ERROR_UNKNOWN = -2,
+} HSD_poll_res;
+
+// Result of `heatshrink_decoder_finish`
+typedef enum {
+  // output is done
+  HSDR_FINISH_DONE = 0,
+  // more output remains
+  HSDR_FINISH_MORE = 1,
+  // NULL argument
+  HSDR_FINISH_ERROR_NULL = -1,
+} HSD_finish_res;
+
+// Decoder allocated by `heatshrink_decoder_alloc`, opaque to C code
+typedef struct heatshrink_decoder heatshrink_decoder;
+
+// Encoder allocated by `heatshrink_encoder_alloc`, opaque to C code
+typedef struct heatshrink_encoder heatshrink_encoder;
+
+// Allocate an encoder compressing with a window of 2^window_sz2 bytes and
+// back-references of up to 2^lookahead_sz2 bytes, or return NULL if the
+// parameters are not supported.
+heatshrink_encoder *heatshrink_encoder_alloc(uint8_t window_sz2, uint8_t lookahead_sz2);
+
+// Free an encoder allocated by `heatshrink_encoder_alloc`
+//
+// # Safety
+//
+// hse must be NULL or a pointer returned by `heatshrink_encoder_alloc`
+// and not freed yet.
+void heatshrink_encoder_free(heatshrink_encoder *hse);
+
+// Reset an encoder to compress a new stream
+//
+// # Safety
+//
+// hse must be NULL or a valid encoder.
+void heatshrink_encoder_reset(heatshrink_encoder *hse);
+
+// Sink up to size bytes of in_buf into the encoder, storing the number of
+// bytes actually sunk in *input_size.
+//
+// # Safety
+//
+// hse must be NULL or a valid encoder, in_buf NULL or valid for reads of
+// size bytes and input_size NULL or valid for writes.
+HSE_sink_res heatshrink_encoder_sink(heatshrink_encoder *hse,
+                                     const uint8_t *in_buf,
+                                     size_t size,
+                                     size_t *input_size);
+
+// Poll up to out_buf_size bytes of compressed data into out_buf, storing
+// the number of bytes written in *output_size.
+//
+// # Safety
+//
+// hse must be NULL or a valid encoder, out_buf NULL or valid for writes of
+// out_buf_size bytes and output_size NULL or valid for writes.
+HSE_poll_res heatshrink_encoder_poll(heatshrink_encoder *hse,
+                                     uint8_t *out_buf,
+                                     size_t out_buf_size,
+                                     size_t *output_size);
+
+// Notify the encoder that the input stream is complete. If more output
+// remains, it must be polled.
+//
+// # Safety
+//
+// hse must be NULL or a valid encoder.
+HSE_finish_res heatshrink_encoder_finish(heatshrink_encoder *hse);
+
+// Allocate a decoder for a stream compressed with a window of
+// 2^window_sz2 bytes and back-references of up to 2^lookahead_sz2 bytes,
+// or return NULL if the parameters are not supported or input_buffer_size
+// is 0.
+heatshrink_decoder *heatshrink_decoder_alloc(uint16_t input_buffer_size,
+                                             uint8_t window_sz2,
+                                             uint8_t lookahead_sz2);
+
+// Free a decoder allocated by `heatshrink_decoder_alloc`
+//
+// # Safety
+//
+// hsd must be NULL or a pointer returned by `heatshrink_decoder_alloc`
+// and not freed yet.
+void heatshrink_decoder_free(heatshrink_decoder *hsd);
+
+// Reset a decoder to uncompress a new stream
+//
+// # Safety
+//
+// hsd must be NULL or a valid decoder.
+void heatshrink_decoder_reset(heatshrink_decoder *hsd);
+
+// Sink up to size bytes of in_buf into the decoder, storing the number of
+// bytes actually sunk in *input_size.
+//
+// # Safety
+//
+// hsd must be NULL or a valid decoder, in_buf NULL or valid for reads of
+// size bytes and input_size NULL or valid for writes.
+HSD_sink_res heatshrink_decoder_sink(heatshrink_decoder *hsd,
+                                     const uint8_t *in_buf,
+                                     size_t size,
+                                     size_t *input_size);
+
+// Poll up to out_buf_size bytes of uncompressed data into out_buf,
+// storing the number of bytes written in *output_size.
+//
+// # Safety
+//
+// hsd must be NULL or a valid decoder, out_buf NULL or valid for writes of
+// out_buf_size bytes and output_size NULL or valid for writes.
+HSD_poll_res heatshrink_decoder_poll(heatshrink_decoder *hsd,
+                                     uint8_t *out_buf,
+                                     size_t out_buf_size,
+                                     size_t *output_size);
+
+// Notify the decoder that the input stream is complete. If more output
+// remains, it must be polled.
+//
+// # Safety
+//
+// hsd must be NULL or a valid decoder.
+HSD_finish_res heatshrink_decoder_finish(heatshrink_decoder *hsd);
+
+#endif  /* HEATSHRINK_H */
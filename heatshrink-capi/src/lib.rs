@@ -0,0 +1,6 @@
+//! C library of the heatshrink compression algorithm, built as a static and
+//! a shared library exporting the API of the C library's
+//! `heatshrink_encoder.h` and `heatshrink_decoder.h`. The header is
+//! generated in `include/heatshrink.h` by the build script.
+
+pub use heatshrink::ffi::*;
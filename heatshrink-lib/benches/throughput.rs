@@ -0,0 +1,156 @@
+//! Encode and decode throughput of the library across corpora, parameters
+//! and search strategies.
+//!
+//! Run with `cargo bench -p heatshrink-lib`, and compare a change against a
+//! baseline with criterion's `--save-baseline` and `--baseline` options.
+//! Building without the default features (`--no-default-features --features
+//! encoder,decoder`) measures the build without the search index.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use heatshrink::{decoder, encoder, Params};
+use std::hint::black_box;
+
+/// Size of each corpus
+const CORPUS_SIZE: usize = 64 * 1024;
+
+/// Window capacity of the instances, large enough for every parameter set
+const WINDOW_SIZE: usize = 2048;
+
+/// Pseudo-random bytes from a linear congruential generator
+fn noise(seed: u32) -> impl Iterator<Item = u8> {
+    (0..).scan(seed, |seed, _: u32| {
+        *seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        Some((*seed >> 16) as u8)
+    })
+}
+
+/// Firmware-like image: code made of 32-bit instructions sharing a few
+/// opcodes, zero-padded sections and a table of strings
+fn firmware() -> Vec<u8> {
+    const OPCODES: [u32; 6] = [
+        0xe59f_0000,
+        0xe12f_ff1e,
+        0xe92d_4000,
+        0xe8bd_8000,
+        0xeb00_0000,
+        0xe350_0000,
+    ];
+    let mut image = Vec::with_capacity(CORPUS_SIZE);
+    let mut random = noise(7);
+
+    while image.len() < CORPUS_SIZE {
+        for _ in 0..512 {
+            let opcode = OPCODES[usize::from(random.next().unwrap()) % OPCODES.len()];
+            let operand = u32::from(random.next().unwrap() & 0x3f);
+            image.extend_from_slice(&(opcode | operand).to_le_bytes());
+        }
+        image.resize(image.len() + 1024, 0);
+        for index in 0..32 {
+            image.extend_from_slice(format!("error {index}: sensor timeout\0").as_bytes());
+        }
+    }
+    image.truncate(CORPUS_SIZE);
+    image
+}
+
+/// The corpora, by name
+fn corpora() -> Vec<(&'static str, Vec<u8>)> {
+    let text: Vec<u8> = [
+        include_str!("../src/lib.rs"),
+        include_str!("../src/encoder.rs"),
+        include_str!("../src/decoder.rs"),
+    ]
+    .concat()
+    .into_bytes()
+    .into_iter()
+    .cycle()
+    .take(CORPUS_SIZE)
+    .collect();
+
+    vec![
+        ("zeros", vec![0; CORPUS_SIZE]),
+        ("text", text),
+        ("random", noise(3).take(CORPUS_SIZE).collect()),
+        ("firmware", firmware()),
+    ]
+}
+
+/// Parameter sets, by name
+fn params() -> [(&'static str, Params); 2] {
+    [
+        ("w8l4", Params::new(8, 4).unwrap()),
+        ("w11l4", Params::new(11, 4).unwrap()),
+    ]
+}
+
+/// Search strategies of the encoder, by name
+fn strategies() -> Vec<(&'static str, bool)> {
+    if cfg!(feature = "heatshrink-use-index") {
+        vec![("index", true), ("linear", false)]
+    } else {
+        vec![("linear", false)]
+    }
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    let mut compressed = vec![0; encoder::encode_bound(CORPUS_SIZE)];
+
+    for (corpus_name, corpus) in corpora() {
+        group.throughput(Throughput::Bytes(corpus.len() as u64));
+        for (params_name, params) in params() {
+            for (strategy_name, use_index) in strategies() {
+                let mut enc =
+                    encoder::HeatshrinkEncoder::<WINDOW_SIZE>::boxed_with_params(params).unwrap();
+                #[cfg(feature = "heatshrink-use-index")]
+                enc.set_use_index(use_index);
+                #[cfg(not(feature = "heatshrink-use-index"))]
+                let _ = use_index;
+
+                let id = BenchmarkId::new(corpus_name, format!("{params_name}/{strategy_name}"));
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        enc.reset();
+                        encoder::encode_with(&mut enc, black_box(&corpus), &mut compressed)
+                            .unwrap()
+                            .len()
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode");
+    let mut compressed = vec![0; encoder::encode_bound(CORPUS_SIZE)];
+    let mut output = vec![0; CORPUS_SIZE + 1];
+
+    for (corpus_name, corpus) in corpora() {
+        group.throughput(Throughput::Bytes(corpus.len() as u64));
+        for (params_name, params) in params() {
+            let mut enc =
+                encoder::HeatshrinkEncoder::<WINDOW_SIZE>::boxed_with_params(params).unwrap();
+            let size = encoder::encode_with(&mut enc, &corpus, &mut compressed)
+                .unwrap()
+                .len();
+            let compressed = &compressed[..size];
+            let mut dec =
+                decoder::HeatshrinkDecoder::<WINDOW_SIZE>::boxed_with_params(params).unwrap();
+
+            group.bench_function(BenchmarkId::new(corpus_name, params_name), |b| {
+                b.iter(|| {
+                    dec.reset();
+                    decoder::decode_with(&mut dec, black_box(compressed), &mut output)
+                        .unwrap()
+                        .len()
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_encode, bench_decode);
+criterion_main!(benches);
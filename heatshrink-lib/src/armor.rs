@@ -0,0 +1,304 @@
+use super::HSError;
+
+/// Default length of the armored lines, as in MIME
+pub const LINE_LENGTH: usize = 76;
+
+/// Base64 alphabet (RFC 4648, not URL safe)
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Padding character of the last group of an armored text
+const PADDING: u8 = b'=';
+
+/// Size of the buffer gathering the output of a call before handing it to
+/// the output callback
+const OUTPUT_CHUNK_SIZE: usize = 64;
+
+/// size of the armored text of input_len bytes wrapped in lines of
+/// line_length characters (not wrapped if 0), each one ended by a new line
+pub const fn armor_bound(input_len: usize, line_length: usize) -> usize {
+    let chars = input_len.div_ceil(3).saturating_mul(4);
+
+    if line_length == 0 {
+        chars
+    } else {
+        chars.saturating_add(chars.div_ceil(line_length))
+    }
+}
+
+/// value of a base64 character
+const fn decode_char(char: u8) -> Option<u32> {
+    match char {
+        b'A'..=b'Z' => Some((char - b'A') as u32),
+        b'a'..=b'z' => Some((char - b'a') as u32 + 26),
+        b'0'..=b'9' => Some((char - b'0') as u32 + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// Bytes gathered in a small buffer to hand them to the output callback a
+/// few at a time rather than one by one
+struct Chunk {
+    data: [u8; OUTPUT_CHUNK_SIZE],
+    len: usize,
+}
+
+impl Chunk {
+    const fn new() -> Self {
+        Chunk {
+            data: [0; OUTPUT_CHUNK_SIZE],
+            len: 0,
+        }
+    }
+
+    /// append a byte, handing the chunk to output when it is full
+    fn push(&mut self, byte: u8, output: &mut impl FnMut(&[u8])) {
+        if self.len == OUTPUT_CHUNK_SIZE {
+            self.flush(output);
+        }
+        if let Some(slot) = self.data.get_mut(self.len) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+
+    /// hand the bytes gathered so far to output
+    fn flush(&mut self, output: &mut impl FnMut(&[u8])) {
+        if let Some(data) = self.data.get(..self.len).filter(|data| !data.is_empty()) {
+            output(data);
+        }
+        self.len = 0;
+    }
+}
+
+/// Writer of a compressed stream (or any data) as base64 text wrapped in
+/// lines, so that it can be pasted into JSON/YAML files or typed into a
+/// serial console
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ArmorWriter {
+    /// characters per line, 0 for a single line
+    line_length: usize,
+    /// bytes of the current 3-byte group
+    group: [u8; 3],
+    /// number of bytes of the current group
+    group_len: usize,
+    /// characters written on the current line
+    column: usize,
+}
+
+impl ArmorWriter {
+    /// Armor the data in lines of line_length characters (`LINE_LENGTH` by
+    /// default), or on a single line if 0
+    pub const fn new(line_length: usize) -> Self {
+        ArmorWriter {
+            line_length,
+            group: [0; 3],
+            group_len: 0,
+            column: 0,
+        }
+    }
+
+    /// Sink some data, handing the armored text to the output callback
+    pub fn sink_all(&mut self, data: &[u8], output: &mut impl FnMut(&[u8])) {
+        let mut chunk = Chunk::new();
+
+        for &byte in data {
+            if let Some(slot) = self.group.get_mut(self.group_len) {
+                *slot = byte;
+                self.group_len += 1;
+            }
+            if self.group_len == self.group.len() {
+                self.write_group(&mut chunk, output);
+            }
+        }
+        chunk.flush(output);
+    }
+
+    /// Signal the end of the data, handing the padded last group and the
+    /// final new line to the output callback. The writer can then armor
+    /// another text.
+    pub fn finish(&mut self, output: &mut impl FnMut(&[u8])) {
+        let mut chunk = Chunk::new();
+
+        if self.group_len > 0 {
+            self.write_group(&mut chunk, output);
+        }
+        if self.column > 0 && self.line_length > 0 {
+            chunk.push(b'\n', output);
+        }
+        self.column = 0;
+        chunk.flush(output);
+    }
+
+    /// write the 4 characters of the current group, padded if it isn't
+    /// complete
+    fn write_group(&mut self, chunk: &mut Chunk, output: &mut impl FnMut(&[u8])) {
+        let [first, second, third] = self.group;
+        let bits = u32::from(first) << 16 | u32::from(second) << 8 | u32::from(third);
+
+        for index in 0..4 {
+            if self.line_length > 0 && self.column == self.line_length {
+                chunk.push(b'\n', output);
+                self.column = 0;
+            }
+            let char = if index <= self.group_len {
+                ALPHABET
+                    .get((bits >> (18 - 6 * index) & 0x3f) as usize)
+                    .copied()
+                    .unwrap_or(PADDING)
+            } else {
+                PADDING
+            };
+            chunk.push(char, output);
+            self.column += 1;
+        }
+        self.group = [0; 3];
+        self.group_len = 0;
+    }
+}
+
+impl Default for ArmorWriter {
+    fn default() -> Self {
+        ArmorWriter::new(LINE_LENGTH)
+    }
+}
+
+/// Reader of base64 text, fed with the text in any size, handing the data
+/// back. White space (such as the line breaks or the indentation of a YAML
+/// block) is skipped.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ArmorReader {
+    /// bits of the characters of the current 4-character group
+    bits: u32,
+    /// number of characters of the current group
+    group_len: usize,
+    /// number of padding characters of the current group
+    padding: usize,
+    /// whether a padded group ended the text
+    ended: bool,
+}
+
+impl ArmorReader {
+    /// Read some armored text
+    pub const fn new() -> Self {
+        ArmorReader {
+            bits: 0,
+            group_len: 0,
+            padding: 0,
+            ended: false,
+        }
+    }
+
+    /// Sink some armored text, handing the data to the output callback.
+    ///
+    /// Fail with `InvalidFrame` on a character out of the base64 alphabet
+    /// or misplaced padding.
+    pub fn sink_all(&mut self, text: &[u8], output: &mut impl FnMut(&[u8])) -> Result<(), HSError> {
+        let mut chunk = Chunk::new();
+
+        for &char in text {
+            if char.is_ascii_whitespace() {
+                continue;
+            }
+            // padding only ends the last group, after at least 2 characters
+            let value = if char == PADDING && self.group_len >= 2 {
+                self.padding += 1;
+                0
+            } else if self.padding == 0 && !self.ended {
+                match decode_char(char) {
+                    Some(value) => value,
+                    None => {
+                        chunk.flush(output);
+                        return Err(HSError::InvalidFrame);
+                    }
+                }
+            } else {
+                chunk.flush(output);
+                return Err(HSError::InvalidFrame);
+            };
+            self.bits = self.bits << 6 | value;
+            self.group_len += 1;
+
+            if self.group_len == 4 {
+                let [_, first, second, third] = self.bits.to_be_bytes();
+                for byte in [first, second, third].into_iter().take(3 - self.padding) {
+                    chunk.push(byte, output);
+                }
+                self.ended = self.padding > 0;
+                self.bits = 0;
+                self.group_len = 0;
+                self.padding = 0;
+            }
+        }
+        chunk.flush(output);
+
+        Ok(())
+    }
+
+    /// Signal the end of the text. Fail with `Truncated` if it ends in the
+    /// middle of a group. The reader can then read another text.
+    pub fn finish(&mut self) -> Result<(), HSError> {
+        let group_len = self.group_len;
+
+        *self = ArmorReader::new();
+        if group_len == 0 {
+            Ok(())
+        } else {
+            Err(HSError::Truncated)
+        }
+    }
+}
+
+/// armor the src buffer to the destination buffer as base64 text wrapped in
+/// lines of line_length characters (not wrapped if 0).
+///
+/// Fail with `OutputFull` if the destination buffer is smaller than
+/// `armor_bound(src.len(), line_length)`.
+pub fn armor<'a>(src: &[u8], dst: &'a mut [u8], line_length: usize) -> Result<&'a [u8], HSError> {
+    let needed = armor_bound(src.len(), line_length);
+    let dst = dst.get_mut(..needed).ok_or(HSError::OutputFull {
+        needed_hint: Some(needed),
+    })?;
+    let mut writer = ArmorWriter::new(line_length);
+    let mut size = 0;
+    let mut write = |data: &[u8]| {
+        if let Some(output) = dst.get_mut(size..size + data.len()) {
+            output.copy_from_slice(data);
+        }
+        size += data.len();
+    };
+
+    writer.sink_all(src, &mut write);
+    writer.finish(&mut write);
+    if size != needed {
+        return Err(HSError::Internal);
+    }
+    Ok(dst)
+}
+
+/// read the base64 text of the src buffer back to the destination buffer.
+///
+/// Fail with `InvalidFrame` if it isn't valid base64, with `Truncated` if
+/// it ends in the middle of a group or with `OutputFull` if the
+/// destination buffer is too small.
+pub fn dearmor<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
+    let mut reader = ArmorReader::new();
+    let mut size = 0;
+    let mut full = false;
+
+    reader.sink_all(src, &mut |data| {
+        match dst.get_mut(size..size + data.len()) {
+            Some(output) => output.copy_from_slice(data),
+            None => full = true,
+        }
+        size += data.len();
+    })?;
+    reader.finish()?;
+    if full {
+        return Err(HSError::OutputFull {
+            needed_hint: Some(size),
+        });
+    }
+    dst.get(..size).ok_or(HSError::Internal)
+}
@@ -0,0 +1,305 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::{HSError, HSpollRes, HSsinkRes, HEATSHRINK_WINDOW_SIZE};
+#[cfg(feature = "decoder")]
+use super::{HSfinishRes, HEATSHRINK_INPUT_BUFFER_SIZE};
+
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+#[cfg(feature = "decoder")]
+use futures_io::AsyncRead;
+#[cfg(feature = "encoder")]
+use futures_io::AsyncWrite;
+use std::io;
+
+/// Size of the buffer used to read compressed data from the wrapped reader or
+/// to write compressed data to the wrapped writer
+const HEATSHRINK_IO_BUFFER_SIZE: usize = 512;
+
+#[cfg(feature = "decoder")]
+/// Async reader decompressing the data read from the wrapped reader
+///
+/// The async counterpart of `io::HeatshrinkReader`, so that host services
+/// can uncompress device uploads within their async pipelines.
+pub struct AsyncHeatshrinkReader<
+    R,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    inner: R,
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    input_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+    input_index: usize,
+    input_size: usize,
+    eof: bool,
+}
+
+#[cfg(feature = "decoder")]
+impl<R: AsyncRead + Unpin> AsyncHeatshrinkReader<R> {
+    /// Create a new reader decompressing data with the default parameters
+    pub fn new(inner: R) -> Self {
+        AsyncHeatshrinkReader::with_decoder(inner, HeatshrinkDecoder::new())
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: AsyncRead + Unpin, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    AsyncHeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Create a new reader decompressing data with the provided decoder
+    /// (and therefore its window and lookahead parameters)
+    pub fn with_decoder(inner: R, decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        AsyncHeatshrinkReader {
+            inner,
+            decoder,
+            input_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+            input_index: 0,
+            input_size: 0,
+            eof: false,
+        }
+    }
+
+    /// Get a reference to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap this reader, returning the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: AsyncRead + Unpin, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> AsyncRead
+    for AsyncHeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        loop {
+            // process the data already sunk into the decoder
+            match this.decoder.poll(buf) {
+                (HSpollRes::PollErrorMisuse, _) => {
+                    return Poll::Ready(Err(io::Error::other(
+                        "Error in HeatshrinkDecoder::poll()",
+                    )));
+                }
+                (HSpollRes::PollErrorLimit, _) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        HSError::OutputLimit,
+                    )));
+                }
+                (HSpollRes::PollErrorBackref, _) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        HSError::InvalidBackref,
+                    )));
+                }
+                (_, 0) => {}
+                (_, output_size) => return Poll::Ready(Ok(output_size)),
+            }
+
+            if this.input_index < this.input_size {
+                // the decoder needs more compressed data
+                match this
+                    .decoder
+                    .sink(&this.input_buffer[this.input_index..this.input_size])
+                {
+                    (HSsinkRes::SinkOK, input_size) => {
+                        this.input_index += input_size;
+                    }
+                    (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                        return Poll::Ready(Err(io::Error::other(
+                            "Error in HeatshrinkDecoder::sink()",
+                        )));
+                    }
+                }
+            } else if this.eof {
+                // all the compressed data has been processed
+                return Poll::Ready(match this.decoder.finish() {
+                    HSfinishRes::FinishDone => Ok(0),
+                    HSfinishRes::FinishMore => Err(io::ErrorKind::UnexpectedEof.into()),
+                });
+            } else {
+                let input_size =
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut this.input_buffer))?;
+                this.input_index = 0;
+                this.input_size = input_size;
+                this.eof = input_size == 0;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+/// Async writer compressing the data written to it into the wrapped writer
+///
+/// The async counterpart of `io::HeatshrinkWriter`. Closing the writer
+/// (e.g. with `AsyncWriteExt::close`) flushes the end of the compressed
+/// stream before closing the wrapped writer.
+pub struct AsyncHeatshrinkWriter<W, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    inner: W,
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    output_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+    /// compressed data of the output buffer not written to the wrapped
+    /// writer yet
+    output_index: usize,
+    output_size: usize,
+    /// whether the writer is being closed, the data sunk before having been
+    /// processed
+    closing: bool,
+    /// whether the end of the compressed stream was polled
+    finished: bool,
+}
+
+#[cfg(feature = "encoder")]
+impl<W: AsyncWrite + Unpin> AsyncHeatshrinkWriter<W> {
+    /// Create a new writer compressing data with the default parameters
+    pub fn new(inner: W) -> Self {
+        AsyncHeatshrinkWriter::with_encoder(inner, HeatshrinkEncoder::new())
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: AsyncWrite + Unpin, const WINDOW_SIZE: usize> AsyncHeatshrinkWriter<W, WINDOW_SIZE> {
+    /// Create a new writer compressing data with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(inner: W, encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        AsyncHeatshrinkWriter {
+            inner,
+            encoder,
+            output_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+            output_index: 0,
+            output_size: 0,
+            closing: false,
+            finished: false,
+        }
+    }
+
+    /// Get a reference to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Unwrap this writer, returning the wrapped writer. The compressed
+    /// stream is only complete once the writer was closed.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// write the compressed data of the output buffer to the wrapped writer
+    fn poll_write_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.output_index < self.output_size {
+            let output_size = ready!(Pin::new(&mut self.inner)
+                .poll_write(cx, &self.output_buffer[self.output_index..self.output_size]))?;
+            if output_size == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.output_index += output_size;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    /// poll the encoder until it needs more input, writing all the
+    /// compressed data to the wrapped writer
+    fn poll_write_output(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        loop {
+            ready!(self.poll_write_buffer(cx))?;
+            match self.encoder.poll(&mut self.output_buffer) {
+                (HSpollRes::PollEmpty, 0) => return Poll::Ready(Ok(())),
+                (HSpollRes::PollMore, output_size) | (HSpollRes::PollEmpty, output_size) => {
+                    self.output_index = 0;
+                    self.output_size = output_size;
+                }
+                (HSpollRes::PollErrorMisuse, _)
+                | (HSpollRes::PollErrorLimit, _)
+                | (HSpollRes::PollErrorBackref, _) => {
+                    return Poll::Ready(Err(io::Error::other(
+                        "Error in HeatshrinkEncoder::poll()",
+                    )));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: AsyncWrite + Unpin, const WINDOW_SIZE: usize> AsyncWrite
+    for AsyncHeatshrinkWriter<W, WINDOW_SIZE>
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // the data sunk by the previous call is processed first, so that
+        // the encoder has room for more
+        ready!(this.poll_write_output(cx))?;
+        Poll::Ready(match this.encoder.sink(buf) {
+            (HSsinkRes::SinkOK, input_size) => Ok(input_size),
+            (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                Err(io::Error::other("Error in HeatshrinkEncoder::sink()"))
+            }
+        })
+    }
+
+    /// Flush the data sunk so far to the wrapped writer and flush it. The
+    /// trailing bits of the compressed stream are only written on close.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_write_output(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        if !this.closing {
+            ready!(this.poll_write_output(cx))?;
+            this.closing = true;
+        }
+        loop {
+            ready!(this.poll_write_buffer(cx))?;
+            if this.finished {
+                break;
+            }
+            match this.encoder.finish_into(&mut this.output_buffer) {
+                Ok(output_size) => {
+                    this.output_size = output_size;
+                    this.finished = true;
+                }
+                Err(HSError::OutputFull { .. }) => this.output_size = this.output_buffer.len(),
+                Err(err) => return Poll::Ready(Err(io::Error::other(err))),
+            }
+            this.output_index = 0;
+        }
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
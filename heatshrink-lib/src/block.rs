@@ -0,0 +1,614 @@
+#[cfg(feature = "decoder")]
+use super::decoder::{decode_exact_with, HeatshrinkDecoder};
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+use super::encoder::encode_bound;
+#[cfg(feature = "encoder")]
+use super::encoder::{encode_with, HeatshrinkEncoder};
+use super::HSError;
+#[cfg(feature = "decoder")]
+use super::{HSpollRes, HEATSHRINK_POLL_BUFFER_SIZE};
+#[cfg(feature = "decoder")]
+use core::cmp::Ordering;
+
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+use rayon::prelude::*;
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+use std::boxed::Box;
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+use std::vec;
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+use std::vec::Vec;
+
+/// Size in bytes of the header of each block: its uncompressed size
+/// followed by its compressed size (32-bit little endian).
+///
+/// The input is split in blocks compressed independently with the same
+/// window and lookahead parameters. As no back-reference crosses a block
+/// boundary, the blocks can be compressed in parallel and the output
+/// doesn't depend on the number of threads.
+pub const BLOCK_HEADER_SIZE: usize = 8;
+
+/// Flag set in the compressed size of a block stored verbatim, as it
+/// doesn't compress (e.g. encrypted payloads)
+pub const STORED_BLOCK_FLAG: u32 = 1 << 31;
+
+/// Largest block size, so that the size of a block never reaches the stored
+/// block flag in its header
+pub const MAX_BLOCK_SIZE: usize = (u32::MAX >> 1) as usize;
+
+/// Magic bytes ending a seekable block container
+pub const SEEK_MAGIC: [u8; 4] = *b"HSSK";
+
+/// Size in bytes of the footer of a seekable block container: the block
+/// size and the number of blocks (32-bit little endian) and the magic
+/// bytes.
+///
+/// A seekable block container is a block container followed by its seek
+/// table, the offset of each block in the container (32-bit little endian),
+/// and by this footer. The blocks of a given range of the uncompressed data
+/// are then found without reading the ones preceding them.
+pub const SEEK_FOOTER_SIZE: usize = 12;
+
+/// Number of blocks compressed by each thread of `encode_parallel` before
+/// they are copied to the destination buffer
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+const PARALLEL_BLOCKS_PER_THREAD: usize = 4;
+
+/// block size actually used for a requested block size
+#[cfg(feature = "encoder")]
+const fn clamp_block_size(block_size: usize) -> usize {
+    if block_size == 0 {
+        1
+    } else if block_size > MAX_BLOCK_SIZE {
+        MAX_BLOCK_SIZE
+    } else {
+        block_size
+    }
+}
+
+/// worst-case size of the block container for an input of input_len bytes
+/// split in blocks of block_size bytes: every block stored verbatim, with
+/// only its header on top of the input.
+///
+/// A destination buffer of this size is always large enough for
+/// `encode_blocks` and `encode_parallel`.
+#[cfg(feature = "encoder")]
+pub const fn block_bound(input_len: usize, block_size: usize) -> usize {
+    let blocks = input_len.div_ceil(clamp_block_size(block_size));
+
+    input_len.saturating_add(blocks.saturating_mul(BLOCK_HEADER_SIZE))
+}
+
+/// append a block to the destination buffer at offset, returning the offset
+/// following it
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+fn write_block(
+    dst: &mut [u8],
+    offset: usize,
+    input: &[u8],
+    compressed: &[u8],
+) -> Result<usize, HSError> {
+    // stored when it doesn't compress
+    let stored = compressed.len() >= input.len();
+    let data_len = if stored {
+        input.len()
+    } else {
+        compressed.len()
+    };
+    let end = offset + BLOCK_HEADER_SIZE + data_len;
+    let block = dst
+        .get_mut(offset..end)
+        .ok_or(HSError::OutputFull { needed_hint: None })?;
+    let (header, data) = block.split_at_mut(BLOCK_HEADER_SIZE);
+
+    write_header(header, input.len(), data_len, stored)?;
+    data.copy_from_slice(if stored { input } else { compressed });
+    Ok(end)
+}
+
+/// fill a block header with the uncompressed and compressed sizes
+#[cfg(feature = "encoder")]
+fn write_header(
+    header: &mut [u8],
+    input_len: usize,
+    output_len: usize,
+    stored: bool,
+) -> Result<(), HSError> {
+    let input_len = u32::try_from(input_len).map_err(|_| HSError::Internal)?;
+    let mut output_len = u32::try_from(output_len)
+        .ok()
+        .filter(|&output_len| output_len < STORED_BLOCK_FLAG)
+        .ok_or(HSError::Internal)?;
+    if stored {
+        output_len |= STORED_BLOCK_FLAG;
+    }
+
+    header
+        .get_mut(..4)
+        .ok_or(HSError::Internal)?
+        .copy_from_slice(&input_len.to_le_bytes());
+    header
+        .get_mut(4..BLOCK_HEADER_SIZE)
+        .ok_or(HSError::Internal)?
+        .copy_from_slice(&output_len.to_le_bytes());
+    Ok(())
+}
+
+/// compress the src buffer to the destination buffer as a block container
+/// of block_size bytes blocks (clamped to `1..=MAX_BLOCK_SIZE`)
+#[cfg(feature = "encoder")]
+pub fn encode_blocks<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let mut enc: HeatshrinkEncoder = Default::default();
+
+    encode_blocks_with(&mut enc, src, dst, block_size)
+}
+
+/// compress the src buffer to the destination buffer as a block container
+/// using the provided instance (and therefore its window and lookahead
+/// parameters and its search settings), which is reset before each block
+#[cfg(feature = "encoder")]
+pub fn encode_blocks_with<'a, const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let full = HSError::OutputFull {
+        needed_hint: Some(block_bound(src.len(), block_size)),
+    };
+    let mut total_output_size = 0;
+
+    for block in src.chunks(clamp_block_size(block_size)) {
+        let (header, data) = dst
+            .get_mut(total_output_size..)
+            .filter(|output| output.len() >= BLOCK_HEADER_SIZE)
+            .ok_or(full)?
+            .split_at_mut(BLOCK_HEADER_SIZE);
+
+        // the block is stored verbatim when it doesn't compress, so there is
+        // no need to compress more than its size
+        enc.reset();
+        let limit = block.len().min(data.len());
+        let output_size = match encode_with(enc, block, data.get_mut(..limit).unwrap_or_default()) {
+            Ok(compressed) if compressed.len() < block.len() => Some(compressed.len()),
+            Ok(_) | Err(HSError::OutputFull { .. }) => None,
+            Err(error) => return Err(error),
+        };
+        let output_size = match output_size {
+            Some(output_size) => output_size,
+            None => {
+                data.get_mut(..block.len())
+                    .ok_or(full)?
+                    .copy_from_slice(block);
+                block.len()
+            }
+        };
+        write_header(header, block.len(), output_size, output_size == block.len())?;
+        total_output_size += BLOCK_HEADER_SIZE + output_size;
+    }
+
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
+
+/// compress the src buffer to the destination buffer as a block container
+/// of block_size bytes blocks, compressing the blocks on the rayon thread
+/// pool. The output is the same as the one of `encode_blocks`.
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+pub fn encode_parallel<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let enc: HeatshrinkEncoder = Default::default();
+
+    encode_parallel_with(&enc, src, dst, block_size)
+}
+
+/// compress the src buffer to the destination buffer as a block container,
+/// compressing the blocks on the rayon thread pool with copies of the
+/// provided instance (and therefore its window and lookahead parameters and
+/// its search settings). The output is the same as the one of
+/// `encode_blocks_with`.
+///
+/// Each thread compresses a few blocks at a time, so the memory used on top
+/// of the destination buffer is bounded by a few blocks per thread.
+#[cfg(all(feature = "encoder", feature = "parallel"))]
+pub fn encode_parallel_with<'a, const WINDOW_SIZE: usize>(
+    enc: &HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let full = HSError::OutputFull {
+        needed_hint: Some(block_bound(src.len(), block_size)),
+    };
+    let block_size = clamp_block_size(block_size);
+    let batch_size = block_size
+        .saturating_mul(rayon::current_num_threads())
+        .saturating_mul(PARALLEL_BLOCKS_PER_THREAD);
+    let mut total_output_size = 0;
+
+    for batch in src.chunks(batch_size) {
+        let blocks = batch
+            .par_chunks(block_size)
+            .map_init(
+                || Box::new(enc.clone()),
+                |enc, block| {
+                    let mut compressed = vec![0; encode_bound(block.len())];
+                    enc.reset();
+                    let output_size = encode_with(enc, block, &mut compressed)?.len();
+                    compressed.truncate(output_size);
+                    Ok((block, compressed))
+                },
+            )
+            .collect::<Result<Vec<_>, HSError>>()?;
+
+        for (input, compressed) in blocks {
+            total_output_size = write_block(dst, total_output_size, input, &compressed).map_err(
+                |error| match error {
+                    HSError::OutputFull { .. } => full,
+                    error => error,
+                },
+            )?;
+        }
+    }
+
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
+
+/// worst-case size of the seekable block container for an input of
+/// input_len bytes split in blocks of block_size bytes
+#[cfg(feature = "encoder")]
+pub const fn seekable_bound(input_len: usize, block_size: usize) -> usize {
+    let blocks = input_len.div_ceil(clamp_block_size(block_size));
+
+    block_bound(input_len, block_size)
+        .saturating_add(blocks.saturating_mul(4))
+        .saturating_add(SEEK_FOOTER_SIZE)
+}
+
+/// compress the src buffer to the destination buffer as a seekable block
+/// container of block_size bytes blocks (clamped to `1..=MAX_BLOCK_SIZE`)
+#[cfg(feature = "encoder")]
+pub fn encode_seekable<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let mut enc: HeatshrinkEncoder = Default::default();
+
+    encode_seekable_with(&mut enc, src, dst, block_size)
+}
+
+/// compress the src buffer to the destination buffer as a seekable block
+/// container using the provided instance (see `encode_blocks_with`).
+///
+/// Fail with `OutputLimit` if the offset of a block doesn't fit in the 32
+/// bits of the seek table.
+#[cfg(feature = "encoder")]
+pub fn encode_seekable_with<'a, const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+    block_size: usize,
+) -> Result<&'a [u8], HSError> {
+    let full = HSError::OutputFull {
+        needed_hint: Some(seekable_bound(src.len(), block_size)),
+    };
+    let container_size = match encode_blocks_with(enc, src, dst, block_size) {
+        Ok(container) => container.len(),
+        Err(HSError::OutputFull { .. }) => return Err(full),
+        Err(error) => return Err(error),
+    };
+    let (container, table) = dst
+        .split_at_mut_checked(container_size)
+        .ok_or(HSError::Internal)?;
+    let mut table = table.iter_mut();
+    let mut write = |bytes: &[u8]| {
+        for &byte in bytes {
+            *table.next().ok_or(full)? = byte;
+        }
+        Ok(())
+    };
+
+    // the offset of each block, found from the sizes in their headers
+    let mut block_offset = 0;
+    let mut block_count = 0u32;
+    while let Some((_, data_size, _)) = read_header(container, block_offset) {
+        write(
+            &u32::try_from(block_offset)
+                .map_err(|_| HSError::OutputLimit)?
+                .to_le_bytes(),
+        )?;
+        block_offset += BLOCK_HEADER_SIZE + data_size;
+        block_count += 1;
+    }
+    let block_size = u32::try_from(clamp_block_size(block_size)).map_err(|_| HSError::Internal)?;
+    write(&block_size.to_le_bytes())?;
+    write(&block_count.to_le_bytes())?;
+    write(&SEEK_MAGIC)?;
+
+    let total_output_size = container_size + 4 * block_count as usize + SEEK_FOOTER_SIZE;
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
+
+/// uncompressed size, size of the data and whether it is stored for the
+/// block at offset in the block container, if any
+fn read_header(container: &[u8], offset: usize) -> Option<(usize, usize, bool)> {
+    let (output_size, input_size) = read_sizes(container, offset)?;
+    let stored = input_size & STORED_BLOCK_FLAG as usize != 0;
+
+    Some((
+        output_size,
+        input_size & !(STORED_BLOCK_FLAG as usize),
+        stored,
+    ))
+}
+
+/// the two 32-bit little endian sizes at offset in bytes, if any
+fn read_sizes(bytes: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let header = bytes.get(offset..)?.get(..BLOCK_HEADER_SIZE)?;
+    let (output_size, input_size) = header.split_at_checked(4)?;
+    let output_size = u32::from_le_bytes(output_size.try_into().ok()?);
+    let input_size = u32::from_le_bytes(input_size.try_into().ok()?);
+
+    Some((
+        usize::try_from(output_size).ok()?,
+        usize::try_from(input_size).ok()?,
+    ))
+}
+
+/// Random access to the uncompressed data of a seekable block container:
+/// only the blocks of the range read are uncompressed
+#[cfg(feature = "decoder")]
+#[derive(Debug, Copy, Clone)]
+pub struct SeekableDecoder<'a> {
+    container: &'a [u8],
+    table: &'a [u8],
+    block_size: usize,
+    block_count: usize,
+}
+
+#[cfg(feature = "decoder")]
+impl<'a> SeekableDecoder<'a> {
+    /// Read the seek table of the seekable block container in src (e.g.
+    /// memory-mapped flash).
+    ///
+    /// Fail with `InvalidHeader` if src doesn't end with a seek table
+    /// footer, or with `Truncated` if it is too short for its seek table.
+    pub fn new(src: &'a [u8]) -> Result<Self, HSError> {
+        let (rest, footer) = src
+            .split_at_checked(src.len().wrapping_sub(SEEK_FOOTER_SIZE))
+            .ok_or(HSError::Truncated)?;
+        let (sizes, magic) = footer.split_at_checked(8).ok_or(HSError::Internal)?;
+        if magic != SEEK_MAGIC {
+            return Err(HSError::InvalidHeader);
+        }
+        let (block_size, block_count) = read_sizes(sizes, 0).ok_or(HSError::Internal)?;
+        if block_size == 0 {
+            return Err(HSError::InvalidHeader);
+        }
+        let (container, table) = rest
+            .split_at_checked(rest.len().wrapping_sub(block_count.saturating_mul(4)))
+            .ok_or(HSError::Truncated)?;
+
+        Ok(SeekableDecoder {
+            container,
+            table,
+            block_size,
+            block_count,
+        })
+    }
+
+    /// Size in bytes of the blocks but the last one
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Number of blocks
+    pub fn block_count(&self) -> usize {
+        self.block_count
+    }
+
+    /// Size in bytes of the uncompressed data
+    pub fn len(&self) -> Result<usize, HSError> {
+        match self.block_count.checked_sub(1) {
+            None => Ok(0),
+            Some(last) => last
+                .checked_mul(self.block_size)
+                .and_then(|size| size.checked_add(self.block(last).ok()?.0))
+                .ok_or(HSError::InvalidHeader),
+        }
+    }
+
+    /// Whether the uncompressed data is empty
+    pub fn is_empty(&self) -> bool {
+        self.block_count == 0
+    }
+
+    /// uncompressed size, data and whether it is stored of a block
+    fn block(&self, index: usize) -> Result<(usize, &'a [u8], bool), HSError> {
+        let offset = self
+            .table
+            .get(index * 4..)
+            .and_then(|entry| entry.get(..4))
+            .ok_or(HSError::Internal)?;
+        let offset = u32::from_le_bytes(offset.try_into().map_err(|_| HSError::Internal)?);
+        let offset = usize::try_from(offset).map_err(|_| HSError::Truncated)?;
+        let (output_size, input_size, stored) =
+            read_header(self.container, offset).ok_or(HSError::Truncated)?;
+        let input = self
+            .container
+            .get(offset + BLOCK_HEADER_SIZE..)
+            .and_then(|input| input.get(..input_size))
+            .ok_or(HSError::Truncated)?;
+
+        Ok((output_size, input, stored))
+    }
+
+    /// Uncompress the data from offset to the destination buffer, returning
+    /// the number of bytes read: less than the size of dst when the end of
+    /// the data is reached.
+    pub fn read_at(&self, offset: usize, dst: &mut [u8]) -> Result<usize, HSError> {
+        let mut dec: HeatshrinkDecoder = Default::default();
+
+        self.read_at_with(&mut dec, offset, dst)
+    }
+
+    /// Uncompress the data from offset to the destination buffer using the
+    /// provided instance (and therefore its window and lookahead
+    /// parameters), which is reset before each block.
+    pub fn read_at_with<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+        &self,
+        dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+        offset: usize,
+        dst: &mut [u8],
+    ) -> Result<usize, HSError> {
+        let mut total_output_size = 0;
+
+        while total_output_size < dst.len() {
+            let position = offset.saturating_add(total_output_size);
+            let index = position / self.block_size;
+            if index >= self.block_count {
+                break;
+            }
+            let (block_output_size, input, stored) = self.block(index)?;
+            let skip = position % self.block_size;
+            if skip >= block_output_size {
+                break;
+            }
+
+            let output = dst.get_mut(total_output_size..).ok_or(HSError::Internal)?;
+            let output_size = (block_output_size - skip).min(output.len());
+            let output = output.get_mut(..output_size).ok_or(HSError::Internal)?;
+            if stored {
+                output.copy_from_slice(
+                    input
+                        .get(skip..skip + output_size)
+                        .ok_or(HSError::TooShort)?,
+                );
+            } else {
+                dec.reset();
+                decode_range(dec, input, skip, output)?;
+            }
+            total_output_size += output_size;
+        }
+
+        Ok(total_output_size)
+    }
+}
+
+/// uncompress the compressed stream in input, dropping its first skip bytes
+/// and filling dst with the following ones
+#[cfg(feature = "decoder")]
+fn decode_range<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    mut input: &[u8],
+    skip: usize,
+    dst: &mut [u8],
+) -> Result<(), HSError> {
+    let mut output_buffer = [0u8; HEATSHRINK_POLL_BUFFER_SIZE];
+    let mut position = 0;
+
+    loop {
+        let (res, input_size, output_size) = dec.poll_from(input, &mut output_buffer);
+        input = input.get(input_size..).ok_or(HSError::Internal)?;
+
+        // copy the part of the output falling in the range
+        let start = skip.saturating_sub(position).min(output_size);
+        let end = (skip + dst.len()).saturating_sub(position).min(output_size);
+        if start < end {
+            let range = position + start - skip..position + end - skip;
+            if let (Some(output), Some(range)) = (output_buffer.get(start..end), dst.get_mut(range))
+            {
+                range.copy_from_slice(output);
+            }
+        }
+        position += output_size;
+        if position >= skip + dst.len() {
+            return Ok(());
+        }
+
+        match res {
+            HSpollRes::PollMore => {}
+            HSpollRes::PollEmpty => return Err(HSError::TooShort),
+            HSpollRes::PollErrorMisuse => return Err(HSError::Internal),
+            HSpollRes::PollErrorLimit => return Err(HSError::OutputLimit),
+            HSpollRes::PollErrorBackref => return Err(HSError::InvalidBackref),
+        }
+    }
+}
+
+/// uncompress the block container in the src buffer to the destination
+/// buffer
+#[cfg(feature = "decoder")]
+pub fn decode_blocks<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+
+    decode_blocks_with(&mut dec, src, dst)
+}
+
+/// uncompress the block container in the src buffer to the destination
+/// buffer using the provided instance (and therefore its window and
+/// lookahead parameters), which is reset before each block.
+///
+/// Fails with `TooShort` or `TooLong` when a block doesn't uncompress to the
+/// size recorded in its header.
+#[cfg(feature = "decoder")]
+pub fn decode_blocks_with<'a, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], HSError> {
+    let mut total_input_size = 0;
+    let mut total_output_size = 0;
+
+    while total_input_size < src.len() {
+        let header = src
+            .get(total_input_size..)
+            .and_then(|input| input.get(..BLOCK_HEADER_SIZE))
+            .ok_or(HSError::Truncated)?;
+        let (output_size, input_size) = header.split_at(4);
+        let output_size =
+            u32::from_le_bytes(output_size.try_into().map_err(|_| HSError::Internal)?);
+        let input_size = u32::from_le_bytes(input_size.try_into().map_err(|_| HSError::Internal)?);
+        let stored = input_size & STORED_BLOCK_FLAG != 0;
+        let output_size =
+            usize::try_from(output_size).map_err(|_| HSError::OutputFull { needed_hint: None })?;
+        let input_size =
+            usize::try_from(input_size & !STORED_BLOCK_FLAG).map_err(|_| HSError::Truncated)?;
+
+        total_input_size += BLOCK_HEADER_SIZE;
+        let input = src
+            .get(total_input_size..)
+            .and_then(|input| input.get(..input_size))
+            .ok_or(HSError::Truncated)?;
+        let output = dst.get_mut(total_output_size..).ok_or(HSError::Internal)?;
+
+        if stored {
+            match input_size.cmp(&output_size) {
+                Ordering::Less => return Err(HSError::TooShort),
+                Ordering::Greater => return Err(HSError::TooLong),
+                Ordering::Equal => output
+                    .get_mut(..output_size)
+                    .ok_or(HSError::OutputFull { needed_hint: None })?
+                    .copy_from_slice(input),
+            }
+        } else {
+            dec.reset();
+            decode_exact_with(dec, input, output, output_size).map_err(|error| match error {
+                HSError::OutputFull { .. } => HSError::OutputFull { needed_hint: None },
+                error => error,
+            })?;
+        }
+
+        total_input_size += input_size;
+        total_output_size += output_size;
+    }
+
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
@@ -0,0 +1,238 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+#[cfg(feature = "encoder")]
+use super::HSsinkRes;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+/// Byte delimiting the packets, never found inside an encoded packet
+pub const PACKET_DELIMITER: u8 = 0;
+
+/// Largest code of a COBS block: 254 data bytes not followed by a zero
+const MAX_BLOCK_CODE: u8 = 0xff;
+
+/// Smallest packet holding some data: a code byte, a literal padded to 2
+/// bytes and the delimiter
+#[cfg(feature = "encoder")]
+const MIN_PACKET_SIZE: usize = 4;
+
+/// Writer of a compressed stream as zero-delimited COBS packets
+///
+/// Each packet carries the data compressed since the previous packet,
+/// flushed to a byte boundary (see `encoder::HeatshrinkEncoder::flush`),
+/// encoded with Consistent Overhead Byte Stuffing so that it holds no zero
+/// byte and ended by a zero: a receiver on a raw UART link resynchronizes on
+/// the delimiters and uncompresses each packet as soon as it is received.
+#[cfg(feature = "encoder")]
+#[derive(Debug)]
+pub struct CobsFrameWriter<const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+}
+
+#[cfg(feature = "encoder")]
+impl<const WINDOW_SIZE: usize> CobsFrameWriter<WINDOW_SIZE> {
+    /// Write the stream compressed by encoder, which must not have been sunk
+    /// yet, as packets
+    pub fn new(encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        CobsFrameWriter { encoder }
+    }
+
+    /// Largest compressed payload fitting in a packet of packet_size bytes
+    /// once encoded: a code byte, one more per 254 bytes and the delimiter
+    const fn payload_size(packet_size: usize) -> usize {
+        let encoded_size = packet_size.saturating_sub(2);
+        let blocks = encoded_size / 255;
+        let rest = encoded_size % 255;
+
+        blocks * 254 + if rest < 254 { rest } else { 253 }
+    }
+
+    /// Largest input compressed in a packet of packet_size bytes: every byte
+    /// encoded as a literal of 9 bits, padded to a byte boundary
+    pub const fn packet_input_size(packet_size: usize) -> usize {
+        Self::payload_size(packet_size) / 9 * 8 + Self::payload_size(packet_size) % 9 * 8 / 9
+    }
+
+    /// Compress the start of src into a packet written at the start of the
+    /// packet buffer, whose size bounds the size of the packet (delimiter
+    /// included). Return the number of bytes consumed from src and the size
+    /// of the packet.
+    ///
+    /// Call it again with the rest of src until all of it is consumed. Fail
+    /// with `OutputFull` if the packet buffer can't hold a single byte of
+    /// src.
+    pub fn write_packet(
+        &mut self,
+        src: &[u8],
+        packet: &mut [u8],
+    ) -> Result<(usize, usize), HSError> {
+        if src.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let input = src
+            .get(..Self::packet_input_size(packet.len()).min(src.len()))
+            .filter(|input| !input.is_empty())
+            .ok_or(HSError::OutputFull {
+                needed_hint: Some(MIN_PACKET_SIZE),
+            })?;
+
+        // compress at the end of the packet, leaving room at its start for
+        // the code bytes of the in-place encoding
+        let payload_end = packet.len().saturating_sub(1);
+        let payload_start = payload_end.saturating_sub(Self::payload_size(packet.len()));
+        let payload = packet
+            .get_mut(payload_start..payload_end)
+            .ok_or(HSError::Internal)?;
+        let mut input_size = 0;
+        let mut payload_size = 0;
+        while input_size < input.len() {
+            match self
+                .encoder
+                .sink(input.get(input_size..).ok_or(HSError::Internal)?)
+            {
+                (HSsinkRes::SinkOK, segment_input_size) => input_size += segment_input_size,
+                (HSsinkRes::SinkFull, _) => {}
+                (HSsinkRes::SinkErrorMisuse, _) => return Err(HSError::InvalidState),
+            }
+            payload_size += self
+                .encoder
+                .poll(payload.get_mut(payload_size..).ok_or(HSError::Internal)?)
+                .1;
+        }
+        // the payload was sized for the worst case
+        payload_size += self
+            .encoder
+            .flush_into(payload.get_mut(payload_size..).ok_or(HSError::Internal)?)
+            .map_err(|_| HSError::Internal)?;
+
+        let packet_size = encode_in_place(packet, payload_start, payload_size)?;
+        *packet.get_mut(packet_size).ok_or(HSError::Internal)? = PACKET_DELIMITER;
+        Ok((input_size, packet_size + 1))
+    }
+
+    /// Unwrap the underlying encoder
+    pub fn into_inner(self) -> HeatshrinkEncoder<WINDOW_SIZE> {
+        self.encoder
+    }
+}
+
+/// COBS encode the data at buffer[start..start + size] to the start of
+/// buffer, returning the encoded size.
+///
+/// The data is read ahead of the encoded bytes written as long as start
+/// leaves room for one code byte per 254 bytes of data.
+#[cfg(feature = "encoder")]
+fn encode_in_place(buffer: &mut [u8], start: usize, size: usize) -> Result<usize, HSError> {
+    let mut code_index = 0;
+    let mut code = 1;
+    let mut write_index = 1;
+
+    for read_index in start..start + size {
+        let byte = *buffer.get(read_index).ok_or(HSError::Internal)?;
+
+        if byte != 0 {
+            *buffer.get_mut(write_index).ok_or(HSError::Internal)? = byte;
+            write_index += 1;
+            code += 1;
+        }
+        if byte == 0 || code == MAX_BLOCK_CODE {
+            *buffer.get_mut(code_index).ok_or(HSError::Internal)? = code;
+            code_index = write_index;
+            write_index += 1;
+            code = 1;
+        }
+    }
+    *buffer.get_mut(code_index).ok_or(HSError::Internal)? = code;
+
+    Ok(write_index)
+}
+
+/// Reader of a compressed stream carried as zero-delimited COBS packets, fed
+/// with the bytes received in any size
+#[cfg(feature = "decoder")]
+#[derive(Debug)]
+pub struct CobsFrameReader<
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    /// code of the current block, 0 at the start of a packet
+    code: u8,
+    /// data bytes left in the current block
+    remaining: usize,
+}
+
+#[cfg(feature = "decoder")]
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> CobsFrameReader<WINDOW_SIZE, INPUT_SIZE> {
+    /// Uncompress the packets with decoder, which must not have been sunk
+    /// yet
+    pub fn new(decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        CobsFrameReader {
+            decoder,
+            code: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Sink the bytes received, uncompressing the packets as they arrive and
+    /// handing the data to the output callback.
+    ///
+    /// Fail with `InvalidFrame` if a packet ends in the middle of a COBS
+    /// block.
+    pub fn sink_all(
+        &mut self,
+        mut input_buffer: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), HSError> {
+        while let Some((&byte, rest)) = input_buffer.split_first() {
+            if self.remaining > 0 {
+                // data bytes of the current block
+                let (data, rest) = input_buffer
+                    .split_at_checked(self.remaining.min(input_buffer.len()))
+                    .ok_or(HSError::Internal)?;
+                if data.contains(&PACKET_DELIMITER) {
+                    return Err(HSError::InvalidFrame);
+                }
+                self.decoder.sink_all(data, output)?;
+                self.remaining -= data.len();
+                input_buffer = rest;
+            } else if byte == PACKET_DELIMITER {
+                // end of a packet, drop its padding
+                self.decoder.sync()?;
+                self.code = 0;
+                input_buffer = rest;
+            } else {
+                // a block follows a block shorter than 254 bytes when a zero
+                // was between them
+                if self.code != 0 && self.code != MAX_BLOCK_CODE {
+                    self.decoder.sink_all(&[0], output)?;
+                }
+                self.code = byte;
+                self.remaining = usize::from(byte) - 1;
+                input_buffer = rest;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Signal the end of the bytes received. Fail with `Truncated` if it
+    /// ends in the middle of a packet.
+    pub fn finish(&mut self) -> Result<(), HSError> {
+        if self.code == 0 {
+            Ok(())
+        } else {
+            Err(HSError::Truncated)
+        }
+    }
+
+    /// Unwrap the underlying decoder
+    pub fn into_inner(self) -> HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE> {
+        self.decoder
+    }
+}
@@ -0,0 +1,105 @@
+use super::decoder::HeatshrinkDecoder;
+use super::encoder::{self, HeatshrinkEncoder};
+use super::HSError;
+use super::HSfinishRes;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Bytes serialized as a compressed stream and uncompressed when
+/// deserialized, so that a large field of a config or telemetry document
+/// shrinks without touching the code using it
+///
+/// T is the container of the uncompressed bytes (`Vec<u8>`, `Box<[u8]>`,
+/// ...), see `CompressedBytes`. The compressed stream is serialized as a
+/// byte array, with the default window and lookahead parameters.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Compressed<T>(pub T);
+
+/// Vector of bytes serialized as a compressed stream
+pub type CompressedBytes = Compressed<Vec<u8>>;
+
+impl<T> Compressed<T> {
+    /// Unwrap the uncompressed bytes
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Compressed<T> {
+    fn from(value: T) -> Self {
+        Compressed(value)
+    }
+}
+
+impl<T> Deref for Compressed<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Compressed<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: AsRef<[u8]>> Serialize for Compressed<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let src = self.0.as_ref();
+        let mut dst = vec![0; encoder::encode_bound(src.len())];
+        let mut enc: HeatshrinkEncoder = Default::default();
+        let compressed =
+            encoder::encode_with(&mut enc, src, &mut dst).map_err(serde::ser::Error::custom)?;
+
+        serializer.serialize_bytes(compressed)
+    }
+}
+
+impl<'de, T: From<Vec<u8>>> Deserialize<'de> for Compressed<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer
+            .deserialize_bytes(CompressedVisitor)
+            .map(|data| Compressed(T::from(data)))
+    }
+}
+
+/// Visitor uncompressing a compressed stream stored as bytes or as a
+/// sequence of bytes (e.g. in JSON)
+struct CompressedVisitor;
+
+impl<'de> Visitor<'de> for CompressedVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a heatshrink compressed stream")
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+        let mut dec: HeatshrinkDecoder = Default::default();
+        let mut data = Vec::new();
+
+        dec.sink_all(bytes, &mut |output| data.extend_from_slice(output))
+            .map_err(E::custom)?;
+        match dec.finish() {
+            HSfinishRes::FinishDone => Ok(data),
+            HSfinishRes::FinishMore => Err(E::custom(HSError::Truncated)),
+        }
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+        // the size hint comes from the document, don't trust it too much
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default().min(4096));
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        self.visit_bytes(&bytes)
+    }
+}
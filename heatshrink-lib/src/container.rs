@@ -0,0 +1,318 @@
+use super::HSError;
+use super::Params;
+
+/// Magic bytes starting a container header
+pub const MAGIC: [u8; 4] = *b"HSHK";
+
+/// Version of the container header layout written by `write_header`
+pub const VERSION: u8 = 1;
+
+/// Version of the container header layout written by
+/// `write_header_with_metadata`: the header is followed by a metadata
+/// section
+pub const METADATA_VERSION: u8 = 2;
+
+/// Size in bytes of a container header: the magic, the version, the window
+/// and lookahead sizes and the original length (64-bit little endian).
+///
+/// The header is optional: a stream preceded by it can be uncompressed
+/// without knowing its parameters out of band, while the compressed stream
+/// itself stays the one of the C library.
+pub const HEADER_SIZE: usize = 15;
+
+/// Size in bytes of the fixed part of a metadata section: the modification
+/// time (64-bit little endian) and the lengths of the name and of the
+/// comment (16-bit little endian), which follow it
+pub const METADATA_FIXED_SIZE: usize = 12;
+
+/// Original length recorded when the length of the uncompressed data is not
+/// known when the header is written (e.g. when compressing a pipe)
+const UNKNOWN_LENGTH: u64 = u64::MAX;
+
+/// Modification time recorded when it is not known
+const UNKNOWN_MTIME: u64 = u64::MAX;
+
+/// Format of a compressed stream
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Format {
+    /// The bare compressed stream, byte compatible with the C library
+    #[default]
+    Raw,
+    /// The compressed stream preceded by a container header
+    Container,
+}
+
+impl Format {
+    /// Format of the compressed data starting src, a container if it starts
+    /// with the magic bytes of a container header.
+    ///
+    /// A raw stream starting with the same 4 bytes is mistaken for a
+    /// container, so the format should be given rather than detected when
+    /// it is known.
+    pub fn detect(src: &[u8]) -> Self {
+        if src.starts_with(&MAGIC) {
+            Format::Container
+        } else {
+            Format::Raw
+        }
+    }
+}
+
+/// Description of the compressed stream following a container header
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header {
+    /// Window and lookahead sizes the stream was compressed with
+    pub params: Params,
+    /// Length of the uncompressed data, when it was known
+    pub original_len: Option<u64>,
+}
+
+impl Header {
+    /// Describe a stream compressed with params, of original_len bytes once
+    /// uncompressed (if known)
+    pub const fn new(params: Params, original_len: Option<u64>) -> Self {
+        Header {
+            params,
+            original_len,
+        }
+    }
+}
+
+/// Description of the uncompressed file, recorded in the metadata section
+/// following a container header so that it can be restored with its
+/// original name (as `gunzip -N` does)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Metadata<'a> {
+    /// Original file name, without its directory
+    pub name: Option<&'a str>,
+    /// Modification time, in seconds since the Unix epoch
+    pub mtime: Option<u64>,
+    /// Free text comment
+    pub comment: Option<&'a str>,
+}
+
+impl<'a> Metadata<'a> {
+    /// Metadata with no name, modification time nor comment
+    pub const fn new() -> Self {
+        Metadata {
+            name: None,
+            mtime: None,
+            comment: None,
+        }
+    }
+
+    /// Size in bytes of the metadata section
+    pub const fn size(&self) -> usize {
+        let name_len = match self.name {
+            Some(name) => name.len(),
+            None => 0,
+        };
+        let comment_len = match self.comment {
+            Some(comment) => comment.len(),
+            None => 0,
+        };
+
+        METADATA_FIXED_SIZE
+            .saturating_add(name_len)
+            .saturating_add(comment_len)
+    }
+}
+
+/// Write the container header to the start of dst, returning its size
+/// (`HEADER_SIZE`).
+///
+/// Fail with `OutputFull` if dst is smaller than `HEADER_SIZE`, or with
+/// `InvalidHeader` if the original length is `u64::MAX`, which marks an
+/// unknown length.
+pub fn write_header(header: &Header, dst: &mut [u8]) -> Result<usize, HSError> {
+    let dst = dst.get_mut(..HEADER_SIZE).ok_or(HSError::OutputFull {
+        needed_hint: Some(HEADER_SIZE),
+    })?;
+    let original_len = match header.original_len {
+        Some(UNKNOWN_LENGTH) => return Err(HSError::InvalidHeader),
+        Some(original_len) => original_len,
+        None => UNKNOWN_LENGTH,
+    };
+    let (magic, fields) = dst.split_at_mut(MAGIC.len());
+    let (fields, length) = fields.split_at_mut(3);
+
+    magic.copy_from_slice(&MAGIC);
+    fields.copy_from_slice(&[
+        VERSION,
+        header.params.window_bits(),
+        header.params.lookahead_bits(),
+    ]);
+    length.copy_from_slice(&original_len.to_le_bytes());
+    Ok(HEADER_SIZE)
+}
+
+/// Write the container header followed by a metadata section (the
+/// modification time, the lengths of the name and of the comment, then the
+/// name and the comment) to the start of dst, returning their size
+/// (`HEADER_SIZE + metadata.size()`).
+///
+/// Fail like `write_header`, or with `InvalidHeader` if the modification
+/// time is `u64::MAX`, which marks an unknown time, or if the name or the
+/// comment is longer than 65535 bytes.
+pub fn write_header_with_metadata(
+    header: &Header,
+    metadata: &Metadata,
+    dst: &mut [u8],
+) -> Result<usize, HSError> {
+    let size = HEADER_SIZE.saturating_add(metadata.size());
+    let dst = dst.get_mut(..size).ok_or(HSError::OutputFull {
+        needed_hint: Some(size),
+    })?;
+    let (fixed, section) = dst.split_at_mut(HEADER_SIZE);
+    write_header(header, fixed)?;
+    *fixed.get_mut(MAGIC.len()).ok_or(HSError::Internal)? = METADATA_VERSION;
+
+    let mtime = match metadata.mtime {
+        Some(UNKNOWN_MTIME) => return Err(HSError::InvalidHeader),
+        Some(mtime) => mtime,
+        None => UNKNOWN_MTIME,
+    };
+    let name = metadata.name.unwrap_or_default().as_bytes();
+    let comment = metadata.comment.unwrap_or_default().as_bytes();
+    let name_len = u16::try_from(name.len()).map_err(|_| HSError::InvalidHeader)?;
+    let comment_len = u16::try_from(comment.len()).map_err(|_| HSError::InvalidHeader)?;
+    let (fields, strings) = section
+        .split_at_mut_checked(METADATA_FIXED_SIZE)
+        .ok_or(HSError::Internal)?;
+    let (name_field, comment_field) = strings
+        .split_at_mut_checked(name.len())
+        .ok_or(HSError::Internal)?;
+
+    fields
+        .get_mut(..8)
+        .ok_or(HSError::Internal)?
+        .copy_from_slice(&mtime.to_le_bytes());
+    fields
+        .get_mut(8..10)
+        .ok_or(HSError::Internal)?
+        .copy_from_slice(&name_len.to_le_bytes());
+    fields
+        .get_mut(10..METADATA_FIXED_SIZE)
+        .ok_or(HSError::Internal)?
+        .copy_from_slice(&comment_len.to_le_bytes());
+    name_field.copy_from_slice(name);
+    comment_field.copy_from_slice(comment);
+    Ok(size)
+}
+
+/// Read the container header at the start of src, returning it with its
+/// size, i.e. the offset of the compressed stream in src. A metadata
+/// section following the header is skipped.
+///
+/// Fail with `Truncated` if src is shorter than `HEADER_SIZE` (or than the
+/// metadata section), or with `InvalidHeader` if it doesn't start with the
+/// magic bytes, is of an unknown version or records unsupported parameters.
+pub fn read_header(src: &[u8]) -> Result<(Header, usize), HSError> {
+    read_header_with_metadata(src).map(|(header, _, size)| (header, size))
+}
+
+/// Read the container header at the start of src and its metadata section
+/// if any, returning them with their size, i.e. the offset of the compressed
+/// stream in src.
+///
+/// Fail like `read_header`, or with `InvalidHeader` if the name or the
+/// comment isn't valid UTF-8.
+pub fn read_header_with_metadata(
+    src: &[u8],
+) -> Result<(Header, Option<Metadata<'_>>, usize), HSError> {
+    let (header, version) = read_fixed_header(src)?;
+    if version != METADATA_VERSION {
+        return Ok((header, None, HEADER_SIZE));
+    }
+
+    let section = src.get(HEADER_SIZE..).ok_or(HSError::Internal)?;
+    let (fields, strings) = section
+        .split_at_checked(METADATA_FIXED_SIZE)
+        .ok_or(HSError::Truncated)?;
+    let field = |range: core::ops::Range<usize>| fields.get(range).ok_or(HSError::Internal);
+    let mtime = u64::from_le_bytes(field(0..8)?.try_into().map_err(|_| HSError::Internal)?);
+    let name_len = u16::from_le_bytes(field(8..10)?.try_into().map_err(|_| HSError::Internal)?);
+    let comment_len = u16::from_le_bytes(
+        field(10..METADATA_FIXED_SIZE)?
+            .try_into()
+            .map_err(|_| HSError::Internal)?,
+    );
+    let (name, strings) = strings
+        .split_at_checked(name_len.into())
+        .ok_or(HSError::Truncated)?;
+    let comment = strings
+        .get(..comment_len.into())
+        .ok_or(HSError::Truncated)?;
+    let metadata = Metadata {
+        name: metadata_string(name)?,
+        mtime: (mtime != UNKNOWN_MTIME).then_some(mtime),
+        comment: metadata_string(comment)?,
+    };
+
+    Ok((
+        header,
+        Some(metadata),
+        HEADER_SIZE + METADATA_FIXED_SIZE + name.len() + comment.len(),
+    ))
+}
+
+/// Read the container header at the start of src, returning it with the
+/// size of the header and of its metadata section if any, without reading
+/// the metadata section itself: only the first `HEADER_SIZE` bytes (and
+/// `METADATA_FIXED_SIZE` more with a metadata section) are needed, to skip
+/// the header of a stream read in small pieces.
+///
+/// Fail like `read_header`.
+pub fn peek_header(src: &[u8]) -> Result<(Header, usize), HSError> {
+    let (header, version) = read_fixed_header(src)?;
+    if version != METADATA_VERSION {
+        return Ok((header, HEADER_SIZE));
+    }
+
+    let fields = src
+        .get(HEADER_SIZE..)
+        .and_then(|section| section.get(..METADATA_FIXED_SIZE))
+        .ok_or(HSError::Truncated)?;
+    let (_, lengths) = fields.split_at_checked(8).ok_or(HSError::Internal)?;
+    let [name_len_low, name_len_high, comment_len_low, comment_len_high] =
+        <[u8; 4]>::try_from(lengths).map_err(|_| HSError::Internal)?;
+    let name_len = u16::from_le_bytes([name_len_low, name_len_high]);
+    let comment_len = u16::from_le_bytes([comment_len_low, comment_len_high]);
+
+    Ok((
+        header,
+        HEADER_SIZE + METADATA_FIXED_SIZE + usize::from(name_len) + usize::from(comment_len),
+    ))
+}
+
+/// string of a metadata section, None if empty
+fn metadata_string(bytes: &[u8]) -> Result<Option<&str>, HSError> {
+    let string = core::str::from_utf8(bytes).map_err(|_| HSError::InvalidHeader)?;
+
+    Ok((!string.is_empty()).then_some(string))
+}
+
+/// read the fixed part of the container header at the start of src,
+/// returning it with its version
+fn read_fixed_header(src: &[u8]) -> Result<(Header, u8), HSError> {
+    let src = src.get(..HEADER_SIZE).ok_or(HSError::Truncated)?;
+    let (magic, fields) = src.split_at(MAGIC.len());
+    let (fields, length) = fields.split_at(3);
+
+    if magic != MAGIC {
+        return Err(HSError::InvalidHeader);
+    }
+    let [version, window_bits, lookahead_bits] =
+        <[u8; 3]>::try_from(fields).map_err(|_| HSError::Internal)?;
+    if version != VERSION && version != METADATA_VERSION {
+        return Err(HSError::InvalidHeader);
+    }
+    let params = Params::new(window_bits, lookahead_bits).map_err(|_| HSError::InvalidHeader)?;
+    let original_len = u64::from_le_bytes(length.try_into().map_err(|_| HSError::Internal)?);
+    let original_len = (original_len != UNKNOWN_LENGTH).then_some(original_len);
+
+    Ok((Header::new(params, original_len), version))
+}
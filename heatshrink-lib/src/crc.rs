@@ -0,0 +1,416 @@
+#[cfg(feature = "decoder")]
+use super::decoder::{self, HeatshrinkDecoder};
+#[cfg(feature = "encoder")]
+use super::encoder::{self, encode_bound, HeatshrinkEncoder};
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+use super::{HSError, HSfinishRes, HSpollRes, HSsinkRes};
+
+/// Largest size in bytes of a checksum trailer
+const MAX_TRAILER_SIZE: usize = 4;
+
+/// Checksum of the uncompressed data, appended as a little-endian trailer to
+/// the compressed stream.
+///
+/// The trailer lets a device detect a corrupted payload (e.g. an OTA image)
+/// before using it. As the compressed stream has no end marker, the trailer
+/// is simply its last `SIZE` bytes.
+pub trait Checksum: Default + Clone {
+    /// Size in bytes of the trailer, at most 4
+    const SIZE: usize;
+
+    /// Add data to the checksum
+    fn update(&mut self, data: &[u8]);
+
+    /// Checksum of the data added so far
+    fn value(&self) -> u32;
+}
+
+/// CRC-32 (IEEE 802.3, as used by zlib and most bootloaders)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+/// CRC-16/CCITT-FALSE, a 2-byte trailer for tiny frames
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Crc16 {
+    crc: u16,
+}
+
+/// table of the CRC-32 of each byte value (reflected polynomial)
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// table of the CRC-16 of each byte value
+const CRC16_TABLE: [u16; 256] = {
+    let mut table = [0u16; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = (index as u16) << 8;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32 { crc: u32::MAX }
+    }
+}
+
+impl Checksum for Crc32 {
+    const SIZE: usize = 4;
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = (self.crc >> 8) ^ CRC32_TABLE[usize::from(self.crc as u8 ^ byte)];
+        }
+    }
+
+    fn value(&self) -> u32 {
+        !self.crc
+    }
+}
+
+impl Default for Crc16 {
+    fn default() -> Self {
+        Crc16 { crc: u16::MAX }
+    }
+}
+
+impl Checksum for Crc16 {
+    const SIZE: usize = 2;
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.crc = (self.crc << 8) ^ CRC16_TABLE[usize::from((self.crc >> 8) as u8 ^ byte)];
+        }
+    }
+
+    fn value(&self) -> u32 {
+        u32::from(self.crc)
+    }
+}
+
+/// size of the trailer of C, whatever its declared size
+const fn trailer_size<C: Checksum>() -> usize {
+    if C::SIZE < MAX_TRAILER_SIZE {
+        C::SIZE
+    } else {
+        MAX_TRAILER_SIZE
+    }
+}
+
+/// trailer bytes of a checksum
+fn trailer<C: Checksum>(checksum: &C) -> [u8; MAX_TRAILER_SIZE] {
+    checksum.value().to_le_bytes()
+}
+
+/// compress the src buffer to the destination buffer followed by the
+/// checksum of src
+#[cfg(feature = "encoder")]
+pub fn encode<'a, C: Checksum>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
+    let mut enc: HeatshrinkEncoder = Default::default();
+
+    encode_with::<C, HEATSHRINK_WINDOW_SIZE>(&mut enc, src, dst)
+}
+
+/// compress the src buffer to the destination buffer followed by the
+/// checksum of src, using the provided instance (and therefore its window
+/// and lookahead parameters)
+#[cfg(feature = "encoder")]
+pub fn encode_with<'a, C: Checksum, const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], HSError> {
+    let full = HSError::OutputFull {
+        needed_hint: Some(encode_bound(src.len()).saturating_add(trailer_size::<C>())),
+    };
+    let output_size = match encoder::encode_with(enc, src, dst) {
+        Ok(compressed) => compressed.len(),
+        Err(HSError::OutputFull { .. }) => return Err(full),
+        Err(error) => return Err(error),
+    };
+    let mut checksum = C::default();
+    checksum.update(src);
+    let total_output_size = output_size + trailer_size::<C>();
+
+    dst.get_mut(output_size..total_output_size)
+        .ok_or(full)?
+        .copy_from_slice(
+            trailer(&checksum)
+                .get(..trailer_size::<C>())
+                .unwrap_or_default(),
+        );
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
+
+/// uncompress the src buffer, ended by the checksum of the uncompressed
+/// data, to the destination buffer
+#[cfg(feature = "decoder")]
+pub fn decode<'a, C: Checksum>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+
+    decode_with::<C, HEATSHRINK_WINDOW_SIZE, HEATSHRINK_INPUT_BUFFER_SIZE>(&mut dec, src, dst)
+}
+
+/// uncompress the src buffer, ended by the checksum of the uncompressed
+/// data, to the destination buffer using the provided instance (and
+/// therefore its window and lookahead parameters).
+///
+/// Fail with `Truncated` if src is shorter than the trailer, or with
+/// `InvalidChecksum` if the uncompressed data doesn't match it.
+#[cfg(feature = "decoder")]
+pub fn decode_with<'a, C: Checksum, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], HSError> {
+    let (stream, expected) = src
+        .split_at_checked(src.len().wrapping_sub(trailer_size::<C>()))
+        .ok_or(HSError::Truncated)?;
+    let output = decoder::decode_with(dec, stream, dst)?;
+    let mut checksum = C::default();
+    checksum.update(output);
+
+    if trailer(&checksum).get(..trailer_size::<C>()) == Some(expected) {
+        Ok(output)
+    } else {
+        Err(HSError::InvalidChecksum)
+    }
+}
+
+/// Encoder appending the checksum of the data sunk to the compressed stream
+#[cfg(feature = "encoder")]
+#[derive(Debug)]
+pub struct CrcEncoder<C: Checksum = Crc32, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    checksum: C,
+    finishing: bool,
+    trailer_size: usize,
+}
+
+#[cfg(feature = "encoder")]
+impl<C: Checksum, const WINDOW_SIZE: usize> CrcEncoder<C, WINDOW_SIZE> {
+    /// Append a checksum to the stream compressed by encoder, which must not
+    /// have been sunk yet
+    pub fn new(encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        CrcEncoder {
+            encoder,
+            checksum: C::default(),
+            finishing: false,
+            trailer_size: 0,
+        }
+    }
+
+    /// Sink data like `HeatshrinkEncoder::sink`, adding it to the checksum
+    pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        let (res, input_size) = self.encoder.sink(input_buffer);
+        self.checksum
+            .update(input_buffer.get(..input_size).unwrap_or_default());
+        (res, input_size)
+    }
+
+    /// Poll the compressed stream like `HeatshrinkEncoder::poll`, followed
+    /// by the trailer once finishing
+    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        let (res, output_size) = self.encoder.poll(output_buffer);
+
+        if res != HSpollRes::PollEmpty
+            || !self.finishing
+            || self.encoder.finish() != HSfinishRes::FinishDone
+        {
+            return (res, output_size);
+        }
+
+        let trailer = trailer(&self.checksum);
+        let pending = trailer
+            .get(self.trailer_size..trailer_size::<C>())
+            .unwrap_or_default();
+        let output = output_buffer.get_mut(output_size..).unwrap_or_default();
+        let copy_size = pending.len().min(output.len());
+        if let (Some(output), Some(pending)) =
+            (output.get_mut(..copy_size), pending.get(..copy_size))
+        {
+            output.copy_from_slice(pending);
+        }
+        self.trailer_size += copy_size;
+
+        if copy_size < pending.len() {
+            (HSpollRes::PollMore, output_size + copy_size)
+        } else {
+            (HSpollRes::PollEmpty, output_size + copy_size)
+        }
+    }
+
+    /// Signal the end of the input data. Like `HeatshrinkEncoder::finish`,
+    /// return More until the stream and its trailer were polled.
+    pub fn finish(&mut self) -> HSfinishRes {
+        self.finishing = true;
+        match self.encoder.finish() {
+            HSfinishRes::FinishDone if self.trailer_size == trailer_size::<C>() => {
+                HSfinishRes::FinishDone
+            }
+            _ => HSfinishRes::FinishMore,
+        }
+    }
+
+    /// Checksum of the data sunk so far
+    pub fn checksum(&self) -> u32 {
+        self.checksum.value()
+    }
+
+    /// Unwrap the underlying encoder
+    pub fn into_inner(self) -> HeatshrinkEncoder<WINDOW_SIZE> {
+        self.encoder
+    }
+}
+
+/// Decoder checking the checksum ending the compressed stream against the
+/// uncompressed data.
+///
+/// The last bytes sunk are held back from the decoder, as they may be the
+/// trailer, until more data is sunk.
+#[cfg(feature = "decoder")]
+#[derive(Debug)]
+pub struct CrcDecoder<
+    C: Checksum = Crc32,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    checksum: C,
+    held: [u8; MAX_TRAILER_SIZE],
+    held_size: usize,
+}
+
+#[cfg(feature = "decoder")]
+impl<C: Checksum, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    CrcDecoder<C, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Check the checksum ending the stream uncompressed by decoder, which
+    /// must not have been sunk yet
+    pub fn new(decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        CrcDecoder {
+            decoder,
+            checksum: C::default(),
+            held: [0; MAX_TRAILER_SIZE],
+            held_size: 0,
+        }
+    }
+
+    /// Sink compressed data like `HeatshrinkDecoder::sink`. The bytes that
+    /// may be the trailer are kept aside.
+    pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        let trailer_size = trailer_size::<C>();
+
+        // forward the held bytes known not to be part of the trailer
+        let held_forward = (self.held_size + input_buffer.len())
+            .saturating_sub(trailer_size)
+            .min(self.held_size);
+        if held_forward > 0 {
+            let (res, forwarded) = self
+                .decoder
+                .sink(self.held.get(..held_forward).unwrap_or_default());
+            self.held
+                .copy_within(forwarded.min(self.held_size)..self.held_size, 0);
+            self.held_size -= forwarded.min(self.held_size);
+            if forwarded < held_forward {
+                return (res, 0);
+            }
+        }
+
+        let input_forward = (self.held_size + input_buffer.len()).saturating_sub(trailer_size);
+        let (forward, hold) = input_buffer
+            .split_at_checked(input_forward)
+            .unwrap_or_default();
+        if !forward.is_empty() {
+            let (res, forwarded) = self.decoder.sink(forward);
+            if forwarded < forward.len() {
+                return (res, forwarded);
+            }
+        }
+
+        if let Some(held) = self
+            .held
+            .get_mut(self.held_size..self.held_size + hold.len())
+        {
+            held.copy_from_slice(hold);
+            self.held_size += hold.len();
+        }
+        (HSsinkRes::SinkOK, input_buffer.len())
+    }
+
+    /// Poll the uncompressed data like `HeatshrinkDecoder::poll`, adding it
+    /// to the checksum
+    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        let (res, output_size) = self.decoder.poll(output_buffer);
+        self.checksum
+            .update(output_buffer.get(..output_size).unwrap_or_default());
+        (res, output_size)
+    }
+
+    /// Signal the end of the compressed data. Like
+    /// `HeatshrinkDecoder::finish`, return More while some data remains to
+    /// be polled, then check the checksum.
+    ///
+    /// Fail with `Truncated` if less than a trailer was sunk, or with
+    /// `InvalidChecksum` if the uncompressed data doesn't match the trailer.
+    pub fn finish(&mut self) -> Result<HSfinishRes, HSError> {
+        if self.held_size < trailer_size::<C>() {
+            return Err(HSError::Truncated);
+        }
+
+        match self.decoder.finish() {
+            HSfinishRes::FinishMore => Ok(HSfinishRes::FinishMore),
+            HSfinishRes::FinishDone => {
+                if trailer(&self.checksum).get(..self.held_size) == self.held.get(..self.held_size)
+                {
+                    Ok(HSfinishRes::FinishDone)
+                } else {
+                    Err(HSError::InvalidChecksum)
+                }
+            }
+        }
+    }
+
+    /// Checksum of the data polled so far
+    pub fn checksum(&self) -> u32 {
+        self.checksum.value()
+    }
+
+    /// Unwrap the underlying decoder
+    pub fn into_inner(self) -> HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE> {
+        self.decoder
+    }
+}
@@ -1,49 +1,316 @@
+use super::ByteSink;
 use super::HSError;
 use super::HSfinishRes;
 use super::HSpollRes;
 use super::HSsinkRes;
+use super::HexPreview;
 use super::OutputInfo;
+use super::ParamError;
+use super::Params;
+#[cfg(feature = "serde")]
+use super::StateBytes;
+#[cfg(feature = "serde")]
+use super::StateBytesMut;
+use super::StateReader;
+use super::StateWriter;
 use super::HEATSHRINK_INPUT_BUFFER_SIZE;
-use super::HEATSHRINK_LOOKAHEAD_BITS;
-use super::HEATSHRINK_WINDOWS_BITS;
+use super::HEATSHRINK_POLL_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
 
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
 use core::cmp::Ordering;
+use core::fmt;
 
+// the order of the states is part of the saved state layout
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 enum HSDstate {
     TagBit,          /* tag bit */
     YieldLiteral,    /* ready to yield literal byte */
     BackrefIndexMsb, /* most significant byte of index */
     BackrefIndexLsb, /* least significant byte of index */
+    BackrefCountMsb, /* most significant byte of count */
     BackrefCountLsb, /* least significant byte of count */
     YieldBackref,    /* ready to yield back-reference */
+    InvalidBackref,  /* back-reference before the start of the stream */
+}
+
+impl HSDstate {
+    /// Decode a state written by `HeatshrinkDecoder::save_state`
+    fn from_u8(value: u8) -> Option<HSDstate> {
+        const STATES: [HSDstate; 8] = [
+            HSDstate::TagBit,
+            HSDstate::YieldLiteral,
+            HSDstate::BackrefIndexMsb,
+            HSDstate::BackrefIndexLsb,
+            HSDstate::BackrefCountMsb,
+            HSDstate::BackrefCountLsb,
+            HSDstate::YieldBackref,
+            HSDstate::InvalidBackref,
+        ];
+
+        STATES.get(usize::from(value)).copied()
+    }
+}
+
+/// First byte of a saved decoder state
+const STATE_MAGIC: u8 = b'D';
+
+/// Version of the saved decoder state layout
+const STATE_VERSION: u8 = 1;
+
+/// Size of the saved decoder state before the pending input and the window
+const STATE_HEADER_SIZE: usize = 58;
+
+/// Flag of a saved decoder state in strict mode
+const STATE_FLAG_STRICT: u8 = 1;
+
+/// Flag of a saved decoder state whose stream was finished
+const STATE_FLAG_FINISHED: u8 = 2;
+
+/// Registers of a saved decoder state, checked but not applied yet
+struct SavedState {
+    params: Params,
+    state: HSDstate,
+    flags: u8,
+    current_byte: u8,
+    bit_index: u8,
+    output_count: u16,
+    pending_size: usize,
+    output_index: usize,
+    head_index: usize,
+    total_in: u64,
+    total_out: u64,
+    output_limit: u64,
+}
+
+impl SavedState {
+    /// Parse the part of a saved state before the pending input, checking
+    /// that it is consistent and that its window and pending input fit in
+    /// window_capacity and input_capacity
+    fn parse(
+        header: &[u8],
+        window_capacity: usize,
+        input_capacity: usize,
+    ) -> Result<SavedState, HSError> {
+        let invalid = |valid: bool| {
+            if valid {
+                Ok(())
+            } else {
+                Err(HSError::InvalidState)
+            }
+        };
+        let mut reader = StateReader { buffer: header };
+
+        invalid(reader.u8()? == STATE_MAGIC && reader.u8()? == STATE_VERSION)?;
+        let params = Params::new(reader.u8()?, reader.u8()?)
+            .and_then(|params| params.check_window_size(window_capacity).map(|_| params))
+            .map_err(|_| HSError::InvalidState)?;
+        let state = HSDstate::from_u8(reader.u8()?).ok_or(HSError::InvalidState)?;
+        let flags = reader.u8()?;
+        let current_byte = reader.u8()?;
+        let bit_index = reader.u8()?;
+        let output_count = reader.u16()?;
+        let pending_size = usize::try_from(reader.u32()?).map_err(|_| HSError::InvalidState)?;
+        let output_index = usize::try_from(reader.u32()?).map_err(|_| HSError::InvalidState)?;
+        let head_index = bounded_head_index(reader.u64()?, params.window_size() as u64);
+        let head_index = usize::try_from(head_index).map_err(|_| HSError::InvalidState)?;
+        let total_in = reader.u64()?;
+        let total_out = reader.u64()?;
+        let output_limit = reader.u64()?;
+        let _reserved = reader.u64()?;
+
+        invalid(flags & !(STATE_FLAG_STRICT | STATE_FLAG_FINISHED) == 0 && bit_index <= 8)?;
+        invalid(pending_size <= input_capacity)?;
+        invalid(output_index <= params.window_size() && total_out <= output_limit)?;
+
+        Ok(SavedState {
+            params,
+            state,
+            flags,
+            current_byte,
+            bit_index,
+            output_count,
+            pending_size,
+            output_index,
+            head_index,
+            total_in,
+            total_out,
+            output_limit,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> serde::Serialize
+    for HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Serialize the state saved by `save_state` as a tuple of byte arrays:
+    /// the header, the pending input, then the window
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeTuple};
+
+        let mut header = [0u8; STATE_HEADER_SIZE];
+        self.write_state_header(&mut StateWriter {
+            buffer: &mut header,
+        });
+
+        let mut tuple = serializer.serialize_tuple(3)?;
+        tuple.serialize_element(&StateBytes(&header))?;
+        tuple.serialize_element(&StateBytes(self.pending_input()))?;
+        tuple.serialize_element(&StateBytes(self.saved_window().map_err(S::Error::custom)?))?;
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> serde::Deserialize<'de>
+    for HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Deserialize a state serialized by any instance whose window fits in
+    /// `WINDOW_SIZE`, checked like `restore_state`
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StateVisitor<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>;
+
+        impl<'de, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> serde::de::Visitor<'de>
+            for StateVisitor<WINDOW_SIZE, INPUT_SIZE>
+        {
+            type Value = HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a saved heatshrink decoder state")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                use serde::de::Error;
+
+                let mut header = [0u8; STATE_HEADER_SIZE];
+                seq.next_element_seed(StateBytesMut(&mut header))?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                let saved = SavedState::parse(&header, WINDOW_SIZE, INPUT_SIZE)
+                    .map_err(A::Error::custom)?;
+
+                let mut dec =
+                    HeatshrinkDecoder::<WINDOW_SIZE, INPUT_SIZE>::from_params(saved.params);
+                let buffer = dec
+                    .input_buffer
+                    .get_mut(..saved.pending_size)
+                    .ok_or_else(|| A::Error::custom(HSError::InvalidState))?;
+                seq.next_element_seed(StateBytesMut(buffer))?
+                    .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                let buffer = dec
+                    .output_buffer
+                    .get_mut(..saved.params.window_size())
+                    .ok_or_else(|| A::Error::custom(HSError::InvalidState))?;
+                seq.next_element_seed(StateBytesMut(buffer))?
+                    .ok_or_else(|| A::Error::invalid_length(2, &self))?;
+                dec.apply_state(&saved);
+
+                Ok(dec)
+            }
+        }
+
+        deserializer.deserialize_tuple(3, StateVisitor)
+    }
 }
 
 /// the decoder instance
-#[derive(Debug)]
-pub struct HeatshrinkDecoder {
+///
+/// `WINDOW_SIZE` is the capacity in bytes of the sliding window. It bounds
+/// the largest window size the instance can be configured with.
+///
+/// `INPUT_SIZE` is the size in bytes of the input buffer, i.e. the most a
+/// `sink` call accepts: larger on hosts for throughput, smaller on MCUs to
+/// save RAM.
+#[derive(Clone)]
+pub struct HeatshrinkDecoder<
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
     input_size: usize,
     input_index: usize,
     output_index: usize,
     head_index: usize,
+    total_in: u64,
+    total_out: u64,
+    output_limit: u64,
+    strict: bool,
+    finished: bool,
     output_count: u16,
     current_byte: u8,
     bit_index: u8,
+    window_bits: u8,
+    lookahead_bits: u8,
     state: HSDstate,
-    input_buffer: [u8; HEATSHRINK_INPUT_BUFFER_SIZE],
-    output_buffer: [u8; 1 << HEATSHRINK_WINDOWS_BITS],
+    input_buffer: [u8; INPUT_SIZE],
+    output_buffer: [u8; WINDOW_SIZE],
 }
 
+#[cfg(feature = "w8l4")]
+/// Decoder using a 256 bytes window and 4 bits back-reference lengths
+/// (`-w 8 -l 4`) when built with `Default::default()`
+pub type HeatshrinkDecoderW8L4 = HeatshrinkDecoder<256>;
+
+#[cfg(feature = "w10l4")]
+/// Decoder using a 1024 bytes window and 4 bits back-reference lengths
+/// (`-w 10 -l 4`) when built with `Default::default()`
+pub type HeatshrinkDecoderW10L4 = HeatshrinkDecoder<1024>;
+
+#[cfg(feature = "w11l4")]
+/// Decoder using a 2048 bytes window and 4 bits back-reference lengths
+/// (`-w 11 -l 4`) when built with `Default::default()`
+pub type HeatshrinkDecoderW11L4 = HeatshrinkDecoder<2048>;
+
+/// Memory footprint in bytes of a decoder with the default window capacity
+/// (see `HeatshrinkDecoder::RAM` for other capacities)
+pub const DECODER_RAM: usize = HeatshrinkDecoder::<HEATSHRINK_WINDOW_SIZE>::RAM;
+
 /// uncompress the src buffer to the destination buffer
 pub fn decode<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
-    let mut total_input_size = 0;
-    let mut total_output_size = 0;
+    let mut dec: HeatshrinkDecoder = Default::default();
+
+    decode_with(&mut dec, src, dst)
+}
 
+#[cfg(feature = "heapless")]
+/// uncompress the src buffer to a `heapless::Vec` of capacity N.
+///
+/// Fail with `OutputFull` if the uncompressed data doesn't fit in N bytes.
+pub fn decode_to_heapless<const N: usize>(src: &[u8]) -> Result<heapless::Vec<u8, N>, HSError> {
     let mut dec: HeatshrinkDecoder = Default::default();
+    let mut dst = heapless::Vec::new();
+    dst.resize(N, 0).map_err(|_| HSError::Internal)?;
+    let (input_size, output_size) = decode_step(&mut dec, src, &mut dst)?;
+    dst.truncate(output_size);
+
+    // the data may fill the vector exactly, check nothing is left
+    let rest = src.get(input_size..).ok_or(HSError::Internal)?;
+    if decode_step(&mut dec, rest, &mut [0])?.1 > 0 {
+        return Err(HSError::OutputFull { needed_hint: None });
+    }
+    match dec.finish() {
+        HSfinishRes::FinishDone => Ok(dst),
+        HSfinishRes::FinishMore => Err(HSError::Truncated),
+    }
+}
+
+/// uncompress the src buffer to the destination buffer using the provided
+/// instance (and therefore its window and lookahead parameters)
+pub fn decode_with<'a, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], HSError> {
+    let mut total_input_size = 0;
+    let mut total_output_size = 0;
 
     while total_input_size < src.len() {
         // Fill the input buffer from the src buffer
-        match dec.sink(&src[total_input_size..]) {
+        match dec.sink(src.get(total_input_size..).ok_or(HSError::Internal)?) {
             (HSsinkRes::SinkOK, segment_input_size) => {
                 total_input_size += segment_input_size;
             }
@@ -54,12 +321,12 @@ pub fn decode<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
         }
 
         if total_output_size == dst.len() {
-            return Err(HSError::OutputFull);
+            return Err(HSError::OutputFull { needed_hint: None });
         } else {
             // process the current input buffer
-            match dec.poll(&mut dst[total_output_size..]) {
+            match dec.poll(dst.get_mut(total_output_size..).ok_or(HSError::Internal)?) {
                 (HSpollRes::PollMore, _) => {
-                    return Err(HSError::OutputFull);
+                    return Err(HSError::OutputFull { needed_hint: None });
                 }
                 (HSpollRes::PollEmpty, segment_output_size) => {
                     total_output_size += segment_output_size;
@@ -67,6 +334,12 @@ pub fn decode<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
                 (HSpollRes::PollErrorMisuse, _) => {
                     return Err(HSError::Internal);
                 }
+                (HSpollRes::PollErrorLimit, _) => {
+                    return Err(HSError::OutputLimit);
+                }
+                (HSpollRes::PollErrorBackref, _) => {
+                    return Err(HSError::InvalidBackref);
+                }
             }
 
             // if all the src buffer is processed, finish the uncompress stream
@@ -74,56 +347,635 @@ pub fn decode<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
                 match dec.finish() {
                     HSfinishRes::FinishDone => {}
                     HSfinishRes::FinishMore => {
-                        return Err(HSError::OutputFull);
+                        return Err(HSError::Truncated);
                     }
                 }
             }
         }
     }
 
-    Ok(&dst[..total_output_size])
+    dst.get(..total_output_size).ok_or(HSError::Internal)
+}
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> fmt::Debug
+    for HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Print the state and counters of the instance along with a preview of
+    /// the input waiting to be uncompressed. Use `dump` to print the buffers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeatshrinkDecoder")
+            .field("state", &self.state)
+            .field("params", &self.params())
+            .field("input_size", &self.input_size)
+            .field("input_index", &self.input_index)
+            .field("output_count", &self.output_count)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .field(
+                "input",
+                &HexPreview(
+                    self.input_buffer
+                        .get(self.input_index.min(self.input_size)..self.input_size)
+                        .unwrap_or_default(),
+                ),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> defmt::Format
+    for HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Log the state and counters of the instance
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "HeatshrinkDecoder {{ state: {}, params: {}, input_size: {=usize}, input_index: {=usize}, output_count: {=u16}, total_in: {=u64}, total_out: {=u64} }}",
+            self.state,
+            self.params(),
+            self.input_size,
+            self.input_index,
+            self.output_count,
+            self.total_in,
+            self.total_out,
+        )
+    }
 }
 
-impl Default for HeatshrinkDecoder {
+/// Debug view of all the fields and buffers of a decoder
+struct Dump<'a, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    &'a HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+);
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> fmt::Debug
+    for Dump<'_, WINDOW_SIZE, INPUT_SIZE>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dec = self.0;
+
+        f.debug_struct("HeatshrinkDecoder")
+            .field("input_size", &dec.input_size)
+            .field("input_index", &dec.input_index)
+            .field("output_index", &dec.output_index)
+            .field("head_index", &dec.head_index)
+            .field("total_in", &dec.total_in)
+            .field("total_out", &dec.total_out)
+            .field("output_limit", &dec.output_limit)
+            .field("strict", &dec.strict)
+            .field("finished", &dec.finished)
+            .field("output_count", &dec.output_count)
+            .field("current_byte", &dec.current_byte)
+            .field("bit_index", &dec.bit_index)
+            .field("window_bits", &dec.window_bits)
+            .field("lookahead_bits", &dec.lookahead_bits)
+            .field("state", &dec.state)
+            .field("input_buffer", &dec.input_buffer)
+            .field("output_buffer", &dec.output_buffer)
+            .finish()
+    }
+}
+
+/// uncompress as much as possible of the src buffer to the destination
+/// buffer using the provided instance.
+///
+/// Return the number of bytes consumed from src and written to dst, so that
+/// the decoder can be fed from the read grant of a lock-free queue (e.g. a
+/// `bbqueue` consumer filled by a DMA UART) and write to the write grant of
+/// another one: release the consumed bytes and commit the written ones, then
+/// call this function again with the next grants. The grants are passed as
+/// slices (bbqueue's `GrantR` and `GrantW` deref to them), the crate doesn't
+/// depend on any queue: the `heatshrink-bbqueue` crate does the cycle with
+/// bbqueue's `Consumer` and `Producer`. Once all the compressed data was sunk,
+/// `HeatshrinkDecoder::finish` checks the stream is complete.
+pub fn decode_step<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    dst: &mut [u8],
+) -> Result<(usize, usize), HSError> {
+    let mut total_input_size = 0;
+    let mut total_output_size = 0;
+
+    while total_output_size < dst.len() {
+        // process the data sunk so far
+        match dec.poll(dst.get_mut(total_output_size..).ok_or(HSError::Internal)?) {
+            (HSpollRes::PollMore, segment_output_size) => {
+                total_output_size += segment_output_size;
+                break;
+            }
+            (HSpollRes::PollEmpty, segment_output_size) => {
+                total_output_size += segment_output_size;
+            }
+            (HSpollRes::PollErrorMisuse, _) => {
+                return Err(HSError::Internal);
+            }
+            (HSpollRes::PollErrorLimit, _) => {
+                return Err(HSError::OutputLimit);
+            }
+            (HSpollRes::PollErrorBackref, _) => {
+                return Err(HSError::InvalidBackref);
+            }
+        }
+
+        if total_input_size == src.len() {
+            break;
+        }
+        match dec.sink(src.get(total_input_size..).ok_or(HSError::Internal)?) {
+            (HSsinkRes::SinkOK, segment_input_size) => {
+                total_input_size += segment_input_size;
+            }
+            (HSsinkRes::SinkFull, _) => {}
+            (HSsinkRes::SinkErrorMisuse, _) => {
+                return Err(HSError::Internal);
+            }
+        }
+    }
+
+    Ok((total_input_size, total_output_size))
+}
+
+/// uncompress the src buffer to the destination buffer, checking the
+/// uncompressed data is exactly expected_len bytes long
+pub fn decode_exact<'a>(
+    src: &[u8],
+    dst: &'a mut [u8],
+    expected_len: usize,
+) -> Result<&'a [u8], HSError> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+
+    decode_exact_with(&mut dec, src, dst, expected_len)
+}
+
+/// uncompress the src buffer to the destination buffer using the provided
+/// instance, checking the uncompressed data is exactly expected_len bytes
+/// long.
+///
+/// Fail with `TooShort` or `TooLong` when the uncompressed data has another
+/// length, and with `OutputFull` when dst can not hold expected_len bytes.
+pub fn decode_exact_with<'a, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+    expected_len: usize,
+) -> Result<&'a [u8], HSError> {
+    if dst.len() < expected_len {
+        return Err(HSError::OutputFull {
+            needed_hint: Some(expected_len),
+        });
+    }
+
+    let dst = dst.get_mut(..expected_len).ok_or(HSError::Internal)?;
+    let mut total_input_size = 0;
+    let mut total_output_size = 0;
+
+    loop {
+        // process the current input buffer
+        let output = if total_output_size < expected_len {
+            dst.get_mut(total_output_size..).ok_or(HSError::Internal)?
+        } else {
+            // any more data is beyond the expected length
+            &mut [0u8; 1][..]
+        };
+        let res = match dec.poll(output) {
+            (HSpollRes::PollErrorMisuse, _) => {
+                return Err(HSError::Internal);
+            }
+            (HSpollRes::PollErrorLimit, _) => {
+                return Err(HSError::OutputLimit);
+            }
+            (HSpollRes::PollErrorBackref, _) => {
+                return Err(HSError::InvalidBackref);
+            }
+            (res, segment_output_size) => {
+                if total_output_size == expected_len && segment_output_size > 0 {
+                    return Err(HSError::TooLong);
+                }
+                total_output_size += segment_output_size;
+                res
+            }
+        };
+
+        if total_input_size == src.len() && res == HSpollRes::PollEmpty {
+            break;
+        }
+
+        // Fill the input buffer from the src buffer
+        match dec.sink(src.get(total_input_size..).ok_or(HSError::Internal)?) {
+            (HSsinkRes::SinkOK, segment_input_size) => {
+                total_input_size += segment_input_size;
+            }
+            (HSsinkRes::SinkFull, _) => {}
+            (HSsinkRes::SinkErrorMisuse, _) => {
+                return Err(HSError::Internal);
+            }
+        }
+    }
+
+    match dec.finish() {
+        HSfinishRes::FinishMore => Err(HSError::Truncated),
+        HSfinishRes::FinishDone if total_output_size < expected_len => Err(HSError::TooShort),
+        HSfinishRes::FinishDone => Ok(dst),
+    }
+}
+
+/// Source of the compressed data read by the state machine
+trait Input<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> {
+    /// Number of bytes left to read
+    fn remaining(&self, dec: &HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> usize;
+
+    /// Read the next byte. Only called when some bytes are left.
+    fn next_byte(&mut self, dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> u8;
+}
+
+/// Input reading the data sunk into the input buffer of the instance
+struct BufferInput;
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Input<WINDOW_SIZE, INPUT_SIZE>
+    for BufferInput
+{
+    fn remaining(&self, dec: &HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> usize {
+        dec.input_size - dec.input_index
+    }
+
+    fn next_byte(&mut self, dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> u8 {
+        let byte = dec.input_buffer.get(dec.input_index).copied().unwrap_or(0);
+        dec.input_index += 1;
+
+        // if we reach the end of buffer, reset input_index and input_size
+        if dec.input_index == dec.input_size {
+            dec.input_index = 0;
+            dec.input_size = 0;
+            // Next call to poll will likely return None (depending on
+            // bit_index) and require a call to sink to continue.
+        }
+
+        byte
+    }
+}
+
+/// Input reading a slice borrowed from the caller
+struct SliceInput<'a> {
+    input: &'a [u8],
+    index: usize,
+}
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Input<WINDOW_SIZE, INPUT_SIZE>
+    for SliceInput<'_>
+{
+    fn remaining(&self, _dec: &HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> usize {
+        self.input.len() - self.index
+    }
+
+    fn next_byte(&mut self, _dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> u8 {
+        let byte = self.input.get(self.index).copied().unwrap_or(0);
+        self.index += 1;
+        byte
+    }
+}
+
+/// ByteSink wrapper enforcing the output limit of a decoder. One byte more
+/// than the limit can be taken, to detect that the limit is exceeded, but it
+/// is not pushed to the wrapped sink.
+struct LimitedSink<'a, S: ByteSink + ?Sized> {
+    sink: &'a mut S,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<S: ByteSink + ?Sized> ByteSink for LimitedSink<'_, S> {
+    fn push_byte(&mut self, byte: u8) {
+        if self.remaining == 0 {
+            self.exceeded = true;
+        } else {
+            self.remaining -= 1;
+            self.sink.push_byte(byte);
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        let (pushed, dropped) = bytes.split_at_checked(remaining).unwrap_or((bytes, &[]));
+        self.remaining -= pushed.len() as u64;
+        self.sink.push_bytes(pushed);
+        if !dropped.is_empty() {
+            self.exceeded = true;
+        }
+    }
+
+    fn remaining_capacity(&self) -> usize {
+        if self.exceeded {
+            0
+        } else {
+            let remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+            self.sink
+                .remaining_capacity()
+                .min(remaining.saturating_add(1))
+        }
+    }
+}
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Default
+    for HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Create an instance using the largest window fitting in `WINDOW_SIZE`
+    /// and 4 bits back-reference lengths (or less for tiny windows).
+    ///
+    /// Fails to build if `WINDOW_SIZE` is smaller than the smallest
+    /// heatshrink window.
     fn default() -> Self {
-        HeatshrinkDecoder::new()
+        HeatshrinkDecoder::from_params(const { Params::default_for_window_size(WINDOW_SIZE) })
     }
 }
 
 impl HeatshrinkDecoder {
     /// Create a new decoder instance
-    pub fn new() -> Self {
+    ///
+    /// Being a `const fn`, it can initialize a `static` (e.g. a
+    /// `static_cell::ConstStaticCell`), so that the state is allocated at
+    /// link time instead of on the stack.
+    pub const fn new() -> Self {
+        HeatshrinkDecoder::from_params(
+            const { Params::default_for_window_size(HEATSHRINK_WINDOW_SIZE) },
+        )
+    }
+
+    /// Create a new decoder instance on the heap
+    #[cfg(feature = "alloc")]
+    pub fn boxed() -> Box<Self> {
+        Box::default()
+    }
+}
+
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE> {
+    /// Memory footprint in bytes of an instance with this window capacity
+    pub const RAM: usize = core::mem::size_of::<Self>();
+
+    /// Largest size in bytes of the state saved by `save_state` with this
+    /// window capacity and input buffer size, to size a static buffer
+    pub const STATE_MAX_SIZE: usize = STATE_HEADER_SIZE + INPUT_SIZE + WINDOW_SIZE;
+
+    /// Create a new decoder instance using the provided parameters, also
+    /// usable to initialize a `static`. They must match the ones the stream
+    /// was compressed with.
+    ///
+    /// Fails if the window does not fit in `WINDOW_SIZE`.
+    pub const fn with_params(params: Params) -> Result<Self, ParamError> {
+        match params.check_window_size(WINDOW_SIZE) {
+            Ok(()) => Ok(HeatshrinkDecoder::from_params(params)),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Create a new instance using parameters whose window fits in
+    /// `WINDOW_SIZE`
+    const fn from_params(params: Params) -> Self {
+        const { assert!(INPUT_SIZE > 0, "the input buffer can not be empty") };
+
         HeatshrinkDecoder {
             input_size: 0,
             input_index: 0,
             output_count: 0,
             output_index: 0,
             head_index: 0,
+            total_in: 0,
+            total_out: 0,
+            output_limit: u64::MAX,
+            strict: false,
+            finished: false,
             current_byte: 0,
             bit_index: 0,
+            window_bits: params.window_bits(),
+            lookahead_bits: params.lookahead_bits(),
             state: HSDstate::TagBit,
-            input_buffer: [0; HEATSHRINK_INPUT_BUFFER_SIZE],
-            output_buffer: [0; 1 << HEATSHRINK_WINDOWS_BITS],
+            input_buffer: [0; INPUT_SIZE],
+            output_buffer: [0; WINDOW_SIZE],
         }
     }
 
-    /// Reset the current decoder instance
-    pub fn reset(&mut self) {
+    /// Create a new decoder instance on the heap using the provided
+    /// parameters. With a `WINDOW_SIZE` of `1 << HEATSHRINK_MAX_WINDOWS_BITS`
+    /// any window size can be selected at runtime.
+    ///
+    /// Fails if the window does not fit in `WINDOW_SIZE`.
+    #[cfg(feature = "alloc")]
+    pub fn boxed_with_params(params: Params) -> Result<Box<Self>, ParamError> {
+        HeatshrinkDecoder::with_params(params).map(Box::new)
+    }
+
+    /// Window and lookahead sizes used by this instance
+    pub fn params(&self) -> Params {
+        Params {
+            window_bits: self.window_bits,
+            lookahead_bits: self.lookahead_bits,
+        }
+    }
+
+    /// Debug view of the instance printing all its fields and buffers, where
+    /// its `Debug` implementation only prints a summary
+    pub fn dump(&self) -> impl fmt::Debug + '_ {
+        Dump(self)
+    }
+
+    /// Abort the current stream (e.g. when a transfer is cancelled),
+    /// discarding any pending input and output.
+    ///
+    /// The instance is then ready for a new stream exactly like after
+    /// `reset`: it can be primed, sunk, polled and finished. The buffers are
+    /// not cleared, as the window is never read before the start of a
+    /// stream (back-references there yield zeros), so this takes a constant
+    /// time.
+    pub fn abort(&mut self) {
         self.input_size = 0;
         self.input_index = 0;
         self.output_count = 0;
         self.output_index = 0;
         self.head_index = 0;
+        self.total_in = 0;
+        self.total_out = 0;
         self.current_byte = 0;
         self.bit_index = 0;
+        self.finished = false;
         self.state = HSDstate::TagBit;
-        // memset self.buffer to 0
+    }
+
+    /// Reset the current decoder instance. Like `abort`, the buffers are not
+    /// cleared, so reusing an instance per message costs no memset.
+    pub fn reset(&mut self) {
+        self.abort();
+    }
+
+    /// Reset the current decoder instance and clear its input buffer and
+    /// window, for users who must not leave the data of the previous stream
+    /// in memory (e.g. key material or personal data).
+    pub fn reset_and_zeroize(&mut self) {
+        self.abort();
         self.input_buffer.fill(0);
         self.output_buffer.fill(0);
     }
 
+    /// Preload the window with the last bytes of dictionary, so that streams
+    /// compressed by an encoder primed with the same dictionary can be
+    /// uncompressed.
+    ///
+    /// Only possible before any data is sunk. Return the number of bytes of
+    /// dictionary loaded in the window.
+    pub fn prime(&mut self, dictionary: &[u8]) -> (HSsinkRes, usize) {
+        if self.total_in != 0 || self.head_index != 0 {
+            return (HSsinkRes::SinkErrorMisuse, 0);
+        }
+
+        let (_, dictionary) = dictionary
+            .split_at_checked(dictionary.len().saturating_sub(self.get_window_size()))
+            .unwrap_or_default();
+        match self.output_buffer.get_mut(..dictionary.len()) {
+            Some(window) => window.copy_from_slice(dictionary),
+            None => return (HSsinkRes::SinkErrorMisuse, 0),
+        }
+        self.head_index = dictionary.len();
+
+        (HSsinkRes::SinkOK, dictionary.len())
+    }
+
+    /// Size in bytes of the state saved by `save_state` for this instance
+    pub fn state_size(&self) -> usize {
+        STATE_HEADER_SIZE + self.pending_input().len() + self.get_window_size()
+    }
+
+    /// Input sunk but not processed yet
+    fn pending_input(&self) -> &[u8] {
+        self.input_buffer
+            .get(self.input_index..self.input_size)
+            .unwrap_or_default()
+    }
+
+    /// Save the state of the instance, e.g. to flash, so that an
+    /// in-progress decompression (such as an OTA image download) can be
+    /// resumed after a reboot by `restore_state`. Return the number of bytes
+    /// written.
+    ///
+    /// The layout is stable across versions of this library: a tag byte
+    /// (`D`), a version byte (1), the parameters, the state machine
+    /// registers, bit position and counters (little-endian), the input sunk
+    /// but not processed yet and the window.
+    ///
+    /// Fail with `OutputFull` if state is smaller than `state_size`.
+    pub fn save_state(&self, state: &mut [u8]) -> Result<usize, HSError> {
+        let size = self.state_size();
+        if state.len() < size {
+            return Err(HSError::OutputFull {
+                needed_hint: Some(size),
+            });
+        }
+
+        let mut writer = StateWriter { buffer: state };
+        self.write_state_header(&mut writer);
+        writer.bytes(self.pending_input());
+        writer.bytes(self.saved_window()?);
+
+        Ok(size)
+    }
+
+    /// Write the part of the saved state before the pending input
+    fn write_state_header(&self, writer: &mut StateWriter) {
+        let mut flags = 0;
+        if self.strict {
+            flags |= STATE_FLAG_STRICT;
+        }
+        if self.finished {
+            flags |= STATE_FLAG_FINISHED;
+        }
+
+        writer.u8(STATE_MAGIC);
+        writer.u8(STATE_VERSION);
+        writer.u8(self.window_bits);
+        writer.u8(self.lookahead_bits);
+        writer.u8(self.state as u8);
+        writer.u8(flags);
+        writer.u8(self.current_byte);
+        writer.u8(self.bit_index);
+        writer.u16(self.output_count);
+        writer.u32(self.pending_input().len() as u32);
+        writer.u32(self.output_index as u32);
+        writer.u64(self.head_index as u64);
+        writer.u64(self.total_in);
+        writer.u64(self.total_out);
+        writer.u64(self.output_limit);
+        writer.u64(0); // reserved
+    }
+
+    /// Window, saved after the pending input
+    fn saved_window(&self) -> Result<&[u8], HSError> {
+        self.output_buffer
+            .get(..self.get_window_size())
+            .ok_or(HSError::Internal)
+    }
+
+    /// Restore a state saved by `save_state`, possibly by another instance,
+    /// to resume its decompression.
+    ///
+    /// Fail with `InvalidState`, leaving the instance untouched, if the
+    /// state is truncated, of another version, inconsistent or if its
+    /// window does not fit in `WINDOW_SIZE` or its pending input in
+    /// `INPUT_SIZE`.
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<(), HSError> {
+        let mut reader = StateReader { buffer: state };
+        let saved = SavedState::parse(reader.bytes(STATE_HEADER_SIZE)?, WINDOW_SIZE, INPUT_SIZE)?;
+        let pending_input = reader.bytes(saved.pending_size)?;
+        let window = reader.bytes(saved.params.window_size())?;
+
+        self.input_buffer
+            .get_mut(..pending_input.len())
+            .ok_or(HSError::InvalidState)?
+            .copy_from_slice(pending_input);
+        self.output_buffer
+            .get_mut(..window.len())
+            .ok_or(HSError::InvalidState)?
+            .copy_from_slice(window);
+        self.apply_state(&saved);
+
+        Ok(())
+    }
+
+    /// Apply the registers of a saved state once its pending input and its
+    /// window are restored
+    fn apply_state(&mut self, saved: &SavedState) {
+        self.window_bits = saved.params.window_bits();
+        self.lookahead_bits = saved.params.lookahead_bits();
+        self.state = saved.state;
+        self.strict = saved.flags & STATE_FLAG_STRICT != 0;
+        self.finished = saved.flags & STATE_FLAG_FINISHED != 0;
+        self.current_byte = saved.current_byte;
+        self.bit_index = saved.bit_index;
+        self.output_count = saved.output_count;
+        self.input_index = 0;
+        self.input_size = saved.pending_size;
+        self.output_index = saved.output_index;
+        self.head_index = saved.head_index;
+        self.total_in = saved.total_in;
+        self.total_out = saved.total_out;
+        self.output_limit = saved.output_limit;
+    }
+
+    /// Number of bytes the next `sink` call can accept, 0 when the instance
+    /// must be polled (or reset once finished) before sinking more, so that
+    /// producers (e.g. DMA transfers) can be sized without trial sinks.
+    pub fn sink_capacity(&self) -> usize {
+        if self.finished {
+            0
+        } else {
+            self.input_buffer.len() - self.input_size
+        }
+    }
+
     /// Add an input buffer to be processed/uncompressed
+    ///
+    /// Return SinkErrorMisuse once `finish` reported the end of the stream:
+    /// the instance must be reset (or aborted) before sinking a new stream.
     pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        if self.finished {
+            return (HSsinkRes::SinkErrorMisuse, 0);
+        }
+
         let remaining_size = self.input_buffer.len() - self.input_size;
 
         if remaining_size == 0 {
@@ -137,51 +989,201 @@ impl HeatshrinkDecoder {
         };
 
         // memcpy content of input_buffer into self.input_buffer.
-        self.input_buffer[self.input_size..(self.input_size + copy_size)]
-            .copy_from_slice(&input_buffer[0..copy_size]);
-        self.input_size += copy_size;
-
-        if self.bit_index == 0 {
-            self.current_byte = self.input_buffer[self.input_index];
-            self.input_index += 1;
-            self.bit_index = 8;
+        let free = self
+            .input_buffer
+            .get_mut(self.input_size..self.input_size + copy_size);
+        match (free, input_buffer.get(..copy_size)) {
+            (Some(free), Some(input)) => free.copy_from_slice(input),
+            _ => return (HSsinkRes::SinkErrorMisuse, 0),
         }
+        self.input_size += copy_size;
+        self.total_in += copy_size as u64;
 
         (HSsinkRes::SinkOK, copy_size)
     }
 
     /// function to process the input/internal buffer and put the uncompressed
     /// stream in the provided buffer.
-    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
-        if output_buffer.is_empty() {
+    pub fn poll(&mut self, mut output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        self.poll_sink(&mut output_buffer)
+    }
+
+    /// Add a single byte to be processed. Equivalent to `sink` with a
+    /// one byte buffer, without the slice bookkeeping.
+    pub fn sink_byte(&mut self, byte: u8) -> HSsinkRes {
+        self.sink(&[byte]).0
+    }
+
+    /// Get the next byte of the uncompressed stream, if one is available.
+    ///
+    /// Each call does a bounded amount of work (O(1)).
+    pub fn poll_byte(&mut self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        match self.poll(&mut byte) {
+            (HSpollRes::PollErrorMisuse, _) | (_, 0) => None,
+            (_, _) => Some(byte[0]),
+        }
+    }
+
+    /// function to process the input/internal buffer and push the
+    /// uncompressed stream to the provided sink.
+    pub fn poll_sink<S: ByteSink + ?Sized>(&mut self, sink: &mut S) -> (HSpollRes, usize) {
+        self.poll_steps(&mut BufferInput, sink, usize::MAX)
+    }
+
+    /// function to process the input/internal buffer and put the
+    /// uncompressed stream in the provided buffer, doing at most max_steps
+    /// state transitions.
+    ///
+    /// Both the output (by the buffer size) and the work done are bounded,
+    /// so that the instance can be driven from a periodic loop. PollMore is
+    /// returned when the budget is used up, as more work may be pending.
+    pub fn poll_with_budget(
+        &mut self,
+        mut output_buffer: &mut [u8],
+        max_steps: usize,
+    ) -> (HSpollRes, usize) {
+        self.poll_steps(&mut BufferInput, &mut output_buffer, max_steps)
+    }
+
+    /// function to uncompress the input slice straight into the provided
+    /// buffer, without copying it to the internal input buffer first (as
+    /// `sink` does).
+    ///
+    /// Return the poll result along with the number of bytes consumed from
+    /// input and written to output_buffer. PollEmpty is returned once all
+    /// the input is consumed, PollMore when output_buffer is full: call
+    /// this function again with the rest of the input. Any data sunk with
+    /// `sink` must have been polled first.
+    pub fn poll_from(
+        &mut self,
+        input: &[u8],
+        mut output_buffer: &mut [u8],
+    ) -> (HSpollRes, usize, usize) {
+        if self.input_size != 0 || self.finished {
+            return (HSpollRes::PollErrorMisuse, 0, 0);
+        }
+
+        let mut slice_input = SliceInput { input, index: 0 };
+        let (res, output_size) = self.poll_steps(&mut slice_input, &mut output_buffer, usize::MAX);
+        self.total_in += slice_input.index as u64;
+
+        (res, slice_input.index, output_size)
+    }
+
+    fn poll_steps<I: Input<WINDOW_SIZE, INPUT_SIZE>, S: ByteSink + ?Sized>(
+        &mut self,
+        input: &mut I,
+        sink: &mut S,
+        max_steps: usize,
+    ) -> (HSpollRes, usize) {
+        let mut limited_sink = LimitedSink {
+            sink,
+            remaining: self.output_limit - self.total_out,
+            exceeded: false,
+        };
+        let (res, output_size) = self.poll_output(input, &mut limited_sink, max_steps);
+
+        if limited_sink.exceeded {
+            // the byte beyond the limit was not pushed to the sink
+            self.total_out += output_size as u64 - 1;
+            (HSpollRes::PollErrorLimit, output_size - 1)
+        } else {
+            self.total_out += output_size as u64;
+            (res, output_size)
+        }
+    }
+
+    /// Limit the number of bytes this instance can produce, so that a small
+    /// stream can not expand to an unbounded amount of data. Once the limit
+    /// is reached, `poll` returns `PollErrorLimit` if more data would be
+    /// produced and the instance must be reset.
+    ///
+    /// The limit covers all the data polled since the instance was created
+    /// or reset, and is kept by `reset`.
+    pub fn set_output_limit(&mut self, output_limit: usize) {
+        self.output_limit = (output_limit as u64).max(self.total_out);
+    }
+
+    /// Enable or disable the strict mode. In strict mode, a back-reference
+    /// pointing before the start of the stream (or of the dictionary the
+    /// instance was primed with) makes `poll` return `PollErrorBackref`
+    /// instead of yielding zeros, and the instance must be reset.
+    ///
+    /// Disabled by default, as encoders may reference the zero-filled window
+    /// when the stream starts with zero bytes (this crate's encoder does).
+    /// Only enable it when the encoder is known not to do so.
+    ///
+    /// The mode is kept by `reset`.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Number of compressed bytes sunk since the instance was created or
+    /// reset
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of bytes polled since the instance was created or reset
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    fn poll_output<I: Input<WINDOW_SIZE, INPUT_SIZE>, S: ByteSink + ?Sized>(
+        &mut self,
+        input: &mut I,
+        sink: &mut S,
+        mut max_steps: usize,
+    ) -> (HSpollRes, usize) {
+        if sink.remaining_capacity() == 0 {
             (HSpollRes::PollErrorMisuse, 0)
         } else {
             let mut output_size: usize = 0;
 
-            let mut output_info = OutputInfo::new(output_buffer, &mut output_size);
+            let mut output_info = OutputInfo::new(sink, &mut output_size);
 
             loop {
                 let previous_state = self.state;
 
+                if max_steps == 0 {
+                    return (HSpollRes::PollMore, output_size);
+                }
+                max_steps -= 1;
+
                 match previous_state {
-                    HSDstate::TagBit => {
-                        self.state = self.st_tag_bit();
-                    }
+                    HSDstate::TagBit => match self.st_tag_bit(input) {
+                        Ok(state) => self.state = state,
+                        Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                    },
                     HSDstate::YieldLiteral => {
-                        self.state = self.st_yield_literal(&mut output_info);
-                    }
-                    HSDstate::BackrefIndexMsb => {
-                        self.state = self.st_backref_index_msb();
-                    }
-                    HSDstate::BackrefIndexLsb => {
-                        self.state = self.st_backref_index_lsb();
-                    }
-                    HSDstate::BackrefCountLsb => {
-                        self.state = self.st_backref_count_lsb();
+                        match self.st_yield_literal(input, &mut output_info) {
+                            Ok(state) => self.state = state,
+                            Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                        }
                     }
+                    HSDstate::BackrefIndexMsb => match self.st_backref_index_msb(input) {
+                        Ok(state) => self.state = state,
+                        Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                    },
+                    HSDstate::BackrefIndexLsb => match self.st_backref_index_lsb(input) {
+                        Ok(state) => self.state = state,
+                        Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                    },
+                    HSDstate::BackrefCountMsb => match self.st_backref_count_msb(input) {
+                        Ok(state) => self.state = state,
+                        Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                    },
+                    HSDstate::BackrefCountLsb => match self.st_backref_count_lsb(input) {
+                        Ok(state) => self.state = state,
+                        Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                    },
                     HSDstate::YieldBackref => {
                         self.state = self.st_yield_backref(&mut output_info);
                     }
+                    HSDstate::InvalidBackref => {
+                        return (HSpollRes::PollErrorBackref, output_size);
+                    }
                 }
 
                 // If the current state cannot advance, check if input or
@@ -197,96 +1199,131 @@ impl HeatshrinkDecoder {
         }
     }
 
-    fn st_tag_bit(&mut self) -> HSDstate {
-        match self.get_bits(1) {
-            None => HSDstate::TagBit,
+    fn st_tag_bit<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<HSDstate, HSError> {
+        match self.get_bits(input, 1)? {
+            None => Ok(HSDstate::TagBit),
             Some(0) => {
                 self.output_index = 0;
-                HSDstate::BackrefIndexLsb
+                if self.window_bits > 8 {
+                    Ok(HSDstate::BackrefIndexMsb)
+                } else {
+                    Ok(HSDstate::BackrefIndexLsb)
+                }
             }
-            Some(_) => HSDstate::YieldLiteral,
+            Some(_) => Ok(HSDstate::YieldLiteral),
         }
     }
 
-    fn st_yield_literal(&mut self, output_info: &mut OutputInfo) -> HSDstate {
+    fn st_yield_literal<I: Input<WINDOW_SIZE, INPUT_SIZE>, S: ByteSink + ?Sized>(
+        &mut self,
+        input: &mut I,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<HSDstate, HSError> {
         // Emit a repeated section from the window buffer, and add it (again)
         // to the window buffer. (Note that the repetition can include itself)
         if output_info.can_take_byte() {
-            match self.get_bits(8) {
-                None => HSDstate::YieldLiteral, // input_buffer is consumed
+            match self.get_bits(input, 8)? {
+                None => Ok(HSDstate::YieldLiteral), // input_buffer is consumed
                 Some(x) => {
                     let c: u8 = x;
-                    let len = self.output_buffer.len();
-                    self.output_buffer[self.head_index % len] = c;
-                    self.head_index += 1;
+                    let len = self.get_window_size();
+                    if let Some(slot) = self.output_buffer.get_mut(self.head_index % len) {
+                        *slot = c;
+                    }
+                    self.head_index = advance_head_index(self.head_index, 1, len);
                     output_info.push_byte(c);
-                    HSDstate::TagBit
+                    Ok(HSDstate::TagBit)
                 }
             }
         } else {
-            HSDstate::YieldLiteral
+            Ok(HSDstate::YieldLiteral)
         }
     }
 
-    fn st_backref_index_msb(&mut self) -> HSDstate {
-        match self.get_bits(0) {
-            None => HSDstate::BackrefIndexMsb,
+    fn st_backref_index_msb<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<HSDstate, HSError> {
+        match self.get_bits(input, self.window_bits - 8)? {
+            None => Ok(HSDstate::BackrefIndexMsb),
             Some(x) => {
                 self.output_index = (x as usize) << 8;
-                HSDstate::BackrefIndexLsb
+                Ok(HSDstate::BackrefIndexLsb)
             }
         }
     }
 
-    fn st_backref_index_lsb(&mut self) -> HSDstate {
-        match self.get_bits(8) {
-            None => HSDstate::BackrefIndexLsb,
+    fn st_backref_index_lsb<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<HSDstate, HSError> {
+        match self.get_bits(input, self.window_bits.min(8))? {
+            None => Ok(HSDstate::BackrefIndexLsb),
             Some(x) => {
                 self.output_index |= x as usize;
                 self.output_index += 1;
                 self.output_count = 0;
-                HSDstate::BackrefCountLsb
+                if self.strict && self.output_index > self.head_index {
+                    Ok(HSDstate::InvalidBackref)
+                } else if self.lookahead_bits > 8 {
+                    Ok(HSDstate::BackrefCountMsb)
+                } else {
+                    Ok(HSDstate::BackrefCountLsb)
+                }
+            }
+        }
+    }
+
+    fn st_backref_count_msb<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<HSDstate, HSError> {
+        match self.get_bits(input, self.lookahead_bits - 8)? {
+            None => Ok(HSDstate::BackrefCountMsb),
+            Some(x) => {
+                self.output_count = (x as u16) << 8;
+                Ok(HSDstate::BackrefCountLsb)
             }
         }
     }
 
-    fn st_backref_count_lsb(&mut self) -> HSDstate {
-        match self.get_bits(HEATSHRINK_LOOKAHEAD_BITS) {
-            None => HSDstate::BackrefCountLsb,
+    fn st_backref_count_lsb<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+    ) -> Result<HSDstate, HSError> {
+        match self.get_bits(input, self.lookahead_bits.min(8))? {
+            None => Ok(HSDstate::BackrefCountLsb),
             Some(x) => {
                 self.output_count |= x as u16;
                 self.output_count += 1;
-                HSDstate::YieldBackref
+                Ok(HSDstate::YieldBackref)
             }
         }
     }
 
-    fn st_yield_backref(&mut self, output_info: &mut OutputInfo) -> HSDstate {
+    fn st_yield_backref<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> HSDstate {
         if output_info.can_take_byte() {
-            let len = self.output_buffer.len();
-            let mut head_index = self.head_index;
-            let output_index = self.output_index;
-
-            let count = if output_info.remaining_free_size() > usize::from(self.output_count) {
-                usize::from(self.output_count)
-            } else {
-                output_info.remaining_free_size()
-            };
-
-            let index_limit = head_index + count;
+            let len = self.get_window_size();
+            let count = usize::from(self.output_count).min(output_info.remaining_free_size());
+            let mut copied = 0;
 
-            while head_index < index_limit {
-                let c = if output_index > head_index {
-                    0
-                } else {
-                    self.output_buffer[(head_index - output_index) % len]
-                };
-                output_info.push_byte(c);
-                self.output_buffer[head_index % len] = c;
-                head_index += 1;
+            // copy the back-reference by runs contiguous in the window
+            while copied < count {
+                let write = self.head_index % len;
+                let run = self.copy_backref_run(write, count - copied);
+                if let Some(bytes) = self.output_buffer.get(write..write + run) {
+                    output_info.push_bytes(bytes);
+                }
+                self.head_index = advance_head_index(self.head_index, run, len);
+                copied += run;
             }
 
-            self.head_index = head_index;
             self.output_count -= count as u16;
 
             if self.output_count == 0 {
@@ -296,16 +1333,72 @@ impl HeatshrinkDecoder {
         HSDstate::YieldBackref
     }
 
-    /// Get the next COUNT bits from the input buffer, saving incremental
-    /// progress. Returns None on end of input.
-    fn get_bits(&mut self, count: u8) -> Option<u8> {
-        assert!(count <= 8);
+    /// Copy the next run of at most remaining bytes of the current
+    /// back-reference to write in the window. Return the length of the run
+    /// (at least 1), which doesn't wrap around the window.
+    fn copy_backref_run(&mut self, write: usize, remaining: usize) -> usize {
+        let len = self.get_window_size();
+        let distance = self.output_index.max(1);
+        let window = self.output_buffer.get_mut(..len).unwrap_or_default();
+        let run = remaining.min(len - write).max(1);
+
+        if distance > self.head_index {
+            // before the start of the stream, the window reads as zeros
+            let run = run.min(distance - self.head_index);
+            if let Some(bytes) = window.get_mut(write..write + run) {
+                bytes.fill(0);
+            }
+            run
+        } else if write < distance {
+            // the source wraps around the end of the window, up to write
+            let read = write + len - distance;
+            if read == write {
+                // a whole window back: the bytes are already in place
+                return run;
+            }
+            let run = run.min(len - read).min(read - write);
+            copy_within_window(window, read, write, run);
+            run
+        } else if run <= distance {
+            copy_within_window(window, write - distance, write, run);
+            run
+        } else {
+            // the run repeats the bytes it writes with a period of distance:
+            // double the repeated bytes until the run is filled
+            if let Some(bytes) = window.get_mut(write - distance..write + run) {
+                let mut filled = distance;
+                while filled < bytes.len() {
+                    let count = filled.min(bytes.len() - filled);
+                    copy_within_window(bytes, 0, filled, count);
+                    filled += count;
+                }
+            }
+            run
+        }
+    }
+
+    fn get_window_size(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    /// Get the next COUNT (up to 8) bits from the input buffer, saving
+    /// incremental progress. Returns None on end of input, and an internal
+    /// error for a larger count.
+    fn get_bits<I: Input<WINDOW_SIZE, INPUT_SIZE>>(
+        &mut self,
+        input: &mut I,
+        count: u8,
+    ) -> Result<Option<u8>, HSError> {
+        if count > 8 || self.bit_index > 8 {
+            return Err(HSError::Internal);
+        }
 
         // If we aren't able to get COUNT bits, suspend immediately, because
         // we don't track how many bits of COUNT we've accumulated before
         // suspend.
-        if (((self.input_size - self.input_index) * 8) + self.bit_index as usize) < count as usize {
-            return None;
+        let available_bits = input.remaining(self).saturating_mul(8);
+        if available_bits.saturating_add(usize::from(self.bit_index)) < usize::from(count) {
+            return Ok(None);
         }
 
         // Get the current byte in the accumulator
@@ -323,17 +1416,15 @@ impl HeatshrinkDecoder {
             }
             Ordering::Equal => {
                 // We are consuming exactly the bits left in current_byte
-                if self.input_size == self.input_index {
-                    // we should load the next byte but the buffer is
+                if input.remaining(self) == 0 {
+                    // we should load the next byte but the input is
                     // consumed. So let's set the bit_index to 0 to show
                     // there is nothning left to consume.
                     self.bit_index = 0;
-                    // This will be set to 8 on next sink
+                    // The next byte will be loaded by the next get_bits
                 } else {
                     // load next byte.
-                    self.current_byte = self.input_buffer[self.input_index];
-                    // increase the consumed index
-                    self.input_index += 1;
+                    self.current_byte = input.next_byte(self);
                     // reset the bit index
                     self.bit_index = 8;
                 }
@@ -343,10 +1434,8 @@ impl HeatshrinkDecoder {
                 // we need to take some bits from next byte
                 // shift accumulator (8 bits) left
                 accumulator <<= 8;
-                // consume next byte from the input buffer
-                self.current_byte = self.input_buffer[self.input_index];
-                // increase the consumed index
-                self.input_index += 1;
+                // consume next byte from the input
+                self.current_byte = input.next_byte(self);
                 // add the byte read to the accumulator
                 accumulator += self.current_byte as u16;
                 // update bit_index
@@ -356,24 +1445,193 @@ impl HeatshrinkDecoder {
             }
         }
 
-        // if we reach the end of buffer, reset input_index and input_size
-        if self.input_index == self.input_size {
-            self.input_index = 0;
-            self.input_size = 0;
-            // Next call to poll will likely return None (depending on
-            // bit_index) and require a call to sink to continue.
-        }
+        Ok(Some(accumulator as u8))
+    }
+
+    /// Whether the bits read since the start of the current token, along
+    /// with the ones left in the current byte, can be the zero bits padding
+    /// the last byte of a stream. Such padding looks like the tag bit of a
+    /// back-reference followed by a zero index (and, with windows of less
+    /// than 7 bits, the start of a zero count).
+    fn is_padding(&self) -> bool {
+        let (token_bits, zero_bits) = match self.state {
+            HSDstate::TagBit => return true,
+            HSDstate::BackrefIndexMsb => (1, self.output_index == 0),
+            HSDstate::BackrefIndexLsb if self.window_bits > 8 => {
+                (1 + self.window_bits - 8, self.output_index == 0)
+            }
+            HSDstate::BackrefIndexLsb => (1, self.output_index == 0),
+            // the index was read, it is stored plus one
+            HSDstate::BackrefCountLsb => (
+                1 + self.window_bits,
+                self.output_index == 1 && self.output_count == 0,
+            ),
+            _ => return false,
+        };
+        let remaining_bits = u16::from(self.current_byte) % (1 << self.bit_index);
 
-        Some(accumulator as u8)
+        zero_bits && remaining_bits == 0 && token_bits + self.bit_index < 8
     }
 
     /// Finish the uncompress stream
-    pub fn finish(&self) -> HSfinishRes {
-        // Return Done if input_buffer is consumed. Else return More.
-        if self.input_size == 0 {
+    ///
+    /// Return More while some input is left to process or when the stream
+    /// ends in the middle of a literal or of a back-reference (it is
+    /// truncated).
+    ///
+    /// Once Done is returned, the stream is complete and sinking more data
+    /// is a misuse until the instance is reset.
+    pub fn finish(&mut self) -> HSfinishRes {
+        // Return Done if input_buffer is consumed at the end of a token.
+        // Else return More.
+        if self.is_done() {
+            self.finished = true;
             HSfinishRes::FinishDone
         } else {
             HSfinishRes::FinishMore
         }
     }
+
+    /// Whether all the input was consumed at the end of a token
+    fn is_done(&self) -> bool {
+        self.input_size == 0 && self.is_padding()
+    }
+
+    /// Check the stream ends here: all the data sunk was processed and only
+    /// the zero bits padding the last byte remain. Call it once all the
+    /// data expected was polled (e.g. when the header of a frame gives the
+    /// uncompressed length), to detect a desynchronized stream instead of
+    /// silently uncompressing junk.
+    ///
+    /// Fail with `TrailingData` when some more input or output is pending.
+    pub fn check_end(&self) -> Result<(), HSError> {
+        if self.is_done() {
+            Ok(())
+        } else {
+            Err(HSError::TrailingData)
+        }
+    }
+
+    /// Drop the zero bits padding the end of a prefix flushed by the encoder
+    /// (see `encoder::HeatshrinkEncoder::flush`), so that the data of the
+    /// following prefix is read from a byte boundary. Call it once all the
+    /// data of a prefix (e.g. a radio packet) was sunk and polled.
+    ///
+    /// The window is kept, as the following data may reference it. Fail
+    /// with `TrailingData`, without dropping anything, when some more input
+    /// or output is pending.
+    pub fn sync(&mut self) -> Result<(), HSError> {
+        self.check_end()?;
+        self.output_index = 0;
+        self.bit_index = 0;
+        self.state = HSDstate::TagBit;
+
+        Ok(())
+    }
+
+    /// Sink the whole input buffer, polling the uncompressed data as needed
+    /// and handing it to the output callback.
+    pub fn sink_all(
+        &mut self,
+        input_buffer: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), HSError> {
+        let mut input_size = 0;
+
+        while input_size < input_buffer.len() {
+            match self.sink(input_buffer.get(input_size..).ok_or(HSError::Internal)?) {
+                (HSsinkRes::SinkOK, segment_input_size) => {
+                    input_size += segment_input_size;
+                }
+                (HSsinkRes::SinkFull, _) => {}
+                (HSsinkRes::SinkErrorMisuse, _) => {
+                    return Err(HSError::Internal);
+                }
+            }
+            self.poll_all(output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Poll all the uncompressed data available, handing it to the output
+    /// callback.
+    pub fn poll_all(&mut self, output: &mut impl FnMut(&[u8])) -> Result<(), HSError> {
+        let mut output_buffer = [0u8; HEATSHRINK_POLL_BUFFER_SIZE];
+
+        loop {
+            match self.poll(&mut output_buffer) {
+                (HSpollRes::PollMore, output_size) => {
+                    output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                }
+                (HSpollRes::PollEmpty, output_size) => {
+                    if output_size > 0 {
+                        output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                    }
+                    return Ok(());
+                }
+                (HSpollRes::PollErrorMisuse, _) => {
+                    return Err(HSError::Internal);
+                }
+                (HSpollRes::PollErrorLimit, output_size) => {
+                    output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                    return Err(HSError::OutputLimit);
+                }
+                (HSpollRes::PollErrorBackref, output_size) => {
+                    output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                    return Err(HSError::InvalidBackref);
+                }
+            }
+        }
+    }
+
+    /// Poll all the uncompressed data available, appending it to any container
+    /// implementing `Extend<u8>` (`Vec`, `heapless::Vec`, `ArrayVec`, ...).
+    pub fn poll_into<E: Extend<u8>>(&mut self, out: &mut E) -> Result<(), HSError> {
+        self.poll_all(&mut |data: &[u8]| out.extend(data.iter().copied()))
+    }
+}
+
+/// Head index count bytes after head_index in a window of window_size
+/// bytes. Once the window is full, only the position in the window matters:
+/// the index then stays in `window_size..2 * window_size` instead of growing
+/// with the stream, so that it can't wrap around (e.g. where `usize` is 16
+/// bits).
+fn advance_head_index(head_index: usize, count: usize, window_size: usize) -> usize {
+    let head_index = head_index + count;
+    if head_index < window_size {
+        head_index
+    } else {
+        (head_index - window_size) % window_size + window_size
+    }
+}
+
+/// Copy count bytes of the window from read to write, the source and the
+/// destination not overlapping
+fn copy_within_window(window: &mut [u8], read: usize, write: usize, count: usize) {
+    let (source, destination) = if read < write {
+        let Some((head, tail)) = window.split_at_mut_checked(write) else {
+            return;
+        };
+        (head.get(read..).unwrap_or_default(), tail)
+    } else {
+        let Some((head, tail)) = window.split_at_mut_checked(read) else {
+            return;
+        };
+        (&*tail, head.get_mut(write..).unwrap_or_default())
+    };
+
+    for (slot, &byte) in destination.iter_mut().zip(source).take(count) {
+        *slot = byte;
+    }
+}
+
+/// Head index of a saved state brought in the range kept by
+/// `advance_head_index`, states saved by older versions growing with the stream
+fn bounded_head_index(head_index: u64, window_size: u64) -> u64 {
+    if head_index < window_size {
+        head_index
+    } else {
+        (head_index - window_size) % window_size + window_size
+    }
 }
@@ -0,0 +1,122 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+use super::{HSfinishRes, HSpollRes, HSsinkRes};
+
+use ::digest::{Digest, Output};
+
+/// Encoder updating a digest (e.g. `sha2::Sha256`) with the data sunk, so
+/// that the hash of a firmware image is computed while it is compressed
+#[cfg(feature = "encoder")]
+#[derive(Debug)]
+pub struct DigestEncoder<D, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    digest: D,
+}
+
+#[cfg(feature = "encoder")]
+impl<D: Digest, const WINDOW_SIZE: usize> DigestEncoder<D, WINDOW_SIZE> {
+    /// Hash the data compressed by encoder, which must not have been sunk
+    /// yet, with digest
+    pub fn new(encoder: HeatshrinkEncoder<WINDOW_SIZE>, digest: D) -> Self {
+        DigestEncoder { encoder, digest }
+    }
+
+    /// Sink data like `HeatshrinkEncoder::sink`, adding it to the digest
+    pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        let (res, input_size) = self.encoder.sink(input_buffer);
+        self.digest
+            .update(input_buffer.get(..input_size).unwrap_or_default());
+        (res, input_size)
+    }
+
+    /// Poll the compressed stream like `HeatshrinkEncoder::poll`
+    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        self.encoder.poll(output_buffer)
+    }
+
+    /// Signal the end of the input data like `HeatshrinkEncoder::finish`
+    pub fn finish(&mut self) -> HSfinishRes {
+        self.encoder.finish()
+    }
+
+    /// Get a reference to the digest of the data sunk so far
+    pub fn digest(&self) -> &D {
+        &self.digest
+    }
+
+    /// Hash of the data sunk, consuming the encoder
+    pub fn finalize(self) -> Output<D> {
+        self.digest.finalize()
+    }
+
+    /// Unwrap the underlying encoder and the digest
+    pub fn into_inner(self) -> (HeatshrinkEncoder<WINDOW_SIZE>, D) {
+        (self.encoder, self.digest)
+    }
+}
+
+/// Decoder updating a digest (e.g. `sha2::Sha256`) with the uncompressed
+/// data, so that a firmware image is verified while it is uncompressed,
+/// without a second pass over it
+#[cfg(feature = "decoder")]
+#[derive(Debug)]
+pub struct DigestDecoder<
+    D,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    digest: D,
+}
+
+#[cfg(feature = "decoder")]
+impl<D: Digest, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    DigestDecoder<D, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Hash the data uncompressed by decoder, which must not have been sunk
+    /// yet, with digest
+    pub fn new(decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>, digest: D) -> Self {
+        DigestDecoder { decoder, digest }
+    }
+
+    /// Sink compressed data like `HeatshrinkDecoder::sink`
+    pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        self.decoder.sink(input_buffer)
+    }
+
+    /// Poll the uncompressed data like `HeatshrinkDecoder::poll`, adding it
+    /// to the digest
+    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        let (res, output_size) = self.decoder.poll(output_buffer);
+        self.digest
+            .update(output_buffer.get(..output_size).unwrap_or_default());
+        (res, output_size)
+    }
+
+    /// Signal the end of the compressed data like
+    /// `HeatshrinkDecoder::finish`
+    pub fn finish(&mut self) -> HSfinishRes {
+        self.decoder.finish()
+    }
+
+    /// Get a reference to the digest of the data polled so far
+    pub fn digest(&self) -> &D {
+        &self.digest
+    }
+
+    /// Hash of the data polled, consuming the decoder. Compare it to the
+    /// expected hash once `finish` returned Done.
+    pub fn finalize(self) -> Output<D> {
+        self.digest.finalize()
+    }
+
+    /// Unwrap the underlying decoder and the digest
+    pub fn into_inner(self) -> (HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>, D) {
+        (self.decoder, self.digest)
+    }
+}
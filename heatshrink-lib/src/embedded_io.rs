@@ -0,0 +1,304 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+#[cfg(feature = "decoder")]
+use super::HSfinishRes;
+use super::HSpollRes;
+use super::HSsinkRes;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+#[cfg(feature = "decoder")]
+use embedded_io_async::Read;
+#[cfg(feature = "encoder")]
+use embedded_io_async::Write;
+use embedded_io_async::{ErrorKind, ErrorType};
+
+/// Size of the buffer used to read compressed data from the wrapped reader or
+/// to write compressed data to the wrapped writer
+const HEATSHRINK_IO_BUFFER_SIZE: usize = 512;
+
+/// Error of the async wrappers: an error of the wrapped reader or writer, or
+/// an error of the compressed stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// The wrapped reader or writer failed
+    Io(E),
+    /// The compressed stream is invalid or truncated
+    Heatshrink(HSError),
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for Error<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Io(error) => error.kind(),
+            Error::Heatshrink(_) => ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "I/O error: {:?}", error),
+            Error::Heatshrink(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for Error<E> {}
+
+#[cfg(feature = "decoder")]
+/// Async reader decompressing the data read from the wrapped reader
+///
+/// The sink/poll/finish loop of the decoder is driven from `read`, which
+/// only awaits the wrapped reader, so that firmware running on an async
+/// executor (e.g. embassy) can uncompress a stream received over a UART or
+/// a socket without blocking the other tasks.
+pub struct HeatshrinkReader<
+    R,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    inner: R,
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    input_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+    input_index: usize,
+    input_size: usize,
+    eof: bool,
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read> HeatshrinkReader<R> {
+    /// Create a new reader decompressing data with the default parameters
+    pub fn new(inner: R) -> Self {
+        HeatshrinkReader::with_decoder(inner, HeatshrinkDecoder::new())
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    HeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Create a new reader decompressing data with the provided decoder
+    /// (and therefore its window and lookahead parameters)
+    pub fn with_decoder(inner: R, decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        HeatshrinkReader {
+            inner,
+            decoder,
+            input_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+            input_index: 0,
+            input_size: 0,
+            eof: false,
+        }
+    }
+
+    /// Get a reference to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap this reader, returning the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> ErrorType
+    for HeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    type Error = Error<R::Error>;
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Read
+    for HeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // process the data already sunk into the decoder
+            match self.decoder.poll(buf) {
+                (HSpollRes::PollErrorMisuse, _) => {
+                    return Err(Error::Heatshrink(HSError::InvalidState));
+                }
+                (HSpollRes::PollErrorLimit, _) => {
+                    return Err(Error::Heatshrink(HSError::OutputLimit));
+                }
+                (HSpollRes::PollErrorBackref, _) => {
+                    return Err(Error::Heatshrink(HSError::InvalidBackref));
+                }
+                (_, 0) => {}
+                (_, output_size) => return Ok(output_size),
+            }
+
+            if self.input_index < self.input_size {
+                // the decoder needs more compressed data
+                match self
+                    .decoder
+                    .sink(&self.input_buffer[self.input_index..self.input_size])
+                {
+                    (HSsinkRes::SinkOK, input_size) => {
+                        self.input_index += input_size;
+                    }
+                    (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                        return Err(Error::Heatshrink(HSError::Internal));
+                    }
+                }
+            } else if self.eof {
+                // all the compressed data has been processed
+                return match self.decoder.finish() {
+                    HSfinishRes::FinishDone => Ok(0),
+                    HSfinishRes::FinishMore => Err(Error::Heatshrink(HSError::Truncated)),
+                };
+            } else {
+                self.input_index = 0;
+                self.input_size = self
+                    .inner
+                    .read(&mut self.input_buffer)
+                    .await
+                    .map_err(Error::Io)?;
+                self.eof = self.input_size == 0;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+/// Async writer compressing the data written to it into the wrapped writer
+///
+/// The sink/poll loop of the encoder is driven from `write`, which only
+/// awaits the wrapped writer. `finish` must be awaited once all the data was
+/// written to flush the end of the compressed stream.
+pub struct HeatshrinkWriter<W, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    inner: W,
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    output_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write> HeatshrinkWriter<W> {
+    /// Create a new writer compressing data with the default parameters
+    pub fn new(inner: W) -> Self {
+        HeatshrinkWriter::with_encoder(inner, HeatshrinkEncoder::new())
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write, const WINDOW_SIZE: usize> HeatshrinkWriter<W, WINDOW_SIZE> {
+    /// Create a new writer compressing data with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(inner: W, encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        HeatshrinkWriter {
+            inner,
+            encoder,
+            output_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+        }
+    }
+
+    /// Get a reference to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Finish the compressed stream, writing its trailing bits to the
+    /// wrapped writer, and return the wrapped writer
+    pub async fn finish(mut self) -> Result<W, Error<W::Error>> {
+        loop {
+            match self.encoder.finish_into(&mut self.output_buffer) {
+                Ok(output_size) => {
+                    self.inner
+                        .write_all(&self.output_buffer[..output_size])
+                        .await
+                        .map_err(Error::Io)?;
+                    break;
+                }
+                Err(HSError::OutputFull { .. }) => {
+                    self.inner
+                        .write_all(&self.output_buffer)
+                        .await
+                        .map_err(Error::Io)?;
+                }
+                Err(error) => return Err(Error::Heatshrink(error)),
+            }
+        }
+        self.inner.flush().await.map_err(Error::Io)?;
+        Ok(self.inner)
+    }
+
+    /// Poll the encoder until it needs more input, writing all the
+    /// compressed data to the wrapped writer
+    async fn write_output(&mut self) -> Result<(), Error<W::Error>> {
+        loop {
+            match self.encoder.poll(&mut self.output_buffer) {
+                (HSpollRes::PollMore, output_size) => {
+                    self.inner
+                        .write_all(&self.output_buffer[..output_size])
+                        .await
+                        .map_err(Error::Io)?;
+                }
+                (HSpollRes::PollEmpty, output_size) => {
+                    self.inner
+                        .write_all(&self.output_buffer[..output_size])
+                        .await
+                        .map_err(Error::Io)?;
+                    return Ok(());
+                }
+                (HSpollRes::PollErrorMisuse, _)
+                | (HSpollRes::PollErrorLimit, _)
+                | (HSpollRes::PollErrorBackref, _) => {
+                    return Err(Error::Heatshrink(HSError::Internal));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write, const WINDOW_SIZE: usize> ErrorType for HeatshrinkWriter<W, WINDOW_SIZE> {
+    type Error = Error<W::Error>;
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write, const WINDOW_SIZE: usize> Write for HeatshrinkWriter<W, WINDOW_SIZE> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.encoder.sink(buf) {
+            (HSsinkRes::SinkOK, input_size) => {
+                // process the data as soon as the encoder input buffer is full
+                self.write_output().await?;
+                Ok(input_size)
+            }
+            (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                Err(Error::Heatshrink(HSError::InvalidState))
+            }
+        }
+    }
+
+    /// Flush the wrapped writer. The compressed data still held by the
+    /// encoder is only written by `finish`.
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await.map_err(Error::Io)
+    }
+}
@@ -1,12 +1,33 @@
+use super::ByteSink;
 use super::HSError;
 use super::HSfinishRes;
 use super::HSpollRes;
 use super::HSsinkRes;
+use super::HexPreview;
 use super::OutputInfo;
-use super::HEATSHRINK_LOOKAHEAD_BITS;
-use super::HEATSHRINK_WINDOWS_BITS;
+use super::ParamError;
+use super::Params;
+#[cfg(feature = "serde")]
+use super::StateBytes;
+#[cfg(feature = "serde")]
+use super::StateBytesMut;
+use super::StateReader;
+use super::StateWriter;
+use super::HEATSHRINK_POLL_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use core::fmt;
+use core::marker::PhantomData;
+#[cfg(feature = "heatshrink-use-index")]
+use core::num::NonZeroU16;
+#[cfg(feature = "heatshrink-use-index")]
+use core::ops::Range;
 
+// the order of the states is part of the saved state layout
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 enum HSEstate {
     NotFull,       /* input buffer not full enough */
     Filled,        /* buffer is full */
@@ -20,103 +41,793 @@ enum HSEstate {
     Done,          /* done */
 }
 
+impl HSEstate {
+    /// Decode a state written by `HeatshrinkEncoder::save_state`
+    fn from_u8(value: u8) -> Option<HSEstate> {
+        const STATES: [HSEstate; 10] = [
+            HSEstate::NotFull,
+            HSEstate::Filled,
+            HSEstate::Search,
+            HSEstate::YieldTagBit,
+            HSEstate::YieldLiteral,
+            HSEstate::YieldBrIndex,
+            HSEstate::YieldBrLength,
+            HSEstate::SaveBacklog,
+            HSEstate::FlushBits,
+            HSEstate::Done,
+        ];
+
+        STATES.get(usize::from(value)).copied()
+    }
+}
+
 #[cfg(not(feature = "heatshrink-use-index"))]
 /// The encoder instance
-#[derive(Debug)]
-pub struct HeatshrinkEncoder {
+///
+/// `WINDOW_SIZE` is the capacity in bytes of the sliding window. It bounds
+/// the largest window size the instance can be configured with.
+#[derive(Clone)]
+pub struct HeatshrinkEncoder<const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
     input_size: usize,
     match_scan_index: usize,
     match_length: usize,
     match_position: usize,
+    max_match_length: usize,
+    max_backref_distance: usize,
+    max_search_depth: usize,
+    lazy_matching: bool,
+    skip_ahead: bool,
+    literal_run: usize,
+    total_in: u64,
+    total_out: u64,
     outgoing_bits: u16,
     outgoing_bits_count: u8,
     flags: u8,
     current_byte: u8,
     bit_index: u8,
+    window_bits: u8,
+    lookahead_bits: u8,
     state: HSEstate,
-    input_buffer: [u8; 2 << HEATSHRINK_WINDOWS_BITS],
+    search: SearchProgress,
+    shifted: usize,
+    #[cfg(feature = "stats")]
+    stats: EncoderStats,
+    #[cfg(test)]
+    steps: usize,
+    input_buffer: [[u8; WINDOW_SIZE]; 2],
 }
 
 #[cfg(feature = "heatshrink-use-index")]
 /// The encoder instance
-#[derive(Debug)]
-pub struct HeatshrinkEncoder {
+///
+/// `WINDOW_SIZE` is the capacity in bytes of the sliding window. It bounds
+/// the largest window size the instance can be configured with.
+#[derive(Clone)]
+pub struct HeatshrinkEncoder<const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
     input_size: usize,
     match_scan_index: usize,
     match_length: usize,
     match_position: usize,
+    max_match_length: usize,
+    max_backref_distance: usize,
+    max_search_depth: usize,
+    lazy_matching: bool,
+    skip_ahead: bool,
+    literal_run: usize,
+    total_in: u64,
+    total_out: u64,
     outgoing_bits: u16,
     outgoing_bits_count: u8,
     flags: u8,
     current_byte: u8,
     bit_index: u8,
+    window_bits: u8,
+    lookahead_bits: u8,
     state: HSEstate,
-    search_index: [Option<usize>; 2 << HEATSHRINK_WINDOWS_BITS],
-    input_buffer: [u8; 2 << HEATSHRINK_WINDOWS_BITS],
+    search: SearchProgress,
+    shifted: usize,
+    use_index: bool,
+    indexed: usize,
+    #[cfg(feature = "stats")]
+    stats: EncoderStats,
+    #[cfg(test)]
+    steps: usize,
+    index_heads: [IndexEntry; INDEX_CHAINS],
+    search_index: [[IndexEntry; WINDOW_SIZE]; 2],
+    input_buffer: [[u8; WINDOW_SIZE]; 2],
+}
+
+#[cfg(feature = "w8l4")]
+/// Encoder using a 256 bytes window and 4 bits back-reference lengths
+/// (`-w 8 -l 4`) when built with `Default::default()`
+pub type HeatshrinkEncoderW8L4 = HeatshrinkEncoder<256>;
+
+#[cfg(feature = "w10l4")]
+/// Encoder using a 1024 bytes window and 4 bits back-reference lengths
+/// (`-w 10 -l 4`) when built with `Default::default()`
+pub type HeatshrinkEncoderW10L4 = HeatshrinkEncoder<1024>;
+
+#[cfg(feature = "w11l4")]
+/// Encoder using a 2048 bytes window and 4 bits back-reference lengths
+/// (`-w 11 -l 4`) when built with `Default::default()`
+pub type HeatshrinkEncoderW11L4 = HeatshrinkEncoder<2048>;
+
+/// Memory footprint in bytes of an encoder with the default window capacity
+/// (see `HeatshrinkEncoder::RAM` for other capacities)
+pub const ENCODER_RAM: usize = HeatshrinkEncoder::<HEATSHRINK_WINDOW_SIZE>::RAM;
+
+/// Fastest compression level, examining a single earlier occurrence per
+/// position
+pub const MIN_LEVEL: u8 = 1;
+
+/// Compression level of the `Default` instances: exhaustive search, the
+/// output being the same as the C library's
+pub const DEFAULT_LEVEL: u8 = 8;
+
+/// Highest compression level, adding lazy matching to the exhaustive search
+/// for a better ratio on text-like data (it can be slightly worse on other
+/// data)
+pub const MAX_LEVEL: u8 = 9;
+
+/// Search depth of each compression level, from `MIN_LEVEL` to `MAX_LEVEL`
+const LEVEL_SEARCH_DEPTHS: [usize; 9] = [1, 2, 4, 8, 16, 32, 128, usize::MAX, usize::MAX];
+
+/// Search depth, lazy matching and skip-ahead of a compression level,
+/// clamped to `MIN_LEVEL..=MAX_LEVEL`. Skip-ahead is used below
+/// `DEFAULT_LEVEL`.
+fn level_options(level: u8) -> (usize, bool, bool) {
+    let level = level.clamp(MIN_LEVEL, MAX_LEVEL);
+    let max_search_depth = LEVEL_SEARCH_DEPTHS
+        .get(usize::from(level - MIN_LEVEL))
+        .copied()
+        .unwrap_or(usize::MAX);
+
+    (max_search_depth, level == MAX_LEVEL, level < DEFAULT_LEVEL)
+}
+
+/// Number of consecutive literals after which the skip-ahead heuristic
+/// starts skipping the search at some positions
+const SKIP_AHEAD_THRESHOLD: usize = 32;
+
+/// Base-2 log of the number of further literals growing the skip-ahead
+/// stride by one position
+const SKIP_AHEAD_STRIDE_SHIFT: u32 = 4;
+
+/// Largest skip-ahead stride: a search every 16 positions
+const SKIP_AHEAD_MAX_STRIDE: usize = 16;
+
+/// Number of bytes indexed, shifted to the backlog or scanned for a match
+/// by a step of `poll_with_budget`, the other steps being the state
+/// transitions and the candidates compared
+const WORK_STEP_SIZE: usize = 64;
+
+/// Progress of the search at the scan index, kept across the calls of
+/// `poll_with_budget`. It is not saved: a restored instance searches again,
+/// with the same result.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum SearchProgress {
+    /// No search in progress
+    Idle,
+    /// Search of the longest match at the scan index
+    Match(MatchSearch),
+    /// Search of a better match at the next position for lazy matching,
+    /// once the (distance, length) match at the scan index was found
+    Lazy((usize, usize), MatchSearch),
+}
+
+/// Search of the longest match for buf[end..end + maxlen] between buf[start]
+/// and buf[end - 1], done by steps
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct MatchSearch {
+    start: usize,
+    end: usize,
+    maxlen: usize,
+    /// Last candidate examined: the position of the index chain, or the end
+    /// of the part of the window left to scan
+    cursor: usize,
+    depth: usize,
+    length: usize,
+    position: usize,
+}
+
+impl MatchSearch {
+    fn new(start: usize, end: usize, maxlen: usize) -> Self {
+        MatchSearch {
+            start,
+            end,
+            maxlen,
+            cursor: end,
+            depth: 0,
+            length: 0,
+            position: 0,
+        }
+    }
+
+    /// Compare the candidate at position to the longest match found so far.
+    /// Return whether it is as long as possible, ending the search.
+    fn compare(&mut self, buffer: &[u8], position: usize) -> bool {
+        if buffer.get(position + self.length) == buffer.get(self.end + self.length) {
+            let len = match_length(buffer, position, self.end, self.maxlen);
+
+            if len > self.length {
+                self.length = len;
+                self.position = position;
+                // don't keep searching
+                return len == self.maxlen;
+            }
+        }
+        false
+    }
+}
+
+/// Number of buckets of the match length histogram of `EncoderStats`
+#[cfg(feature = "stats")]
+pub const STATS_MATCH_LENGTH_BUCKETS: usize = 16;
+
+/// Statistics of a compressed stream, to tune a dictionary or pick the
+/// window and lookahead sizes for some data
+#[cfg(feature = "stats")]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EncoderStats {
+    /// Number of literals emitted
+    pub literals: u64,
+    /// Number of back-references emitted
+    pub backrefs: u64,
+    /// Histogram of the back-reference lengths: `match_lengths[n - 1]`
+    /// counts the back-references of `n` bytes, the last bucket also
+    /// counting the longer ones
+    pub match_lengths: [u64; STATS_MATCH_LENGTH_BUCKETS],
+    /// Number of bits emitted for the literals, tag bits included
+    pub literal_bits: u64,
+    /// Number of bits emitted for the back-references, tag bits included
+    pub backref_bits: u64,
+    /// Number of bits padding the last byte of the stream or of a flush
+    pub padding_bits: u64,
+}
+
+#[cfg(feature = "stats")]
+impl EncoderStats {
+    const fn new() -> Self {
+        EncoderStats {
+            literals: 0,
+            backrefs: 0,
+            match_lengths: [0; STATS_MATCH_LENGTH_BUCKETS],
+            literal_bits: 0,
+            backref_bits: 0,
+            padding_bits: 0,
+        }
+    }
+
+    fn add_literal(&mut self) {
+        self.literals += 1;
+        self.literal_bits += 1 + 8;
+    }
+
+    fn add_backref(&mut self, length: usize, bits: u8) {
+        self.backrefs += 1;
+        self.backref_bits += 1 + u64::from(bits);
+        let bucket = length.clamp(1, STATS_MATCH_LENGTH_BUCKETS) - 1;
+        if let Some(count) = self.match_lengths.get_mut(bucket) {
+            *count += 1;
+        }
+    }
 }
 
 /// A constant flag to set an encoder as finishing
 const FLAG_IS_FINISHING: u8 = 1;
 
+/// A constant flag to set an encoder as primed with a dictionary
+const FLAG_IS_PRIMED: u8 = 2;
+
+/// A constant flag to set an encoder as flushing the data sunk so far
+const FLAG_IS_FLUSHING: u8 = 4;
+
+/// Option of a saved encoder state set when the search index is used
+const STATE_OPTION_USE_INDEX: u8 = 1;
+
+/// Option of a saved encoder state set when lazy matching is enabled
+const STATE_OPTION_LAZY_MATCHING: u8 = 2;
+
+/// Option of a saved encoder state set when skip-ahead is enabled
+const STATE_OPTION_SKIP_AHEAD: u8 = 4;
+
+/// First byte of a saved encoder state
+const STATE_MAGIC: u8 = b'E';
+
+/// Version of the saved encoder state layout
+const STATE_VERSION: u8 = 1;
+
+/// Size of the saved encoder state before the window
+const STATE_HEADER_SIZE: usize = 60;
+
+/// Registers of a saved encoder state, checked but not applied yet
+struct SavedState {
+    params: Params,
+    state: HSEstate,
+    flags: u8,
+    current_byte: u8,
+    bit_index: u8,
+    outgoing_bits_count: u8,
+    #[cfg_attr(not(feature = "heatshrink-use-index"), allow(dead_code))]
+    use_index: bool,
+    lazy_matching: bool,
+    skip_ahead: bool,
+    outgoing_bits: u16,
+    input_size: usize,
+    match_scan_index: usize,
+    match_length: usize,
+    match_position: usize,
+    max_match_length: usize,
+    max_backref_distance: usize,
+    max_search_depth: usize,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl SavedState {
+    /// Parse the part of a saved state before the window, checking that it
+    /// is consistent and that its window fits in window_capacity
+    fn parse(header: &[u8], window_capacity: usize) -> Result<SavedState, HSError> {
+        let invalid = |valid: bool| {
+            if valid {
+                Ok(())
+            } else {
+                Err(HSError::InvalidState)
+            }
+        };
+        let mut reader = StateReader { buffer: header };
+
+        invalid(reader.u8()? == STATE_MAGIC && reader.u8()? == STATE_VERSION)?;
+        let params = Params::new(reader.u8()?, reader.u8()?)
+            .and_then(|params| params.check_window_size(window_capacity).map(|_| params))
+            .map_err(|_| HSError::InvalidState)?;
+        let state = HSEstate::from_u8(reader.u8()?).ok_or(HSError::InvalidState)?;
+        let flags = reader.u8()?;
+        let current_byte = reader.u8()?;
+        let bit_index = reader.u8()?;
+        let outgoing_bits_count = reader.u8()?;
+        let options = reader.u8()?;
+        let outgoing_bits = reader.u16()?;
+        let mut sizes = [0usize; 6];
+        for size in sizes.iter_mut() {
+            *size = usize::try_from(reader.u32()?).map_err(|_| HSError::InvalidState)?;
+        }
+        let [input_size, match_scan_index, match_length, match_position, max_match_length, max_backref_distance] =
+            sizes;
+        let max_search_depth = usize::try_from(reader.u64()?).unwrap_or(usize::MAX);
+        let total_in = reader.u64()?;
+        let total_out = reader.u64()?;
+        let window_size = params.window_size();
+
+        invalid(flags & !(FLAG_IS_FINISHING | FLAG_IS_PRIMED | FLAG_IS_FLUSHING) == 0)?;
+        invalid((1..=8).contains(&bit_index) && outgoing_bits_count <= 16)?;
+        invalid(
+            options
+                & !(STATE_OPTION_USE_INDEX | STATE_OPTION_LAZY_MATCHING | STATE_OPTION_SKIP_AHEAD)
+                == 0,
+        )?;
+        invalid(input_size <= window_size && match_scan_index <= input_size)?;
+        invalid(match_position <= window_size && match_length <= 1 << params.lookahead_bits())?;
+        invalid(max_match_length <= 1 << params.lookahead_bits())?;
+        invalid(max_backref_distance <= window_size)?;
+
+        Ok(SavedState {
+            params,
+            state,
+            flags,
+            current_byte,
+            bit_index,
+            outgoing_bits_count,
+            use_index: options & STATE_OPTION_USE_INDEX != 0,
+            lazy_matching: options & STATE_OPTION_LAZY_MATCHING != 0,
+            skip_ahead: options & STATE_OPTION_SKIP_AHEAD != 0,
+            outgoing_bits,
+            input_size,
+            match_scan_index,
+            match_length,
+            match_position,
+            max_match_length,
+            max_backref_distance,
+            max_search_depth,
+            total_in,
+            total_out,
+        })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<const WINDOW_SIZE: usize> serde::Serialize for HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Serialize the state saved by `save_state` as a tuple of byte arrays:
+    /// the header, then the used part of the window
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::{Error, SerializeTuple};
+
+        let mut header = [0u8; STATE_HEADER_SIZE];
+        self.write_state_header(&mut StateWriter {
+            buffer: &mut header,
+        });
+
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&StateBytes(&header))?;
+        tuple.serialize_element(&StateWindow(self.saved_window().map_err(S::Error::custom)?))?;
+        tuple.end()
+    }
+}
+
+/// Window of a saved state, serialized as one byte array like `StateBytes`
+/// whether it is given in one piece or, while it is shifted, in several
+#[cfg(feature = "serde")]
+struct StateWindow<'a>([&'a [u8]; 3]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for StateWindow<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeSeq;
+
+        match self.0 {
+            [window, [], []] => serializer.serialize_bytes(window),
+            pieces => {
+                let size = pieces.iter().map(|piece| piece.len()).sum();
+                let mut seq = serializer.serialize_seq(Some(size))?;
+                for byte in pieces.iter().flat_map(|piece| piece.iter()) {
+                    seq.serialize_element(byte)?;
+                }
+                seq.end()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const WINDOW_SIZE: usize> serde::Deserialize<'de> for HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Deserialize a state serialized by any instance whose window fits in
+    /// `WINDOW_SIZE`, checked like `restore_state`
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StateVisitor<const WINDOW_SIZE: usize>;
+
+        impl<'de, const WINDOW_SIZE: usize> serde::de::Visitor<'de> for StateVisitor<WINDOW_SIZE> {
+            type Value = HeatshrinkEncoder<WINDOW_SIZE>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a saved heatshrink encoder state")
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                use serde::de::Error;
+
+                let mut header = [0u8; STATE_HEADER_SIZE];
+                seq.next_element_seed(StateBytesMut(&mut header))?
+                    .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+                let saved = SavedState::parse(&header, WINDOW_SIZE).map_err(A::Error::custom)?;
+
+                let mut enc = HeatshrinkEncoder::<WINDOW_SIZE>::from_params(saved.params);
+                let buffer = enc
+                    .input_buffer
+                    .as_flattened_mut()
+                    .get_mut(..2 * saved.params.window_size())
+                    .ok_or_else(|| A::Error::custom(HSError::InvalidState))?;
+                seq.next_element_seed(StateBytesMut(buffer))?
+                    .ok_or_else(|| A::Error::invalid_length(1, &self))?;
+                enc.apply_state(&saved);
+
+                Ok(enc)
+            }
+        }
+
+        deserializer.deserialize_tuple(2, StateVisitor)
+    }
+}
+
 /// compress the src buffer to the destination buffer
 pub fn encode<'a>(src: &[u8], dst: &'a mut [u8]) -> Result<&'a [u8], HSError> {
+    let mut enc: HeatshrinkEncoder = Default::default();
+
+    encode_with(&mut enc, src, dst)
+}
+
+/// compress the src buffer to the destination buffer using the provided
+/// instance (and therefore its window and lookahead parameters)
+pub fn encode_with<'a, const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], HSError> {
+    match encode_partial(enc, src, dst)? {
+        (HSfinishRes::FinishDone, _, output_size) => {
+            dst.get(..output_size).ok_or(HSError::Internal)
+        }
+        (HSfinishRes::FinishMore, _, _) => Err(HSError::OutputFull {
+            needed_hint: Some(encode_bound(src.len())),
+        }),
+    }
+}
+
+#[cfg(feature = "heapless")]
+/// compress the src buffer to a `heapless::Vec` of capacity N.
+///
+/// Fail with `OutputFull` if the compressed stream doesn't fit in N bytes.
+pub fn encode_to_heapless<const N: usize>(src: &[u8]) -> Result<heapless::Vec<u8, N>, HSError> {
+    let mut dst = heapless::Vec::new();
+    dst.resize(N, 0).map_err(|_| HSError::Internal)?;
+    let output_size = encode(src, &mut dst)?.len();
+    dst.truncate(output_size);
+
+    Ok(dst)
+}
+
+/// worst-case size of the compressed stream for an input of input_len bytes:
+/// every byte emitted as a literal (a tag bit and 8 bits) and the last byte
+/// padded.
+///
+/// A destination buffer of this size is always large enough for `encode`,
+/// whatever the window and lookahead parameters.
+pub const fn encode_bound(input_len: usize) -> usize {
+    input_len.saturating_add(input_len.div_ceil(8))
+}
+
+/// compress as much as possible of the src buffer to the destination buffer
+/// using the provided instance.
+///
+/// Return whether the compressed stream is complete along with the number of
+/// bytes consumed from src and written to dst. When the destination buffer is
+/// full, `FinishMore` is returned and the compression can be resumed by
+/// calling this function again with the same instance, the rest of the src
+/// buffer and a fresh destination buffer.
+pub fn encode_partial<const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &mut [u8],
+) -> Result<(HSfinishRes, usize, usize), HSError> {
     let mut total_input_size = 0;
     let mut total_output_size = 0;
 
-    let mut enc: HeatshrinkEncoder = Default::default();
+    loop {
+        if total_output_size == dst.len() {
+            // the stream may be complete with the destination buffer exactly
+            // full (or empty, for an empty src buffer): run the states left
+            // that don't emit anything
+            if total_input_size == src.len() {
+                enc.finish();
+                enc.poll(&mut []);
+                if let HSfinishRes::FinishDone = enc.finish() {
+                    return Ok((HSfinishRes::FinishDone, total_input_size, total_output_size));
+                }
+            }
+            return Ok((HSfinishRes::FinishMore, total_input_size, total_output_size));
+        }
 
-    while total_input_size < src.len() {
-        // Fill the input buffer from the src buffer
-        match enc.sink(&src[total_input_size..]) {
-            (HSsinkRes::SinkOK, segment_input_size) => {
-                total_input_size += segment_input_size;
+        // process the current input buffer
+        match enc.poll(dst.get_mut(total_output_size..).ok_or(HSError::Internal)?) {
+            (HSpollRes::PollMore, segment_output_size) => {
+                total_output_size += segment_output_size;
+                return Ok((HSfinishRes::FinishMore, total_input_size, total_output_size));
             }
-            (HSsinkRes::SinkFull, _) => {
-                return Err(HSError::Internal);
+            (HSpollRes::PollEmpty, segment_output_size) => {
+                total_output_size += segment_output_size;
             }
-            (HSsinkRes::SinkErrorMisuse, _) => {
+            (HSpollRes::PollErrorMisuse, _)
+            | (HSpollRes::PollErrorLimit, _)
+            | (HSpollRes::PollErrorBackref, _) => {
                 return Err(HSError::Internal);
             }
         }
 
-        // if all the src buffer is processed, finish the compress stream
-        if total_input_size == src.len() {
-            match enc.finish() {
-                HSfinishRes::FinishDone => {}
-                HSfinishRes::FinishMore => {}
-            }
-        }
-
-        if total_output_size == dst.len() {
-            return Err(HSError::OutputFull);
-        } else {
-            // process the current input buffer
-            match enc.poll(&mut dst[total_output_size..]) {
-                (HSpollRes::PollMore, _) => {
-                    return Err(HSError::OutputFull);
+        if total_input_size < src.len() {
+            // Fill the input buffer from the src buffer
+            match enc.sink(src.get(total_input_size..).ok_or(HSError::Internal)?) {
+                (HSsinkRes::SinkOK, segment_input_size) => {
+                    total_input_size += segment_input_size;
                 }
-                (HSpollRes::PollEmpty, segment_output_size) => {
-                    total_output_size += segment_output_size;
+                (HSsinkRes::SinkFull, _) => {
+                    return Err(HSError::Internal);
                 }
-                (HSpollRes::PollErrorMisuse, _) => {
+                (HSsinkRes::SinkErrorMisuse, _) => {
                     return Err(HSError::Internal);
                 }
             }
+        } else if let HSfinishRes::FinishDone = enc.finish() {
+            // all the src buffer is processed and the stream is complete
+            return Ok((HSfinishRes::FinishDone, total_input_size, total_output_size));
+        }
+    }
+}
+
+/// compress as much as possible of the src buffer to the destination buffer
+/// using the provided instance, without ending the compressed stream.
+///
+/// Return the number of bytes consumed from src and written to dst, so that
+/// the encoder can be fed from the read grant of a lock-free queue (e.g. a
+/// `bbqueue` consumer filled by a DMA UART) and write to the write grant of
+/// another one: release the consumed bytes and commit the written ones, then
+/// call this function again with the next grants. The grants are passed as
+/// slices (bbqueue's `GrantR` and `GrantW` deref to them), the crate doesn't
+/// depend on any queue: the `heatshrink-bbqueue` crate does the cycle with
+/// bbqueue's `Consumer` and `Producer`. Once all the data was sunk,
+/// `encode_partial` with an empty src ends the compressed stream.
+pub fn encode_step<const WINDOW_SIZE: usize>(
+    enc: &mut HeatshrinkEncoder<WINDOW_SIZE>,
+    src: &[u8],
+    dst: &mut [u8],
+) -> Result<(usize, usize), HSError> {
+    let mut total_input_size = 0;
+    let mut total_output_size = 0;
+
+    while total_output_size < dst.len() {
+        // process the data sunk so far
+        match enc.poll(dst.get_mut(total_output_size..).ok_or(HSError::Internal)?) {
+            (HSpollRes::PollMore, segment_output_size) => {
+                total_output_size += segment_output_size;
+                break;
+            }
+            (HSpollRes::PollEmpty, segment_output_size) => {
+                total_output_size += segment_output_size;
+            }
+            (HSpollRes::PollErrorMisuse, _)
+            | (HSpollRes::PollErrorLimit, _)
+            | (HSpollRes::PollErrorBackref, _) => {
+                return Err(HSError::Internal);
+            }
+        }
+
+        if total_input_size == src.len() {
+            break;
+        }
+        match enc.sink(src.get(total_input_size..).ok_or(HSError::Internal)?) {
+            (HSsinkRes::SinkOK, segment_input_size) => {
+                total_input_size += segment_input_size;
+            }
+            (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                return Err(HSError::Internal);
+            }
         }
     }
 
-    Ok(&dst[..total_output_size])
+    Ok((total_input_size, total_output_size))
+}
+
+impl<const WINDOW_SIZE: usize> fmt::Debug for HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Print the state and counters of the instance along with a preview of
+    /// the input waiting to be compressed. Use `dump` to print the buffers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let input_start = self.get_input_offset() + self.match_scan_index.min(self.input_size);
+        let input_end = self.get_input_offset() + self.input_size;
+
+        f.debug_struct("HeatshrinkEncoder")
+            .field("state", &self.state)
+            .field("params", &self.params())
+            .field("is_finishing", &self.is_finishing())
+            .field("input_size", &self.input_size)
+            .field("match_scan_index", &self.match_scan_index)
+            .field("total_in", &self.total_in)
+            .field("total_out", &self.total_out)
+            .field(
+                "input",
+                &HexPreview(
+                    self.input_buffer
+                        .as_flattened()
+                        .get(input_start..input_end)
+                        .unwrap_or_default(),
+                ),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<const WINDOW_SIZE: usize> defmt::Format for HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Log the state and counters of the instance
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "HeatshrinkEncoder {{ state: {}, params: {}, is_finishing: {=bool}, input_size: {=usize}, match_scan_index: {=usize}, total_in: {=u64}, total_out: {=u64} }}",
+            self.state,
+            self.params(),
+            self.is_finishing(),
+            self.input_size,
+            self.match_scan_index,
+            self.total_in,
+            self.total_out,
+        )
+    }
+}
+
+/// Debug view of all the fields and buffers of an encoder
+struct Dump<'a, const WINDOW_SIZE: usize>(&'a HeatshrinkEncoder<WINDOW_SIZE>);
+
+impl<const WINDOW_SIZE: usize> fmt::Debug for Dump<'_, WINDOW_SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let enc = self.0;
+        let mut d = f.debug_struct("HeatshrinkEncoder");
+
+        d.field("input_size", &enc.input_size)
+            .field("match_scan_index", &enc.match_scan_index)
+            .field("match_length", &enc.match_length)
+            .field("match_position", &enc.match_position)
+            .field("max_match_length", &enc.max_match_length)
+            .field("max_backref_distance", &enc.max_backref_distance)
+            .field("max_search_depth", &enc.max_search_depth)
+            .field("lazy_matching", &enc.lazy_matching)
+            .field("skip_ahead", &enc.skip_ahead)
+            .field("literal_run", &enc.literal_run)
+            .field("total_in", &enc.total_in)
+            .field("total_out", &enc.total_out)
+            .field("outgoing_bits", &enc.outgoing_bits)
+            .field("outgoing_bits_count", &enc.outgoing_bits_count)
+            .field("flags", &enc.flags)
+            .field("current_byte", &enc.current_byte)
+            .field("bit_index", &enc.bit_index)
+            .field("window_bits", &enc.window_bits)
+            .field("lookahead_bits", &enc.lookahead_bits)
+            .field("state", &enc.state)
+            .field("search", &enc.search)
+            .field("shifted", &enc.shifted);
+        #[cfg(feature = "heatshrink-use-index")]
+        d.field("use_index", &enc.use_index)
+            .field("indexed", &enc.indexed)
+            .field("search_index", &enc.search_index.as_flattened());
+        d.field("input_buffer", &enc.input_buffer.as_flattened())
+            .finish()
+    }
 }
 
-impl Default for HeatshrinkEncoder {
+impl<const WINDOW_SIZE: usize> Default for HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Create an instance using the largest window fitting in `WINDOW_SIZE`
+    /// and 4 bits back-reference lengths (or less for tiny windows).
+    ///
+    /// Fails to build if `WINDOW_SIZE` is smaller than the smallest
+    /// heatshrink window.
     fn default() -> Self {
-        HeatshrinkEncoder::new()
+        HeatshrinkEncoder::from_params(const { Params::default_for_window_size(WINDOW_SIZE) })
     }
 }
 
 impl HeatshrinkEncoder {
     /// Create a new encoder instance
-    pub fn new() -> Self {
+    ///
+    /// Being a `const fn`, it can initialize a `static` (e.g. a
+    /// `static_cell::ConstStaticCell`), so that the state is allocated at
+    /// link time instead of on the stack.
+    pub const fn new() -> Self {
+        HeatshrinkEncoder::from_params(
+            const { Params::default_for_window_size(HEATSHRINK_WINDOW_SIZE) },
+        )
+    }
+
+    /// Create a new encoder instance on the heap
+    #[cfg(feature = "alloc")]
+    pub fn boxed() -> Box<Self> {
+        Box::default()
+    }
+}
+
+impl<const WINDOW_SIZE: usize> HeatshrinkEncoder<WINDOW_SIZE> {
+    /// Memory footprint in bytes of an instance with this window capacity,
+    /// including its search index when the `heatshrink-use-index` feature
+    /// is enabled
+    pub const RAM: usize = core::mem::size_of::<Self>();
+
+    /// Largest size in bytes of the state saved by `save_state` with this
+    /// window capacity, to size a static buffer
+    pub const STATE_MAX_SIZE: usize = STATE_HEADER_SIZE + 2 * WINDOW_SIZE;
+
+    /// Create a new encoder instance using the provided parameters, also
+    /// usable to initialize a `static`.
+    ///
+    /// Fails if the window does not fit in `WINDOW_SIZE`.
+    pub const fn with_params(params: Params) -> Result<Self, ParamError> {
+        match params.check_window_size(WINDOW_SIZE) {
+            Ok(()) => Ok(HeatshrinkEncoder::from_params(params)),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Create a new instance using parameters whose window fits in
+    /// `WINDOW_SIZE`
+    const fn from_params(params: Params) -> Self {
         #[cfg(feature = "heatshrink-use-index")]
         {
             HeatshrinkEncoder {
@@ -124,14 +835,33 @@ impl HeatshrinkEncoder {
                 match_scan_index: 0,
                 match_length: 0,
                 match_position: 0,
+                max_match_length: 1 << params.lookahead_bits(),
+                max_backref_distance: params.window_size(),
+                max_search_depth: usize::MAX,
+                lazy_matching: false,
+                skip_ahead: false,
+                literal_run: 0,
+                total_in: 0,
+                total_out: 0,
                 outgoing_bits: 0,
                 outgoing_bits_count: 0,
                 flags: 0,
                 current_byte: 0,
                 bit_index: 8,
+                window_bits: params.window_bits(),
+                lookahead_bits: params.lookahead_bits(),
                 state: HSEstate::NotFull,
-                search_index: [None; 2 << HEATSHRINK_WINDOWS_BITS],
-                input_buffer: [0; 2 << HEATSHRINK_WINDOWS_BITS],
+                search: SearchProgress::Idle,
+                shifted: 0,
+                use_index: true,
+                indexed: 0,
+                #[cfg(feature = "stats")]
+                stats: EncoderStats::new(),
+                #[cfg(test)]
+                steps: 0,
+                index_heads: [None; INDEX_CHAINS],
+                search_index: [[None; WINDOW_SIZE]; 2],
+                input_buffer: [[0; WINDOW_SIZE]; 2],
             }
         }
 
@@ -142,35 +872,407 @@ impl HeatshrinkEncoder {
                 match_scan_index: 0,
                 match_length: 0,
                 match_position: 0,
+                max_match_length: 1 << params.lookahead_bits(),
+                max_backref_distance: params.window_size(),
+                max_search_depth: usize::MAX,
+                lazy_matching: false,
+                skip_ahead: false,
+                literal_run: 0,
+                total_in: 0,
+                total_out: 0,
                 outgoing_bits: 0,
                 outgoing_bits_count: 0,
                 flags: 0,
                 current_byte: 0,
                 bit_index: 8,
+                window_bits: params.window_bits(),
+                lookahead_bits: params.lookahead_bits(),
                 state: HSEstate::NotFull,
-                input_buffer: [0; 2 << HEATSHRINK_WINDOWS_BITS],
+                search: SearchProgress::Idle,
+                shifted: 0,
+                #[cfg(feature = "stats")]
+                stats: EncoderStats::new(),
+                #[cfg(test)]
+                steps: 0,
+                input_buffer: [[0; WINDOW_SIZE]; 2],
             }
         }
     }
 
-    /// Reset the current encoder instance
-    pub fn reset(&mut self) {
+    /// Create a new encoder instance on the heap using the provided
+    /// parameters. With a `WINDOW_SIZE` of `1 << HEATSHRINK_MAX_WINDOWS_BITS`
+    /// any window size can be selected at runtime.
+    ///
+    /// Fails if the window does not fit in `WINDOW_SIZE`.
+    #[cfg(feature = "alloc")]
+    pub fn boxed_with_params(params: Params) -> Result<Box<Self>, ParamError> {
+        HeatshrinkEncoder::with_params(params).map(Box::new)
+    }
+
+    /// Window and lookahead sizes used by this instance
+    pub fn params(&self) -> Params {
+        Params {
+            window_bits: self.window_bits,
+            lookahead_bits: self.lookahead_bits,
+        }
+    }
+
+    /// Debug view of the instance printing all its fields and buffers, where
+    /// its `Debug` implementation only prints a summary
+    pub fn dump(&self) -> impl fmt::Debug + '_ {
+        Dump(self)
+    }
+
+    /// Limit the length of the back-references emitted by this instance.
+    ///
+    /// The value is capped to the largest length allowed by the lookahead
+    /// size. Lengths too short to be worth a back-reference result in only
+    /// literals being emitted.
+    pub fn set_max_match_length(&mut self, max_match_length: usize) {
+        self.max_match_length = max_match_length.min(self.get_lookahead_size());
+    }
+
+    /// Limit how far back in the window the back-references emitted by this
+    /// instance can point.
+    ///
+    /// The value is capped to the window size.
+    pub fn set_max_backref_distance(&mut self, max_backref_distance: usize) {
+        self.max_backref_distance = max_backref_distance.min(self.get_input_buffer_size());
+    }
+
+    /// Limit the number of earlier occurrences of a byte examined when
+    /// searching for a back-reference.
+    ///
+    /// Lower values trade compression ratio for speed. A depth of 0 disables
+    /// the search, so only literals are emitted.
+    pub fn set_max_search_depth(&mut self, max_search_depth: usize) {
+        self.max_search_depth = max_search_depth;
+    }
+
+    /// Select the compression level, from `MIN_LEVEL` (fastest) to
+    /// `MAX_LEVEL` (best ratio on text), setting the search depth, lazy
+    /// matching and skip-ahead (see `set_max_search_depth`,
+    /// `set_lazy_matching` and `set_skip_ahead`). Levels out of this range
+    /// are clamped to it.
+    pub fn set_level(&mut self, level: u8) {
+        (self.max_search_depth, self.lazy_matching, self.skip_ahead) = level_options(level);
+    }
+
+    /// Select lazy matching: a back-reference is only emitted if the next
+    /// position doesn't start a longer one (long enough to pay for a literal
+    /// emitted first).
+    ///
+    /// This examines most positions twice for a better compression ratio on
+    /// text-like data. It is disabled by default, the output then being the
+    /// same as the C library's.
+    pub fn set_lazy_matching(&mut self, lazy_matching: bool) {
+        self.lazy_matching = lazy_matching;
+    }
+
+    /// Select skip-ahead: after 32 consecutive literals, the search is only
+    /// done at one position out of a stride growing with the number of
+    /// literals (up to 16), the other positions being emitted as literals.
+    ///
+    /// This speeds up the compression of incompressible regions (e.g.
+    /// already compressed or encrypted payloads) at the cost of missing
+    /// some matches right after them. It is disabled by default, the output
+    /// then being the same as the C library's.
+    ///
+    /// The count of literals is not saved by `save_state`, so a resumed
+    /// stream may slightly differ from an uninterrupted one.
+    pub fn set_skip_ahead(&mut self, skip_ahead: bool) {
+        self.skip_ahead = skip_ahead;
+    }
+
+    /// Select whether the search index is used to find back-references.
+    ///
+    /// Without the index the window is scanned linearly, which is slower but
+    /// produces the same compressed stream.
+    #[cfg(feature = "heatshrink-use-index")]
+    pub fn set_use_index(&mut self, use_index: bool) {
+        self.use_index = use_index;
+        // the search in progress follows the index or scans the window
+        self.search = SearchProgress::Idle;
+    }
+
+    /// Preload the window with the last bytes of dictionary so that the
+    /// first bytes sunk can be encoded as back-references to it. The
+    /// dictionary is indexed along with the first input bytes.
+    ///
+    /// The decoder must be primed with the same dictionary (see
+    /// `decoder::HeatshrinkDecoder::prime`).
+    ///
+    /// Only possible once, before any data is sunk. Return the number of
+    /// bytes of dictionary loaded in the window.
+    pub fn prime(&mut self, dictionary: &[u8]) -> (HSsinkRes, usize) {
+        if self.flags != 0 || self.total_in != 0 || self.state != HSEstate::NotFull {
+            return (HSsinkRes::SinkErrorMisuse, 0);
+        }
+
+        let offset = self.get_input_offset();
+        let (_, dictionary) = dictionary
+            .split_at_checked(dictionary.len().saturating_sub(offset))
+            .unwrap_or_default();
+        match self
+            .input_buffer
+            .as_flattened_mut()
+            .get_mut(offset - dictionary.len()..offset)
+        {
+            Some(backlog) => backlog.copy_from_slice(dictionary),
+            None => return (HSsinkRes::SinkErrorMisuse, 0),
+        }
+        self.flags |= FLAG_IS_PRIMED;
+
+        (HSsinkRes::SinkOK, dictionary.len())
+    }
+
+    /// Abort the current stream (e.g. when a transfer is cancelled),
+    /// discarding any pending input and output.
+    ///
+    /// The instance is then ready for a new stream exactly like after
+    /// `reset`: it can be primed, sunk, polled and finished. Only the
+    /// backlog part of the window is cleared, and only when the stream wrote
+    /// to it, the rest of the window and the search index being rewritten
+    /// before they are used.
+    pub fn abort(&mut self) {
+        // The backlog is only written by a dictionary or once the input
+        // was shifted by st_save_backlog, after which total_in exceeds
+        // input_size.
+        let backlog_written =
+            self.flags & FLAG_IS_PRIMED != 0 || self.total_in != self.input_size as u64;
+
         self.input_size = 0;
         self.match_scan_index = 0;
         self.match_length = 0;
         self.match_position = 0;
+        self.literal_run = 0;
+        self.total_in = 0;
+        self.total_out = 0;
         self.outgoing_bits = 0;
         self.outgoing_bits_count = 0;
         self.flags = 0;
         self.current_byte = 0;
         self.bit_index = 8;
         self.state = HSEstate::NotFull;
-        // memset self.buffer to 0
-        self.input_buffer.fill(0);
+        self.search = SearchProgress::Idle;
+        self.shifted = 0;
+        #[cfg(feature = "heatshrink-use-index")]
+        {
+            self.indexed = 0;
+        }
+        #[cfg(feature = "stats")]
+        {
+            self.stats = EncoderStats::default();
+        }
+        // memset the backlog to 0
+        if backlog_written {
+            let offset = self.get_input_offset();
+            if let Some(backlog) = self.input_buffer.as_flattened_mut().get_mut(..offset) {
+                backlog.fill(0);
+            }
+        }
+    }
+
+    /// Reset the current encoder instance.
+    ///
+    /// Like `abort`, only the bookkeeping fields are reset and the backlog
+    /// cleared if the previous stream wrote to it (back-references can
+    /// reach it before the window fills up, as in the C library): the rest
+    /// of the window is left as is until it is filled, so back-to-back
+    /// short messages don't pay for clearing the whole buffer.
+    pub fn reset(&mut self) {
+        self.abort();
+    }
+
+    /// Reset the current encoder instance and clear its whole window and
+    /// search index, for users who must not leave the data of the previous
+    /// stream in memory (e.g. key material or personal data).
+    pub fn reset_and_zeroize(&mut self) {
+        self.abort();
+        self.input_buffer.as_flattened_mut().fill(0);
+        #[cfg(feature = "heatshrink-use-index")]
+        self.search_index.as_flattened_mut().fill(None);
+    }
+
+    /// Size in bytes of the state saved by `save_state` for this instance
+    pub fn state_size(&self) -> usize {
+        STATE_HEADER_SIZE + 2 * self.get_input_buffer_size()
+    }
+
+    /// Save the state of the instance, e.g. to flash, so that an
+    /// in-progress compression can be resumed after a power cycle by
+    /// `restore_state`. Return the number of bytes written.
+    ///
+    /// The layout is stable across versions of this library: a tag byte
+    /// (`E`), a version byte (1), the parameters, the state machine
+    /// registers and counters (little-endian) and the used part of the
+    /// window. The search index is not saved but rebuilt on restore.
+    ///
+    /// Fail with `OutputFull` if state is smaller than `state_size`.
+    pub fn save_state(&self, state: &mut [u8]) -> Result<usize, HSError> {
+        let size = self.state_size();
+        if state.len() < size {
+            return Err(HSError::OutputFull {
+                needed_hint: Some(size),
+            });
+        }
+
+        let mut writer = StateWriter { buffer: state };
+        self.write_state_header(&mut writer);
+        for piece in self.saved_window()? {
+            writer.bytes(piece);
+        }
+
+        Ok(size)
+    }
+
+    /// Write the part of the saved state before the window
+    fn write_state_header(&self, writer: &mut StateWriter) {
+        #[cfg(feature = "heatshrink-use-index")]
+        let use_index = self.use_index;
+        #[cfg(not(feature = "heatshrink-use-index"))]
+        let use_index = false;
+        let mut options = 0;
+        if use_index {
+            options |= STATE_OPTION_USE_INDEX;
+        }
+        if self.lazy_matching {
+            options |= STATE_OPTION_LAZY_MATCHING;
+        }
+        if self.skip_ahead {
+            options |= STATE_OPTION_SKIP_AHEAD;
+        }
+        // a shift of the window in progress is saved as done
+        let (state, input_size, match_scan_index) = if self.state == HSEstate::SaveBacklog {
+            (
+                HSEstate::NotFull,
+                self.input_size - self.match_scan_index,
+                0,
+            )
+        } else {
+            (self.state, self.input_size, self.match_scan_index)
+        };
+
+        writer.u8(STATE_MAGIC);
+        writer.u8(STATE_VERSION);
+        writer.u8(self.window_bits);
+        writer.u8(self.lookahead_bits);
+        writer.u8(state as u8);
+        writer.u8(self.flags);
+        writer.u8(self.current_byte);
+        writer.u8(self.bit_index);
+        writer.u8(self.outgoing_bits_count);
+        writer.u8(options);
+        writer.u16(self.outgoing_bits);
+        for value in [
+            input_size,
+            match_scan_index,
+            self.match_length,
+            self.match_position,
+            self.max_match_length,
+            self.max_backref_distance,
+        ] {
+            writer.u32(value as u32);
+        }
+        writer.u64(u64::try_from(self.max_search_depth).unwrap_or(u64::MAX));
+        writer.u64(self.total_in);
+        writer.u64(self.total_out);
+    }
+
+    /// Used part of the window, saved after the header of the state.
+    ///
+    /// While the window is shifted, its pieces are given in the order they
+    /// have once shifted: the bytes already moved, those left to move and
+    /// the end of the window, which the shift leaves in place.
+    fn saved_window(&self) -> Result<[&[u8]; 3], HSError> {
+        let window = self
+            .input_buffer
+            .as_flattened()
+            .get(..2 * self.get_input_buffer_size())
+            .ok_or(HSError::Internal)?;
+        if self.state != HSEstate::SaveBacklog {
+            return Ok([window, &[], &[]]);
+        }
+
+        let shift = self.match_scan_index;
+        Ok([
+            window.get(..self.shifted).ok_or(HSError::Internal)?,
+            window
+                .get(shift + self.shifted..)
+                .ok_or(HSError::Internal)?,
+            window
+                .get(window.len().saturating_sub(shift)..)
+                .ok_or(HSError::Internal)?,
+        ])
+    }
+
+    /// Restore a state saved by `save_state`, possibly by another instance,
+    /// to resume its compression.
+    ///
+    /// Fail with `InvalidState`, leaving the instance untouched, if the
+    /// state is truncated, of another version, inconsistent or if its
+    /// window does not fit in `WINDOW_SIZE`.
+    pub fn restore_state(&mut self, state: &[u8]) -> Result<(), HSError> {
+        let mut reader = StateReader { buffer: state };
+        let saved = SavedState::parse(reader.bytes(STATE_HEADER_SIZE)?, WINDOW_SIZE)?;
+        let window = reader.bytes(2 * saved.params.window_size())?;
+
+        self.input_buffer
+            .as_flattened_mut()
+            .get_mut(..window.len())
+            .ok_or(HSError::InvalidState)?
+            .copy_from_slice(window);
+        self.apply_state(&saved);
+
+        Ok(())
+    }
+
+    /// Apply the registers of a saved state once its window is restored
+    fn apply_state(&mut self, saved: &SavedState) {
+        self.window_bits = saved.params.window_bits();
+        self.lookahead_bits = saved.params.lookahead_bits();
+        self.state = saved.state;
+        self.flags = saved.flags;
+        self.current_byte = saved.current_byte;
+        self.bit_index = saved.bit_index;
+        self.outgoing_bits_count = saved.outgoing_bits_count;
+        self.outgoing_bits = saved.outgoing_bits;
+        self.input_size = saved.input_size;
+        self.match_scan_index = saved.match_scan_index;
+        self.match_length = saved.match_length;
+        self.match_position = saved.match_position;
+        self.max_match_length = saved.max_match_length;
+        self.max_backref_distance = saved.max_backref_distance;
+        self.max_search_depth = saved.max_search_depth;
+        self.lazy_matching = saved.lazy_matching;
+        self.skip_ahead = saved.skip_ahead;
+        // the count of literals and the search in progress are not saved,
+        // skip-ahead and the search start over
+        self.literal_run = 0;
+        self.search = SearchProgress::Idle;
+        self.shifted = 0;
+        self.total_in = saved.total_in;
+        self.total_out = saved.total_out;
+        #[cfg(feature = "stats")]
+        {
+            self.stats = EncoderStats::default();
+        }
         #[cfg(feature = "heatshrink-use-index")]
         {
-            // memset self.search_index to None
-            self.search_index.fill(None);
+            self.use_index = saved.use_index;
+        }
+        // the index of the window being searched is rebuilt
+        self.do_indexing();
+    }
+
+    /// Number of bytes the next `sink` call can accept, 0 when the instance
+    /// must be polled (or reset once finishing) before sinking more, so that
+    /// producers (e.g. DMA transfers) can be sized without trial sinks.
+    pub fn sink_capacity(&self) -> usize {
+        if self.is_finishing() || self.state != HSEstate::NotFull {
+            0
+        } else {
+            self.get_input_buffer_size() - self.input_size
         }
     }
 
@@ -201,10 +1303,18 @@ impl HeatshrinkEncoder {
         let write_offset = self.get_input_offset() + self.input_size;
 
         // memcpy content of input_buffer into self.input_buffer
-        self.input_buffer[write_offset..write_offset + copy_size]
-            .copy_from_slice(&input_buffer[0..copy_size]);
+        let free = self
+            .input_buffer
+            .as_flattened_mut()
+            .get_mut(write_offset..write_offset + copy_size);
+        match (free, input_buffer.get(..copy_size)) {
+            (Some(free), Some(input)) => free.copy_from_slice(input),
+            _ => return (HSsinkRes::SinkErrorMisuse, 0),
+        }
         self.input_size += copy_size;
 
+        self.total_in += copy_size as u64;
+
         if self.input_size == self.get_input_buffer_size() {
             self.state = HSEstate::Filled;
         }
@@ -214,63 +1324,202 @@ impl HeatshrinkEncoder {
 
     /// function to process the input/internal buffer and put the compressed
     /// stream in the provided buffer.
-    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
-        if output_buffer.is_empty() {
-            (HSpollRes::PollMore, 0)
-        } else {
-            let mut output_size: usize = 0;
-            let mut output_info = OutputInfo::new(output_buffer, &mut output_size);
+    pub fn poll(&mut self, mut output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        self.poll_sink(&mut output_buffer)
+    }
 
-            loop {
-                let previous_state = self.state;
+    /// Add a single byte to be processed. Equivalent to `sink` with a
+    /// one byte buffer, without the slice bookkeeping.
+    pub fn sink_byte(&mut self, byte: u8) -> HSsinkRes {
+        self.sink(&[byte]).0
+    }
 
-                match previous_state {
-                    HSEstate::NotFull => {
-                        return (HSpollRes::PollEmpty, output_size);
-                    }
-                    HSEstate::Filled => {
-                        self.do_indexing();
-                        self.state = HSEstate::Search;
-                    }
-                    HSEstate::Search => {
-                        self.state = self.st_step_search();
-                    }
-                    HSEstate::YieldTagBit => {
-                        self.state = self.st_yield_tag_bit(&mut output_info);
-                    }
-                    HSEstate::YieldLiteral => {
-                        self.state = self.st_yield_literal(&mut output_info);
-                    }
-                    HSEstate::YieldBrIndex => {
-                        self.state = self.st_yield_br_index(&mut output_info);
-                    }
-                    HSEstate::YieldBrLength => {
-                        self.state = self.st_yield_br_length(&mut output_info);
-                    }
-                    HSEstate::SaveBacklog => {
-                        self.state = self.st_save_backlog();
-                    }
-                    HSEstate::FlushBits => {
-                        self.state = self.st_flush_bit_buffer(&mut output_info);
-                        return (HSpollRes::PollEmpty, output_size);
-                    }
-                    HSEstate::Done => {
-                        return (HSpollRes::PollEmpty, output_size);
-                    }
-                }
+    /// Get the next byte of the compressed stream, if one is available.
+    ///
+    /// Unlike `HeatshrinkDecoder::poll_byte`, a call is not O(1): once per
+    /// window of input, a call indexes the filled window and shifts it to
+    /// make room, in O(window size), and a search for a back-reference
+    /// follows up to `max_search_depth` candidates of up to a lookahead each
+    /// (or scans the window, without the index). With the index and a
+    /// bounded search depth, the cost is O(1) per byte amortized over a
+    /// window, see `set_max_search_depth`. Use `poll_byte_with_budget` to
+    /// bound the cost of every call.
+    pub fn poll_byte(&mut self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        match self.poll(&mut byte) {
+            (HSpollRes::PollErrorMisuse, _) | (_, 0) => None,
+            (_, _) => Some(byte[0]),
+        }
+    }
 
-                // If the current state cannot advance, check if output
-                // buffer is exhausted.
-                if self.state == previous_state && !output_info.can_take_byte() {
-                    return (HSpollRes::PollMore, output_size);
-                }
-            }
+    /// Get the next byte of the compressed stream, if one is available,
+    /// doing at most max_steps steps of work (see `poll_with_budget`), so
+    /// that every call is O(1).
+    ///
+    /// PollMore is returned with no byte when the budget is used up before
+    /// a byte is complete; the work in progress is resumed by the next call.
+    pub fn poll_byte_with_budget(&mut self, max_steps: usize) -> (HSpollRes, Option<u8>) {
+        let mut byte = [0u8; 1];
+        match self.poll_with_budget(&mut byte, max_steps) {
+            (res, 0) => (res, None),
+            (res, _) => (res, Some(byte[0])),
         }
     }
 
-    /// Finish the compression stream.
-    pub fn finish(&mut self) -> HSfinishRes {
-        self.flags |= FLAG_IS_FINISHING;
+    /// function to process the input/internal buffer and push the
+    /// compressed stream to the provided sink.
+    pub fn poll_sink<S: ByteSink + ?Sized>(&mut self, sink: &mut S) -> (HSpollRes, usize) {
+        self.poll_steps(sink, usize::MAX)
+    }
+
+    /// function to process the input/internal buffer and put the compressed
+    /// stream in the provided buffer, doing at most max_steps steps of work.
+    ///
+    /// A step is a state transition, or indexing, shifting or scanning 64
+    /// bytes of the window, or comparing a candidate of the search for a
+    /// back-reference (up to a lookahead). Both the output (by the buffer
+    /// size) and the work done are bounded, so that the instance can be
+    /// driven from a periodic loop. PollMore is returned when the budget is
+    /// used up, as more work may be pending: the indexing, shift or search
+    /// in progress is resumed by the next call.
+    pub fn poll_with_budget(
+        &mut self,
+        mut output_buffer: &mut [u8],
+        max_steps: usize,
+    ) -> (HSpollRes, usize) {
+        self.poll_steps(&mut output_buffer, max_steps)
+    }
+
+    fn poll_steps<S: ByteSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        max_steps: usize,
+    ) -> (HSpollRes, usize) {
+        let mut budget = max_steps;
+        let (res, output_size) = self.poll_output(sink, &mut budget);
+        #[cfg(test)]
+        {
+            self.steps += max_steps - budget;
+        }
+        self.total_out += output_size as u64;
+        (res, output_size)
+    }
+
+    /// Number of bytes sunk since the instance was created or reset
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    /// Number of compressed bytes polled since the instance was created or
+    /// reset
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Tell if the compressed stream emitted so far is larger than the input
+    /// it encodes by more than margin bytes, e.g. to abandon the compression
+    /// of an already compressed payload and send it raw instead.
+    ///
+    /// The input still waiting in the window is not counted, so that the
+    /// check can be done after any poll.
+    pub fn is_expanding(&self, margin: u64) -> bool {
+        let pending = self.input_size.saturating_sub(self.match_scan_index) as u64;
+        let encoded_bits = 8 * self.total_in.saturating_sub(pending);
+        let compressed_bits = 8 * self.total_out + u64::from(8 - self.bit_index.min(8));
+
+        compressed_bits > encoded_bits + 8 * margin
+    }
+
+    /// Statistics of the compressed stream emitted since the instance was
+    /// created, reset or restored
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> &EncoderStats {
+        &self.stats
+    }
+
+    /// Number of steps of work done by the poll calls since the instance
+    /// was created
+    #[cfg(test)]
+    pub(crate) fn steps(&self) -> usize {
+        self.steps
+    }
+
+    fn poll_output<S: ByteSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        budget: &mut usize,
+    ) -> (HSpollRes, usize) {
+        // even without room for output, the states that don't emit any
+        // (e.g. the end of an empty stream) are run
+        let mut output_size: usize = 0;
+        let mut output_info = OutputInfo::new(sink, &mut output_size);
+
+        loop {
+            let previous_state = self.state;
+
+            // the states working on the window take a step per part of it
+            let has_budget = match previous_state {
+                HSEstate::Filled | HSEstate::Search | HSEstate::SaveBacklog => *budget > 0,
+                _ => take_step(budget),
+            };
+            if !has_budget {
+                return (HSpollRes::PollMore, output_size);
+            }
+
+            match previous_state {
+                HSEstate::NotFull => {
+                    return (HSpollRes::PollEmpty, output_size);
+                }
+                HSEstate::Filled => {
+                    if self.index_by_steps(budget) {
+                        self.state = HSEstate::Search;
+                    }
+                }
+                HSEstate::Search => match self.st_step_search(budget) {
+                    Ok(state) => self.state = state,
+                    Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                },
+                HSEstate::YieldTagBit => match self.st_yield_tag_bit(&mut output_info) {
+                    Ok(state) => self.state = state,
+                    Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                },
+                HSEstate::YieldLiteral => match self.st_yield_literal(&mut output_info) {
+                    Ok(state) => self.state = state,
+                    Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                },
+                HSEstate::YieldBrIndex => match self.st_yield_br_index(&mut output_info) {
+                    Ok(state) => self.state = state,
+                    Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                },
+                HSEstate::YieldBrLength => match self.st_yield_br_length(&mut output_info) {
+                    Ok(state) => self.state = state,
+                    Err(_) => return (HSpollRes::PollErrorMisuse, output_size),
+                },
+                HSEstate::SaveBacklog => {
+                    self.state = self.st_save_backlog(budget);
+                }
+                HSEstate::FlushBits => {
+                    self.state = self.st_flush_bit_buffer(&mut output_info);
+                    if self.state != HSEstate::SaveBacklog {
+                        return (HSpollRes::PollEmpty, output_size);
+                    }
+                }
+                HSEstate::Done => {
+                    return (HSpollRes::PollEmpty, output_size);
+                }
+            }
+
+            // If the current state cannot advance, check if output
+            // buffer is exhausted.
+            if self.state == previous_state && !output_info.can_take_byte() {
+                return (HSpollRes::PollMore, output_size);
+            }
+        }
+    }
+
+    /// Finish the compression stream.
+    pub fn finish(&mut self) -> HSfinishRes {
+        self.flags |= FLAG_IS_FINISHING;
 
         if self.state == HSEstate::NotFull {
             self.state = HSEstate::Filled;
@@ -283,116 +1532,411 @@ impl HeatshrinkEncoder {
         }
     }
 
-    fn st_step_search(&mut self) -> HSEstate {
-        if self.match_scan_index
-            + (if self.is_finishing() {
-                1
-            } else {
-                self.get_lookahead_size()
-            })
-            > self.input_size
-        {
-            if self.is_finishing() {
-                HSEstate::FlushBits
-            } else {
-                HSEstate::SaveBacklog
+    /// Finish the compression stream and put all its remaining compressed
+    /// data in the provided buffer, returning the number of bytes written.
+    ///
+    /// If the buffer is too small, it is filled and `OutputFull` is returned.
+    /// Calling this function again with a fresh buffer provides the rest of
+    /// the compressed stream.
+    pub fn finish_into(&mut self, output_buffer: &mut [u8]) -> Result<usize, HSError> {
+        self.poll_until(output_buffer, Self::finish)
+    }
+
+    /// Flush the data sunk so far without ending the stream: all of it is
+    /// compressed (without waiting for a full lookahead) and the last byte
+    /// is padded with zero bits.
+    ///
+    /// The output polled up to this point is a decodable prefix of the
+    /// stream, e.g. a radio packet, while the window is kept so that the
+    /// following data still references the previous one. The decoder must
+    /// drop the padding at the end of each such prefix (see
+    /// `decoder::HeatshrinkDecoder::sync`).
+    ///
+    /// Like `finish`, return More until all the flushed data was polled.
+    /// Each flush costs some compression ratio and a copy of the window.
+    pub fn flush(&mut self) -> HSfinishRes {
+        if self.is_finishing() {
+            return self.finish();
+        }
+
+        if self.state == HSEstate::NotFull {
+            if self.input_size == self.match_scan_index && self.bit_index == 8 {
+                return HSfinishRes::FinishDone;
             }
-        } else {
-            let end = self.get_input_offset() + self.match_scan_index;
-            let start = end - self.get_input_buffer_size();
-            let max_possible =
-                if self.input_size < (self.get_lookahead_size() + self.match_scan_index) {
-                    self.input_size - self.match_scan_index
-                } else {
-                    self.get_lookahead_size()
-                };
+            self.flags |= FLAG_IS_FLUSHING;
+            self.state = HSEstate::Filled;
+        }
 
-            match self.find_longest_match(start, end, max_possible) {
-                None => {
-                    self.match_scan_index += 1;
-                    self.match_length = 0;
+        HSfinishRes::FinishMore
+    }
+
+    /// Flush the data sunk so far (see `flush`) and put its compressed data
+    /// in the provided buffer, returning the number of bytes written.
+    ///
+    /// If the buffer is too small, it is filled and `OutputFull` is returned.
+    /// Calling this function again with a fresh buffer provides the rest of
+    /// the flushed data.
+    pub fn flush_into(&mut self, output_buffer: &mut [u8]) -> Result<usize, HSError> {
+        self.poll_until(output_buffer, Self::flush)
+    }
+
+    /// Poll the compressed data into the provided buffer until done
+    /// reports FinishDone
+    fn poll_until(
+        &mut self,
+        output_buffer: &mut [u8],
+        done: fn(&mut Self) -> HSfinishRes,
+    ) -> Result<usize, HSError> {
+        let mut output_size = 0;
+
+        loop {
+            if let HSfinishRes::FinishDone = done(self) {
+                return Ok(output_size);
+            }
+
+            let (res, segment_output_size) = self.poll(
+                output_buffer
+                    .get_mut(output_size..)
+                    .ok_or(HSError::Internal)?,
+            );
+            output_size += segment_output_size;
+
+            match res {
+                // the states that don't emit anything were run, the next
+                // one needs room for output
+                HSpollRes::PollMore if output_size == output_buffer.len() => {
+                    return Err(HSError::OutputFull { needed_hint: None });
+                }
+                HSpollRes::PollMore | HSpollRes::PollEmpty => {}
+                HSpollRes::PollErrorMisuse
+                | HSpollRes::PollErrorLimit
+                | HSpollRes::PollErrorBackref => {
+                    return Err(HSError::Internal);
+                }
+            }
+        }
+    }
+
+    /// Sink the whole input buffer, polling the compressed data as needed
+    /// and handing it to the output callback.
+    pub fn sink_all(
+        &mut self,
+        input_buffer: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), HSError> {
+        let mut input_size = 0;
+
+        while input_size < input_buffer.len() {
+            match self.sink(input_buffer.get(input_size..).ok_or(HSError::Internal)?) {
+                (HSsinkRes::SinkOK, segment_input_size) => {
+                    input_size += segment_input_size;
+                }
+                (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                    return Err(HSError::Internal);
+                }
+            }
+            self.poll_all(output)?;
+        }
+
+        Ok(())
+    }
+
+    /// Poll all the compressed data available, handing it to the output
+    /// callback. Once `finish` was called, this flushes the end of the
+    /// compressed stream.
+    pub fn poll_all(&mut self, output: &mut impl FnMut(&[u8])) -> Result<(), HSError> {
+        let mut output_buffer = [0u8; HEATSHRINK_POLL_BUFFER_SIZE];
+
+        loop {
+            match self.poll(&mut output_buffer) {
+                (HSpollRes::PollMore, output_size) => {
+                    output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                }
+                (HSpollRes::PollEmpty, output_size) => {
+                    if output_size > 0 {
+                        output(output_buffer.get(..output_size).ok_or(HSError::Internal)?);
+                    }
+                    if !self.is_finishing() {
+                        return Ok(());
+                    }
+                    if let HSfinishRes::FinishDone = self.finish() {
+                        return Ok(());
+                    }
+                }
+                (HSpollRes::PollErrorMisuse, _)
+                | (HSpollRes::PollErrorLimit, _)
+                | (HSpollRes::PollErrorBackref, _) => {
+                    return Err(HSError::Internal);
+                }
+            }
+        }
+    }
+
+    /// Poll all the compressed data available, appending it to any container
+    /// implementing `Extend<u8>` (`Vec`, `heapless::Vec`, `ArrayVec`, ...).
+    /// Once `finish` was called, this flushes the end of the compressed
+    /// stream.
+    pub fn poll_into<E: Extend<u8>>(&mut self, out: &mut E) -> Result<(), HSError> {
+        self.poll_all(&mut |data: &[u8]| out.extend(data.iter().copied()))
+    }
+
+    fn st_step_search(&mut self, budget: &mut usize) -> Result<HSEstate, HSError> {
+        let mut search = match self.search {
+            SearchProgress::Idle => {
+                if !take_step(budget) {
+                    return Ok(HSEstate::Search);
                 }
-                Some(position_result) => {
-                    self.match_position = position_result.0;
-                    self.match_length = position_result.1;
-                    assert!(self.match_position <= 1 << HEATSHRINK_WINDOWS_BITS);
+                if self.match_scan_index
+                    + (if self.is_finishing() || self.is_flushing() {
+                        1
+                    } else {
+                        self.get_lookahead_size()
+                    })
+                    > self.input_size
+                {
+                    return if self.is_finishing() || self.is_flushing() {
+                        Ok(HSEstate::FlushBits)
+                    } else {
+                        Ok(HSEstate::SaveBacklog)
+                    };
                 }
+                if self.skips_search() {
+                    return self.set_match(None);
+                }
+
+                let end = self.get_input_offset() + self.match_scan_index;
+                let start = end - self.max_backref_distance;
+                let max_possible =
+                    if self.input_size < (self.max_match_length + self.match_scan_index) {
+                        self.input_size - self.match_scan_index
+                    } else {
+                        self.max_match_length
+                    };
+                SearchProgress::Match(MatchSearch::new(start, end, max_possible))
+            }
+            search => search,
+        };
+
+        let buffer = self.input_buffer.as_flattened();
+        loop {
+            match &mut search {
+                SearchProgress::Idle => return Err(HSError::Internal),
+                SearchProgress::Match(match_search) => {
+                    if !self.advance_search(buffer, match_search, budget) {
+                        self.search = search;
+                        return Ok(HSEstate::Search);
+                    }
+                    let found = self.search_result(match_search);
+
+                    // lazy matching: look for a better match at the next
+                    // position before emitting a back-reference
+                    let end = match_search.end + 1;
+                    let start = end.saturating_sub(self.max_backref_distance);
+                    let maxlen =
+                        (self.input_size - self.match_scan_index - 1).min(self.max_match_length);
+                    match found {
+                        Some(found @ (_, length))
+                            if self.lazy_matching && maxlen > length && start < end =>
+                        {
+                            search =
+                                SearchProgress::Lazy(found, MatchSearch::new(start, end, maxlen));
+                        }
+                        _ => return self.set_match(found),
+                    }
+                }
+                SearchProgress::Lazy(found, lazy_search) => {
+                    if !self.advance_search(buffer, lazy_search, budget) {
+                        self.search = search;
+                        return Ok(HSEstate::Search);
+                    }
+                    let found = *found;
+                    let backref_bits = usize::from(1 + self.window_bits + self.lookahead_bits);
+                    let better = self
+                        .search_result(lazy_search)
+                        .is_some_and(|(_, next_length)| {
+                            (next_length + 1) * backref_bits > found.1 * (9 + backref_bits)
+                        });
+                    return self.set_match(if better { None } else { Some(found) });
+                }
+            }
+        }
+    }
+
+    /// Emit the (distance, length) back-reference found at the scan index,
+    /// or a literal, once the search is done
+    fn set_match(&mut self, found: Option<(usize, usize)>) -> Result<HSEstate, HSError> {
+        self.search = SearchProgress::Idle;
+        match found {
+            None => {
+                self.match_scan_index += 1;
+                self.match_length = 0;
+                self.literal_run = self.literal_run.saturating_add(1);
+            }
+            // a back-reference can't reach past the window
+            Some((position, _)) if position > 1 << self.window_bits => {
+                return Err(HSError::Internal);
+            }
+            Some(position_result) => {
+                self.match_position = position_result.0;
+                self.match_length = position_result.1;
+                self.literal_run = 0;
             }
-            HSEstate::YieldTagBit
         }
+        Ok(HSEstate::YieldTagBit)
     }
 
-    fn st_yield_tag_bit(&mut self, output_info: &mut OutputInfo) -> HSEstate {
+    fn st_yield_tag_bit<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<HSEstate, HSError> {
         if output_info.can_take_byte() {
             if self.match_length == 0 {
-                self.add_tag_bit(output_info, 0x1);
-                HSEstate::YieldLiteral
+                #[cfg(feature = "stats")]
+                self.stats.add_literal();
+                if self.token_fits(output_info, 9) {
+                    // the whole literal at once
+                    let byte = self.get_literal_byte();
+                    self.push_bits(9, 0x100 | u32::from(byte), output_info)?;
+                    return Ok(HSEstate::Search);
+                }
+                self.add_tag_bit(output_info, 0x1)?;
+                Ok(HSEstate::YieldLiteral)
             } else {
-                self.add_tag_bit(output_info, 0);
+                #[cfg(feature = "stats")]
+                self.stats
+                    .add_backref(self.match_length, self.window_bits + self.lookahead_bits);
+                let token_bits = 1 + self.window_bits + self.lookahead_bits;
+                if self.token_fits(output_info, token_bits.into()) {
+                    // the whole back-reference at once
+                    let index = self.match_position as u32 - 1;
+                    let count = self.match_length as u32 - 1;
+                    self.push_bits(
+                        token_bits,
+                        index << self.lookahead_bits | count,
+                        output_info,
+                    )?;
+                    self.match_scan_index += self.match_length;
+                    self.match_length = 0;
+                    return Ok(HSEstate::Search);
+                }
+                self.add_tag_bit(output_info, 0)?;
                 self.outgoing_bits = self.match_position as u16 - 1;
-                self.outgoing_bits_count = 8;
-                HSEstate::YieldBrIndex
+                self.outgoing_bits_count = self.window_bits;
+                Ok(HSEstate::YieldBrIndex)
             }
         } else {
-            HSEstate::YieldTagBit
+            Ok(HSEstate::YieldTagBit)
         }
     }
 
-    fn st_yield_literal(&mut self, output_info: &mut OutputInfo) -> HSEstate {
+    fn st_yield_literal<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<HSEstate, HSError> {
         if output_info.can_take_byte() {
-            self.push_literal_byte(output_info);
-            HSEstate::Search
+            self.push_literal_byte(output_info)?;
+            Ok(HSEstate::Search)
         } else {
-            HSEstate::YieldLiteral
+            Ok(HSEstate::YieldLiteral)
         }
     }
 
-    fn st_yield_br_index(&mut self, output_info: &mut OutputInfo) -> HSEstate {
+    fn st_yield_br_index<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<HSEstate, HSError> {
         if output_info.can_take_byte() {
-            if self.push_outgoing_bits(output_info) > 0 {
-                HSEstate::YieldBrIndex
+            if self.push_outgoing_bits(output_info)? > 0 {
+                Ok(HSEstate::YieldBrIndex)
             } else {
                 self.outgoing_bits = self.match_length as u16 - 1;
-                self.outgoing_bits_count = 4;
-                HSEstate::YieldBrLength
+                self.outgoing_bits_count = self.lookahead_bits;
+                Ok(HSEstate::YieldBrLength)
             }
         } else {
-            HSEstate::YieldBrIndex
+            Ok(HSEstate::YieldBrIndex)
         }
     }
 
-    fn st_yield_br_length(&mut self, output_info: &mut OutputInfo) -> HSEstate {
+    fn st_yield_br_length<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<HSEstate, HSError> {
         if output_info.can_take_byte() {
-            if self.push_outgoing_bits(output_info) > 0 {
-                HSEstate::YieldBrLength
+            if self.push_outgoing_bits(output_info)? > 0 {
+                Ok(HSEstate::YieldBrLength)
             } else {
                 self.match_scan_index += self.match_length;
                 self.match_length = 0;
-                HSEstate::Search
+                Ok(HSEstate::Search)
             }
         } else {
-            HSEstate::YieldBrLength
+            Ok(HSEstate::YieldBrLength)
         }
     }
 
-    fn st_save_backlog(&mut self) -> HSEstate {
-        self.save_backlog();
+    /// Copy processed data to beginning of buffer, so it can be used for
+    /// future matches, a step of the budget at a time. Don't bother checking
+    /// whether the input is less than the maximum size, because if it isn't,
+    /// we're done anyway.
+    fn st_save_backlog(&mut self, budget: &mut usize) -> HSEstate {
+        let shift = self.match_scan_index;
+        let size = 2 * self.get_input_buffer_size();
+        let moved = size.saturating_sub(shift);
+
+        while self.shifted < moved {
+            if !take_step(budget) {
+                return HSEstate::SaveBacklog;
+            }
+            let step_end = (self.shifted + WORK_STEP_SIZE).min(moved);
+            if let Some(buffer) = self
+                .input_buffer
+                .as_flattened_mut()
+                .get_mut(self.shifted..step_end + shift)
+            {
+                buffer.copy_within(shift.min(buffer.len()).., 0);
+            }
+            self.shifted = step_end;
+        }
+
+        self.input_size -= shift;
+        self.match_scan_index = 0;
+        self.shifted = 0;
         HSEstate::NotFull
     }
 
-    fn st_flush_bit_buffer(&self, output_info: &mut OutputInfo) -> HSEstate {
-        if self.bit_index == 8 {
-            HSEstate::Done
-        } else if output_info.can_take_byte() {
+    fn st_flush_bit_buffer<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> HSEstate {
+        if self.bit_index != 8 {
+            if !output_info.can_take_byte() {
+                return HSEstate::FlushBits;
+            }
             output_info.push_byte(self.current_byte);
+            #[cfg(feature = "stats")]
+            {
+                self.stats.padding_bits += u64::from(self.bit_index);
+            }
+            self.current_byte = 0;
+            self.bit_index = 8;
+        }
+
+        if self.is_finishing() {
             HSEstate::Done
         } else {
-            HSEstate::FlushBits
+            // a flush: keep the stream going from a byte boundary
+            self.flags &= !FLAG_IS_FLUSHING;
+            HSEstate::SaveBacklog
         }
     }
 
-    fn add_tag_bit(&mut self, output_info: &mut OutputInfo, tag: u8) {
-        self.push_bits(1, tag, output_info)
+    fn add_tag_bit<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+        tag: u8,
+    ) -> Result<(), HSError> {
+        self.push_bits(1, tag.into(), output_info)
     }
 
     fn get_input_offset(&self) -> usize {
@@ -400,193 +1944,826 @@ impl HeatshrinkEncoder {
     }
 
     fn get_input_buffer_size(&self) -> usize {
-        self.input_buffer.len() / 2
+        1 << self.window_bits
     }
 
     fn get_lookahead_size(&self) -> usize {
-        1 << HEATSHRINK_LOOKAHEAD_BITS
+        1 << self.lookahead_bits
     }
 
     fn is_finishing(&self) -> bool {
         (self.flags & FLAG_IS_FINISHING) == FLAG_IS_FINISHING
     }
 
+    fn is_flushing(&self) -> bool {
+        (self.flags & FLAG_IS_FLUSHING) == FLAG_IS_FLUSHING
+    }
+
+    /// Index the window being searched at once
     fn do_indexing(&mut self) {
         #[cfg(feature = "heatshrink-use-index")]
         {
+            self.indexed = 0;
+        }
+        let mut budget = usize::MAX;
+        self.index_by_steps(&mut budget);
+    }
+
+    /// Index the window being searched, a step of the budget at a time.
+    /// Return whether it is done.
+    #[cfg_attr(not(feature = "heatshrink-use-index"), allow(unused_variables))]
+    fn index_by_steps(&mut self, budget: &mut usize) -> bool {
+        #[cfg(feature = "heatshrink-use-index")]
+        if self.use_index {
             /* Build an index array I that contains flattened linked lists
-             * for the previous instances of every byte in the buffer.
+             * for the previous instances of every 2 bytes in the buffer.
              *
-             * For example, if buf[200] == 'x', then index[200] will either
-             * be an offset i such that buf[i] == 'x', or a None value
-             * to indicate end-of-list. This significantly speeds up matching,
-             * while only using sizeof(Option<u16>)*sizeof(buffer) bytes of
-             * RAM.
+             * For example, if buf[200..202] == "xy", then index[200] will
+             * either be an offset i such that buf[i..i + 2] == "xy" (or
+             * hashes like it), or a None value to indicate end-of-list.
+             * This significantly speeds up matching, while only using
+             * sizeof(Option<NonZeroU16>)*sizeof(buffer) bytes of RAM.
              *
              * Future optimization options:
              * -  The last lookahead_sz bytes of the index will not be
              *    usable, so temporary data could be stored there to
              *    dynamically improve the index.
              * */
-            let mut last: [Option<usize>; 256] = [None; 256];
             let end = self.get_input_offset() + self.input_size - 1;
-
-            for i in 0..end {
-                let v: usize = self.input_buffer[i].into();
-                self.search_index[i] = last[v];
-                last[v] = Some(i);
+            if self.indexed == 0 {
+                self.index_heads = [None; INDEX_CHAINS];
+            }
+            while self.indexed < end {
+                if !take_step(budget) {
+                    return false;
+                }
+                let positions = self.indexed..(self.indexed + WORK_STEP_SIZE).min(end);
+                self.indexed = positions.end;
+                index_positions(
+                    self.input_buffer.as_flattened(),
+                    self.search_index.as_flattened_mut(),
+                    &mut self.index_heads,
+                    positions,
+                );
             }
+            self.indexed = 0;
         }
+        true
     }
 
     /// Return the longest match for the bytes at buf[end:end+maxlen] between
     /// buf[start] and buf[end-1]. If no match is found, return -1.
     fn find_longest_match(
         &self,
+        buffer: &[u8],
         start: usize,
         end: usize,
         maxlen: usize,
     ) -> Option<(usize, usize)> {
-        let mut match_maxlen: usize = 0;
-        let mut match_index: usize = 0;
-
-        #[cfg(not(feature = "heatshrink-use-index"))]
-        {
-            let mut position = end - 1;
-
-            while position >= start {
-                if (self.input_buffer[position] == self.input_buffer[end])
-                    && (self.input_buffer[position + match_maxlen]
-                        == self.input_buffer[end + match_maxlen])
-                {
-                    let mut len = 1;
-                    while len < maxlen {
-                        if self.input_buffer[position + len] != self.input_buffer[end + len] {
-                            break;
-                        }
-                        len += 1;
-                    }
+        let mut search = MatchSearch::new(start, end, maxlen);
+        let mut budget = usize::MAX;
+        self.advance_search(buffer, &mut search, &mut budget);
+        self.search_result(&search)
+    }
 
-                    if len > match_maxlen {
-                        match_maxlen = len;
-                        match_index = position;
-                        if len == maxlen {
-                            // don't keep searching
-                            break;
-                        }
-                    }
-                }
+    /// The (distance, length) back-reference to the match found by a
+    /// search, if it is long enough to be worth it
+    fn search_result(&self, search: &MatchSearch) -> Option<(usize, usize)> {
+        let break_even_point: usize = (1 + self.window_bits + self.lookahead_bits).into();
 
-                if position == 0 {
-                    break;
-                } else {
-                    position -= 1;
-                }
-            }
+        // Instead of comparing break_even_point against 8*match_maxlen,
+        // compare match_maxlen against break_even_point/8 to avoid
+        // overflow. Since MIN_WINDOW_BITS and MIN_LOOKAHEAD_BITS are 4 and
+        // 3, respectively, break_even_point/8 will always be at least 1.
+        if search.length > (break_even_point / 8) {
+            Some((search.end - search.position, search.length))
+        } else {
+            None
         }
+    }
 
+    /// Advance the search for the longest match, a step of the budget per
+    /// part of the window scanned or candidate compared. Return whether it
+    /// is done.
+    fn advance_search(&self, buffer: &[u8], search: &mut MatchSearch, budget: &mut usize) -> bool {
         #[cfg(feature = "heatshrink-use-index")]
-        {
-            let mut position = end;
+        if self.use_index {
+            return self.advance_search_indexed(buffer, search, budget);
+        }
 
-            while let Some(next_position) = self.search_index[position] {
-                position = next_position;
+        self.advance_search_linear(buffer, search, budget)
+    }
 
-                if position < start {
-                    break;
-                } else if self.input_buffer[position + match_maxlen]
-                    != self.input_buffer[end + match_maxlen]
-                {
-                    continue;
-                } else {
-                    let mut len = 1;
+    /// Whether the bytes at buf[end:end+maxlen] have a match long enough
+    /// that a literal followed by it costs fewer bits per byte than the
+    /// back-reference of length found before end, for lazy matching
+    fn finds_better_match(&self, buffer: &[u8], end: usize, maxlen: usize, length: usize) -> bool {
+        if maxlen <= length {
+            return false;
+        }
 
-                    while len < maxlen {
-                        if self.input_buffer[position + len] != self.input_buffer[end + len] {
-                            break;
-                        }
-                        len += 1;
-                    }
+        let start = end.saturating_sub(self.max_backref_distance);
+        let backref_bits = usize::from(1 + self.window_bits + self.lookahead_bits);
+        start < end
+            && self
+                .find_longest_match(buffer, start, end, maxlen)
+                .is_some_and(|(_, next_length)| {
+                    (next_length + 1) * backref_bits > length * (9 + backref_bits)
+                })
+    }
 
-                    if len > match_maxlen {
-                        match_maxlen = len;
-                        match_index = position;
-                        if len == maxlen {
-                            // don't keep searching
-                            break;
-                        }
-                    }
+    /// Scan the window backward from buf[end-1] to buf[start] for the
+    /// longest match, WORK_STEP_SIZE bytes at a time.
+    fn advance_search_linear(
+        &self,
+        buffer: &[u8],
+        search: &mut MatchSearch,
+        budget: &mut usize,
+    ) -> bool {
+        let Some(&first) = buffer.get(search.end) else {
+            return true;
+        };
+
+        while search.cursor > search.start {
+            if !take_step(budget) {
+                return false;
+            }
+            let chunk_start = search
+                .cursor
+                .saturating_sub(WORK_STEP_SIZE)
+                .max(search.start);
+            let mut window = buffer.get(chunk_start..search.cursor).unwrap_or_default();
+            // the step of the scan also pays for the first candidate
+            let mut paid = true;
+
+            // only stop at the positions starting with the same byte
+            while let Some(offset) = rfind_byte(window, first) {
+                let position = chunk_start + offset;
+                window = window.get(..offset).unwrap_or_default();
+
+                if search.depth == self.max_search_depth {
+                    return true;
+                }
+                if !paid && !take_step(budget) {
+                    search.cursor = position + 1;
+                    return false;
+                }
+                paid = false;
+                search.depth += 1;
+                search.cursor = position;
+
+                if search.compare(buffer, position) {
+                    return true;
                 }
             }
+            search.cursor = chunk_start;
         }
 
-        let break_even_point: usize =
-            (1 + HEATSHRINK_WINDOWS_BITS + HEATSHRINK_LOOKAHEAD_BITS).into();
+        true
+    }
 
-        // Instead of comparing break_even_point against 8*match_maxlen,
-        // compare match_maxlen against break_even_point/8 to avoid
-        // overflow. Since MIN_WINDOW_BITS and MIN_LOOKAHEAD_BITS are 4 and
-        // 3, respectively, break_even_point/8 will always be at least 1.
-        if match_maxlen > (break_even_point / 8) {
-            Some((end - match_index, match_maxlen))
-        } else {
-            None
+    /// Follow the search index from buf[end] down to buf[start] for the
+    /// longest match, a candidate at a time.
+    #[cfg(feature = "heatshrink-use-index")]
+    fn advance_search_indexed(
+        &self,
+        buffer: &[u8],
+        search: &mut MatchSearch,
+        budget: &mut usize,
+    ) -> bool {
+        while let Some(position) = self
+            .search_index
+            .as_flattened()
+            .get(search.cursor)
+            .copied()
+            .and_then(index_position)
+        {
+            if position < search.start || search.depth == self.max_search_depth {
+                break;
+            }
+            if !take_step(budget) {
+                return false;
+            }
+            search.depth += 1;
+            search.cursor = position;
+
+            if search.compare(buffer, position) {
+                return true;
+            }
         }
+
+        true
     }
 
-    fn push_outgoing_bits(&mut self, output_info: &mut OutputInfo) -> u8 {
+    fn push_outgoing_bits<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<u8, HSError> {
+        // Values wider than a byte (large windows) are sent most significant
+        // byte first, the remaining low bits being masked out on the next call.
         let (count, bits) = if self.outgoing_bits_count > 8 {
             (
                 8,
-                self.outgoing_bits as u8 >> (self.outgoing_bits_count - 8),
+                (self.outgoing_bits >> (self.outgoing_bits_count - 8)) as u8,
             )
         } else {
-            (self.outgoing_bits_count, self.outgoing_bits as u8)
+            (
+                self.outgoing_bits_count,
+                (self.outgoing_bits & ((1 << self.outgoing_bits_count) - 1)) as u8,
+            )
         };
 
         if count > 0 {
-            self.push_bits(count, bits, output_info);
+            self.push_bits(count, bits.into(), output_info)?;
             self.outgoing_bits_count -= count;
         }
 
-        count
+        Ok(count)
     }
 
-    /// Push COUNT (max 8) bits to the output buffer, which has room.
-    /// Bytes are set from the lowest bits, up.
-    fn push_bits(&mut self, count: u8, bits: u8, output_info: &mut OutputInfo) {
-        assert!(count > 0 && count <= 8);
+    /// Push the COUNT (1 to 32) low bits of bits to the output buffer, most
+    /// significant first. The output has room for the bytes they complete.
+    /// Any other count is an internal error.
+    ///
+    /// The bits are accumulated in a word after the bits of the current
+    /// byte, and all the completed bytes are then pushed at once.
+    fn push_bits<S: ByteSink + ?Sized>(
+        &mut self,
+        count: u8,
+        bits: u32,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<(), HSError> {
+        if count == 0 || count > 32 || self.bit_index > 8 {
+            return Err(HSError::Internal);
+        }
 
-        if count >= self.bit_index {
-            let shift = count - self.bit_index;
-            let tmp_byte = self.current_byte | bits >> shift;
-            output_info.push_byte(tmp_byte);
-            self.bit_index = 8 - shift;
-            if shift == 0 {
-                self.current_byte = 0;
-            } else {
-                self.current_byte = bits << self.bit_index;
+        let mask = (1u64 << count) - 1;
+        let accumulator =
+            u64::from(self.current_byte) >> self.bit_index << count | u64::from(bits) & mask;
+        let mut accumulator_count = 8 - self.bit_index + count;
+
+        while accumulator_count >= 8 {
+            accumulator_count -= 8;
+            output_info.push_byte((accumulator >> accumulator_count) as u8);
+        }
+
+        // the remaining bits start the current byte, from its highest bit
+        self.bit_index = 8 - accumulator_count;
+        self.current_byte = (accumulator << self.bit_index) as u8;
+        Ok(())
+    }
+
+    /// Byte emitted as a literal, before the scan index
+    fn get_literal_byte(&self) -> u8 {
+        self.input_buffer
+            .as_flattened()
+            .get(self.get_input_offset() + self.match_scan_index - 1)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn push_literal_byte<S: ByteSink + ?Sized>(
+        &mut self,
+        output_info: &mut OutputInfo<S>,
+    ) -> Result<(), HSError> {
+        self.push_bits(8, self.get_literal_byte().into(), output_info)
+    }
+
+    /// Compress the whole src buffer to the destination buffer, scanning
+    /// src in place instead of copying it to the input buffer of the
+    /// instance chunk by chunk (as `sink` does). Return the number of bytes
+    /// written to dst.
+    ///
+    /// The window is read from the data of src before each position, so
+    /// back-references never point before the start of the stream and the
+    /// output may slightly differ from `encode`'s when src contains zeros.
+    ///
+    /// Only possible on a fresh (or reset) instance, not primed with a
+    /// dictionary. The instance is finished afterwards.
+    pub fn encode_slice(&mut self, src: &[u8], dst: &mut [u8]) -> Result<usize, HSError> {
+        if self.flags != 0 || self.total_in != 0 || self.state != HSEstate::NotFull {
+            return Err(HSError::Internal);
+        }
+        self.flags |= FLAG_IS_FINISHING;
+
+        let output_full = HSError::OutputFull {
+            needed_hint: Some(encode_bound(src.len())),
+        };
+        let window_size = self.get_input_buffer_size();
+        let mut output = dst;
+        let mut output_size = 0;
+        let mut output_info = OutputInfo::new(&mut output, &mut output_size);
+        let mut base = 0;
+
+        while base < src.len() {
+            // scan the next chunk of src along with the window before it
+            let offset = base.min(window_size);
+            let buffer = src.get(base - offset..).ok_or(HSError::Internal)?;
+            let scan_end = offset + window_size.min(src.len() - base);
+            let mut scan = offset;
+
+            #[cfg(feature = "heatshrink-use-index")]
+            if self.use_index {
+                build_index(buffer, self.search_index.as_flattened_mut(), scan_end);
             }
-        } else {
-            self.bit_index -= count;
-            self.current_byte |= bits << self.bit_index;
+
+            while scan < scan_end {
+                let start = scan.saturating_sub(self.max_backref_distance);
+                let maxlen = self.max_match_length.min(buffer.len() - scan);
+                let found = if start < scan && !self.skips_search() {
+                    self.find_longest_match(buffer, start, scan, maxlen)
+                } else {
+                    None
+                };
+                // the index stops at scan_end
+                let found = found.filter(|&(_, length)| {
+                    !self.lazy_matching
+                        || scan + 1 >= scan_end
+                        || !self.finds_better_match(
+                            buffer,
+                            scan + 1,
+                            self.max_match_length.min(buffer.len() - scan - 1),
+                            length,
+                        )
+                });
+
+                match found {
+                    None => {
+                        if !self.token_fits(&output_info, 9) {
+                            return Err(output_full);
+                        }
+                        let byte = *buffer.get(scan).ok_or(HSError::Internal)?;
+                        self.push_bits(9, 0x100 | u32::from(byte), &mut output_info)?;
+                        self.literal_run = self.literal_run.saturating_add(1);
+                        scan += 1;
+                    }
+                    Some((position, length)) => {
+                        let token_bits = 1 + self.window_bits + self.lookahead_bits;
+                        if !self.token_fits(&output_info, token_bits.into()) {
+                            return Err(output_full);
+                        }
+                        let index = position as u32 - 1;
+                        let count = length as u32 - 1;
+                        self.push_bits(
+                            token_bits,
+                            index << self.lookahead_bits | count,
+                            &mut output_info,
+                        )?;
+                        self.literal_run = 0;
+                        scan += length;
+                    }
+                }
+            }
+
+            base += scan - offset;
         }
+
+        if self.bit_index != 8 {
+            if !output_info.can_take_byte() {
+                return Err(output_full);
+            }
+            output_info.push_byte(self.current_byte);
+        }
+
+        self.state = HSEstate::Done;
+        self.total_in = src.len() as u64;
+        self.total_out += output_size as u64;
+
+        Ok(output_size)
     }
 
-    fn push_literal_byte(&mut self, output_info: &mut OutputInfo) {
-        self.push_bits(
-            8,
-            self.input_buffer[self.get_input_offset() + self.match_scan_index - 1],
-            output_info,
-        );
+    /// Whether skip-ahead emits the current position as a literal without
+    /// searching it: past the threshold, only one position out of the
+    /// stride is searched
+    fn skips_search(&self) -> bool {
+        if !self.skip_ahead || self.literal_run < SKIP_AHEAD_THRESHOLD {
+            return false;
+        }
+        let run = self.literal_run - SKIP_AHEAD_THRESHOLD;
+        let stride = (1 + (run >> SKIP_AHEAD_STRIDE_SHIFT)).min(SKIP_AHEAD_MAX_STRIDE);
+
+        !run.is_multiple_of(stride)
     }
 
-    fn save_backlog(&mut self) {
-        // Copy processed data to beginning of buffer, so it can be used for
-        // future matches. Don't bother checking whether the input is less
-        // than the maximum size, because if it isn't, we're done anyway.
-        self.input_buffer.copy_within(self.match_scan_index.., 0);
-        self.input_size -= self.match_scan_index;
-        self.match_scan_index = 0;
+    /// Whether the output has room for the bytes completed by pushing
+    /// token_bits more bits
+    fn token_fits<S: ByteSink + ?Sized>(
+        &self,
+        output_info: &OutputInfo<S>,
+        token_bits: usize,
+    ) -> bool {
+        (usize::from(8 - self.bit_index) + token_bits) / 8 <= output_info.remaining_free_size()
+    }
+}
+
+/// Take a step of the budget of a poll call, if one is left
+fn take_step(budget: &mut usize) -> bool {
+    if *budget == 0 {
+        return false;
+    }
+    *budget -= 1;
+    true
+}
+
+/// Number of bytes, up to maxlen, matching between buffer[position..] and
+/// buffer[end..]
+#[cfg(not(feature = "simd"))]
+fn match_length(buffer: &[u8], position: usize, end: usize, maxlen: usize) -> usize {
+    let candidate = buffer.get(position..).unwrap_or_default();
+    let current = buffer.get(end..).unwrap_or_default();
+
+    candidate
+        .iter()
+        .zip(current)
+        .take(maxlen)
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+/// Number of bytes, up to maxlen, matching between buffer[position..] and
+/// buffer[end..].
+///
+/// The bytes are compared 16 at a time as `u128` words: the first
+/// mismatching byte is given by the trailing zeros of their difference, the
+/// last few bytes being compared one by one.
+#[cfg(feature = "simd")]
+fn match_length(buffer: &[u8], position: usize, end: usize, maxlen: usize) -> usize {
+    const WORD_SIZE: usize = core::mem::size_of::<u128>();
+    let candidate = buffer.get(position..).unwrap_or_default();
+    let current = buffer.get(end..).unwrap_or_default();
+    let maxlen = maxlen.min(candidate.len()).min(current.len());
+    let (candidate, current) = (
+        candidate.get(..maxlen).unwrap_or_default(),
+        current.get(..maxlen).unwrap_or_default(),
+    );
+    let mut length = 0;
+
+    for (a, b) in candidate
+        .chunks_exact(WORD_SIZE)
+        .zip(current.chunks_exact(WORD_SIZE))
+    {
+        let (Ok(a), Ok(b)) = (a.try_into(), b.try_into()) else {
+            break;
+        };
+        let difference = u128::from_le_bytes(a) ^ u128::from_le_bytes(b);
+        if difference != 0 {
+            return length + (difference.trailing_zeros() / 8) as usize;
+        }
+        length += WORD_SIZE;
+    }
+
+    length
+        + candidate
+            .iter()
+            .zip(current)
+            .skip(length)
+            .take_while(|(a, b)| a == b)
+            .count()
+}
+
+/// Position of the last occurrence of byte in haystack.
+///
+/// The haystack is scanned backward 8 bytes at a time, comparing the words
+/// to byte repeated with the usual "has zero byte" bit trick, and only the
+/// word holding byte is then scanned byte by byte.
+fn rfind_byte(haystack: &[u8], byte: u8) -> Option<usize> {
+    const ONES: u64 = u64::from_ne_bytes([0x01; 8]);
+    const HIGHS: u64 = u64::from_ne_bytes([0x80; 8]);
+    let pattern = ONES * u64::from(byte);
+    let mut end = haystack.len();
+
+    while let Some(word) = end
+        .checked_sub(8)
+        .and_then(|word_start| haystack.get(word_start..end))
+        .and_then(|word| word.try_into().ok())
+    {
+        let x = u64::from_ne_bytes(word) ^ pattern;
+        if x.wrapping_sub(ONES) & !x & HIGHS != 0 {
+            break;
+        }
+        end -= 8;
+    }
+
+    haystack.get(..end)?.iter().rposition(|&b| b == byte)
+}
+
+/// Entry of the search index: the previous position starting with the same
+/// 2 bytes (or colliding with them), stored plus one so that `None` fits in
+/// the 16 bits of a `NonZeroU16`. The positions in a window of at most 2^15
+/// bytes and its lookahead, that are linked to, are below 2^16 - 1.
+#[cfg(feature = "heatshrink-use-index")]
+type IndexEntry = Option<NonZeroU16>;
+
+/// Index entry linking to position
+#[cfg(feature = "heatshrink-use-index")]
+fn index_entry(position: usize) -> IndexEntry {
+    u16::try_from(position + 1).ok().and_then(NonZeroU16::new)
+}
+
+/// Position an index entry links to
+#[cfg(feature = "heatshrink-use-index")]
+fn index_position(entry: IndexEntry) -> Option<usize> {
+    entry.map(|entry| usize::from(entry.get()) - 1)
+}
+
+/// Number of chains of the search index
+#[cfg(feature = "heatshrink-use-index")]
+const INDEX_CHAINS: usize = 256;
+
+/// Chain of the search index of the 2 bytes starting with first
+#[cfg(feature = "heatshrink-use-index")]
+fn index_chain(first: u8, second: u8) -> usize {
+    usize::from(u16::from_le_bytes([first, second]).wrapping_mul(0x9e37) >> 8) % INDEX_CHAINS
+}
+
+/// Link every position of buffer before end to the previous position
+/// starting with the same 2 bytes (or colliding with them), in search_index.
+///
+/// Back-references are at least 2 bytes long, so chaining on 2 bytes rather
+/// than 1 only skips positions that can't match, which keeps repetitive data
+/// made of a few distinct bytes from degenerating into a linear search.
+#[cfg(feature = "heatshrink-use-index")]
+fn build_index(buffer: &[u8], search_index: &mut [IndexEntry], end: usize) {
+    index_positions(buffer, search_index, &mut [None; INDEX_CHAINS], 0..end);
+}
+
+/// Link the positions of buffer to the previous position of their chain in
+/// search_index, heads holding the last position indexed in every chain, so
+/// that an index can be built a part at a time
+#[cfg(feature = "heatshrink-use-index")]
+fn index_positions(
+    buffer: &[u8],
+    search_index: &mut [IndexEntry],
+    heads: &mut [IndexEntry; INDEX_CHAINS],
+    positions: Range<usize>,
+) {
+    let bytes = buffer.get(positions.start..).unwrap_or_default();
+    let next_bytes = bytes.iter().skip(1).chain(core::iter::repeat(&0));
+    let entries = search_index.get_mut(positions.start..).unwrap_or_default();
+
+    for (i, ((&byte, &next), previous)) in bytes
+        .iter()
+        .zip(next_bytes)
+        .zip(entries)
+        .take(positions.len())
+        .enumerate()
+    {
+        if let Some(chain) = heads.get_mut(index_chain(byte, next)) {
+            *previous = *chain;
+            *chain = index_entry(positions.start + i);
+        }
+    }
+}
+
+/// Builder of a configured [`HeatshrinkEncoder`]
+///
+/// The options left unset keep the values used by `Default::default()`.
+///
+/// ```
+/// use heatshrink::encoder::EncoderBuilder;
+///
+/// let enc = EncoderBuilder::<1024>::new()
+///     .window_bits(10)
+///     .lookahead_bits(5)
+///     .max_search_depth(16)
+///     .dictionary(b"temperature=")
+///     .build()
+///     .unwrap();
+/// assert_eq!(enc.params().window_bits(), 10);
+/// ```
+#[derive(Debug, Clone)]
+pub struct EncoderBuilder<'a, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    window_bits: Option<u8>,
+    lookahead_bits: Option<u8>,
+    max_search_depth: usize,
+    lazy_matching: bool,
+    skip_ahead: bool,
+    #[cfg(feature = "heatshrink-use-index")]
+    use_index: bool,
+    dictionary: &'a [u8],
+}
+
+impl<const WINDOW_SIZE: usize> Default for EncoderBuilder<'_, WINDOW_SIZE> {
+    fn default() -> Self {
+        EncoderBuilder {
+            window_bits: None,
+            lookahead_bits: None,
+            max_search_depth: usize::MAX,
+            lazy_matching: false,
+            skip_ahead: false,
+            #[cfg(feature = "heatshrink-use-index")]
+            use_index: true,
+            dictionary: &[],
+        }
+    }
+}
+
+impl<'a, const WINDOW_SIZE: usize> EncoderBuilder<'a, WINDOW_SIZE> {
+    /// Create a new builder with the default options
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Base-2 log of LZSS sliding window size
+    pub fn window_bits(mut self, window_bits: u8) -> Self {
+        self.window_bits = Some(window_bits);
+        self
+    }
+
+    /// Number of bits used for back-reference lengths
+    pub fn lookahead_bits(mut self, lookahead_bits: u8) -> Self {
+        self.lookahead_bits = Some(lookahead_bits);
+        self
+    }
+
+    /// Window and lookahead sizes, as validated by [`Params::new`]
+    pub fn params(self, params: Params) -> Self {
+        self.window_bits(params.window_bits())
+            .lookahead_bits(params.lookahead_bits())
+    }
+
+    /// Match-search effort (see [`HeatshrinkEncoder::set_max_search_depth`])
+    pub fn max_search_depth(mut self, max_search_depth: usize) -> Self {
+        self.max_search_depth = max_search_depth;
+        self
+    }
+
+    /// Compression level (see [`HeatshrinkEncoder::set_level`]), setting the
+    /// match-search effort, lazy matching and skip-ahead
+    pub fn level(mut self, level: u8) -> Self {
+        (self.max_search_depth, self.lazy_matching, self.skip_ahead) = level_options(level);
+        self
+    }
+
+    /// Whether back-references are deferred when the next position starts a
+    /// longer one (see [`HeatshrinkEncoder::set_lazy_matching`])
+    pub fn lazy_matching(mut self, lazy_matching: bool) -> Self {
+        self.lazy_matching = lazy_matching;
+        self
+    }
+
+    /// Whether the search is skipped at some positions of incompressible
+    /// regions (see [`HeatshrinkEncoder::set_skip_ahead`])
+    pub fn skip_ahead(mut self, skip_ahead: bool) -> Self {
+        self.skip_ahead = skip_ahead;
+        self
+    }
+
+    /// Whether the search index is used (see
+    /// [`HeatshrinkEncoder::set_use_index`])
+    #[cfg(feature = "heatshrink-use-index")]
+    pub fn use_index(mut self, use_index: bool) -> Self {
+        self.use_index = use_index;
+        self
+    }
+
+    /// Dictionary preloaded in the window. Only its last window size bytes
+    /// are used, and the decoder must be primed with the same dictionary
+    /// (see `decoder::HeatshrinkDecoder::prime`).
+    pub fn dictionary(mut self, dictionary: &'a [u8]) -> Self {
+        self.dictionary = dictionary;
+        self
+    }
+
+    /// Create the encoder instance.
+    ///
+    /// Fails if the window and lookahead sizes are not supported or if the
+    /// window does not fit in `WINDOW_SIZE`.
+    pub fn build(self) -> Result<HeatshrinkEncoder<WINDOW_SIZE>, ParamError> {
+        let default_params = Params::for_window_size(WINDOW_SIZE)?;
+        let params = Params::new(
+            self.window_bits.unwrap_or(default_params.window_bits()),
+            self.lookahead_bits
+                .unwrap_or(default_params.lookahead_bits()),
+        )?;
+
+        let mut encoder = HeatshrinkEncoder::with_params(params)?;
+        encoder.set_max_search_depth(self.max_search_depth);
+        encoder.set_lazy_matching(self.lazy_matching);
+        encoder.set_skip_ahead(self.skip_ahead);
+        #[cfg(feature = "heatshrink-use-index")]
+        encoder.set_use_index(self.use_index);
+        if !self.dictionary.is_empty() {
+            encoder.prime(self.dictionary);
+        }
+
+        Ok(encoder)
+    }
+}
+
+/// Marker of an [`Encoder`] accepting input data
+#[derive(Debug, Clone)]
+pub struct Sinking;
+
+/// Marker of an [`Encoder`] flushing the end of the compressed stream
+#[derive(Debug, Clone)]
+pub struct Finishing;
+
+/// Encoder wrapper tracking in its type whether input data can still be
+/// sunk, so that sinking data after finishing the stream does not compile.
+///
+/// An `Encoder<Sinking>` becomes an `Encoder<Finishing>` when `finish` is
+/// called. The `Encoder<Finishing>` must then be polled until its `finish`
+/// returns the [`Finished`] encoder.
+///
+/// ```compile_fail
+/// let mut enc = heatshrink::encoder::Encoder::new().finish();
+/// enc.sink(b"too late");
+/// ```
+#[derive(Debug, Clone)]
+pub struct Encoder<S, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    state: PhantomData<S>,
+}
+
+/// Encoder whose compressed stream is complete
+#[derive(Debug, Clone)]
+pub struct Finished<const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+}
+
+impl Encoder<Sinking> {
+    /// Create a new encoder instance using the default parameters
+    pub fn new() -> Self {
+        Encoder::with_encoder(HeatshrinkEncoder::new())
+    }
+}
+
+impl Default for Encoder<Sinking> {
+    fn default() -> Self {
+        Encoder::new()
+    }
+}
+
+impl<const WINDOW_SIZE: usize> Encoder<Sinking, WINDOW_SIZE> {
+    /// Wrap the provided encoder (and therefore its parameters). The
+    /// encoder is reset so that it accepts input data.
+    pub fn with_encoder(mut encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        encoder.reset();
+        Encoder {
+            encoder,
+            state: PhantomData,
+        }
+    }
+
+    /// Number of bytes the next `sink` call can accept (see
+    /// `HeatshrinkEncoder::sink_capacity`)
+    pub fn sink_capacity(&self) -> usize {
+        self.encoder.sink_capacity()
+    }
+
+    /// Add an input buffer to be processed/compressed
+    pub fn sink(&mut self, input_buffer: &[u8]) -> (HSsinkRes, usize) {
+        self.encoder.sink(input_buffer)
+    }
+
+    /// Flush the data sunk so far into the provided buffer without ending
+    /// the stream (see `HeatshrinkEncoder::flush_into`)
+    pub fn flush_into(&mut self, output_buffer: &mut [u8]) -> Result<usize, HSError> {
+        self.encoder.flush_into(output_buffer)
+    }
+
+    /// Signal the end of the input data
+    pub fn finish(mut self) -> Encoder<Finishing, WINDOW_SIZE> {
+        self.encoder.finish();
+        Encoder {
+            encoder: self.encoder,
+            state: PhantomData,
+        }
+    }
+}
+
+impl<const WINDOW_SIZE: usize> Encoder<Finishing, WINDOW_SIZE> {
+    /// Return the finished encoder if all the compressed stream was polled,
+    /// or this encoder so that it can be polled again.
+    // the encoder is handed back by value, as it is everywhere in this API
+    #[allow(clippy::result_large_err)]
+    pub fn finish(mut self) -> Result<Finished<WINDOW_SIZE>, Self> {
+        match self.encoder.finish() {
+            HSfinishRes::FinishDone => Ok(Finished {
+                encoder: self.encoder,
+            }),
+            HSfinishRes::FinishMore => Err(self),
+        }
+    }
+}
+
+impl<S, const WINDOW_SIZE: usize> Encoder<S, WINDOW_SIZE> {
+    /// function to process the input/internal buffer and put the compressed
+    /// stream in the provided buffer.
+    pub fn poll(&mut self, output_buffer: &mut [u8]) -> (HSpollRes, usize) {
+        self.encoder.poll(output_buffer)
+    }
+}
+
+impl<const WINDOW_SIZE: usize> Finished<WINDOW_SIZE> {
+    /// Reset the encoder so that it can compress a new stream
+    pub fn reset(self) -> Encoder<Sinking, WINDOW_SIZE> {
+        Encoder::with_encoder(self.encoder)
+    }
+
+    /// Unwrap the underlying encoder
+    pub fn into_inner(self) -> HeatshrinkEncoder<WINDOW_SIZE> {
+        self.encoder
     }
 }
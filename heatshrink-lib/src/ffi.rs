@@ -0,0 +1,423 @@
+// the C API hands raw pointers over, the rest of the crate stays safe
+#![allow(unsafe_code)]
+// the names of the C API are kept as they are in heatshrink.h
+#![allow(non_camel_case_types)]
+
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSfinishRes;
+use super::HSpollRes;
+use super::HSsinkRes;
+use super::Params;
+use super::HEATSHRINK_MAX_WINDOWS_BITS;
+
+use alloc::boxed::Box;
+use core::slice;
+
+/// Window capacity of the instances allocated for windows of up to 11 bits
+const SMALL_WINDOW_SIZE: usize = 1 << 11;
+
+/// Window capacity of the instances allocated for larger windows. The
+/// buffers of the encoder must fit in a 16-bit `usize` on 16-bit targets.
+const LARGE_WINDOW_SIZE: usize = if usize::BITS > 16 {
+    1 << HEATSHRINK_MAX_WINDOWS_BITS
+} else {
+    1 << 13
+};
+
+/// Size of the input buffer of the decoders, the size requested from
+/// `heatshrink_decoder_alloc` is only checked to be non-zero
+#[cfg(feature = "decoder")]
+const DECODER_INPUT_SIZE: usize = 256;
+
+/// Call the same code on the instance of a handle whatever its capacity
+macro_rules! with_instance {
+    ($handle:expr, $instance:ident => $body:expr) => {
+        match $handle {
+            Instance::Small($instance) => $body,
+            Instance::Large($instance) => $body,
+        }
+    };
+}
+
+/// Instance sized for the window it was allocated for, so that small
+/// windows don't pay for the largest one
+enum Instance<S, L> {
+    Small(S),
+    Large(L),
+}
+
+/// Result of `heatshrink_encoder_sink`
+#[cfg(feature = "encoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSE_sink_res {
+    /// data sunk into the input buffer
+    HSER_SINK_OK = 0,
+    /// NULL argument
+    HSER_SINK_ERROR_NULL = -1,
+    /// the encoder must be polled or was finished
+    HSER_SINK_ERROR_MISUSE = -2,
+}
+
+/// Result of `heatshrink_encoder_poll`
+#[cfg(feature = "encoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSE_poll_res {
+    /// input exhausted
+    HSER_POLL_EMPTY = 0,
+    /// poll again for more output
+    HSER_POLL_MORE = 1,
+    /// NULL argument
+    HSER_POLL_ERROR_NULL = -1,
+    /// empty output buffer
+    HSER_POLL_ERROR_MISUSE = -2,
+}
+
+/// Result of `heatshrink_encoder_finish`
+#[cfg(feature = "encoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSE_finish_res {
+    /// encoding is complete
+    HSER_FINISH_DONE = 0,
+    /// more output remains, poll it
+    HSER_FINISH_MORE = 1,
+    /// NULL argument
+    HSER_FINISH_ERROR_NULL = -1,
+}
+
+/// Result of `heatshrink_decoder_sink`
+#[cfg(feature = "decoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSD_sink_res {
+    /// data sunk, ready to poll
+    HSDR_SINK_OK = 0,
+    /// out of space in the input buffer, poll first
+    HSDR_SINK_FULL = 1,
+    /// NULL argument
+    HSDR_SINK_ERROR_NULL = -1,
+}
+
+/// Result of `heatshrink_decoder_poll`
+#[cfg(feature = "decoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSD_poll_res {
+    /// input exhausted
+    HSDR_POLL_EMPTY = 0,
+    /// more data remaining, call again with a fresh output buffer
+    HSDR_POLL_MORE = 1,
+    /// NULL argument
+    HSDR_POLL_ERROR_NULL = -1,
+    /// invalid compressed stream or empty output buffer
+    HSDR_POLL_ERROR_UNKNOWN = -2,
+}
+
+/// Result of `heatshrink_decoder_finish`
+#[cfg(feature = "decoder")]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HSD_finish_res {
+    /// output is done
+    HSDR_FINISH_DONE = 0,
+    /// more output remains
+    HSDR_FINISH_MORE = 1,
+    /// NULL argument
+    HSDR_FINISH_ERROR_NULL = -1,
+}
+
+/// Encoder allocated by `heatshrink_encoder_alloc`, opaque to C code
+#[cfg(feature = "encoder")]
+pub struct heatshrink_encoder {
+    instance: Instance<
+        Box<HeatshrinkEncoder<SMALL_WINDOW_SIZE>>,
+        Box<HeatshrinkEncoder<LARGE_WINDOW_SIZE>>,
+    >,
+}
+
+/// Decoder allocated by `heatshrink_decoder_alloc`, opaque to C code
+#[cfg(feature = "decoder")]
+pub struct heatshrink_decoder {
+    instance: Instance<
+        Box<HeatshrinkDecoder<SMALL_WINDOW_SIZE, DECODER_INPUT_SIZE>>,
+        Box<HeatshrinkDecoder<LARGE_WINDOW_SIZE, DECODER_INPUT_SIZE>>,
+    >,
+}
+
+/// Allocate an encoder compressing with a window of 2^window_sz2 bytes and
+/// back-references of up to 2^lookahead_sz2 bytes, or return NULL if the
+/// parameters are not supported.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub extern "C" fn heatshrink_encoder_alloc(
+    window_sz2: u8,
+    lookahead_sz2: u8,
+) -> *mut heatshrink_encoder {
+    let Ok(params) = Params::new(window_sz2, lookahead_sz2) else {
+        return core::ptr::null_mut();
+    };
+    let instance = if params.window_size() <= SMALL_WINDOW_SIZE {
+        HeatshrinkEncoder::boxed_with_params(params).map(Instance::Small)
+    } else {
+        HeatshrinkEncoder::boxed_with_params(params).map(Instance::Large)
+    };
+
+    match instance {
+        Ok(instance) => Box::into_raw(Box::new(heatshrink_encoder { instance })),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Free an encoder allocated by `heatshrink_encoder_alloc`
+///
+/// # Safety
+///
+/// hse must be NULL or a pointer returned by `heatshrink_encoder_alloc`
+/// and not freed yet.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_encoder_free(hse: *mut heatshrink_encoder) {
+    if !hse.is_null() {
+        drop(unsafe { Box::from_raw(hse) });
+    }
+}
+
+/// Reset an encoder to compress a new stream
+///
+/// # Safety
+///
+/// hse must be NULL or a valid encoder.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_encoder_reset(hse: *mut heatshrink_encoder) {
+    if let Some(hse) = unsafe { hse.as_mut() } {
+        with_instance!(&mut hse.instance, enc => enc.reset());
+    }
+}
+
+/// Sink up to size bytes of in_buf into the encoder, storing the number of
+/// bytes actually sunk in *input_size.
+///
+/// # Safety
+///
+/// hse must be NULL or a valid encoder, in_buf NULL or valid for reads of
+/// size bytes and input_size NULL or valid for writes.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_encoder_sink(
+    hse: *mut heatshrink_encoder,
+    in_buf: *const u8,
+    size: usize,
+    input_size: *mut usize,
+) -> HSE_sink_res {
+    let (hse, input_size) = unsafe { (hse.as_mut(), input_size.as_mut()) };
+    let (Some(hse), Some(input_size), false) = (hse, input_size, in_buf.is_null()) else {
+        return HSE_sink_res::HSER_SINK_ERROR_NULL;
+    };
+    let in_buf = unsafe { slice::from_raw_parts(in_buf, size) };
+
+    let (res, sunk) = with_instance!(&mut hse.instance, enc => enc.sink(in_buf));
+    *input_size = sunk;
+    match res {
+        HSsinkRes::SinkOK => HSE_sink_res::HSER_SINK_OK,
+        HSsinkRes::SinkFull | HSsinkRes::SinkErrorMisuse => HSE_sink_res::HSER_SINK_ERROR_MISUSE,
+    }
+}
+
+/// Poll up to out_buf_size bytes of compressed data into out_buf, storing
+/// the number of bytes written in *output_size.
+///
+/// # Safety
+///
+/// hse must be NULL or a valid encoder, out_buf NULL or valid for writes of
+/// out_buf_size bytes and output_size NULL or valid for writes.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_encoder_poll(
+    hse: *mut heatshrink_encoder,
+    out_buf: *mut u8,
+    out_buf_size: usize,
+    output_size: *mut usize,
+) -> HSE_poll_res {
+    let (hse, output_size) = unsafe { (hse.as_mut(), output_size.as_mut()) };
+    let (Some(hse), Some(output_size), false) = (hse, output_size, out_buf.is_null()) else {
+        return HSE_poll_res::HSER_POLL_ERROR_NULL;
+    };
+    if out_buf_size == 0 {
+        return HSE_poll_res::HSER_POLL_ERROR_MISUSE;
+    }
+    let out_buf = unsafe { slice::from_raw_parts_mut(out_buf, out_buf_size) };
+
+    let (res, polled) = with_instance!(&mut hse.instance, enc => enc.poll(out_buf));
+    *output_size = polled;
+    match res {
+        HSpollRes::PollEmpty => HSE_poll_res::HSER_POLL_EMPTY,
+        HSpollRes::PollMore => HSE_poll_res::HSER_POLL_MORE,
+        HSpollRes::PollErrorMisuse | HSpollRes::PollErrorLimit | HSpollRes::PollErrorBackref => {
+            HSE_poll_res::HSER_POLL_ERROR_MISUSE
+        }
+    }
+}
+
+/// Notify the encoder that the input stream is complete. If more output
+/// remains, it must be polled.
+///
+/// # Safety
+///
+/// hse must be NULL or a valid encoder.
+#[cfg(feature = "encoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_encoder_finish(hse: *mut heatshrink_encoder) -> HSE_finish_res {
+    let Some(hse) = (unsafe { hse.as_mut() }) else {
+        return HSE_finish_res::HSER_FINISH_ERROR_NULL;
+    };
+
+    match with_instance!(&mut hse.instance, enc => enc.finish()) {
+        HSfinishRes::FinishDone => HSE_finish_res::HSER_FINISH_DONE,
+        HSfinishRes::FinishMore => HSE_finish_res::HSER_FINISH_MORE,
+    }
+}
+
+/// Allocate a decoder for a stream compressed with a window of
+/// 2^window_sz2 bytes and back-references of up to 2^lookahead_sz2 bytes,
+/// or return NULL if the parameters are not supported or input_buffer_size
+/// is 0.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub extern "C" fn heatshrink_decoder_alloc(
+    input_buffer_size: u16,
+    window_sz2: u8,
+    lookahead_sz2: u8,
+) -> *mut heatshrink_decoder {
+    let Ok(params) = Params::new(window_sz2, lookahead_sz2) else {
+        return core::ptr::null_mut();
+    };
+    if input_buffer_size == 0 {
+        return core::ptr::null_mut();
+    }
+    let instance = if params.window_size() <= SMALL_WINDOW_SIZE {
+        HeatshrinkDecoder::boxed_with_params(params).map(Instance::Small)
+    } else {
+        HeatshrinkDecoder::boxed_with_params(params).map(Instance::Large)
+    };
+
+    match instance {
+        Ok(instance) => Box::into_raw(Box::new(heatshrink_decoder { instance })),
+        Err(_) => core::ptr::null_mut(),
+    }
+}
+
+/// Free a decoder allocated by `heatshrink_decoder_alloc`
+///
+/// # Safety
+///
+/// hsd must be NULL or a pointer returned by `heatshrink_decoder_alloc`
+/// and not freed yet.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_decoder_free(hsd: *mut heatshrink_decoder) {
+    if !hsd.is_null() {
+        drop(unsafe { Box::from_raw(hsd) });
+    }
+}
+
+/// Reset a decoder to uncompress a new stream
+///
+/// # Safety
+///
+/// hsd must be NULL or a valid decoder.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_decoder_reset(hsd: *mut heatshrink_decoder) {
+    if let Some(hsd) = unsafe { hsd.as_mut() } {
+        with_instance!(&mut hsd.instance, dec => dec.reset());
+    }
+}
+
+/// Sink up to size bytes of in_buf into the decoder, storing the number of
+/// bytes actually sunk in *input_size.
+///
+/// # Safety
+///
+/// hsd must be NULL or a valid decoder, in_buf NULL or valid for reads of
+/// size bytes and input_size NULL or valid for writes.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_decoder_sink(
+    hsd: *mut heatshrink_decoder,
+    in_buf: *const u8,
+    size: usize,
+    input_size: *mut usize,
+) -> HSD_sink_res {
+    let (hsd, input_size) = unsafe { (hsd.as_mut(), input_size.as_mut()) };
+    let (Some(hsd), Some(input_size), false) = (hsd, input_size, in_buf.is_null()) else {
+        return HSD_sink_res::HSDR_SINK_ERROR_NULL;
+    };
+    let in_buf = unsafe { slice::from_raw_parts(in_buf, size) };
+
+    let (res, sunk) = with_instance!(&mut hsd.instance, dec => dec.sink(in_buf));
+    *input_size = sunk;
+    match res {
+        HSsinkRes::SinkOK => HSD_sink_res::HSDR_SINK_OK,
+        HSsinkRes::SinkFull | HSsinkRes::SinkErrorMisuse => HSD_sink_res::HSDR_SINK_FULL,
+    }
+}
+
+/// Poll up to out_buf_size bytes of uncompressed data into out_buf,
+/// storing the number of bytes written in *output_size.
+///
+/// # Safety
+///
+/// hsd must be NULL or a valid decoder, out_buf NULL or valid for writes of
+/// out_buf_size bytes and output_size NULL or valid for writes.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_decoder_poll(
+    hsd: *mut heatshrink_decoder,
+    out_buf: *mut u8,
+    out_buf_size: usize,
+    output_size: *mut usize,
+) -> HSD_poll_res {
+    let (hsd, output_size) = unsafe { (hsd.as_mut(), output_size.as_mut()) };
+    let (Some(hsd), Some(output_size), false) = (hsd, output_size, out_buf.is_null()) else {
+        return HSD_poll_res::HSDR_POLL_ERROR_NULL;
+    };
+    if out_buf_size == 0 {
+        return HSD_poll_res::HSDR_POLL_ERROR_UNKNOWN;
+    }
+    let out_buf = unsafe { slice::from_raw_parts_mut(out_buf, out_buf_size) };
+
+    let (res, polled) = with_instance!(&mut hsd.instance, dec => dec.poll(out_buf));
+    *output_size = polled;
+    match res {
+        HSpollRes::PollEmpty => HSD_poll_res::HSDR_POLL_EMPTY,
+        HSpollRes::PollMore => HSD_poll_res::HSDR_POLL_MORE,
+        HSpollRes::PollErrorMisuse | HSpollRes::PollErrorLimit | HSpollRes::PollErrorBackref => {
+            HSD_poll_res::HSDR_POLL_ERROR_UNKNOWN
+        }
+    }
+}
+
+/// Notify the decoder that the input stream is complete. If more output
+/// remains, it must be polled.
+///
+/// # Safety
+///
+/// hsd must be NULL or a valid decoder.
+#[cfg(feature = "decoder")]
+#[no_mangle]
+pub unsafe extern "C" fn heatshrink_decoder_finish(hsd: *mut heatshrink_decoder) -> HSD_finish_res {
+    let Some(hsd) = (unsafe { hsd.as_mut() }) else {
+        return HSD_finish_res::HSDR_FINISH_ERROR_NULL;
+    };
+
+    match with_instance!(&mut hsd.instance, dec => dec.finish()) {
+        HSfinishRes::FinishDone => HSD_finish_res::HSDR_FINISH_DONE,
+        HSfinishRes::FinishMore => HSD_finish_res::HSDR_FINISH_MORE,
+    }
+}
@@ -0,0 +1,258 @@
+use super::decoder::HeatshrinkDecoder;
+use super::HSError;
+use super::HSfinishRes;
+
+use embedded_storage::nor_flash::NorFlashErrorKind;
+#[cfg(feature = "embedded-storage")]
+use embedded_storage::nor_flash::ReadNorFlash;
+#[cfg(feature = "embedded-storage-async")]
+use embedded_storage_async::nor_flash::ReadNorFlash as AsyncReadNorFlash;
+
+/// Size of the stack buffer the compressed data is read into from the flash
+pub const FLASH_CHUNK_SIZE: usize = 64;
+
+/// Error of the flash helpers: an error of the flash, a region the flash
+/// can't read, or an error of the compressed stream
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The flash failed to read
+    Flash(E),
+    /// The region is out of the flash (`OutOfBounds`), or the read size of
+    /// the flash is larger than `FLASH_CHUNK_SIZE` (`NotAligned`)
+    Region(NorFlashErrorKind),
+    /// The compressed data is invalid or truncated, or doesn't fit in the
+    /// destination buffer
+    Heatshrink(HSError),
+}
+
+impl<E: core::fmt::Debug> core::fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Flash(error) => write!(f, "flash error: {:?}", error),
+            Error::Region(error) => write!(f, "flash region error: {}", error),
+            Error::Heatshrink(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl<E: core::fmt::Debug> core::error::Error for Error<E> {}
+
+/// Chunks of the region of len bytes at offset, as (address, size, start,
+/// stop): read size bytes at address, aligned on the read size of the flash,
+/// and keep the bytes between start and stop
+struct Chunks {
+    read_size: usize,
+    chunk_size: usize,
+    position: usize,
+    offset: usize,
+    end: usize,
+}
+
+impl Chunks {
+    fn new(
+        read_size: usize,
+        capacity: usize,
+        offset: u32,
+        len: usize,
+    ) -> Result<Self, NorFlashErrorKind> {
+        let read_size = read_size.max(1);
+        let chunk_size = FLASH_CHUNK_SIZE - FLASH_CHUNK_SIZE % read_size;
+        if chunk_size == 0 {
+            return Err(NorFlashErrorKind::NotAligned);
+        }
+        let offset = offset as usize;
+        let end = offset
+            .checked_add(len)
+            .ok_or(NorFlashErrorKind::OutOfBounds)?;
+        if end.div_ceil(read_size) * read_size > capacity {
+            return Err(NorFlashErrorKind::OutOfBounds);
+        }
+
+        Ok(Chunks {
+            read_size,
+            chunk_size,
+            position: offset - offset % read_size,
+            offset,
+            end,
+        })
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = (u32, usize, usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
+        let remaining = self.end - self.position;
+        let size = self
+            .chunk_size
+            .min(remaining.div_ceil(self.read_size) * self.read_size);
+        let start = self.offset.saturating_sub(self.position);
+        let chunk = (self.position as u32, size, start, size.min(remaining));
+
+        self.position += size;
+        Some(chunk)
+    }
+}
+
+/// Destination buffer the uncompressed data is copied to, remembering if it
+/// overflowed
+struct SliceOutput<'a> {
+    dst: &'a mut [u8],
+    size: usize,
+    full: bool,
+}
+
+impl<'a> SliceOutput<'a> {
+    fn new(dst: &'a mut [u8]) -> Self {
+        SliceOutput {
+            dst,
+            size: 0,
+            full: false,
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        match self.dst.get_mut(self.size..self.size + data.len()) {
+            Some(output) => {
+                output.copy_from_slice(data);
+                self.size += data.len();
+            }
+            None => self.full = true,
+        }
+    }
+
+    fn into_slice(self) -> Result<&'a [u8], HSError> {
+        if self.full {
+            return Err(HSError::OutputFull { needed_hint: None });
+        }
+        self.dst.get(..self.size).ok_or(HSError::Internal)
+    }
+}
+
+/// Sink the bytes between start and stop of the chunk read from the flash
+fn sink_chunk<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    chunk: &[u8],
+    start: usize,
+    stop: usize,
+    output: &mut impl FnMut(&[u8]),
+) -> Result<(), HSError> {
+    dec.sink_all(chunk.get(start..stop).ok_or(HSError::Internal)?, output)
+}
+
+/// Check the compressed stream read from the flash is complete
+fn finish<const WINDOW_SIZE: usize, const INPUT_SIZE: usize>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+) -> Result<(), HSError> {
+    match dec.finish() {
+        HSfinishRes::FinishDone => Ok(()),
+        HSfinishRes::FinishMore => Err(HSError::Truncated),
+    }
+}
+
+#[cfg(feature = "embedded-storage")]
+/// uncompress the compressed data stored in the len bytes of the flash at
+/// offset to the destination buffer
+///
+/// The data is read `FLASH_CHUNK_SIZE` bytes at a time, so that compressed
+/// assets or configurations can be read from an external flash without a
+/// staging buffer the size of the region.
+pub fn decode_from_flash<'a, F: ReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], Error<F::Error>> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+    let mut output = SliceOutput::new(dst);
+
+    decode_from_flash_with(&mut dec, flash, offset, len, &mut |data| output.write(data))?;
+    output.into_slice().map_err(Error::Heatshrink)
+}
+
+#[cfg(feature = "embedded-storage")]
+/// uncompress the compressed data stored in the len bytes of the flash at
+/// offset using the provided instance (and therefore its window and
+/// lookahead parameters), handing the uncompressed data to output as it is
+/// produced
+pub fn decode_from_flash_with<
+    F: ReadNorFlash,
+    const WINDOW_SIZE: usize,
+    const INPUT_SIZE: usize,
+>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    output: &mut impl FnMut(&[u8]),
+) -> Result<(), Error<F::Error>> {
+    let mut buffer = [0u8; FLASH_CHUNK_SIZE];
+
+    for (address, size, start, stop) in
+        Chunks::new(F::READ_SIZE, flash.capacity(), offset, len).map_err(Error::Region)?
+    {
+        let chunk = buffer
+            .get_mut(..size)
+            .ok_or(Error::Heatshrink(HSError::Internal))?;
+        flash.read(address, chunk).map_err(Error::Flash)?;
+        sink_chunk(dec, chunk, start, stop, output).map_err(Error::Heatshrink)?;
+    }
+
+    finish(dec).map_err(Error::Heatshrink)
+}
+
+#[cfg(feature = "embedded-storage-async")]
+/// uncompress the compressed data stored in the len bytes of the async flash
+/// at offset to the destination buffer
+///
+/// The task awaits the flash between the chunks of `FLASH_CHUNK_SIZE` bytes,
+/// so that firmware running on an async executor (e.g. embassy) can
+/// uncompress an OTA image from a QSPI flash without blocking its other
+/// tasks.
+pub async fn decode_from_flash_async<'a, F: AsyncReadNorFlash>(
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    dst: &'a mut [u8],
+) -> Result<&'a [u8], Error<F::Error>> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+    let mut output = SliceOutput::new(dst);
+
+    decode_from_flash_with_async(&mut dec, flash, offset, len, &mut |data| output.write(data))
+        .await?;
+    output.into_slice().map_err(Error::Heatshrink)
+}
+
+#[cfg(feature = "embedded-storage-async")]
+/// uncompress the compressed data stored in the len bytes of the async flash
+/// at offset using the provided instance (and therefore its window and
+/// lookahead parameters), handing the uncompressed data to output as it is
+/// produced
+pub async fn decode_from_flash_with_async<
+    F: AsyncReadNorFlash,
+    const WINDOW_SIZE: usize,
+    const INPUT_SIZE: usize,
+>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    flash: &mut F,
+    offset: u32,
+    len: usize,
+    output: &mut impl FnMut(&[u8]),
+) -> Result<(), Error<F::Error>> {
+    let mut buffer = [0u8; FLASH_CHUNK_SIZE];
+
+    for (address, size, start, stop) in
+        Chunks::new(F::READ_SIZE, flash.capacity(), offset, len).map_err(Error::Region)?
+    {
+        let chunk = buffer
+            .get_mut(..size)
+            .ok_or(Error::Heatshrink(HSError::Internal))?;
+        flash.read(address, chunk).await.map_err(Error::Flash)?;
+        sink_chunk(dec, chunk, start, stop, output).map_err(Error::Heatshrink)?;
+    }
+
+    finish(dec).map_err(Error::Heatshrink)
+}
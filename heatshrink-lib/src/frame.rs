@@ -0,0 +1,204 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+#[cfg(feature = "encoder")]
+use super::HSsinkRes;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+/// Size in bytes of the header of each frame: the size of its payload
+/// (16-bit little endian).
+///
+/// Each frame carries the data compressed since the previous frame, flushed
+/// to a byte boundary (see `encoder::HeatshrinkEncoder::flush`), so that the
+/// receiver uncompresses each frame as soon as it is received while the
+/// following frames still reference the data of the previous ones.
+pub const FRAME_HEADER_SIZE: usize = 2;
+
+/// Largest payload of a frame
+pub const MAX_FRAME_PAYLOAD: usize = u16::MAX as usize;
+
+/// Encoder writing a compressed stream as length-prefixed frames
+#[cfg(feature = "encoder")]
+#[derive(Debug)]
+pub struct FrameEncoder<const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+}
+
+#[cfg(feature = "encoder")]
+impl<const WINDOW_SIZE: usize> FrameEncoder<WINDOW_SIZE> {
+    /// Write the stream compressed by encoder, which must not have been sunk
+    /// yet, as frames
+    pub fn new(encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        FrameEncoder { encoder }
+    }
+
+    /// Largest input compressed in a frame of frame_size bytes: every byte
+    /// encoded as a literal of 9 bits, padded to a byte boundary
+    pub const fn frame_input_size(frame_size: usize) -> usize {
+        let payload_size = frame_size.saturating_sub(FRAME_HEADER_SIZE);
+        let payload_size = if payload_size > MAX_FRAME_PAYLOAD {
+            MAX_FRAME_PAYLOAD
+        } else {
+            payload_size
+        };
+
+        payload_size * 8 / 9
+    }
+
+    /// Compress the start of src into a frame written at the start of the
+    /// frame buffer, whose size bounds the size of the frame. Return the
+    /// number of bytes consumed from src and the size of the frame.
+    ///
+    /// Call it again with the rest of src until all of it is consumed. Fail
+    /// with `OutputFull` if the frame buffer can't hold a single byte of
+    /// src.
+    pub fn encode_frame(
+        &mut self,
+        src: &[u8],
+        frame: &mut [u8],
+    ) -> Result<(usize, usize), HSError> {
+        if src.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let input = src
+            .get(..Self::frame_input_size(frame.len()).min(src.len()))
+            .filter(|input| !input.is_empty())
+            .ok_or(HSError::OutputFull {
+                needed_hint: Some(FRAME_HEADER_SIZE + 2),
+            })?;
+        let (header, payload) = frame
+            .split_at_mut_checked(FRAME_HEADER_SIZE)
+            .ok_or(HSError::Internal)?;
+
+        let mut input_size = 0;
+        let mut payload_size = 0;
+        while input_size < input.len() {
+            match self
+                .encoder
+                .sink(input.get(input_size..).ok_or(HSError::Internal)?)
+            {
+                (HSsinkRes::SinkOK, segment_input_size) => input_size += segment_input_size,
+                (HSsinkRes::SinkFull, _) => {}
+                (HSsinkRes::SinkErrorMisuse, _) => return Err(HSError::InvalidState),
+            }
+            payload_size += self
+                .encoder
+                .poll(payload.get_mut(payload_size..).ok_or(HSError::Internal)?)
+                .1;
+        }
+        // the payload was sized for the worst case
+        payload_size += self
+            .encoder
+            .flush_into(payload.get_mut(payload_size..).ok_or(HSError::Internal)?)
+            .map_err(|_| HSError::Internal)?;
+
+        header.copy_from_slice(
+            &u16::try_from(payload_size)
+                .map_err(|_| HSError::Internal)?
+                .to_le_bytes(),
+        );
+        Ok((input_size, FRAME_HEADER_SIZE + payload_size))
+    }
+
+    /// Unwrap the underlying encoder
+    pub fn into_inner(self) -> HeatshrinkEncoder<WINDOW_SIZE> {
+        self.encoder
+    }
+}
+
+/// Decoder of a compressed stream carried as length-prefixed frames, fed
+/// with the bytes received in any size
+#[cfg(feature = "decoder")]
+#[derive(Debug)]
+pub struct FrameDecoder<
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    max_payload_size: usize,
+    header: [u8; FRAME_HEADER_SIZE],
+    header_size: usize,
+    payload_size: usize,
+}
+
+#[cfg(feature = "decoder")]
+impl<const WINDOW_SIZE: usize, const INPUT_SIZE: usize> FrameDecoder<WINDOW_SIZE, INPUT_SIZE> {
+    /// Uncompress the frames with decoder, which must not have been sunk
+    /// yet. Frames of more than max_frame_size bytes are rejected, so that a
+    /// receiver buffering whole frames bounds its buffer.
+    pub fn new(decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>, max_frame_size: usize) -> Self {
+        FrameDecoder {
+            decoder,
+            max_payload_size: max_frame_size.saturating_sub(FRAME_HEADER_SIZE),
+            header: [0; FRAME_HEADER_SIZE],
+            header_size: 0,
+            payload_size: 0,
+        }
+    }
+
+    /// Sink the bytes received, uncompressing the payload of the frames as it
+    /// arrives and handing it to the output callback.
+    ///
+    /// Fail with `InvalidFrame` if a frame is larger than the largest frame
+    /// size accepted.
+    pub fn sink_all(
+        &mut self,
+        mut input_buffer: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), HSError> {
+        while !input_buffer.is_empty() {
+            if self.header_size < FRAME_HEADER_SIZE {
+                // start of a frame
+                let (byte, rest) = input_buffer.split_first().ok_or(HSError::Internal)?;
+                *self
+                    .header
+                    .get_mut(self.header_size)
+                    .ok_or(HSError::Internal)? = *byte;
+                self.header_size += 1;
+                input_buffer = rest;
+
+                if self.header_size == FRAME_HEADER_SIZE {
+                    self.payload_size = usize::from(u16::from_le_bytes(self.header));
+                    if self.payload_size > self.max_payload_size {
+                        return Err(HSError::InvalidFrame);
+                    }
+                }
+            } else {
+                let (payload, rest) = input_buffer
+                    .split_at_checked(self.payload_size.min(input_buffer.len()))
+                    .ok_or(HSError::Internal)?;
+                self.decoder.sink_all(payload, output)?;
+                self.payload_size -= payload.len();
+                input_buffer = rest;
+            }
+
+            if self.header_size == FRAME_HEADER_SIZE && self.payload_size == 0 {
+                // end of a frame, drop its padding
+                self.decoder.sync()?;
+                self.header_size = 0;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Signal the end of the bytes received. Fail with `Truncated` if it
+    /// ends in the middle of a frame.
+    pub fn finish(&mut self) -> Result<(), HSError> {
+        if self.header_size == 0 {
+            Ok(())
+        } else {
+            Err(HSError::Truncated)
+        }
+    }
+
+    /// Unwrap the underlying decoder
+    pub fn into_inner(self) -> HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE> {
+        self.decoder
+    }
+}
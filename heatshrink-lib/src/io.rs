@@ -0,0 +1,422 @@
+use super::container::{self, Format};
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+#[cfg(feature = "decoder")]
+use super::HSfinishRes;
+use super::HSpollRes;
+use super::HSsinkRes;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+use std::boxed::Box;
+use std::io;
+#[cfg(feature = "decoder")]
+use std::io::Read;
+#[cfg(feature = "encoder")]
+use std::io::Write;
+
+/// Size of the buffer used to read compressed data from the wrapped reader or
+/// to write compressed data to the wrapped writer
+const HEATSHRINK_IO_BUFFER_SIZE: usize = 512;
+
+/// Callback receiving the number of bytes sunk into and polled from the
+/// encoder/decoder so far
+type ProgressCallback = Box<dyn FnMut(u64, u64)>;
+
+#[cfg(feature = "decoder")]
+/// Reader decompressing the data read from the wrapped reader
+///
+/// The sink/poll/finish loop of the decoder is driven internally so the
+/// uncompressed stream can be consumed with the usual `std::io` helpers.
+/// The compressed data is a raw stream unless another format is set with
+/// `set_format`.
+pub struct HeatshrinkReader<
+    R,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    inner: R,
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    input_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+    input_index: usize,
+    input_size: usize,
+    eof: bool,
+    progress: Option<ProgressCallback>,
+    /// format of the compressed data, detected from its first bytes if None
+    format: Option<Format>,
+    /// whether the container header, if any, was read
+    header_read: bool,
+    /// bytes of the container header left to skip, once it was parsed
+    header_skip: usize,
+    /// length of the uncompressed data recorded in the container header
+    original_len: Option<u64>,
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read> HeatshrinkReader<R> {
+    /// Create a new reader decompressing data with the default parameters
+    pub fn new(inner: R) -> Self {
+        HeatshrinkReader::with_decoder(inner, HeatshrinkDecoder::new())
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    HeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Create a new reader decompressing data with the provided decoder
+    /// (and therefore its window and lookahead parameters)
+    pub fn with_decoder(inner: R, decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        HeatshrinkReader {
+            inner,
+            decoder,
+            input_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+            input_index: 0,
+            input_size: 0,
+            eof: false,
+            progress: None,
+            format: Some(Format::Raw),
+            header_read: false,
+            header_skip: 0,
+            original_len: None,
+        }
+    }
+
+    /// Set the format of the compressed data, or detect it from its first
+    /// bytes if None (see `container::Format::detect`). It must be called
+    /// before reading any data.
+    ///
+    /// The parameters of a container header replace those of the decoder
+    /// when they differ, and the length it records is checked at the end of
+    /// the stream.
+    pub fn set_format(&mut self, format: Option<Format>) {
+        self.format = format;
+    }
+
+    /// Get a reference to the wrapped reader
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped reader
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    /// Unwrap this reader, returning the wrapped reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Register a callback called with the number of compressed bytes
+    /// consumed and of uncompressed bytes produced so far, each time some
+    /// data is read
+    pub fn set_progress_callback(&mut self, progress: impl FnMut(u64, u64) + 'static) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// read ahead until the input buffer holds at least len bytes or the
+    /// wrapped reader is at its end
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        while !self.eof && self.input_size - self.input_index < len {
+            let input_size = self.inner.read(&mut self.input_buffer[self.input_size..])?;
+            self.input_size += input_size;
+            self.eof = input_size == 0;
+        }
+        Ok(())
+    }
+
+    /// detect the format of the compressed data and skip its container
+    /// header if any, configuring the decoder with its parameters.
+    ///
+    /// If the wrapped reader fails (e.g. `Interrupted` or `WouldBlock`), the
+    /// next call resumes where this one stopped.
+    fn read_header(&mut self) -> io::Result<()> {
+        if self.header_skip == 0 {
+            let format = match self.format {
+                Some(format) => format,
+                None => {
+                    self.fill(container::MAGIC.len())?;
+                    Format::detect(&self.input_buffer[self.input_index..self.input_size])
+                }
+            };
+            if format == Format::Raw {
+                self.header_read = true;
+                return Ok(());
+            }
+
+            self.fill(container::HEADER_SIZE + container::METADATA_FIXED_SIZE)?;
+            let (header, skip) =
+                container::peek_header(&self.input_buffer[self.input_index..self.input_size])
+                    .map_err(|error| match error {
+                        HSError::Truncated => io::ErrorKind::UnexpectedEof.into(),
+                        error => io::Error::new(io::ErrorKind::InvalidData, error),
+                    })?;
+            if self.decoder.params() != header.params {
+                self.decoder = HeatshrinkDecoder::with_params(header.params)
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+            }
+            self.original_len = header.original_len;
+            self.header_skip = skip;
+        }
+
+        while self.header_skip > 0 {
+            let skipped = self.header_skip.min(self.input_size - self.input_index);
+            self.input_index += skipped;
+            self.header_skip -= skipped;
+            if self.header_skip > 0 {
+                if self.eof {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.input_index = 0;
+                self.input_size = 0;
+                self.input_size = self.inner.read(&mut self.input_buffer)?;
+                self.eof = self.input_size == 0;
+            }
+        }
+
+        self.header_read = true;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<R: Read, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Read
+    for HeatshrinkReader<R, WINDOW_SIZE, INPUT_SIZE>
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if !self.header_read {
+            self.read_header()?;
+        }
+
+        loop {
+            // process the data already sunk into the decoder
+            match self.decoder.poll(buf) {
+                (HSpollRes::PollErrorMisuse, _) => {
+                    return Err(io::Error::other("Error in HeatshrinkDecoder::poll()"));
+                }
+                (HSpollRes::PollErrorLimit, _) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        HSError::OutputLimit,
+                    ));
+                }
+                (HSpollRes::PollErrorBackref, _) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        HSError::InvalidBackref,
+                    ));
+                }
+                (_, 0) => {}
+                (_, output_size) => {
+                    if let Some(progress) = &mut self.progress {
+                        progress(self.decoder.total_in(), self.decoder.total_out());
+                    }
+                    return Ok(output_size);
+                }
+            }
+
+            if self.input_index < self.input_size {
+                // the decoder needs more compressed data
+                match self
+                    .decoder
+                    .sink(&self.input_buffer[self.input_index..self.input_size])
+                {
+                    (HSsinkRes::SinkOK, input_size) => {
+                        self.input_index += input_size;
+                    }
+                    (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                        return Err(io::Error::other("Error in HeatshrinkDecoder::sink()"));
+                    }
+                }
+            } else if self.eof {
+                // all the compressed data has been processed
+                return match self.decoder.finish() {
+                    HSfinishRes::FinishDone => match self.original_len {
+                        Some(len) if len > self.decoder.total_out() => Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            HSError::TooShort,
+                        )),
+                        Some(len) if len < self.decoder.total_out() => {
+                            Err(io::Error::new(io::ErrorKind::InvalidData, HSError::TooLong))
+                        }
+                        _ => Ok(0),
+                    },
+                    HSfinishRes::FinishMore => Err(io::ErrorKind::UnexpectedEof.into()),
+                };
+            } else {
+                // emptied first, so that a failed read is simply retried
+                self.input_index = 0;
+                self.input_size = 0;
+                self.input_size = self.inner.read(&mut self.input_buffer)?;
+                self.eof = self.input_size == 0;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+/// Writer compressing the data written to it into the wrapped writer
+///
+/// The sink/poll loop of the encoder is driven internally so the stream can
+/// be compressed with the usual `std::io` helpers (e.g. `std::io::copy`).
+/// `finish` must be called once all the data was written to flush the end of
+/// the compressed stream, written as a raw stream unless another format is
+/// set with `set_format`.
+pub struct HeatshrinkWriter<W, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    inner: W,
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    output_buffer: [u8; HEATSHRINK_IO_BUFFER_SIZE],
+    progress: Option<ProgressCallback>,
+    format: Format,
+    /// whether the container header, if any, was written
+    header_written: bool,
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write> HeatshrinkWriter<W> {
+    /// Create a new writer compressing data with the default parameters
+    pub fn new(inner: W) -> Self {
+        HeatshrinkWriter::with_encoder(inner, HeatshrinkEncoder::new())
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write, const WINDOW_SIZE: usize> HeatshrinkWriter<W, WINDOW_SIZE> {
+    /// Create a new writer compressing data with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(inner: W, encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        HeatshrinkWriter {
+            inner,
+            encoder,
+            output_buffer: [0; HEATSHRINK_IO_BUFFER_SIZE],
+            progress: None,
+            format: Format::Raw,
+            header_written: false,
+        }
+    }
+
+    /// Set the format of the compressed data. It must be called before
+    /// writing any data.
+    ///
+    /// A container header records the parameters of the encoder, but not the
+    /// length of the data which isn't known when it is written.
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Get a reference to the wrapped writer
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped writer
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// Register a callback called with the number of uncompressed bytes
+    /// consumed and of compressed bytes produced so far, each time some data
+    /// is written and when the stream is finished
+    pub fn set_progress_callback(&mut self, progress: impl FnMut(u64, u64) + 'static) {
+        self.progress = Some(Box::new(progress));
+    }
+
+    /// Finish the compressed stream, writing its trailing bits to the
+    /// wrapped writer, and return the wrapped writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_header()?;
+        loop {
+            match self.encoder.finish_into(&mut self.output_buffer) {
+                Ok(output_size) => {
+                    self.inner.write_all(&self.output_buffer[..output_size])?;
+                    break;
+                }
+                Err(HSError::OutputFull { .. }) => {
+                    self.inner.write_all(&self.output_buffer)?;
+                }
+                Err(err) => return Err(io::Error::other(err)),
+            }
+        }
+        self.report_progress();
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// write the container header before the compressed stream, if any
+    fn write_header(&mut self) -> io::Result<()> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+        if self.format == Format::Container {
+            let header = container::Header::new(self.encoder.params(), None);
+            let mut buffer = [0u8; container::HEADER_SIZE];
+            container::write_header(&header, &mut buffer).map_err(io::Error::other)?;
+            self.inner.write_all(&buffer)?;
+        }
+        Ok(())
+    }
+
+    fn report_progress(&mut self) {
+        if let Some(progress) = &mut self.progress {
+            progress(self.encoder.total_in(), self.encoder.total_out());
+        }
+    }
+
+    /// Poll the encoder until it needs more input, writing all the
+    /// compressed data to the wrapped writer
+    fn write_output(&mut self) -> io::Result<()> {
+        loop {
+            match self.encoder.poll(&mut self.output_buffer) {
+                (HSpollRes::PollMore, output_size) => {
+                    self.inner.write_all(&self.output_buffer[..output_size])?;
+                }
+                (HSpollRes::PollEmpty, output_size) => {
+                    self.inner.write_all(&self.output_buffer[..output_size])?;
+                    return Ok(());
+                }
+                (HSpollRes::PollErrorMisuse, _)
+                | (HSpollRes::PollErrorLimit, _)
+                | (HSpollRes::PollErrorBackref, _) => {
+                    return Err(io::Error::other("Error in HeatshrinkEncoder::poll()"));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<W: Write, const WINDOW_SIZE: usize> Write for HeatshrinkWriter<W, WINDOW_SIZE> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.write_header()?;
+
+        match self.encoder.sink(buf) {
+            (HSsinkRes::SinkOK, input_size) => {
+                // process the data as soon as the encoder input buffer is full
+                self.write_output()?;
+                self.report_progress();
+                Ok(input_size)
+            }
+            (HSsinkRes::SinkFull, _) | (HSsinkRes::SinkErrorMisuse, _) => {
+                Err(io::Error::other("Error in HeatshrinkEncoder::sink()"))
+            }
+        }
+    }
+
+    /// Flush the wrapped writer. The compressed data still held by the
+    /// encoder is only written by `finish`.
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,18 +1,93 @@
 #![crate_type = "rlib"]
 #![no_std]
 #![deny(warnings)]
-#![forbid(unsafe_code)]
+// only the C API of the ffi module hands raw pointers over, and the
+// wasm-bindgen glue of the wasm module is generated with unsafe code
+#![cfg_attr(not(any(feature = "ffi", feature = "wasm")), forbid(unsafe_code))]
+#![cfg_attr(any(feature = "ffi", feature = "wasm"), deny(unsafe_code))]
 #![deny(missing_docs)]
+// the shared helpers are unused when both halves are compiled out
+#![cfg_attr(not(any(feature = "encoder", feature = "decoder")), allow(dead_code))]
 
 //! Minimal compression & decompression library for embedded use
 //! Implements the Heatshrink compression algorithm
 //! described here <https://github.com/atomicobject/heatshrink>
 //! and here <https://spin.atomicobject.com/2013/03/14/heatshrink-embedded-data-compression/>
 
+/// module to carry a compressed stream as base64 text
+#[cfg(feature = "armor")]
+pub mod armor;
+/// module to (un)compress data through futures AsyncRead/AsyncWrite traits
+#[cfg(all(feature = "futures", any(feature = "encoder", feature = "decoder")))]
+pub mod async_io;
+/// module to (un)compress data as independent blocks
+#[cfg(any(feature = "encoder", feature = "decoder"))]
+pub mod block;
+/// module to carry a compressed stream in COBS packets
+#[cfg(all(feature = "cobs", any(feature = "encoder", feature = "decoder")))]
+pub mod cobs;
+/// module to serialize bytes as a compressed stream with serde
+#[cfg(all(
+    feature = "serde",
+    feature = "alloc",
+    feature = "encoder",
+    feature = "decoder"
+))]
+pub mod compressed;
+/// module to describe a compressed stream with a small header
+pub mod container;
+/// module to append and check a checksum of the uncompressed data
+#[cfg(all(feature = "crc", any(feature = "encoder", feature = "decoder")))]
+pub mod crc;
 /// module to uncompress some compressed data
+#[cfg(feature = "decoder")]
 pub mod decoder;
+/// module to hash the uncompressed data while streaming
+#[cfg(all(feature = "digest", any(feature = "encoder", feature = "decoder")))]
+pub mod digest;
+/// module to (un)compress data through embedded-io-async traits
+#[cfg(all(
+    feature = "embedded-io-async",
+    any(feature = "encoder", feature = "decoder")
+))]
+pub mod embedded_io;
 /// module to compress data
+#[cfg(feature = "encoder")]
 pub mod encoder;
+/// module exporting the C API of heatshrink.h
+#[cfg(all(feature = "ffi", any(feature = "encoder", feature = "decoder")))]
+pub mod ffi;
+/// module to uncompress data stored in a NOR flash
+#[cfg(all(
+    any(feature = "embedded-storage", feature = "embedded-storage-async"),
+    feature = "decoder"
+))]
+pub mod flash;
+/// module to carry a compressed stream in length-prefixed frames
+#[cfg(any(feature = "encoder", feature = "decoder"))]
+pub mod frame;
+/// module to (un)compress data through std::io traits
+#[cfg(all(feature = "std", any(feature = "encoder", feature = "decoder")))]
+pub mod io;
+/// module to compress log text and records on the fly
+#[cfg(feature = "encoder")]
+pub mod log;
+/// module to serialize data with postcard and compress it on the fly
+#[cfg(all(feature = "postcard", feature = "encoder", feature = "decoder"))]
+pub mod postcard;
+/// module to (un)compress futures streams of Bytes chunk by chunk
+#[cfg(all(feature = "stream", any(feature = "encoder", feature = "decoder")))]
+pub mod stream;
+/// module of helpers around the compression
+pub mod util;
+/// module exporting JavaScript bindings with wasm-bindgen
+#[cfg(all(feature = "wasm", feature = "encoder", feature = "decoder"))]
+pub mod wasm;
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
 
 /// Base-2 log of LZSS sliding window size
 pub const HEATSHRINK_WINDOWS_BITS: u8 = 8;
@@ -20,10 +95,29 @@ pub const HEATSHRINK_WINDOWS_BITS: u8 = 8;
 /// Number of bits used for back-reference lengths
 pub const HEATSHRINK_LOOKAHEAD_BITS: u8 = 4;
 
-const HEATSHRINK_INPUT_BUFFER_SIZE: usize = 32;
+/// Smallest supported base-2 log of LZSS sliding window size
+pub const HEATSHRINK_MIN_WINDOWS_BITS: u8 = 4;
+
+/// Largest supported base-2 log of LZSS sliding window size
+pub const HEATSHRINK_MAX_WINDOWS_BITS: u8 = 15;
+
+/// Smallest supported number of bits used for back-reference lengths
+pub const HEATSHRINK_MIN_LOOKAHEAD_BITS: u8 = 3;
+
+/// Size in bytes of the default LZSS sliding window
+pub const HEATSHRINK_WINDOW_SIZE: usize = 1 << HEATSHRINK_WINDOWS_BITS;
+
+/// Size in bytes of the default decoder input buffer
+pub const HEATSHRINK_INPUT_BUFFER_SIZE: usize = 32;
+
+/// Size of the stack buffer the output is polled into by `sink_all` and
+/// `poll_all`
+const HEATSHRINK_POLL_BUFFER_SIZE: usize = 64;
 
 /// Return code for sink finction call
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum HSsinkRes {
     /// instance is not in correct state.
     SinkErrorMisuse,
@@ -34,7 +128,9 @@ pub enum HSsinkRes {
 }
 
 /// Return code for poll function call
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum HSpollRes {
     /// Error in input parameters
     PollErrorMisuse,
@@ -42,10 +138,18 @@ pub enum HSpollRes {
     PollMore,
     /// No more data to process
     PollEmpty,
+    /// The output limit of the decoder was reached and more data would be
+    /// produced
+    PollErrorLimit,
+    /// The decoder is in strict mode and a back-reference points before the
+    /// start of the stream
+    PollErrorBackref,
 }
 
 /// Return code for finish function call
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum HSfinishRes {
     /// More data availble in input buffer
     FinishMore,
@@ -54,50 +158,483 @@ pub enum HSfinishRes {
 }
 
 /// Error that can be encountered while (un)compresing data
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
 pub enum HSError {
     /// The output buffer was not large enough to hold output data
-    OutputFull,
+    OutputFull {
+        /// When known, a size of output buffer large enough to hold all the
+        /// output data
+        needed_hint: Option<usize>,
+    },
+    /// The compressed stream ended in the middle of a literal or of a
+    /// back-reference
+    Truncated,
+    /// A back-reference points before the start of the uncompressed stream
+    InvalidBackref,
+    /// The decoder produced more data than its output limit
+    OutputLimit,
+    /// The uncompressed data is shorter than expected
+    TooShort,
+    /// The uncompressed data is longer than expected
+    TooLong,
+    /// Some data follows the end of the compressed stream
+    TrailingData,
+    /// A saved state is truncated, of an unknown version or inconsistent
+    InvalidState,
+    /// A container header has no magic bytes, an unknown version or
+    /// unsupported parameters
+    InvalidHeader,
+    /// The uncompressed data doesn't match the checksum trailer
+    InvalidChecksum,
+    /// A frame is larger than the largest frame size accepted, or a packet
+    /// or an armored text is not validly encoded
+    InvalidFrame,
     /// Some internal error did occur
     Internal,
 }
 
-/// Structure to manage the output buffer and keep track of how much it is
-/// filled
-pub struct OutputInfo<'a, 'b> {
-    output_buffer: &'a mut [u8],
+impl core::fmt::Display for HSError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            HSError::OutputFull {
+                needed_hint: Some(needed),
+            } => write!(f, "output buffer is full ({} bytes are needed)", needed),
+            HSError::OutputFull { needed_hint: None } => write!(f, "output buffer is full"),
+            HSError::Truncated => write!(f, "compressed stream is truncated"),
+            HSError::InvalidBackref => {
+                write!(f, "back-reference points before the start of the stream")
+            }
+            HSError::OutputLimit => write!(f, "output limit exceeded"),
+            HSError::TooShort => write!(f, "uncompressed data is shorter than expected"),
+            HSError::TooLong => write!(f, "uncompressed data is longer than expected"),
+            HSError::TrailingData => write!(f, "data follows the end of the compressed stream"),
+            HSError::InvalidState => write!(f, "saved state is invalid"),
+            HSError::InvalidHeader => write!(f, "container header is invalid"),
+            HSError::InvalidChecksum => write!(f, "checksum of the uncompressed data is invalid"),
+            HSError::InvalidFrame => write!(f, "frame is too large or invalid"),
+            HSError::Internal => write!(f, "internal error"),
+        }
+    }
+}
+
+impl core::error::Error for HSError {}
+
+/// Window and lookahead sizes of a heatshrink stream. They are not stored in
+/// the compressed stream so the encoder and the decoder must agree on them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Params {
+    window_bits: u8,
+    lookahead_bits: u8,
+}
+
+/// Error returned when heatshrink parameters are not valid
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParamError {
+    /// The window size is not within the supported range
+    WindowBitsOutOfRange,
+    /// The back-reference length is smaller than the supported minimum
+    LookaheadBitsTooSmall,
+    /// The back-reference length is not smaller than the window size
+    LookaheadBitsTooLarge,
+    /// The window does not fit in the capacity of the encoder/decoder
+    WindowTooLarge,
+}
+
+impl core::fmt::Display for ParamError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParamError::WindowBitsOutOfRange => write!(
+                f,
+                "window size must be between {} and {} bits",
+                HEATSHRINK_MIN_WINDOWS_BITS, HEATSHRINK_MAX_WINDOWS_BITS
+            ),
+            ParamError::LookaheadBitsTooSmall => write!(
+                f,
+                "back-reference length must be at least {} bits",
+                HEATSHRINK_MIN_LOOKAHEAD_BITS
+            ),
+            ParamError::LookaheadBitsTooLarge => {
+                write!(f, "back-reference length must be smaller than window size")
+            }
+            ParamError::WindowTooLarge => {
+                write!(f, "window size exceeds the capacity of the encoder/decoder")
+            }
+        }
+    }
+}
+
+impl core::error::Error for ParamError {}
+
+impl Params {
+    /// Create a new set of parameters after checking they are supported by
+    /// heatshrink: a window of 4 to 15 bits and a back-reference length of
+    /// at least 3 bits that is smaller than the window.
+    pub const fn new(window_bits: u8, lookahead_bits: u8) -> Result<Params, ParamError> {
+        if window_bits < HEATSHRINK_MIN_WINDOWS_BITS || window_bits > HEATSHRINK_MAX_WINDOWS_BITS {
+            Err(ParamError::WindowBitsOutOfRange)
+        } else if lookahead_bits < HEATSHRINK_MIN_LOOKAHEAD_BITS {
+            Err(ParamError::LookaheadBitsTooSmall)
+        } else if lookahead_bits >= window_bits {
+            Err(ParamError::LookaheadBitsTooLarge)
+        } else {
+            Ok(Params {
+                window_bits,
+                lookahead_bits,
+            })
+        }
+    }
+
+    /// Parameters of the `Default` instances with a window capacity of
+    /// window_size bytes. Meant to be evaluated at compile time, so that a
+    /// capacity too small for any heatshrink window fails the build.
+    const fn default_for_window_size(window_size: usize) -> Params {
+        match Params::for_window_size(window_size) {
+            Ok(params) => params,
+            Err(_) => panic!("WINDOW_SIZE too small for heatshrink"),
+        }
+    }
+
+    /// Largest parameters fitting in a window of window_size bytes, using
+    /// the default back-reference length when possible
+    const fn for_window_size(window_size: usize) -> Result<Params, ParamError> {
+        if window_size < 1 << HEATSHRINK_MIN_WINDOWS_BITS {
+            return Err(ParamError::WindowBitsOutOfRange);
+        }
+        let mut window_bits = window_size.ilog2() as u8;
+        if window_bits > HEATSHRINK_MAX_WINDOWS_BITS {
+            window_bits = HEATSHRINK_MAX_WINDOWS_BITS;
+        }
+        let lookahead_bits = if HEATSHRINK_LOOKAHEAD_BITS < window_bits {
+            HEATSHRINK_LOOKAHEAD_BITS
+        } else {
+            window_bits - 1
+        };
+        Params::new(window_bits, lookahead_bits)
+    }
+
+    /// Base-2 log of LZSS sliding window size
+    pub const fn window_bits(&self) -> u8 {
+        self.window_bits
+    }
+
+    /// Number of bits used for back-reference lengths
+    pub const fn lookahead_bits(&self) -> u8 {
+        self.lookahead_bits
+    }
+
+    /// Size in bytes of the LZSS sliding window
+    pub const fn window_size(&self) -> usize {
+        1 << self.window_bits
+    }
+
+    /// Check the window fits in a buffer of window_size bytes
+    const fn check_window_size(&self, window_size: usize) -> Result<(), ParamError> {
+        if self.window_size() > window_size {
+            Err(ParamError::WindowTooLarge)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            window_bits: HEATSHRINK_WINDOWS_BITS,
+            lookahead_bits: HEATSHRINK_LOOKAHEAD_BITS,
+        }
+    }
+}
+
+/// Uncompressed data of the known-answer vector of `self_test`
+const SELF_TEST_PLAIN: &[u8] =
+    b"heatshrink self-test: abcabcabcabcabcabc 0000000000000000 heatshrink";
+
+/// `SELF_TEST_PLAIN` compressed with the default parameters
+const SELF_TEST_COMPRESSED: [u8; 37] = [
+    0xb4, 0x59, 0x6c, 0x37, 0x4b, 0x9d, 0xa2, 0xe5, 0x69, 0xb7, 0x5a, 0xe4, 0x17, 0x3b, 0x2d, 0xb2,
+    0xcd, 0x2d, 0xba, 0x59, 0x6e, 0x77, 0x49, 0xd4, 0x82, 0xc3, 0x62, 0xb1, 0x80, 0xba, 0x41, 0x30,
+    0x00, 0x74, 0x80, 0x73, 0x20,
+];
+
+/// Run a small known-answer vector through the encoder and the decoder
+/// compiled in, e.g. as a power-on self-test of the firmware.
+///
+/// Fail with the error of the encoder or of the decoder, or with `Internal`
+/// if either does not produce the expected output.
+pub fn self_test() -> Result<(), HSError> {
+    #[cfg(feature = "encoder")]
+    {
+        let mut compressed = [0u8; SELF_TEST_COMPRESSED.len()];
+        if encoder::encode(SELF_TEST_PLAIN, &mut compressed)? != SELF_TEST_COMPRESSED {
+            return Err(HSError::Internal);
+        }
+    }
+
+    #[cfg(feature = "decoder")]
+    {
+        let mut plain = [0u8; SELF_TEST_PLAIN.len()];
+        let expected_len = SELF_TEST_PLAIN.len();
+        if decoder::decode_exact(&SELF_TEST_COMPRESSED, &mut plain, expected_len)?
+            != SELF_TEST_PLAIN
+        {
+            return Err(HSError::Internal);
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of bytes of the buffers shown by the Debug implementations
+const HEATSHRINK_DEBUG_PREVIEW_SIZE: usize = 16;
+
+/// Debug helper printing the first bytes of a buffer in hex and its size
+struct HexPreview<'a>(&'a [u8]);
+
+impl core::fmt::Debug for HexPreview<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for byte in self.0.iter().take(HEATSHRINK_DEBUG_PREVIEW_SIZE) {
+            write!(f, "{:02x}", byte)?;
+        }
+        if self.0.len() > HEATSHRINK_DEBUG_PREVIEW_SIZE {
+            write!(f, "..")?;
+        }
+        write!(f, " ({} bytes)", self.0.len())
+    }
+}
+
+/// Destination of the bytes produced by an encoder or a decoder
+///
+/// It is implemented for byte slices (which are filled from the start and
+/// shrink as bytes are pushed) and can be implemented to output directly into
+/// ring buffers, UART drivers, flash writers, etc.
+pub trait ByteSink {
+    /// Push a byte. Only called when `remaining_capacity` is not 0.
+    fn push_byte(&mut self, byte: u8);
+
+    /// Push a run of bytes. Only called with no more bytes than
+    /// `remaining_capacity`. The default implementation pushes them one by
+    /// one.
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push_byte(byte);
+        }
+    }
+
+    /// Number of bytes that can still be pushed
+    fn remaining_capacity(&self) -> usize;
+}
+
+impl ByteSink for &mut [u8] {
+    fn push_byte(&mut self, byte: u8) {
+        // a byte pushed to a full slice is dropped
+        if let Some((first, rest)) = core::mem::take(self).split_first_mut() {
+            *first = byte;
+            *self = rest;
+        }
+    }
+
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        // bytes pushed past the end of the slice are dropped
+        let count = bytes.len().min(self.len());
+        if let Some((head, rest)) = core::mem::take(self).split_at_mut_checked(count) {
+            for (slot, &byte) in head.iter_mut().zip(bytes) {
+                *slot = byte;
+            }
+            *self = rest;
+        }
+    }
+
+    fn remaining_capacity(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Structure to manage the output sink and keep track of how many bytes were
+/// pushed to it
+struct OutputInfo<'a, 'b, S: ByteSink + ?Sized> {
+    sink: &'a mut S,
     output_size: &'b mut usize,
 }
 
-impl<'a, 'b> OutputInfo<'a, 'b> {
+impl<'a, 'b, S: ByteSink + ?Sized> OutputInfo<'a, 'b, S> {
     /// Create a new OutputInfo instance from provided parameters
-    fn new(output_buffer: &'a mut [u8], output_size: &'b mut usize) -> Self {
-        OutputInfo {
-            output_buffer,
-            output_size,
-        }
+    fn new(sink: &'a mut S, output_size: &'b mut usize) -> Self {
+        OutputInfo { sink, output_size }
     }
 
-    /// Add a byte to the OutputInfo referenced buffer
+    /// Add a byte to the OutputInfo referenced sink
     fn push_byte(&mut self, byte: u8) {
-        self.output_buffer[*self.output_size] = byte;
+        self.sink.push_byte(byte);
         *self.output_size += 1;
     }
 
-    /// Check if there is space left in the OutputInfo buffer
+    /// Add a run of bytes to the OutputInfo referenced sink
+    #[cfg(feature = "decoder")]
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        self.sink.push_bytes(bytes);
+        *self.output_size += bytes.len();
+    }
+
+    /// Check if there is space left in the OutputInfo sink
     fn can_take_byte(&self) -> bool {
-        *self.output_size < self.output_buffer.len()
+        self.sink.remaining_capacity() > 0
     }
 
-    /// get the free space in the buffer
+    /// get the free space in the sink
     fn remaining_free_size(&self) -> usize {
-        self.output_buffer.len() - *self.output_size
+        self.sink.remaining_capacity()
+    }
+}
+
+/// Little-endian writer of the saved state of an encoder or a decoder
+struct StateWriter<'a> {
+    buffer: &'a mut [u8],
+}
+
+impl StateWriter<'_> {
+    /// Append bytes to the state. The buffer was checked to be large enough
+    /// for the whole state.
+    fn bytes(&mut self, bytes: &[u8]) {
+        if let Some((head, tail)) =
+            core::mem::take(&mut self.buffer).split_at_mut_checked(bytes.len())
+        {
+            head.copy_from_slice(bytes);
+            self.buffer = tail;
+        }
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+}
+
+/// Little-endian reader of the saved state of an encoder or a decoder
+struct StateReader<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Take the next bytes of the state, failing if it is truncated
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], HSError> {
+        let (head, tail) = self
+            .buffer
+            .split_at_checked(len)
+            .ok_or(HSError::InvalidState)?;
+        self.buffer = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], HSError> {
+        self.bytes(N)?.try_into().map_err(|_| HSError::InvalidState)
+    }
+
+    fn u8(&mut self) -> Result<u8, HSError> {
+        self.array().map(u8::from_le_bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, HSError> {
+        self.array().map(u16::from_le_bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, HSError> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> Result<u64, HSError> {
+        self.array().map(u64::from_le_bytes)
+    }
+}
+
+/// Bytes of a saved state, serialized as a byte array rather than as a
+/// sequence of integers
+#[cfg(feature = "serde")]
+struct StateBytes<'a>(&'a [u8]);
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for StateBytes<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Deserialize bytes of a saved state in place, into a buffer of exactly
+/// their size, whether the format stores them as bytes or as a sequence
+#[cfg(feature = "serde")]
+struct StateBytesMut<'a>(&'a mut [u8]);
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::DeserializeSeed<'de> for StateBytesMut<'_> {
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_bytes(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::de::Visitor<'de> for StateBytesMut<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} bytes of saved state", self.0.len())
+    }
+
+    fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<(), E> {
+        if bytes.len() != self.0.len() {
+            return Err(E::invalid_length(bytes.len(), &self));
+        }
+        self.0.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        use serde::de::Error;
+
+        let size = self.0.len();
+        for (index, byte) in self.0.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| A::Error::invalid_length(index, &"the size of the saved state"))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(A::Error::invalid_length(
+                size + 1,
+                &"the size of the saved state",
+            ));
+        }
+
+        Ok(())
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "encoder", feature = "decoder"))]
 mod test {
-    use super::{decoder, encoder};
+    extern crate std;
+
+    use super::{
+        block, decoder, encoder, ByteSink, HSError, HSfinishRes, HSpollRes, HSsinkRes, ParamError,
+        Params,
+    };
+    use proptest::collection::vec;
+    use proptest::prelude::{any, Just, Strategy};
+    use proptest::{prop_assert_eq, prop_oneof, proptest};
 
     fn compare(src: &[u8]) {
         let mut compressed_buffer: [u8; 512] = [0; 512];
@@ -110,6 +647,21 @@ mod test {
         assert_eq!(src, out2);
     }
 
+    fn compare_with<const WINDOW_SIZE: usize>(src: &[u8], window_bits: u8, lookahead_bits: u8) {
+        let mut compressed_buffer: [u8; 2048] = [0; 2048];
+        let mut uncompressed_buffer: [u8; 1024] = [0; 1024];
+
+        let params = Params::new(window_bits, lookahead_bits).unwrap();
+
+        let mut enc = encoder::HeatshrinkEncoder::<WINDOW_SIZE>::with_params(params).unwrap();
+        let out1 = encoder::encode_with(&mut enc, src, &mut compressed_buffer).unwrap();
+
+        let mut dec = decoder::HeatshrinkDecoder::<WINDOW_SIZE>::with_params(params).unwrap();
+        let out2 = decoder::decode_with(&mut dec, out1, &mut uncompressed_buffer).unwrap();
+
+        assert_eq!(src, out2);
+    }
+
     #[test]
     fn alpha() {
         let src = [
@@ -188,4 +740,3965 @@ mod test {
 
         assert_eq!(expected, out);
     }
+
+    #[test]
+    fn clib_compatibility_w11() {
+        // `heatshrink -e -w 11 -l 4` output for "abc" repeated 6 times
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let expected = b"abcabcabcabcabcabc";
+        let mut dst: [u8; 100] = [0; 100];
+
+        let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+        let out = decoder::decode_with(&mut dec, &src, &mut dst).unwrap();
+        assert_eq!(expected, out);
+
+        let mut enc = encoder::HeatshrinkEncoder::<2048>::default();
+        let out = encoder::encode_with(&mut enc, expected, &mut dst).unwrap();
+        assert_eq!(src, out);
+    }
+
+    #[test]
+    fn window_sizes() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 251) as u8 ^ (i / 300) as u8;
+        }
+        compare_with::<16>(&src, 4, 3);
+        compare_with::<256>(&src, 5, 4);
+        compare_with::<256>(&src, 8, 4);
+        compare_with::<512>(&src, 9, 8);
+        compare_with::<2048>(&src, 11, 4);
+        compare_with::<4096>(&src, 10, 6);
+        compare_with::<4096>(&src, 12, 8);
+        compare_with::<4096>(&src, 12, 11);
+    }
+
+    #[test]
+    fn multi_byte_backref() {
+        // "abc" repeated 6 times with -w 12 -l 10: both the index and the
+        // count of the back-reference are wider than a byte
+        let src = hex_literal::hex!("b0d8ac60020380");
+        let expected = b"abcabcabcabcabcabc";
+        let mut dst: [u8; 100] = [0; 100];
+
+        let params = Params::new(12, 10).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::<4096>::with_params(params).unwrap();
+        let out = decoder::decode_with(&mut dec, &src, &mut dst).unwrap();
+        assert_eq!(expected, out);
+
+        let mut enc = encoder::HeatshrinkEncoder::<4096>::with_params(params).unwrap();
+        let out = encoder::encode_with(&mut enc, expected, &mut dst).unwrap();
+        assert_eq!(src, out);
+
+        // back-references further than 256 bytes need the index MSB
+        let mut src = [0u8; 900];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = ((i % 300) * 7 % 256) as u8;
+        }
+        let mut compressed_buffer: [u8; 1024] = [0; 1024];
+        let mut uncompressed_buffer: [u8; 1024] = [0; 1024];
+        let params = Params::new(9, 8).unwrap();
+        let mut enc = encoder::HeatshrinkEncoder::<512>::with_params(params).unwrap();
+        let out1 = encoder::encode_with(&mut enc, &src, &mut compressed_buffer).unwrap();
+        assert!(out1.len() < 400);
+        let mut dec = decoder::HeatshrinkDecoder::<512>::with_params(params).unwrap();
+        let out2 = decoder::decode_with(&mut dec, out1, &mut uncompressed_buffer).unwrap();
+        assert_eq!(src, out2);
+    }
+
+    #[cfg(feature = "w11l4")]
+    #[test]
+    fn preset_w11l4() {
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let mut dst: [u8; 100] = [0; 100];
+
+        let mut dec = decoder::HeatshrinkDecoderW11L4::default();
+        let out = decoder::decode_with(&mut dec, &src, &mut dst).unwrap();
+        assert_eq!(b"abcabcabcabcabcabc", out);
+
+        let mut enc = encoder::HeatshrinkEncoderW11L4::default();
+        assert_eq!(enc.params(), Params::new(11, 4).unwrap());
+        let out = encoder::encode_with(&mut enc, b"abcabcabcabcabcabc", &mut dst).unwrap();
+        assert_eq!(src, out);
+    }
+
+    #[test]
+    fn params_validation() {
+        assert_eq!(Params::new(8, 4), Ok(Params::default()));
+        assert_eq!(Params::new(3, 2), Err(ParamError::WindowBitsOutOfRange));
+        assert_eq!(Params::new(16, 4), Err(ParamError::WindowBitsOutOfRange));
+        assert_eq!(Params::new(8, 2), Err(ParamError::LookaheadBitsTooSmall));
+        assert_eq!(Params::new(8, 8), Err(ParamError::LookaheadBitsTooLarge));
+
+        let params = Params::new(9, 4).unwrap();
+        assert!(matches!(
+            encoder::HeatshrinkEncoder::<256>::with_params(params),
+            Err(ParamError::WindowTooLarge)
+        ));
+        assert!(matches!(
+            decoder::HeatshrinkDecoder::<256>::with_params(params),
+            Err(ParamError::WindowTooLarge)
+        ));
+        assert!(decoder::HeatshrinkDecoder::<1024>::with_params(params).is_ok());
+    }
+
+    #[test]
+    fn match_limits() {
+        let mut src = [0u8; 600];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 100) as u8;
+        }
+        let mut compressed_buffer: [u8; 1024] = [0; 1024];
+        let mut uncompressed_buffer: [u8; 1024] = [0; 1024];
+
+        // matches are 100 bytes away so none can be used
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        enc.set_max_backref_distance(64);
+        let out1 = encoder::encode_with(&mut enc, &src, &mut compressed_buffer).unwrap();
+        assert_eq!(out1.len(), (src.len() * 9).div_ceil(8));
+        let out2 = decoder::decode(out1, &mut uncompressed_buffer).unwrap();
+        assert_eq!(src, out2);
+
+        // short back-references need more of them to cover the input
+        let default_len = encoder::encode(&src, &mut compressed_buffer).unwrap().len();
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        enc.set_max_match_length(4);
+        let out1 = encoder::encode_with(&mut enc, &src, &mut compressed_buffer).unwrap();
+        assert!(out1.len() > default_len);
+        let out2 = decoder::decode(out1, &mut uncompressed_buffer).unwrap();
+        assert_eq!(src, out2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reader() {
+        use std::io::Read;
+
+        let src = hex_literal::hex!("90D4B2B549A4082BE00F000E4C46DF2817C605F005B4BE0825F00280");
+        let expected = decoder::decode(&src, &mut [0; 100]).unwrap().to_vec();
+
+        let mut out = std::vec::Vec::new();
+        let mut reader = super::io::HeatshrinkReader::new(&src[..]);
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(expected, out);
+
+        // read one byte at a time
+        let mut reader = super::io::HeatshrinkReader::new(&src[..]);
+        let mut byte = [0u8; 1];
+        out.clear();
+        while reader.read(&mut byte).unwrap() == 1 {
+            out.push(byte[0]);
+        }
+        assert_eq!(expected, out);
+
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let mut reader = super::io::HeatshrinkReader::with_decoder(
+            &src[..],
+            decoder::HeatshrinkDecoder::<2048>::default(),
+        );
+        out.clear();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(b"abcabcabcabcabcabc", &out[..]);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn writer() {
+        use std::io::Write;
+
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 251) as u8 ^ (i / 300) as u8;
+        }
+        let mut compressed_buffer = [0u8; 4096];
+        let expected = encoder::encode(&src, &mut compressed_buffer).unwrap();
+
+        let mut writer = super::io::HeatshrinkWriter::new(std::vec::Vec::new());
+        std::io::copy(&mut &src[..], &mut writer).unwrap();
+        assert_eq!(expected, writer.finish().unwrap());
+
+        // write in small irregular chunks
+        let mut writer = super::io::HeatshrinkWriter::new(std::vec::Vec::new());
+        for chunk in src.chunks(7) {
+            writer.write_all(chunk).unwrap();
+        }
+        let out = writer.finish().unwrap();
+        assert_eq!(expected, out);
+
+        let mut back = std::vec::Vec::new();
+        std::io::copy(&mut super::io::HeatshrinkReader::new(&out[..]), &mut back).unwrap();
+        assert_eq!(&src[..], back);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stream_format() {
+        use super::container::{self, Format, Header, Metadata};
+        use std::io::{Read, Write};
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let params = Params::new(10, 5).unwrap();
+        let mut raw = [0u8; 4096];
+        let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+        let raw = encoder::encode_with(&mut enc, src, &mut raw).unwrap();
+
+        let mut writer = super::io::HeatshrinkWriter::with_encoder(
+            std::vec::Vec::new(),
+            encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap(),
+        );
+        writer.set_format(Format::Container);
+        writer.write_all(src).unwrap();
+        let contained = writer.finish().unwrap();
+        assert_eq!(
+            container::read_header(&contained),
+            Ok((Header::new(params, None), container::HEADER_SIZE))
+        );
+        assert_eq!(&contained[container::HEADER_SIZE..], raw);
+        assert_eq!(Format::detect(&contained), Format::Container);
+        assert_eq!(Format::detect(raw), Format::Raw);
+
+        // the parameters are read from the header, whatever the decoder
+        let mut with_metadata = [0u8; 4096];
+        let metadata = Metadata {
+            name: Some("decoder.rs"),
+            ..Metadata::new()
+        };
+        let header_size = container::write_header_with_metadata(
+            &Header::new(params, Some(3000)),
+            &metadata,
+            &mut with_metadata,
+        )
+        .unwrap();
+        with_metadata[header_size..header_size + raw.len()].copy_from_slice(raw);
+        let with_metadata = &with_metadata[..header_size + raw.len()];
+        for (stream, format) in [
+            (&contained[..], Some(Format::Container)),
+            (&contained[..], None),
+            (with_metadata, None),
+        ] {
+            let mut reader = super::io::HeatshrinkReader::with_decoder(
+                stream,
+                decoder::HeatshrinkDecoder::<1024>::default(),
+            );
+            reader.set_format(format);
+            let mut out = std::vec::Vec::new();
+            reader.read_to_end(&mut out).unwrap();
+            assert_eq!(&out[..], src);
+        }
+        let mut reader = super::io::HeatshrinkReader::with_decoder(
+            raw,
+            decoder::HeatshrinkDecoder::<1024>::with_params(params).unwrap(),
+        );
+        reader.set_format(None);
+        let mut out = std::vec::Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(&out[..], src);
+
+        // the length recorded in the header is checked
+        let mut short = with_metadata.to_vec();
+        short[7..15].copy_from_slice(&3001u64.to_le_bytes());
+        let mut reader = super::io::HeatshrinkReader::new(&short[..]);
+        reader.set_format(None);
+        let error = reader.read_to_end(&mut std::vec::Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        let mut reader = super::io::HeatshrinkReader::new(&with_metadata[..20]);
+        reader.set_format(Some(Format::Container));
+        let error = reader.read_to_end(&mut std::vec::Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // the reads failing with WouldBlock (here between all the bytes) are
+        // retried, in the header as in the stream
+        struct Trickle<'a> {
+            data: &'a [u8],
+            ready: bool,
+        }
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.ready = !self.ready;
+                if !self.ready {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                let size = buf.len().min(self.data.len()).min(1);
+                buf[..size].copy_from_slice(&self.data[..size]);
+                self.data = &self.data[size..];
+                Ok(size)
+            }
+        }
+        for (stream, format) in [
+            (&contained[..], Some(Format::Container)),
+            (with_metadata, None),
+            (raw, None),
+        ] {
+            let mut reader = super::io::HeatshrinkReader::with_decoder(
+                Trickle {
+                    data: stream,
+                    ready: false,
+                },
+                decoder::HeatshrinkDecoder::<1024>::with_params(params).unwrap(),
+            );
+            reader.set_format(format);
+            let mut out = std::vec::Vec::new();
+            let mut buffer = [0u8; 64];
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(size) => out.extend_from_slice(&buffer[..size]),
+                    Err(error) => assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock),
+                }
+            }
+            assert_eq!(&out[..], src);
+        }
+    }
+
+    #[cfg(feature = "embedded-io-async")]
+    #[test]
+    fn embedded_io_async() {
+        use super::embedded_io::{Error, HeatshrinkReader, HeatshrinkWriter};
+        use core::future::Future;
+        use core::pin::pin;
+        use core::task::{Context, Poll, Waker};
+        use embedded_io_async::{Read, Write};
+
+        // the wrapped slices never pend
+        fn block_on<T>(future: impl Future<Output = T>) -> T {
+            let mut future = pin!(future);
+            let mut context = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            }
+        }
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut expected = [0u8; 4096];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        let mut compressed = [0u8; 4096];
+        let compressed_size = block_on(async {
+            let mut writer = HeatshrinkWriter::new(&mut compressed[..]);
+            for chunk in src.chunks(7) {
+                writer.write_all(chunk).await.unwrap();
+            }
+            let rest = writer.finish().await.unwrap();
+            4096 - rest.len()
+        });
+        assert_eq!(&compressed[..compressed_size], expected);
+
+        let mut output = [0u8; 3000];
+        let output_size = block_on(async {
+            let mut reader = HeatshrinkReader::new(&compressed[..compressed_size]);
+            let mut output_size = 0;
+            loop {
+                match reader.read(&mut output[output_size..]).await.unwrap() {
+                    0 => break output_size,
+                    read => output_size += read,
+                }
+            }
+        });
+        assert_eq!(&output[..output_size], src);
+
+        block_on(async {
+            let mut reader = HeatshrinkReader::new(&compressed[..compressed_size - 1]);
+            let mut output = [0u8; 3000];
+            assert_eq!(
+                reader.read_exact(&mut output).await,
+                Err(embedded_io_async::ReadExactError::Other(Error::Heatshrink(
+                    HSError::Truncated
+                )))
+            );
+        });
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn futures_async_io() {
+        use super::async_io::{AsyncHeatshrinkReader, AsyncHeatshrinkWriter};
+        use core::future::poll_fn;
+        use core::pin::{pin, Pin};
+        use core::task::{Context, Poll, Waker};
+        use futures_io::{AsyncRead, AsyncWrite};
+
+        fn block_on<T>(future: impl core::future::Future<Output = T>) -> T {
+            let mut future = pin!(future);
+            let mut context = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            }
+        }
+
+        // pending every other call, a few bytes at a time
+        struct Throttled<T> {
+            inner: T,
+            pending: bool,
+        }
+        impl<T: AsyncRead + Unpin> AsyncRead for Throttled<T> {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<std::io::Result<usize>> {
+                self.pending = !self.pending;
+                if self.pending {
+                    return Poll::Pending;
+                }
+                let size = buf.len().min(5);
+                Pin::new(&mut self.inner).poll_read(cx, &mut buf[..size])
+            }
+        }
+        impl<T: AsyncWrite + Unpin> AsyncWrite for Throttled<T> {
+            fn poll_write(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<std::io::Result<usize>> {
+                self.pending = !self.pending;
+                if self.pending {
+                    return Poll::Pending;
+                }
+                let size = buf.len().min(5);
+                Pin::new(&mut self.inner).poll_write(cx, &buf[..size])
+            }
+            fn poll_flush(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Pin::new(&mut self.inner).poll_flush(cx)
+            }
+            fn poll_close(
+                mut self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+            ) -> Poll<std::io::Result<()>> {
+                Pin::new(&mut self.inner).poll_close(cx)
+            }
+        }
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut expected = [0u8; 4096];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        let mut writer = AsyncHeatshrinkWriter::new(Throttled {
+            inner: std::vec::Vec::new(),
+            pending: false,
+        });
+        block_on(async {
+            for mut chunk in src.chunks(100) {
+                while !chunk.is_empty() {
+                    let size = poll_fn(|cx| Pin::new(&mut writer).poll_write(cx, chunk))
+                        .await
+                        .unwrap();
+                    chunk = &chunk[size..];
+                }
+            }
+            poll_fn(|cx| Pin::new(&mut writer).poll_close(cx))
+                .await
+                .unwrap();
+        });
+        let compressed = writer.into_inner().inner;
+        assert_eq!(compressed, expected);
+
+        let mut reader = AsyncHeatshrinkReader::new(Throttled {
+            inner: &compressed[..],
+            pending: false,
+        });
+        let mut output = std::vec::Vec::new();
+        block_on(async {
+            let mut buffer = [0u8; 64];
+            loop {
+                match poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut buffer))
+                    .await
+                    .unwrap()
+                {
+                    0 => break,
+                    size => output.extend_from_slice(&buffer[..size]),
+                }
+            }
+        });
+        assert_eq!(output, src);
+
+        let mut reader = AsyncHeatshrinkReader::new(&compressed[..compressed.len() - 1]);
+        let error = block_on(async {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut buffer)).await {
+                    Ok(0) => break None,
+                    Ok(_) => {}
+                    Err(error) => break Some(error.kind()),
+                }
+            }
+        });
+        assert_eq!(error, Some(std::io::ErrorKind::UnexpectedEof));
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn bytes_stream() {
+        use super::stream::{DecodeStream, EncodeStream};
+        use bytes::Bytes;
+        use core::pin::Pin;
+        use core::task::{Context, Poll, Waker};
+        use futures_core::Stream;
+
+        // stream of the chunks of a vector, ready every other poll
+        struct Chunks {
+            chunks: alloc::vec::IntoIter<Bytes>,
+            pending: bool,
+        }
+        impl Chunks {
+            fn new(data: &[u8], chunk_size: usize) -> Self {
+                let chunks: alloc::vec::Vec<Bytes> = data
+                    .chunks(chunk_size)
+                    .map(Bytes::copy_from_slice)
+                    .collect();
+                Chunks {
+                    chunks: chunks.into_iter(),
+                    pending: false,
+                }
+            }
+        }
+        impl Stream for Chunks {
+            type Item = Bytes;
+
+            fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<Bytes>> {
+                self.pending = !self.pending;
+                if self.pending {
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.chunks.next())
+            }
+        }
+
+        fn collect<S: Stream<Item = Result<Bytes, HSError>> + Unpin>(
+            mut stream: S,
+        ) -> Result<alloc::vec::Vec<u8>, HSError> {
+            let mut context = Context::from_waker(Waker::noop());
+            let mut output = alloc::vec::Vec::new();
+            loop {
+                match Pin::new(&mut stream).poll_next(&mut context) {
+                    Poll::Ready(Some(chunk)) => output.extend_from_slice(&chunk?),
+                    Poll::Ready(None) => return Ok(output),
+                    Poll::Pending => {}
+                }
+            }
+        }
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut expected = [0u8; 4096];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        let compressed = collect(EncodeStream::new(Chunks::new(src, 100))).unwrap();
+        assert_eq!(compressed, expected);
+        let output = collect(DecodeStream::new(Chunks::new(&compressed, 7))).unwrap();
+        assert_eq!(output, src);
+
+        let truncated = &compressed[..compressed.len() - 1];
+        assert_eq!(
+            collect(DecodeStream::new(Chunks::new(truncated, 7))),
+            Err(HSError::Truncated)
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn progress_callback() {
+        use std::cell::Cell;
+        use std::io::Write;
+        use std::rc::Rc;
+
+        let src = [0x55u8; 3000];
+        let last = Rc::new(Cell::new((0, 0)));
+
+        let mut writer = super::io::HeatshrinkWriter::new(std::vec::Vec::new());
+        let progress = last.clone();
+        writer.set_progress_callback(move |total_in, total_out| {
+            // the counters never go backward
+            assert!(total_in >= progress.get().0 && total_out >= progress.get().1);
+            progress.set((total_in, total_out));
+        });
+        writer.write_all(&src[..1000]).unwrap();
+        assert_eq!(last.get().0, 1000);
+        writer.write_all(&src[1000..]).unwrap();
+        let compressed = writer.finish().unwrap();
+        assert_eq!(last.get(), (3000, compressed.len() as u64));
+
+        let mut reader = super::io::HeatshrinkReader::new(&compressed[..]);
+        let progress = last.clone();
+        reader.set_progress_callback(move |total_in, total_out| {
+            progress.set((total_in, total_out));
+        });
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(last.get(), (compressed.len() as u64, 3000));
+    }
+
+    #[test]
+    fn resumable_encode() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 251) as u8 ^ (i / 300) as u8;
+        }
+        let mut compressed_buffer = [0u8; 2048];
+        let expected = encoder::encode(&src, &mut compressed_buffer).unwrap();
+
+        // compress into 16 bytes chunks, resuming after each one
+        let mut out = [0u8; 2048];
+        let mut out_size = 0;
+        let mut input_size = 0;
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        loop {
+            let mut chunk = [0u8; 16];
+            let (res, consumed, produced) =
+                encoder::encode_partial(&mut enc, &src[input_size..], &mut chunk).unwrap();
+            input_size += consumed;
+            out[out_size..out_size + produced].copy_from_slice(&chunk[..produced]);
+            out_size += produced;
+            if let HSfinishRes::FinishDone = res {
+                break;
+            }
+            assert_eq!(produced, chunk.len());
+        }
+        assert_eq!(input_size, src.len());
+        assert_eq!(expected, &out[..out_size]);
+
+        // the one-shot helper still reports a too small destination buffer
+        assert_eq!(
+            encoder::encode(&src, &mut out[..expected.len() - 1]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(1125)
+            })
+        );
+        assert!(encoder::encode(&src, &mut out[..expected.len()]).is_ok());
+
+        // an empty input fits in an empty destination buffer
+        assert!(matches!(encoder::encode(&[], &mut []), Ok(&[])));
+    }
+
+    #[cfg(feature = "heapless")]
+    #[test]
+    fn heapless_vec() {
+        let src = &include_bytes!("decoder.rs")[..1000];
+        let mut expected = [0u8; 2048];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        let compressed = encoder::encode_to_heapless::<1200>(src).unwrap();
+        assert_eq!(compressed, expected);
+        let output = decoder::decode_to_heapless::<1000>(&compressed).unwrap();
+        assert_eq!(output, src);
+
+        assert!(matches!(
+            encoder::encode_to_heapless::<100>(src),
+            Err(HSError::OutputFull { .. })
+        ));
+        assert!(matches!(
+            decoder::decode_to_heapless::<999>(&compressed),
+            Err(HSError::OutputFull { .. })
+        ));
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn postcard_flavor() {
+        let mut samples = [0u8; 300];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            *sample = (i % 10) as u8;
+        }
+        let value = (42u32, "temperature", &samples[..]);
+
+        let mut serialized = [0u8; 400];
+        let serialized = ::postcard::to_slice(&value, &mut serialized).unwrap();
+        let mut expected = [0u8; 400];
+        let expected = encoder::encode(serialized, &mut expected).unwrap();
+
+        let mut compressed = [0u8; 400];
+        let compressed = super::postcard::to_slice(&value, &mut compressed).unwrap();
+        assert_eq!(compressed, expected);
+        assert!(compressed.len() < serialized.len());
+
+        let mut scratch = [0u8; 400];
+        let output: (u32, &str, &[u8]) =
+            super::postcard::from_bytes(compressed, &mut scratch).unwrap();
+        assert_eq!(output, value);
+
+        assert_eq!(
+            super::postcard::to_slice(&value, &mut [0u8; 20]),
+            Err(super::postcard::Error::Postcard(
+                ::postcard::Error::SerializeBufferFull
+            ))
+        );
+        assert_eq!(
+            super::postcard::from_bytes::<(u32, &str, &[u8])>(compressed, &mut [0u8; 100]),
+            Err(super::postcard::Error::Heatshrink(HSError::OutputFull {
+                needed_hint: None
+            }))
+        );
+    }
+
+    #[test]
+    fn compressing_fmt_writer() {
+        use super::log::CompressingFmtWriter;
+        use core::fmt::Write;
+
+        let text = core::str::from_utf8(&include_bytes!("decoder.rs")[..2000]).unwrap();
+        let mut compressed = [0u8; 2000];
+        let compressed_size = core::cell::Cell::new(0);
+        let mut frames = 0;
+        let mut writer = CompressingFmtWriter::<_, 256, 32>::with_encoder(
+            Default::default(),
+            |frame: &[u8]| {
+                assert!(!frame.is_empty() && frame.len() <= 32);
+                let size = compressed_size.get();
+                compressed[size..size + frame.len()].copy_from_slice(frame);
+                compressed_size.set(size + frame.len());
+                frames += 1;
+            },
+        );
+        for line in text.split_inclusive('\n') {
+            writer.write_str(line).unwrap();
+        }
+        write!(writer, "x={}", 42).unwrap();
+        writer.flush().unwrap();
+        let flushed_size = compressed_size.get();
+        write!(writer, " {:?}", HSError::Truncated).unwrap();
+        writer.finish().unwrap();
+        assert!(frames > 1);
+
+        // the flushed prefix is decodable on its own
+        let mut output = [0u8; 2100];
+        let mut output_size = 0;
+        let mut write = |data: &[u8]| {
+            output[output_size..output_size + data.len()].copy_from_slice(data);
+            output_size += data.len();
+        };
+        let mut dec: decoder::HeatshrinkDecoder = Default::default();
+        dec.sink_all(&compressed[..flushed_size], &mut write)
+            .unwrap();
+        dec.sync().unwrap();
+        dec.sink_all(&compressed[flushed_size..compressed_size.get()], &mut write)
+            .unwrap();
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+
+        assert_eq!(&output[..text.len()], text.as_bytes());
+        assert_eq!(&output[text.len()..output_size], b"x=42 Truncated");
+    }
+
+    #[test]
+    fn log_compressor() {
+        use super::frame::FrameDecoder;
+        use super::log::LogCompressor;
+
+        let text = &include_bytes!("decoder.rs")[..3000];
+        let mut log = LogCompressor::<256, 128>::with_encoder(Default::default());
+        #[cfg(feature = "crc")]
+        log.set_crc(true);
+        let mut frames = [0u8; 4000];
+        let mut frames_size = 0;
+        let mut frame_count = 0;
+        let mut output = |frame: &[u8]| {
+            assert!(frame.len() <= 128);
+            frames[frames_size..frames_size + frame.len()].copy_from_slice(frame);
+            frames_size += frame.len();
+            frame_count += 1;
+        };
+        for record in text.split_inclusive(|&byte| byte == b'\n') {
+            log.append(record, &mut output).unwrap();
+        }
+        assert!(log.pending() > 0);
+        log.flush(&mut output).unwrap();
+        assert_eq!(log.pending(), 0);
+        log.flush(&mut output).unwrap();
+        assert!(frame_count > 10);
+
+        let mut decoder: FrameDecoder = FrameDecoder::new(Default::default(), 128);
+        let mut uncompressed = [0u8; 3000];
+        let mut uncompressed_size = 0;
+        let mut frames = &frames[..frames_size];
+        while !frames.is_empty() {
+            let payload_size = usize::from(u16::from_le_bytes([frames[0], frames[1]]));
+            let (frame, rest) = frames.split_at(2 + payload_size);
+            #[cfg(feature = "crc")]
+            let rest = {
+                use super::crc::{Checksum, Crc32};
+                let mut crc = Crc32::default();
+                crc.update(frame);
+                assert_eq!(rest[..4], crc.value().to_le_bytes());
+                &rest[4..]
+            };
+            decoder
+                .sink_all(frame, &mut |data| {
+                    uncompressed[uncompressed_size..uncompressed_size + data.len()]
+                        .copy_from_slice(data);
+                    uncompressed_size += data.len();
+                })
+                .unwrap();
+            frames = rest;
+        }
+        decoder.finish().unwrap();
+        assert_eq!(&uncompressed[..uncompressed_size], text);
+
+        let mut log = LogCompressor::<256, 3>::with_encoder(Default::default());
+        assert_eq!(
+            log.append(b"x", &mut |_| {}),
+            Err(HSError::OutputFull {
+                needed_hint: Some(4)
+            })
+        );
+    }
+
+    #[cfg(feature = "embedded-storage")]
+    #[test]
+    fn nor_flash() {
+        use super::flash::{decode_from_flash, Error};
+        use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+        /// flash reading 4 aligned bytes at a time
+        struct Flash([u8; 1024]);
+
+        impl ErrorType for Flash {
+            type Error = NorFlashErrorKind;
+        }
+
+        impl ReadNorFlash for Flash {
+            const READ_SIZE: usize = 4;
+
+            fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                let offset = offset as usize;
+                if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) {
+                    return Err(NorFlashErrorKind::NotAligned);
+                }
+                let data = self
+                    .0
+                    .get(offset..offset + bytes.len())
+                    .ok_or(NorFlashErrorKind::OutOfBounds)?;
+                bytes.copy_from_slice(data);
+                Ok(())
+            }
+
+            fn capacity(&self) -> usize {
+                self.0.len()
+            }
+        }
+
+        let src = &include_bytes!("decoder.rs")[..1500];
+        let mut compressed = [0u8; 1000];
+        let compressed = encoder::encode(src, &mut compressed).unwrap();
+        let mut flash = Flash([0xff; 1024]);
+        flash.0[6..6 + compressed.len()].copy_from_slice(compressed);
+
+        let mut dst = [0u8; 1500];
+        let output = decode_from_flash(&mut flash, 6, compressed.len(), &mut dst).unwrap();
+        assert_eq!(output, src);
+
+        assert_eq!(
+            decode_from_flash(&mut flash, 6, compressed.len(), &mut [0u8; 1000]),
+            Err(Error::Heatshrink(HSError::OutputFull { needed_hint: None }))
+        );
+        assert_eq!(
+            decode_from_flash(&mut flash, 6, compressed.len() - 1, &mut dst),
+            Err(Error::Heatshrink(HSError::Truncated))
+        );
+        assert_eq!(
+            decode_from_flash(&mut flash, 1000, 25, &mut dst),
+            Err(Error::Region(NorFlashErrorKind::OutOfBounds))
+        );
+    }
+
+    #[cfg(feature = "embedded-storage-async")]
+    #[test]
+    fn async_nor_flash() {
+        use super::flash::{decode_from_flash_async, decode_from_flash_with_async, Error};
+        use core::future::Future;
+        use core::pin::pin;
+        use core::task::{Context, Poll, Waker};
+        use embedded_storage_async::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+        /// flash pending once before each read
+        struct Flash([u8; 512]);
+
+        impl ErrorType for Flash {
+            type Error = NorFlashErrorKind;
+        }
+
+        impl ReadNorFlash for Flash {
+            const READ_SIZE: usize = 1;
+
+            async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+                let mut pending = true;
+                core::future::poll_fn(|_| {
+                    if core::mem::take(&mut pending) {
+                        Poll::Pending
+                    } else {
+                        Poll::Ready(())
+                    }
+                })
+                .await;
+                let offset = offset as usize;
+                let data = self
+                    .0
+                    .get(offset..offset + bytes.len())
+                    .ok_or(NorFlashErrorKind::OutOfBounds)?;
+                bytes.copy_from_slice(data);
+                Ok(())
+            }
+
+            fn capacity(&self) -> usize {
+                self.0.len()
+            }
+        }
+
+        fn block_on<T>(future: impl Future<Output = T>) -> T {
+            let mut future = pin!(future);
+            let mut context = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                    return output;
+                }
+            }
+        }
+
+        let src = &include_bytes!("decoder.rs")[..800];
+        let mut compressed = [0u8; 512];
+        let compressed = encoder::encode(src, &mut compressed).unwrap();
+        let mut flash = Flash([0; 512]);
+        flash.0[3..3 + compressed.len()].copy_from_slice(compressed);
+
+        let mut dst = [0u8; 800];
+        let output = block_on(decode_from_flash_async(
+            &mut flash,
+            3,
+            compressed.len(),
+            &mut dst,
+        ))
+        .unwrap();
+        assert_eq!(output, src);
+
+        let mut dec =
+            decoder::HeatshrinkDecoder::<2048>::with_params(Params::new(8, 4).unwrap()).unwrap();
+        let mut size = 0;
+        block_on(decode_from_flash_with_async(
+            &mut dec,
+            &mut flash,
+            3,
+            compressed.len(),
+            &mut |data| size += data.len(),
+        ))
+        .unwrap();
+        assert_eq!(size, src.len());
+
+        assert_eq!(
+            block_on(decode_from_flash_async(&mut flash, 500, 13, &mut dst)),
+            Err(Error::Region(NorFlashErrorKind::OutOfBounds))
+        );
+    }
+
+    // only the successful paths, JsError can't be built outside of wasm
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn wasm_bindings() {
+        use super::wasm::{decode, encode, Decoder, Encoder};
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut expected = [0u8; 4000];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+        assert_eq!(encode(src, None, None).unwrap(), expected);
+        assert_eq!(decode(expected, None, None).unwrap(), src);
+
+        let compressed = encode(src, Some(12), Some(5)).unwrap();
+        let mut encoder = Encoder::new(Some(12), Some(5)).unwrap();
+        let mut output = alloc::vec::Vec::new();
+        for chunk in src.chunks(100) {
+            output.extend(encoder.push(chunk).unwrap());
+        }
+        output.extend(encoder.finish().unwrap());
+        assert_eq!(output, compressed);
+
+        let mut decoder = Decoder::new(Some(12), Some(5)).unwrap();
+        let mut output = alloc::vec::Vec::new();
+        for chunk in compressed.chunks(7) {
+            output.extend(decoder.push(chunk).unwrap());
+        }
+        assert!(decoder.finish().unwrap().is_empty());
+        assert_eq!(output, src);
+    }
+
+    #[cfg(feature = "ffi")]
+    #[test]
+    #[allow(unsafe_code)]
+    fn c_api() {
+        use super::ffi::*;
+        use core::ptr::{null, null_mut};
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        for (window_bits, lookahead_bits) in [(8, 4), (12, 5)] {
+            let params = Params::new(window_bits, lookahead_bits).unwrap();
+            let mut enc = encoder::HeatshrinkEncoder::<4096>::with_params(params).unwrap();
+            let mut expected = [0u8; 4096];
+            let expected = encoder::encode_with(&mut enc, src, &mut expected).unwrap();
+
+            // the loops of the C examples, with 100 bytes buffers
+            let hse = heatshrink_encoder_alloc(window_bits, lookahead_bits);
+            assert!(!hse.is_null());
+            let mut compressed = alloc::vec::Vec::new();
+            let mut chunk = [0u8; 100];
+            let mut input_size = 0;
+            unsafe {
+                while input_size < src.len() {
+                    let mut sunk = 0;
+                    let rest = &src[input_size..];
+                    let res = heatshrink_encoder_sink(hse, rest.as_ptr(), rest.len(), &mut sunk);
+                    assert_eq!(res, HSE_sink_res::HSER_SINK_OK);
+                    input_size += sunk;
+                    loop {
+                        let mut polled = 0;
+                        let res =
+                            heatshrink_encoder_poll(hse, chunk.as_mut_ptr(), 100, &mut polled);
+                        compressed.extend_from_slice(&chunk[..polled]);
+                        if res == HSE_poll_res::HSER_POLL_EMPTY {
+                            break;
+                        }
+                    }
+                }
+                while heatshrink_encoder_finish(hse) == HSE_finish_res::HSER_FINISH_MORE {
+                    let mut polled = 0;
+                    heatshrink_encoder_poll(hse, chunk.as_mut_ptr(), 100, &mut polled);
+                    compressed.extend_from_slice(&chunk[..polled]);
+                }
+                heatshrink_encoder_free(hse);
+            }
+            assert_eq!(compressed, expected);
+
+            let hsd = heatshrink_decoder_alloc(64, window_bits, lookahead_bits);
+            assert!(!hsd.is_null());
+            let mut output = alloc::vec::Vec::new();
+            let mut input_size = 0;
+            unsafe {
+                while input_size < compressed.len() {
+                    let mut sunk = 0;
+                    let rest = &compressed[input_size..];
+                    heatshrink_decoder_sink(hsd, rest.as_ptr(), rest.len(), &mut sunk);
+                    input_size += sunk;
+                    loop {
+                        let mut polled = 0;
+                        let res =
+                            heatshrink_decoder_poll(hsd, chunk.as_mut_ptr(), 100, &mut polled);
+                        output.extend_from_slice(&chunk[..polled]);
+                        if res == HSD_poll_res::HSDR_POLL_EMPTY {
+                            break;
+                        }
+                    }
+                }
+                assert_eq!(
+                    heatshrink_decoder_finish(hsd),
+                    HSD_finish_res::HSDR_FINISH_DONE
+                );
+                heatshrink_decoder_free(hsd);
+            }
+            assert_eq!(output, src);
+        }
+
+        // invalid parameters and NULL arguments
+        assert!(heatshrink_encoder_alloc(16, 4).is_null());
+        assert!(heatshrink_decoder_alloc(0, 8, 4).is_null());
+        let hse = heatshrink_encoder_alloc(8, 4);
+        let mut chunk = [0u8; 1];
+        let mut size = 0;
+        unsafe {
+            assert_eq!(
+                heatshrink_encoder_sink(hse, null(), 0, &mut size),
+                HSE_sink_res::HSER_SINK_ERROR_NULL
+            );
+            assert_eq!(
+                heatshrink_encoder_poll(null_mut(), chunk.as_mut_ptr(), 1, &mut size),
+                HSE_poll_res::HSER_POLL_ERROR_NULL
+            );
+            assert_eq!(
+                heatshrink_decoder_finish(null_mut()),
+                HSD_finish_res::HSDR_FINISH_ERROR_NULL
+            );
+            heatshrink_encoder_free(hse);
+            heatshrink_decoder_free(null_mut());
+        }
+    }
+
+    #[test]
+    fn queue_grants() {
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut expected = [0u8; 4096];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        // feed the encoder with 13 bytes read grants and 5 bytes write grants
+        let mut compressed = [0u8; 4096];
+        let mut input_size = 0;
+        let mut output_size = 0;
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        while input_size < src.len() {
+            let read_grant = &src[input_size..(input_size + 13).min(src.len())];
+            let write_grant = &mut compressed[output_size..output_size + 5];
+            let (consumed, produced) =
+                encoder::encode_step(&mut enc, read_grant, write_grant).unwrap();
+            input_size += consumed;
+            output_size += produced;
+        }
+        loop {
+            let write_grant = &mut compressed[output_size..output_size + 5];
+            let (res, _, produced) = encoder::encode_partial(&mut enc, &[], write_grant).unwrap();
+            output_size += produced;
+            if let HSfinishRes::FinishDone = res {
+                break;
+            }
+        }
+        assert_eq!(&compressed[..output_size], expected);
+
+        let mut output = [0u8; 3000];
+        let mut input_size = 0;
+        let mut output_size = 0;
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        while input_size < expected.len() {
+            let read_grant = &expected[input_size..(input_size + 5).min(expected.len())];
+            let write_grant = &mut output[output_size..(output_size + 13).min(src.len())];
+            let (consumed, produced) =
+                decoder::decode_step(&mut dec, read_grant, write_grant).unwrap();
+            input_size += consumed;
+            output_size += produced;
+        }
+        while output_size < src.len() {
+            let write_grant = &mut output[output_size..(output_size + 13).min(src.len())];
+            let (_, produced) = decoder::decode_step(&mut dec, &[], write_grant).unwrap();
+            output_size += produced;
+        }
+        assert!(matches!(dec.finish(), HSfinishRes::FinishDone));
+        assert_eq!(output, src);
+    }
+
+    #[test]
+    fn errors() {
+        let src = hex_literal::hex!("90D4B2B549A4082BE00F000E4C46DF2817C605F005B4BE0825F00280");
+        let mut dst: [u8; 50] = [0; 50];
+
+        assert_eq!(
+            decoder::decode(&src, &mut dst),
+            Err(HSError::OutputFull { needed_hint: None })
+        );
+
+        let mut dst: [u8; 4] = [0; 4];
+        let err = encoder::encode(&src, &mut dst).unwrap_err();
+        assert_eq!(
+            err,
+            HSError::OutputFull {
+                needed_hint: Some(32)
+            }
+        );
+        let _: &dyn core::error::Error = &err;
+    }
+
+    #[test]
+    fn static_codecs() {
+        use std::sync::Mutex;
+
+        const PARAMS: Params = match Params::new(10, 4) {
+            Ok(params) => params,
+            Err(_) => panic!(),
+        };
+        static ENCODER: Mutex<encoder::HeatshrinkEncoder<1024>> =
+            Mutex::new(match encoder::HeatshrinkEncoder::with_params(PARAMS) {
+                Ok(enc) => enc,
+                Err(_) => panic!(),
+            });
+        static DECODER: Mutex<decoder::HeatshrinkDecoder<1024>> =
+            Mutex::new(match decoder::HeatshrinkDecoder::with_params(PARAMS) {
+                Ok(dec) => dec,
+                Err(_) => panic!(),
+            });
+        static DEFAULT_DECODER: Mutex<decoder::HeatshrinkDecoder> =
+            Mutex::new(decoder::HeatshrinkDecoder::new());
+
+        let src = b"abcabcabcabcabcabc static state";
+        let mut compressed = [0u8; 64];
+        let mut output = [0u8; 64];
+        let compressed =
+            encoder::encode_with(&mut ENCODER.lock().unwrap(), src, &mut compressed).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut DECODER.lock().unwrap(), compressed, &mut output),
+            Ok(&src[..])
+        );
+
+        let compressed = encoder::encode(src, &mut [0u8; 64]).unwrap().to_vec();
+        assert_eq!(
+            decoder::decode_with(
+                &mut DEFAULT_DECODER.lock().unwrap(),
+                &compressed,
+                &mut output
+            ),
+            Ok(&src[..])
+        );
+        assert_eq!(
+            encoder::HeatshrinkEncoder::<1024>::with_params(Params::new(11, 4).unwrap()).err(),
+            Some(ParamError::WindowTooLarge)
+        );
+    }
+
+    #[test]
+    fn typestate_encoder() {
+        let src = b"abcabcabcabcabcabc";
+        let mut dst: [u8; 100] = [0; 100];
+        let expected = encoder::encode(src, &mut dst).unwrap().to_vec();
+
+        let mut out = [0u8; 100];
+        let mut out_size = 0;
+        let mut enc = encoder::Encoder::new();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, 18)));
+        let mut enc = enc.finish();
+        let finished = loop {
+            let (_, size) = enc.poll(&mut out[out_size..out_size + 1]);
+            out_size += size;
+            enc = match enc.finish() {
+                Ok(finished) => break finished,
+                Err(enc) => enc,
+            };
+        };
+        assert_eq!(expected, &out[..out_size]);
+
+        // the encoder can be reused once reset
+        let mut enc = finished.reset();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, 18)));
+    }
+
+    #[test]
+    fn finish_into() {
+        let src = b"abcabcabcabcabcabc";
+        let mut dst: [u8; 100] = [0; 100];
+        let expected = encoder::encode(src, &mut dst).unwrap().to_vec();
+
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, 18)));
+        assert_eq!(enc.finish_into(&mut dst), Ok(expected.len()));
+        assert_eq!(expected, &dst[..expected.len()]);
+
+        // a too small buffer is filled and the rest is provided by next call
+        enc.reset();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, 18)));
+        assert_eq!(
+            enc.finish_into(&mut dst[..4]),
+            Err(HSError::OutputFull { needed_hint: None })
+        );
+        assert_eq!(enc.finish_into(&mut dst[4..]), Ok(expected.len() - 4));
+        assert_eq!(expected, &dst[..expected.len()]);
+
+        // an empty stream is finished without any room for output
+        enc.reset();
+        assert_eq!(enc.finish_into(&mut []), Ok(0));
+    }
+
+    #[test]
+    fn encoder_builder() {
+        let src = b"temperature=21.5;temperature=21.7;temperature=21.6;";
+        let mut expected: [u8; 100] = [0; 100];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+        let mut dst: [u8; 100] = [0; 100];
+
+        // unset options keep the default values
+        let mut enc = encoder::EncoderBuilder::<256>::new().build().unwrap();
+        assert_eq!(enc.params(), Params::default());
+        assert_eq!(encoder::encode_with(&mut enc, src, &mut dst), Ok(expected));
+
+        let mut enc = encoder::EncoderBuilder::<1024>::new()
+            .params(Params::new(10, 5).unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(enc.params(), Params::new(10, 5).unwrap());
+        let output_size = encoder::encode_with(&mut enc, src, &mut dst).unwrap().len();
+        let mut dec = decoder::HeatshrinkDecoder::<1024>::with_params(enc.params()).unwrap();
+        let mut out: [u8; 100] = [0; 100];
+        let out = decoder::decode_with(&mut dec, &dst[..output_size], &mut out).unwrap();
+        assert_eq!(src, out);
+
+        assert_eq!(
+            encoder::EncoderBuilder::<256>::new()
+                .window_bits(9)
+                .build()
+                .err(),
+            Some(ParamError::WindowTooLarge)
+        );
+        assert_eq!(
+            encoder::EncoderBuilder::<256>::new()
+                .lookahead_bits(2)
+                .build()
+                .err(),
+            Some(ParamError::LookaheadBitsTooSmall)
+        );
+
+        // without search only literals are emitted
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .max_search_depth(0)
+            .build()
+            .unwrap();
+        let literals = encoder::encode_with(&mut enc, src, &mut dst).unwrap();
+        assert_eq!(literals.len(), src.len() + src.len().div_ceil(8));
+
+        // a shallow search still produces a valid stream
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .max_search_depth(1)
+            .build()
+            .unwrap();
+        let output_size = encoder::encode_with(&mut enc, src, &mut dst).unwrap().len();
+        assert!(output_size >= expected.len());
+        let mut out: [u8; 100] = [0; 100];
+        assert_eq!(decoder::decode(&dst[..output_size], &mut out).unwrap(), src);
+
+        // the linear search produces the same stream as the index
+        #[cfg(feature = "heatshrink-use-index")]
+        {
+            let mut enc = encoder::EncoderBuilder::<256>::new()
+                .use_index(false)
+                .build()
+                .unwrap();
+            assert_eq!(encoder::encode_with(&mut enc, src, &mut dst), Ok(expected));
+        }
+
+        // a dictionary holding the repeated pattern improves the compression
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .dictionary(b"temperature=")
+            .build()
+            .unwrap();
+        assert!(encoder::encode_with(&mut enc, src, &mut dst).unwrap().len() < expected.len());
+    }
+
+    #[test]
+    fn totals() {
+        let src = b"abcabcabcabcabcabc";
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        assert_eq!(enc.total_in(), src.len() as u64);
+        assert_eq!(enc.total_out(), compressed.len() as u64);
+        enc.reset();
+        assert_eq!((enc.total_in(), enc.total_out()), (0, 0));
+
+        let mut out: [u8; 100] = [0; 100];
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        decoder::decode_with(&mut dec, compressed, &mut out).unwrap();
+        assert_eq!(dec.total_in(), compressed.len() as u64);
+        assert_eq!(dec.total_out(), src.len() as u64);
+        dec.reset();
+        assert_eq!((dec.total_in(), dec.total_out()), (0, 0));
+    }
+
+    #[test]
+    fn sink_all_poll_all() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 2048] = [0; 2048];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let mut compressed_size = 0;
+        let mut output = |data: &[u8]| {
+            compressed[compressed_size..compressed_size + data.len()].copy_from_slice(data);
+            compressed_size += data.len();
+        };
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        for chunk in src.chunks(100) {
+            enc.sink_all(chunk, &mut output).unwrap();
+        }
+        enc.finish();
+        enc.poll_all(&mut output).unwrap();
+        assert!(matches!(enc.finish(), HSfinishRes::FinishDone));
+        assert_eq!(expected, &compressed[..compressed_size]);
+
+        let mut out: [u8; 1000] = [0; 1000];
+        let mut out_size = 0;
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.sink_all(&compressed[..compressed_size], &mut |data: &[u8]| {
+            out[out_size..out_size + data.len()].copy_from_slice(data);
+            out_size += data.len();
+        })
+        .unwrap();
+        assert!(matches!(dec.finish(), HSfinishRes::FinishDone));
+        assert_eq!(src, out);
+
+        // sinking after finish is a misuse
+        assert_eq!(enc.sink_all(&src, &mut |_| {}), Err(HSError::Internal));
+
+        // an empty stream produces no output
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        enc.finish();
+        enc.poll_all(&mut |_| panic!("unexpected output")).unwrap();
+    }
+
+    /// ByteSink accepting at most 5 bytes per poll, to exercise partial polls
+    struct ChunkedSink {
+        data: [u8; 100],
+        len: usize,
+        chunk_end: usize,
+    }
+
+    impl ByteSink for ChunkedSink {
+        fn push_byte(&mut self, byte: u8) {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+
+        fn remaining_capacity(&self) -> usize {
+            self.chunk_end - self.len
+        }
+    }
+
+    #[test]
+    fn byte_sink() {
+        let src = b"abcabcabcabcabcabc";
+        let mut expected: [u8; 100] = [0; 100];
+        let expected = encoder::encode(src, &mut expected).unwrap();
+
+        let mut sink = ChunkedSink {
+            data: [0; 100],
+            len: 0,
+            chunk_end: 0,
+        };
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, 18)));
+        enc.finish();
+        while let HSfinishRes::FinishMore = enc.finish() {
+            sink.chunk_end = sink.len + 5;
+            enc.poll_sink(&mut sink);
+        }
+        assert_eq!(expected, &sink.data[..sink.len]);
+        assert_eq!(enc.total_out(), expected.len() as u64);
+
+        let mut sink = ChunkedSink {
+            data: [0; 100],
+            len: 0,
+            chunk_end: 0,
+        };
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert!(matches!(dec.sink(expected), (HSsinkRes::SinkOK, _)));
+        loop {
+            sink.chunk_end = sink.len + 5;
+            if let (HSpollRes::PollEmpty, _) = dec.poll_sink(&mut sink) {
+                break;
+            }
+        }
+        assert_eq!(src, &sink.data[..sink.len]);
+
+        // a full sink can not be polled into
+        assert_eq!(
+            dec.poll_sink(&mut &mut [0u8; 0][..]).0,
+            HSpollRes::PollErrorMisuse
+        );
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 2048] = [0; 2048];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let mut compressed_size = 0;
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        for &byte in &src {
+            while !matches!(enc.sink_byte(byte), HSsinkRes::SinkOK) {
+                // the encoder input buffer is full: drain its output first
+                while let Some(byte) = enc.poll_byte() {
+                    compressed[compressed_size] = byte;
+                    compressed_size += 1;
+                }
+            }
+        }
+        while let HSfinishRes::FinishMore = enc.finish() {
+            while let Some(byte) = enc.poll_byte() {
+                compressed[compressed_size] = byte;
+                compressed_size += 1;
+            }
+        }
+        assert_eq!(expected, &compressed[..compressed_size]);
+
+        let mut out: [u8; 1000] = [0; 1000];
+        let mut out_size = 0;
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        for &byte in expected {
+            assert!(matches!(dec.sink_byte(byte), HSsinkRes::SinkOK));
+            while let Some(byte) = dec.poll_byte() {
+                out[out_size] = byte;
+                out_size += 1;
+            }
+        }
+        assert!(matches!(dec.finish(), HSfinishRes::FinishDone));
+        assert_eq!(src, &out[..out_size]);
+    }
+
+    #[test]
+    fn clone_checkpoint() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 2048] = [0; 2048];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        // checkpoint the encoder in the middle of the stream
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let mut dst: [u8; 2048] = [0; 2048];
+        let (_, input_size, output_size) =
+            encoder::encode_partial(&mut enc, &src, &mut dst[..300]).unwrap();
+        let mut checkpoint = enc.clone();
+
+        // go on with the original then resume from the checkpoint
+        let (_, _, end_size) =
+            encoder::encode_partial(&mut enc, &src[input_size..], &mut dst[output_size..]).unwrap();
+        assert_eq!(expected, &dst[..output_size + end_size]);
+        dst[output_size..].fill(0);
+        let (_, _, end_size) =
+            encoder::encode_partial(&mut checkpoint, &src[input_size..], &mut dst[output_size..])
+                .unwrap();
+        assert_eq!(expected, &dst[..output_size + end_size]);
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert!(matches!(dec.sink(&expected[..20]), (HSsinkRes::SinkOK, 20)));
+        let mut checkpoint = dec.clone();
+        let mut out: [u8; 1000] = [0; 1000];
+        let mut other: [u8; 1000] = [0; 1000];
+        assert_eq!(dec.poll(&mut out), checkpoint.poll(&mut other));
+        assert_eq!(out, other);
+    }
+
+    /// fmt::Write implementation collecting the output in a fixed buffer
+    struct FmtBuffer {
+        data: [u8; 32768],
+        len: usize,
+    }
+
+    impl core::fmt::Write for FmtBuffer {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            self.data[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+            Ok(())
+        }
+    }
+
+    impl FmtBuffer {
+        fn format(args: core::fmt::Arguments) -> FmtBuffer {
+            let mut buffer = FmtBuffer {
+                data: [0; 32768],
+                len: 0,
+            };
+            core::fmt::write(&mut buffer, args).unwrap();
+            buffer
+        }
+
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.data[..self.len]).unwrap()
+        }
+    }
+
+    #[test]
+    fn debug_summary() {
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert!(matches!(enc.sink(&[0xab; 40]), (HSsinkRes::SinkOK, 40)));
+        let summary = FmtBuffer::format(format_args!("{:?}", enc));
+        assert!(summary.len < 400);
+        assert!(summary
+            .as_str()
+            .contains("input: abababababababababababababababab.. (40 bytes)"));
+        let dump = FmtBuffer::format(format_args!("{:?}", enc.dump()));
+        assert!(dump.len > 512);
+        assert!(dump.as_str().contains("input_buffer: ["));
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert!(matches!(dec.sink(&[0x12, 0x34]), (HSsinkRes::SinkOK, 2)));
+        let summary = FmtBuffer::format(format_args!("{:?}", dec));
+        assert!(summary.len < 400);
+        assert!(summary.as_str().contains("input: 1234 (2 bytes)"));
+        let dump = FmtBuffer::format(format_args!("{:?}", dec.dump()));
+        assert!(dump.len > 256);
+        assert!(dump.as_str().contains("output_buffer: ["));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn boxed() {
+        let src = b"abcabcabcabcabcabc";
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut out: [u8; 100] = [0; 100];
+
+        let mut enc = encoder::HeatshrinkEncoder::boxed();
+        let mut dec = decoder::HeatshrinkDecoder::boxed();
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        assert_eq!(
+            src,
+            decoder::decode_with(&mut dec, compressed, &mut out).unwrap()
+        );
+
+        // the window size is selected at runtime
+        let params = Params::new(11, 4).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::<32768>::boxed_with_params(params).unwrap();
+        let compressed = hex_literal::hex!("b0d8ac6005c0");
+        assert_eq!(
+            src,
+            decoder::decode_with(&mut dec, &compressed, &mut out).unwrap()
+        );
+        assert_eq!(
+            encoder::HeatshrinkEncoder::<256>::boxed_with_params(params).err(),
+            Some(ParamError::WindowTooLarge)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn poll_into() {
+        use alloc::vec::Vec;
+
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 2048] = [0; 2048];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        let mut compressed = Vec::new();
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let mut input_size = 0;
+        while input_size < src.len() {
+            match enc.sink(&src[input_size..]) {
+                (HSsinkRes::SinkOK, segment_input_size) => input_size += segment_input_size,
+                _ => panic!("sink() failed"),
+            }
+            enc.poll_into(&mut compressed).unwrap();
+        }
+        enc.finish();
+        enc.poll_into(&mut compressed).unwrap();
+        assert_eq!(expected, compressed);
+
+        let mut out = Vec::new();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        for chunk in compressed.chunks(10) {
+            assert!(matches!(dec.sink(chunk), (HSsinkRes::SinkOK, _)));
+            dec.poll_into(&mut out).unwrap();
+        }
+        assert_eq!(&src[..], out);
+    }
+
+    #[test]
+    fn dictionary() {
+        let dictionary = b"{\"temperature\":,\"humidity\":,\"pressure\":}";
+        let src = b"{\"temperature\":21.5,\"humidity\":48,\"pressure\":1013}";
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut out: [u8; 100] = [0; 100];
+
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .dictionary(dictionary)
+            .build()
+            .unwrap();
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        let mut unprimed: [u8; 100] = [0; 100];
+        assert!(compressed.len() < encoder::encode(src, &mut unprimed).unwrap().len());
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert!(matches!(dec.prime(dictionary), (HSsinkRes::SinkOK, 40)));
+        assert_eq!(
+            src,
+            decoder::decode_with(&mut dec, compressed, &mut out).unwrap()
+        );
+
+        // priming twice or after sinking data is a misuse
+        assert!(matches!(
+            dec.prime(dictionary),
+            (HSsinkRes::SinkErrorMisuse, 0)
+        ));
+        dec.reset();
+        assert!(matches!(dec.sink(compressed), (HSsinkRes::SinkOK, _)));
+        assert!(matches!(
+            dec.prime(dictionary),
+            (HSsinkRes::SinkErrorMisuse, 0)
+        ));
+
+        // only the end of a dictionary larger than the window is used
+        let mut large = [0u8; 300];
+        large[300 - dictionary.len()..].copy_from_slice(dictionary);
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .dictionary(&large)
+            .build()
+            .unwrap();
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert!(matches!(dec.prime(&large), (HSsinkRes::SinkOK, 256)));
+        assert_eq!(
+            src,
+            decoder::decode_with(&mut dec, compressed, &mut out).unwrap()
+        );
+    }
+
+    #[test]
+    fn encoder_prime() {
+        let dictionary = b"{\"temperature\":,\"humidity\":,\"pressure\":}";
+        let src = b"{\"temperature\":21.5,\"humidity\":48,\"pressure\":1013}";
+        let mut expected: [u8; 100] = [0; 100];
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .dictionary(dictionary)
+            .build()
+            .unwrap();
+        let expected = encoder::encode_with(&mut enc, src, &mut expected).unwrap();
+
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert!(matches!(enc.prime(dictionary), (HSsinkRes::SinkOK, 40)));
+        assert!(matches!(
+            enc.prime(dictionary),
+            (HSsinkRes::SinkErrorMisuse, 0)
+        ));
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        assert_eq!(expected, compressed);
+
+        // priming after sinking data or finishing is a misuse
+        enc.reset();
+        assert!(matches!(enc.sink(src), (HSsinkRes::SinkOK, _)));
+        assert!(matches!(
+            enc.prime(dictionary),
+            (HSsinkRes::SinkErrorMisuse, 0)
+        ));
+        enc.reset();
+        enc.finish();
+        assert!(matches!(
+            enc.prime(dictionary),
+            (HSsinkRes::SinkErrorMisuse, 0)
+        ));
+
+        // the dictionary is indexed as well as searched linearly
+        #[cfg(feature = "heatshrink-use-index")]
+        {
+            let mut enc = encoder::EncoderBuilder::<256>::new()
+                .use_index(false)
+                .dictionary(dictionary)
+                .build()
+                .unwrap();
+            let mut linear: [u8; 100] = [0; 100];
+            assert_eq!(
+                encoder::encode_with(&mut enc, src, &mut linear),
+                Ok(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn output_limit() {
+        // a few bytes expanding to 256 zeros
+        let mut compressed: [u8; 100] = [0; 100];
+        let compressed = encoder::encode(&[0; 256], &mut compressed).unwrap();
+        let mut out: [u8; 300] = [0; 300];
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.set_output_limit(256);
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&[0; 256][..])
+        );
+
+        dec.reset();
+        dec.set_output_limit(100);
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Err(HSError::OutputLimit)
+        );
+        assert_eq!(dec.total_out(), 100);
+
+        // the data up to the limit is still produced
+        dec.reset();
+        assert!(matches!(dec.sink(compressed), (HSsinkRes::SinkOK, _)));
+        assert_eq!(dec.poll(&mut out[..60]), (HSpollRes::PollMore, 60));
+        assert_eq!(dec.poll(&mut out), (HSpollRes::PollErrorLimit, 40));
+    }
+
+    #[test]
+    fn decode_exact() {
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let mut dst: [u8; 100] = [0; 100];
+        let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+        assert_eq!(
+            decoder::decode_exact_with(&mut dec, &src, &mut dst, 18),
+            Ok(&b"abcabcabcabcabcabc"[..])
+        );
+
+        let src = hex_literal::hex!("90D4B2B549A4082BE00F000E4C46DF2817C605F005B4BE0825F00280");
+        let expected_len = decoder::decode(&src, &mut dst).unwrap().len();
+        assert_eq!(
+            decoder::decode_exact(&src, &mut dst, expected_len)
+                .unwrap()
+                .len(),
+            expected_len
+        );
+        assert_eq!(
+            decoder::decode_exact(&src, &mut dst, expected_len + 1),
+            Err(HSError::TooShort)
+        );
+        assert_eq!(
+            decoder::decode_exact(&src, &mut dst, expected_len - 1),
+            Err(HSError::TooLong)
+        );
+        assert_eq!(
+            decoder::decode_exact(&src, &mut dst[..10], expected_len),
+            Err(HSError::OutputFull {
+                needed_hint: Some(expected_len)
+            })
+        );
+        assert_eq!(decoder::decode_exact(&[], &mut dst, 0), Ok(&[][..]));
+    }
+
+    #[test]
+    fn strict_backref() {
+        let src = b"{\"temperature\":21.5,\"humidity\":48,\"humidity\":48}";
+        let mut compressed: [u8; 100] = [0; 100];
+        let mut out: [u8; 100] = [0; 100];
+        let compressed = encoder::encode(src, &mut compressed).unwrap();
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.set_strict(true);
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&src[..])
+        );
+
+        // the encoder references the zero-filled window for leading zeros
+        let mut compressed: [u8; 100] = [0; 100];
+        let compressed = encoder::encode(&[0; 40], &mut compressed).unwrap();
+        dec.reset();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Err(HSError::InvalidBackref)
+        );
+        dec.set_strict(false);
+        dec.reset();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&[0; 40][..])
+        );
+
+        // the data before the invalid back-reference is still produced:
+        // literal 'a' then a back-reference 6 bytes back
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.set_strict(true);
+        assert!(matches!(
+            dec.sink(&hex_literal::hex!("b08140")),
+            (HSsinkRes::SinkOK, 3)
+        ));
+        assert_eq!(dec.poll(&mut out), (HSpollRes::PollErrorBackref, 1));
+        assert_eq!(out[0], b'a');
+        assert_eq!(dec.poll(&mut out), (HSpollRes::PollErrorBackref, 0));
+
+        // a primed instance can reference the dictionary
+        let dictionary = b"{\"temperature\":,\"humidity\":,\"pressure\":}";
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .dictionary(dictionary)
+            .build()
+            .unwrap();
+        let mut compressed: [u8; 100] = [0; 100];
+        let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.set_strict(true);
+        assert!(matches!(dec.prime(dictionary), (HSsinkRes::SinkOK, 40)));
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&src[..])
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        // 'a', 'b', 'c' literals then a 15 bytes back-reference
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let mut dst: [u8; 100] = [0; 100];
+
+        let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &src, &mut dst),
+            Ok(&b"abcabcabcabcabcabc"[..])
+        );
+
+        // cut in the middle of a literal or of the back-reference count
+        for len in [1, 2, 3, 5] {
+            dec.reset();
+            assert_eq!(
+                decoder::decode_with(&mut dec, &src[..len], &mut dst),
+                Err(HSError::Truncated)
+            );
+        }
+
+        // the padding of the last byte is not mistaken for a truncation
+        dec.reset();
+        assert!(matches!(dec.sink(&src), (HSsinkRes::SinkOK, 6)));
+        assert_eq!(dec.poll(&mut dst[..10]), (HSpollRes::PollMore, 10));
+        assert!(matches!(dec.finish(), HSfinishRes::FinishMore));
+        assert_eq!(dec.poll(&mut dst), (HSpollRes::PollEmpty, 8));
+        assert!(matches!(dec.finish(), HSfinishRes::FinishDone));
+
+        // with a 6 bits window, the padding also covers the index
+        let params = Params::new(6, 3).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::<64>::with_params(params).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &hex_literal::hex!("8000"), &mut dst),
+            Ok(&[0][..])
+        );
+        dec.reset();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &hex_literal::hex!("8001"), &mut dst),
+            Err(HSError::Truncated)
+        );
+    }
+
+    #[test]
+    fn trailing_data() {
+        let mut dst: [u8; 18] = [0; 18];
+
+        let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+        assert_eq!(
+            dec.sink(&hex_literal::hex!("b0d8ac6005c0")),
+            (HSsinkRes::SinkOK, 6)
+        );
+        assert_eq!(dec.poll(&mut dst), (HSpollRes::PollMore, 18));
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(dec.check_end(), Ok(()));
+
+        // a byte after the end of the stream, or non-zero padding bits
+        for src in [
+            &hex_literal::hex!("b0d8ac6005c000")[..],
+            &hex_literal::hex!("b0d8ac6005c080"),
+            &hex_literal::hex!("b0d8ac6005c1"),
+        ] {
+            dec.reset();
+            assert_eq!(dec.sink(src), (HSsinkRes::SinkOK, src.len()));
+            assert_eq!(dec.poll(&mut dst), (HSpollRes::PollMore, 18));
+            assert_eq!(dec.check_end(), Err(HSError::TrailingData));
+        }
+    }
+
+    #[test]
+    fn poll_with_budget() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 2048] = [0; 2048];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let mut compressed_size = 0;
+        let mut input_size = 0;
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        loop {
+            if input_size < src.len() {
+                if let (HSsinkRes::SinkOK, size) = enc.sink(&src[input_size..]) {
+                    input_size += size;
+                }
+            } else if enc.finish() == HSfinishRes::FinishDone {
+                break;
+            }
+            // a few steps and bytes per call until the encoder runs dry
+            loop {
+                let output = &mut compressed[compressed_size..compressed_size + 4];
+                match enc.poll_with_budget(output, 3) {
+                    (HSpollRes::PollMore, size) => compressed_size += size,
+                    (HSpollRes::PollEmpty, size) => {
+                        compressed_size += size;
+                        break;
+                    }
+                    res => panic!("{:?}", res),
+                }
+            }
+        }
+        assert_eq!(expected, &compressed[..compressed_size]);
+
+        // a few steps per call until the decoder runs dry
+        fn drain(dec: &mut decoder::HeatshrinkDecoder, out: &mut [u8]) -> usize {
+            let mut out_size = 0;
+            loop {
+                match dec.poll_with_budget(&mut out[out_size..], 5) {
+                    (HSpollRes::PollMore, size) => out_size += size,
+                    (HSpollRes::PollEmpty, size) => return out_size + size,
+                    res => panic!("{:?}", res),
+                }
+            }
+        }
+
+        let mut out: [u8; 1024] = [0; 1024];
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert_eq!(dec.sink(&expected[..20]), (HSsinkRes::SinkOK, 20));
+        // no work is done without budget, and a literal takes two steps
+        assert_eq!(dec.poll_with_budget(&mut out, 0), (HSpollRes::PollMore, 0));
+        assert_eq!(dec.poll_with_budget(&mut out, 2), (HSpollRes::PollMore, 1));
+        let mut out_size = 1;
+        for chunk in expected[20..].chunks(20) {
+            out_size += drain(&mut dec, &mut out[out_size..]);
+            assert_eq!(dec.sink(chunk), (HSsinkRes::SinkOK, chunk.len()));
+        }
+        out_size += drain(&mut dec, &mut out[out_size..]);
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(src, &out[..out_size]);
+    }
+
+    #[test]
+    fn poll_byte_with_budget() {
+        let mut src = [0u8; 5000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8 ^ (i * i % 251 / 240) as u8;
+        }
+
+        // the indexing, shift and search of a large window are done by
+        // steps, whatever the options, checkpointing the stream now and then
+        for (use_index, lazy_matching, budget) in [
+            (true, false, 1),
+            (false, false, 2),
+            (true, true, 3),
+            (false, true, 1),
+        ] {
+            let mut enc = encoder::EncoderBuilder::<4096>::new()
+                .window_bits(12)
+                .lookahead_bits(6)
+                .max_search_depth(usize::MAX)
+                .lazy_matching(lazy_matching)
+                .build()
+                .unwrap();
+            #[cfg(feature = "heatshrink-use-index")]
+            enc.set_use_index(use_index);
+            let _ = use_index;
+            let mut expected: [u8; 16384] = [0; 16384];
+            let expected = encoder::encode_with(&mut enc.clone(), &src, &mut expected).unwrap();
+
+            let mut state = [0u8; encoder::HeatshrinkEncoder::<4096>::STATE_MAX_SIZE];
+            let mut compressed: [u8; 16384] = [0; 16384];
+            let mut compressed_size = 0;
+            let mut input_size = 0;
+            let mut calls = 0;
+            let mut checkpoint_size = 0;
+            loop {
+                if input_size < src.len() {
+                    if let (HSsinkRes::SinkOK, size) = enc.sink(&src[input_size..]) {
+                        input_size += size;
+                    }
+                } else if enc.finish() == HSfinishRes::FinishDone {
+                    break;
+                }
+                loop {
+                    let steps = enc.steps();
+                    let (res, byte) = enc.poll_byte_with_budget(budget);
+                    assert!(enc.steps() - steps <= budget);
+                    if let Some(byte) = byte {
+                        compressed[compressed_size] = byte;
+                        compressed_size += 1;
+                    }
+                    calls += 1;
+                    // the search in progress is not saved, so the stream
+                    // must move on between the checkpoints
+                    if calls % 97 == 0 && compressed_size > checkpoint_size {
+                        checkpoint_size = compressed_size;
+                        enc.save_state(&mut state).unwrap();
+                        enc = encoder::EncoderBuilder::<4096>::new().build().unwrap();
+                        enc.restore_state(&state).unwrap();
+                    }
+                    match res {
+                        HSpollRes::PollMore => {}
+                        HSpollRes::PollEmpty => break,
+                        res => panic!("{:?}", res),
+                    }
+                }
+            }
+            assert_eq!(expected, &compressed[..compressed_size]);
+            // the window was searched in more calls than there are bytes
+            assert!(calls > 2 * src.len());
+        }
+    }
+
+    #[test]
+    fn abort() {
+        let mut first = [0u8; 1000];
+        for (i, b) in first.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        // the second stream references the zero-filled window
+        let mut second = [0u8; 100];
+        second[50..].copy_from_slice(&first[..50]);
+        let mut expected: [u8; 200] = [0; 200];
+        let expected = encoder::encode(&second, &mut expected).unwrap();
+
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert_eq!(enc.sink(&first), (HSsinkRes::SinkOK, 256));
+        assert_eq!(enc.poll(&mut compressed[..10]), (HSpollRes::PollMore, 10));
+        enc.abort();
+        assert_eq!(enc.total_in(), 0);
+        assert_eq!(
+            encoder::encode_with(&mut enc, &second, &mut compressed),
+            Ok(expected)
+        );
+
+        let mut out: [u8; 1000] = [0; 1000];
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let compressed = encoder::encode(&first, &mut compressed).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert_eq!(dec.sink(compressed), (HSsinkRes::SinkOK, 32));
+        assert_eq!(dec.poll(&mut out), (HSpollRes::PollEmpty, 28));
+        dec.abort();
+        assert_eq!(dec.total_out(), 0);
+        assert_eq!(
+            decoder::decode_with(&mut dec, expected, &mut out),
+            Ok(&second[..])
+        );
+    }
+
+    #[test]
+    fn encode_bound() {
+        assert_eq!(encoder::encode_bound(0), 0);
+        assert_eq!(encoder::encode_bound(1), 2);
+        assert_eq!(encoder::encode_bound(8), 9);
+
+        // bytes without any repetition are all emitted as literals
+        let mut src = [0u8; 256];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut dst = [0u8; encoder::encode_bound(256)];
+        assert_eq!(encoder::encode(&src, &mut dst).unwrap().len(), dst.len());
+        assert_eq!(
+            encoder::encode(&src, &mut dst[..encoder::encode_bound(256) - 1]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(encoder::encode_bound(256))
+            })
+        );
+    }
+
+    #[test]
+    fn ram() {
+        const _: () = assert!(decoder::DECODER_RAM < 512);
+
+        assert_eq!(
+            encoder::ENCODER_RAM,
+            core::mem::size_of::<encoder::HeatshrinkEncoder>()
+        );
+        assert_eq!(
+            decoder::DECODER_RAM,
+            core::mem::size_of::<decoder::HeatshrinkDecoder>()
+        );
+        // the window takes most of the footprint
+        const _: () = assert!(decoder::HeatshrinkDecoder::<2048>::RAM >= 2048);
+        const _: () = assert!(decoder::HeatshrinkDecoder::<2048>::RAM < 2048 + 512);
+        const _: () = assert!(encoder::HeatshrinkEncoder::<2048>::RAM >= 2 * 2048);
+        // the search index takes 2 bytes per position of the window, and 2
+        // per chain for the heads of the index built by steps
+        #[cfg(feature = "heatshrink-use-index")]
+        const _: () = assert!(encoder::HeatshrinkEncoder::<2048>::RAM < 2 * 2048 * 3 + 1024);
+    }
+
+    #[test]
+    fn poll_from() {
+        let mut src = [0u8; 1000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut compressed: [u8; 2048] = [0; 2048];
+        let compressed = encoder::encode(&src, &mut compressed).unwrap();
+
+        // the input is read in place, whatever the size of the output
+        let mut out: [u8; 1024] = [0; 1024];
+        let mut input_size = 0;
+        let mut out_size = 0;
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        loop {
+            let out_end = (out_size + 7).min(out.len());
+            match dec.poll_from(&compressed[input_size..], &mut out[out_size..out_end]) {
+                (HSpollRes::PollMore, consumed, size) => {
+                    input_size += consumed;
+                    out_size += size;
+                }
+                (HSpollRes::PollEmpty, consumed, size) => {
+                    input_size += consumed;
+                    out_size += size;
+                    break;
+                }
+                res => panic!("{:?}", res),
+            }
+        }
+        assert_eq!(input_size, compressed.len());
+        assert_eq!(dec.total_in(), compressed.len() as u64);
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(src, &out[..out_size]);
+
+        // the data sunk must be polled first
+        dec.reset();
+        assert_eq!(dec.sink(&compressed[..10]), (HSsinkRes::SinkOK, 10));
+        assert_eq!(
+            dec.poll_from(&compressed[10..], &mut out),
+            (HSpollRes::PollErrorMisuse, 0, 0)
+        );
+        let (_, out_size) = dec.poll(&mut out);
+        let (res, consumed, size) = dec.poll_from(&compressed[10..], &mut out[out_size..]);
+        assert_eq!(
+            (res, consumed),
+            (HSpollRes::PollEmpty, compressed.len() - 10)
+        );
+        assert_eq!(src, &out[..out_size + size]);
+    }
+
+    #[test]
+    fn encode_slice() {
+        // no zero bytes, so that encode does not reference the zero-filled
+        // window either
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (1 + (i % 37) as u8) ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 4096] = [0; 4096];
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let mut out: [u8; 4096] = [0; 4096];
+
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let size = enc.encode_slice(&src, &mut compressed).unwrap();
+        assert_eq!(expected, &compressed[..size]);
+        assert_eq!(enc.total_in(), src.len() as u64);
+        assert_eq!(enc.total_out(), size as u64);
+        assert_eq!(enc.finish(), HSfinishRes::FinishDone);
+        assert_eq!(
+            enc.encode_slice(&src, &mut compressed),
+            Err(HSError::Internal)
+        );
+
+        let mut enc = encoder::EncoderBuilder::<2048>::new()
+            .window_bits(11)
+            .max_search_depth(4)
+            .build()
+            .unwrap();
+        let mut expected: [u8; 4096] = [0; 4096];
+        let expected = encoder::encode_with(&mut enc.clone(), &src, &mut expected).unwrap();
+        let size = enc.encode_slice(&src, &mut compressed).unwrap();
+        assert_eq!(expected, &compressed[..size]);
+
+        // back-references never point before the start of the stream
+        src[..100].fill(0);
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let size = enc.encode_slice(&src, &mut compressed).unwrap();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        dec.set_strict(true);
+        assert_eq!(
+            decoder::decode_with(&mut dec, &compressed[..size], &mut out),
+            Ok(&src[..])
+        );
+
+        // bytes without any repetition are all emitted as literals
+        for (i, b) in src[..256].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let bound = encoder::encode_bound(256);
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        assert_eq!(
+            enc.encode_slice(&src[..256], &mut compressed[..bound]),
+            Ok(bound)
+        );
+        enc.reset();
+        assert_eq!(
+            enc.encode_slice(&src[..256], &mut compressed[..bound - 1]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(bound)
+            })
+        );
+        assert_eq!(encoder::encode_bound(0), 0);
+        enc.reset();
+        assert_eq!(enc.encode_slice(&[], &mut []), Ok(0));
+    }
+
+    #[test]
+    fn decoder_sink_misuse() {
+        let src = b"abcabcabcabc";
+        let mut compressed: [u8; 64] = [0; 64];
+        let compressed = encoder::encode(src, &mut compressed).unwrap();
+        let mut out: [u8; 64] = [0; 64];
+
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&src[..])
+        );
+        // the stream is complete, more data is not appended to it
+        assert_eq!(dec.sink(compressed), (HSsinkRes::SinkErrorMisuse, 0));
+        assert_eq!(dec.sink_byte(0), HSsinkRes::SinkErrorMisuse);
+        assert_eq!(
+            dec.poll_from(compressed, &mut out),
+            (HSpollRes::PollErrorMisuse, 0, 0)
+        );
+        assert_eq!(dec.check_end(), Ok(()));
+
+        // a stream still expecting data can be sunk after finish
+        dec.reset();
+        assert_eq!(dec.sink(&compressed[..2]), (HSsinkRes::SinkOK, 2));
+        assert_eq!(dec.poll(&mut out).0, HSpollRes::PollEmpty);
+        assert_eq!(dec.finish(), HSfinishRes::FinishMore);
+        assert_eq!(
+            dec.sink(&compressed[2..]),
+            (HSsinkRes::SinkOK, compressed.len() - 2)
+        );
+        assert_eq!(dec.poll(&mut out).0, HSpollRes::PollEmpty);
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+
+        dec.abort();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut out),
+            Ok(&src[..])
+        );
+    }
+
+    #[test]
+    fn encoder_flush() {
+        fn round_trip<const W: usize>(
+            enc: &mut encoder::HeatshrinkEncoder<W>,
+            dec: &mut decoder::HeatshrinkDecoder<W>,
+            src: &[u8],
+        ) {
+            let mut out: [u8; 1024] = [0; 1024];
+            let mut total_size = 0;
+
+            for chunk in src.chunks(100) {
+                // every packet is uncompressed as soon as it is received
+                let mut packet: [u8; 256] = [0; 256];
+                let mut size = 0;
+                let mut input = chunk;
+                while !input.is_empty() {
+                    let (res, input_size) = enc.sink(input);
+                    assert_eq!(res, HSsinkRes::SinkOK);
+                    input = &input[input_size..];
+                    size += enc.poll(&mut packet[size..]).1;
+                }
+                size += enc.flush_into(&mut packet[size..]).unwrap();
+
+                let mut packet = &packet[..size];
+                while !packet.is_empty() {
+                    let (res, input_size, output_size) = dec.poll_from(packet, &mut out);
+                    assert_eq!(res, HSpollRes::PollEmpty);
+                    assert_eq!(&out[..output_size], &src[total_size..][..output_size]);
+                    packet = &packet[input_size..];
+                    total_size += output_size;
+                }
+                assert_eq!(total_size as u64, enc.total_in());
+                assert_eq!(dec.sync(), Ok(()));
+            }
+
+            // the compression context is kept across packets
+            assert!(enc.total_out() < src.len() as u64 / 2);
+            assert_eq!(enc.flush_into(&mut []), Ok(0));
+            assert_eq!(enc.finish_into(&mut out), Ok(0));
+            assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        }
+
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+
+        round_trip(
+            &mut encoder::HeatshrinkEncoder::new(),
+            &mut decoder::HeatshrinkDecoder::new(),
+            &src,
+        );
+        round_trip(
+            &mut encoder::HeatshrinkEncoder::<2048>::default(),
+            &mut decoder::HeatshrinkDecoder::<2048>::default(),
+            &src,
+        );
+
+        // the padding of a packet is not taken for a back-reference
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        let mut compressed: [u8; 16] = [0; 16];
+        let mut out: [u8; 16] = [0; 16];
+        assert_eq!(enc.sink(b"a"), (HSsinkRes::SinkOK, 1));
+        assert_eq!(enc.flush_into(&mut compressed), Ok(2));
+        assert_eq!(
+            dec.poll_from(&compressed[..2], &mut out),
+            (HSpollRes::PollEmpty, 2, 1)
+        );
+        assert_eq!(dec.sync(), Ok(()));
+        assert_eq!(enc.sink(b"b"), (HSsinkRes::SinkOK, 1));
+        assert_eq!(enc.flush_into(&mut compressed), Ok(2));
+        assert_eq!(
+            dec.poll_from(&compressed[..1], &mut out),
+            (HSpollRes::PollEmpty, 1, 0)
+        );
+        assert_eq!(dec.sync(), Err(HSError::TrailingData));
+        assert_eq!(
+            dec.poll_from(&compressed[1..2], &mut out),
+            (HSpollRes::PollEmpty, 1, 1)
+        );
+        assert_eq!(&out[..1], b"b");
+        assert_eq!(dec.sync(), Ok(()));
+    }
+
+    #[test]
+    fn encoder_state() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 4096] = [0; 4096];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        // checkpoint the stream after every chunk, resuming in a new instance
+        let mut state = [0u8; encoder::HeatshrinkEncoder::<256>::STATE_MAX_SIZE];
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        let mut size = 0;
+        for chunk in src.chunks(100) {
+            assert_eq!(
+                enc.sink_all(chunk, &mut |data: &[u8]| {
+                    compressed[size..][..data.len()].copy_from_slice(data);
+                    size += data.len();
+                }),
+                Ok(())
+            );
+            assert_eq!(enc.save_state(&mut state), Ok(enc.state_size()));
+            enc = encoder::HeatshrinkEncoder::new();
+            assert_eq!(enc.restore_state(&state), Ok(()));
+        }
+        assert_eq!(enc.total_in(), src.len() as u64);
+        size += enc.finish_into(&mut compressed[size..]).unwrap();
+        assert_eq!(expected, &compressed[..size]);
+
+        // in the middle of polling, with a larger window
+        let mut enc = encoder::HeatshrinkEncoder::<2048>::default();
+        let mut expected: [u8; 4096] = [0; 4096];
+        let expected = encoder::encode_with(&mut enc.clone(), &src, &mut expected).unwrap();
+        let mut state = [0u8; encoder::HeatshrinkEncoder::<2048>::STATE_MAX_SIZE];
+        assert_eq!(enc.sink(&src), (HSsinkRes::SinkOK, 2048));
+        assert_eq!(enc.poll(&mut compressed[..10]), (HSpollRes::PollMore, 10));
+        enc.save_state(&mut state).unwrap();
+        let mut resumed = encoder::HeatshrinkEncoder::<2048>::default();
+        resumed.restore_state(&state).unwrap();
+        assert_eq!(
+            encoder::encode_with(&mut resumed, &src[2048..], &mut compressed[10..])
+                .map(|out| out.len()),
+            Ok(expected.len() - 10)
+        );
+        assert_eq!(expected, &compressed[..expected.len()]);
+
+        // the window must fit in the capacity of the instance
+        assert_eq!(
+            encoder::HeatshrinkEncoder::new().restore_state(&state),
+            Err(HSError::InvalidState)
+        );
+        assert_eq!(
+            enc.save_state(&mut state[..100]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(enc.state_size())
+            })
+        );
+        assert_eq!(
+            resumed.restore_state(&state[..100]),
+            Err(HSError::InvalidState)
+        );
+        state[1] = 2;
+        assert_eq!(resumed.restore_state(&state), Err(HSError::InvalidState));
+    }
+    #[test]
+    fn decoder_state() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let compressed = encoder::encode(&src, &mut compressed).unwrap();
+
+        // checkpoint the stream after every chunk, resuming in a new instance
+        let mut state = [0u8; decoder::HeatshrinkDecoder::<256>::STATE_MAX_SIZE];
+        let mut output: [u8; 3000] = [0; 3000];
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        let mut size = 0;
+        for chunk in compressed.chunks(50) {
+            assert_eq!(
+                dec.sink_all(chunk, &mut |data: &[u8]| {
+                    output[size..][..data.len()].copy_from_slice(data);
+                    size += data.len();
+                }),
+                Ok(())
+            );
+            assert_eq!(dec.save_state(&mut state), Ok(dec.state_size()));
+            dec = decoder::HeatshrinkDecoder::new();
+            assert_eq!(dec.restore_state(&state), Ok(()));
+        }
+        assert_eq!(dec.state_size(), 58 + 256);
+        assert_eq!(dec.total_in(), compressed.len() as u64);
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(src, output);
+
+        // in the middle of polling, with input pending
+        let mut dec = decoder::HeatshrinkDecoder::<256>::new();
+        let mut output: [u8; 4096] = [0; 4096];
+        assert_eq!(dec.sink(compressed), (HSsinkRes::SinkOK, 32));
+        assert_eq!(dec.poll(&mut output[..10]), (HSpollRes::PollMore, 10));
+        assert!(dec.state_size() > decoder::HeatshrinkDecoder::<256>::STATE_MAX_SIZE - 32);
+        dec.save_state(&mut state).unwrap();
+        let mut resumed = decoder::HeatshrinkDecoder::<256>::new();
+        resumed.restore_state(&state).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut resumed, &compressed[32..], &mut output[10..])
+                .map(|out| out.len()),
+            Ok(src.len() - 10)
+        );
+        assert_eq!(src, output[..src.len()]);
+
+        // the window must fit in the capacity of the instance
+        let large = decoder::HeatshrinkDecoder::<2048>::default();
+        let mut large_state = [0u8; decoder::HeatshrinkDecoder::<2048>::STATE_MAX_SIZE];
+        let size = large.save_state(&mut large_state).unwrap();
+        assert_eq!(
+            resumed.restore_state(&large_state[..size]),
+            Err(HSError::InvalidState)
+        );
+        assert_eq!(
+            dec.save_state(&mut state[..100]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(dec.state_size())
+            })
+        );
+        assert_eq!(
+            resumed.restore_state(&state[..100]),
+            Err(HSError::InvalidState)
+        );
+        state[0] = b'E';
+        assert_eq!(resumed.restore_state(&state), Err(HSError::InvalidState));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_state() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut expected: [u8; 4096] = [0; 4096];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        // migrate the encoder in the middle of polling
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        let mut compressed: [u8; 4096] = [0; 4096];
+        assert_eq!(enc.sink(&src), (HSsinkRes::SinkOK, 256));
+        assert_eq!(enc.poll(&mut compressed[..10]), (HSpollRes::PollMore, 10));
+        let json = serde_json::to_string(&enc).unwrap();
+        let mut enc: encoder::HeatshrinkEncoder<256> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            encoder::encode_with(&mut enc, &src[256..], &mut compressed[10..]).map(|out| out.len()),
+            Ok(expected.len() - 10)
+        );
+        assert_eq!(expected, &compressed[..expected.len()]);
+
+        // the window is serialized like saved, even while it is shifted
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        let mut state = [0u8; encoder::HeatshrinkEncoder::<256>::STATE_MAX_SIZE];
+        let header_size = enc.state_size() - 2 * 256;
+        assert_eq!(enc.sink(&src), (HSsinkRes::SinkOK, 256));
+        let mut size = 0;
+        loop {
+            let (res, output_size) = enc.poll_with_budget(&mut compressed[size..], 1);
+            size += output_size;
+            let state_size = enc.save_state(&mut state).unwrap();
+            assert_eq!(
+                serde_json::to_string(&enc).unwrap(),
+                serde_json::to_string(&(&state[..header_size], &state[header_size..state_size]))
+                    .unwrap()
+            );
+            if res == HSpollRes::PollEmpty {
+                break;
+            }
+        }
+
+        // migrate the decoder with input pending, into a larger instance
+        let mut dec = decoder::HeatshrinkDecoder::<256>::new();
+        let mut output: [u8; 4096] = [0; 4096];
+        assert_eq!(dec.sink(expected), (HSsinkRes::SinkOK, 32));
+        assert_eq!(dec.poll(&mut output[..10]), (HSpollRes::PollMore, 10));
+        let json = serde_json::to_string(&dec).unwrap();
+        let mut dec: decoder::HeatshrinkDecoder<2048> = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &expected[32..], &mut output[10..]).map(|out| out.len()),
+            Ok(src.len() - 10)
+        );
+        assert_eq!(src, output[..src.len()]);
+
+        // the state is checked like by restore_state
+        assert!(serde_json::from_str::<decoder::HeatshrinkDecoder<128>>(&json).is_err());
+        assert!(serde_json::from_str::<encoder::HeatshrinkEncoder<256>>(&json).is_err());
+        assert!(serde_json::from_str::<decoder::HeatshrinkDecoder<256>>("[[68],[],[]]").is_err());
+    }
+
+    #[cfg(all(feature = "serde", feature = "alloc"))]
+    #[test]
+    fn compressed_bytes() {
+        use super::compressed::{Compressed, CompressedBytes};
+
+        let src = include_bytes!("decoder.rs")[..3000].to_vec();
+        let value = (42u32, CompressedBytes::from(src.clone()));
+        let json = serde_json::to_string(&value).unwrap();
+        let expected = serde_json::to_string(&src).unwrap();
+        assert!(json.len() < expected.len());
+
+        let (id, data): (u32, Compressed<alloc::boxed::Box<[u8]>>) =
+            serde_json::from_str(&json).unwrap();
+        assert_eq!(id, 42);
+        assert_eq!(&data[..], &src[..]);
+
+        // a truncated stream
+        let json = serde_json::to_string(&[0xb0u8]).unwrap();
+        assert!(serde_json::from_str::<CompressedBytes>(&json).is_err());
+    }
+
+    #[test]
+    fn self_test() {
+        assert_eq!(super::self_test(), Ok(()));
+        compare(super::SELF_TEST_PLAIN);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn encoder_stats() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        let size = encoder::encode_with(&mut enc, &src, &mut compressed)
+            .unwrap()
+            .len();
+
+        // every bit of the stream is accounted for
+        let stats = *enc.stats();
+        assert!(stats.literals > 0 && stats.backrefs > 0);
+        assert_eq!(stats.match_lengths.iter().sum::<u64>(), stats.backrefs);
+        assert_eq!(stats.literal_bits, 9 * stats.literals);
+        assert_eq!(stats.backref_bits, (1 + 8 + 4) * stats.backrefs);
+        assert_eq!(
+            stats.literal_bits + stats.backref_bits + stats.padding_bits,
+            8 * size as u64
+        );
+        assert_eq!(
+            stats.literals
+                + stats
+                    .match_lengths
+                    .iter()
+                    .enumerate()
+                    .map(|(n, count)| (n as u64 + 1) * count)
+                    .sum::<u64>(),
+            src.len() as u64
+        );
+
+        enc.reset();
+        assert_eq!(*enc.stats(), encoder::EncoderStats::default());
+    }
+
+    #[test]
+    fn encoder_is_expanding() {
+        // noise from a linear congruential generator does not compress
+        let mut noise = [0u8; 2000];
+        let mut seed: u32 = 1;
+        for b in noise.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (seed >> 16) as u8;
+        }
+        let mut compressible = [0u8; 2000];
+        for (i, b) in compressible.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+
+        // noise is detected early enough to be sent raw instead
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        assert!(!enc.is_expanding(0));
+        enc.sink_all(&noise[..1000], &mut |_: &[u8]| {}).unwrap();
+        assert!(enc.is_expanding(16));
+
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        for chunk in compressible.chunks(100) {
+            enc.sink_all(chunk, &mut |_: &[u8]| {}).unwrap();
+            assert!(!enc.is_expanding(16));
+        }
+    }
+
+    #[test]
+    fn util_estimate_ratio() {
+        let mut noise = [0u8; 2000];
+        let mut seed: u32 = 1;
+        for b in noise.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (seed >> 16) as u8;
+        }
+        let mut compressible = [0u8; 2000];
+        for (i, b) in compressible.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+
+        // the estimate is close to the ratio of the encoder
+        let mut compressed: [u8; 4096] = [0; 4096];
+        for src in [&noise[..], &compressible, b"abcabcabcabcabcd"] {
+            let size = encoder::encode(src, &mut compressed).unwrap().len();
+            let ratio = size as f32 / src.len() as f32;
+            let estimate = super::util::estimate_ratio(src);
+            assert!((ratio - estimate).abs() < 0.1, "{} vs {}", ratio, estimate);
+        }
+        assert!(super::util::estimate_ratio(&noise) > 1.0);
+        assert!(super::util::estimate_ratio(&compressible) < 0.5);
+        assert_eq!(super::util::estimate_ratio(&[]), 1.0);
+    }
+
+    #[test]
+    fn sink_capacity() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let mut output: [u8; 4096] = [0; 4096];
+
+        // sinking exactly the capacity never reports SinkFull
+        let mut enc = encoder::HeatshrinkEncoder::<256>::new();
+        let mut input_size = 0;
+        let mut size = 0;
+        while input_size < src.len() {
+            let capacity = enc.sink_capacity();
+            let chunk = &src[input_size..(input_size + capacity).min(src.len())];
+            assert_eq!(enc.sink(chunk), (HSsinkRes::SinkOK, chunk.len()));
+            input_size += chunk.len();
+            assert!(enc.sink_capacity() == capacity - chunk.len() || enc.sink_capacity() == 0);
+            size += enc.poll(&mut compressed[size..]).1;
+        }
+        assert_eq!(enc.finish(), HSfinishRes::FinishMore);
+        assert_eq!(enc.sink_capacity(), 0);
+        size += enc.finish_into(&mut compressed[size..]).unwrap();
+
+        let mut dec = decoder::HeatshrinkDecoder::<256>::new();
+        let mut input_size = 0;
+        let mut output_size = 0;
+        while input_size < size {
+            let capacity = dec.sink_capacity();
+            assert!(capacity > 0);
+            let chunk = &compressed[input_size..(input_size + capacity).min(size)];
+            assert_eq!(dec.sink(chunk), (HSsinkRes::SinkOK, chunk.len()));
+            input_size += chunk.len();
+            assert_eq!(dec.sink_capacity(), capacity - chunk.len());
+            loop {
+                let (res, polled) = dec.poll(&mut output[output_size..]);
+                output_size += polled;
+                if res == HSpollRes::PollEmpty {
+                    break;
+                }
+            }
+        }
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        assert_eq!(dec.sink_capacity(), 0);
+        assert_eq!(src, output[..output_size]);
+    }
+
+    #[test]
+    fn decoder_input_size() {
+        let mut src = [0u8; 3000];
+        for (i, b) in src.iter_mut().enumerate() {
+            *b = (i % 37) as u8 ^ (i / 200) as u8;
+        }
+        let mut compressed: [u8; 4096] = [0; 4096];
+        let compressed = encoder::encode(&src, &mut compressed).unwrap();
+        let mut output: [u8; 4096] = [0; 4096];
+
+        // a large input buffer takes the compressed stream in larger chunks
+        let mut dec = decoder::HeatshrinkDecoder::<256, 512>::default();
+        assert_eq!(dec.sink_capacity(), 512);
+        assert_eq!(dec.sink(compressed), (HSsinkRes::SinkOK, 512));
+        dec.abort();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut output).map(|out| out.len()),
+            Ok(src.len())
+        );
+        assert_eq!(src, output[..src.len()]);
+
+        let mut dec = decoder::HeatshrinkDecoder::<256, 1>::default();
+        assert_eq!(
+            decoder::decode_with(&mut dec, compressed, &mut output).map(|out| out.len()),
+            Ok(src.len())
+        );
+        assert_eq!(src, output[..src.len()]);
+
+        // the pending input of a saved state must fit in the input buffer
+        let mut dec = decoder::HeatshrinkDecoder::<256, 512>::default();
+        let mut state = [0u8; decoder::HeatshrinkDecoder::<256, 512>::STATE_MAX_SIZE];
+        assert_eq!(dec.sink(compressed), (HSsinkRes::SinkOK, 512));
+        dec.save_state(&mut state).unwrap();
+        assert_eq!(
+            decoder::HeatshrinkDecoder::<256>::new().restore_state(&state),
+            Err(HSError::InvalidState)
+        );
+        let mut resumed = decoder::HeatshrinkDecoder::<256, 1024>::default();
+        assert_eq!(resumed.restore_state(&state), Ok(()));
+        assert_eq!(resumed.sink_capacity(), 1024 - 512);
+    }
+
+    #[cfg(all(feature = "alloc", feature = "heatshrink-use-index"))]
+    #[test]
+    fn index_max_window() {
+        use alloc::vec;
+
+        // the whole 2^16 bytes buffer of the largest window is indexed
+        let src: alloc::vec::Vec<u8> = (0..70000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 4000) as u8)
+            .collect();
+        let params = Params::new(15, 4).unwrap();
+        let mut compressed = vec![0u8; 80000];
+        let mut enc = encoder::HeatshrinkEncoder::<32768>::boxed_with_params(params).unwrap();
+        let size = encoder::encode_with(&mut enc, &src, &mut compressed)
+            .unwrap()
+            .len();
+
+        let mut linear = vec![0u8; 80000];
+        let mut enc = encoder::HeatshrinkEncoder::<32768>::boxed_with_params(params).unwrap();
+        enc.set_use_index(false);
+        assert_eq!(
+            encoder::encode_with(&mut enc, &src, &mut linear),
+            Ok(&compressed[..size])
+        );
+
+        let mut out = vec![0u8; 70001];
+        let mut dec = decoder::HeatshrinkDecoder::<32768>::boxed_with_params(params).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &compressed[..size], &mut out),
+            Ok(&src[..])
+        );
+    }
+
+    #[test]
+    fn long_stream_positions() {
+        // longer than a 16 bits usize can count
+        let src: [u8; 70000] = core::array::from_fn(|i| (i % 37) as u8 ^ (i / 200) as u8);
+        let mut compressed = [0u8; encoder::encode_bound(70000)];
+        let compressed = encoder::encode(&src, &mut compressed).unwrap();
+        let mut output = [0u8; 70001];
+        assert_eq!(decoder::decode(compressed, &mut output), Ok(&src[..]));
+        assert_eq!(encoder::encode_bound(usize::MAX), usize::MAX);
+
+        // the head index saved by older versions grew with the stream
+        let (first, rest) = compressed.split_at(compressed.len() / 2);
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        let mut size = 0;
+        dec.sink_all(first, &mut |data: &[u8]| {
+            output[size..][..data.len()].copy_from_slice(data);
+            size += data.len();
+        })
+        .unwrap();
+        let mut state = [0u8; decoder::HeatshrinkDecoder::<256>::STATE_MAX_SIZE];
+        dec.save_state(&mut state).unwrap();
+        let head_index = u64::from_le_bytes(state[18..26].try_into().unwrap());
+        assert!((256..512).contains(&head_index));
+        state[18..26].copy_from_slice(&(head_index + (1 << 40)).to_le_bytes());
+        let mut resumed = decoder::HeatshrinkDecoder::<256>::new();
+        resumed.restore_state(&state).unwrap();
+        assert_eq!(
+            decoder::decode_with(&mut resumed, rest, &mut output[size..]).map(|out| out.len()),
+            Ok(src.len() - size)
+        );
+        assert_eq!(src, output[..src.len()]);
+    }
+
+    #[test]
+    fn reset_dirty_instance() {
+        let mut seed: u32 = 3;
+        let noise: [u8; 1000] = core::array::from_fn(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        });
+        let src: [u8; 1000] = core::array::from_fn(|i| (i % 37) as u8 ^ (i / 200) as u8);
+        let mut expected = [0u8; 2000];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        // the buffers left by the previous stream are not cleared
+        let mut compressed = [0u8; 2000];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        encoder::encode_with(&mut enc, &noise, &mut compressed).unwrap();
+        enc.reset();
+        assert_eq!(
+            encoder::encode_with(&mut enc, &src, &mut compressed),
+            Ok(expected)
+        );
+
+        let mut output = [0u8; 1001];
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        let noise_compressed = encoder::encode(&noise, &mut compressed).unwrap();
+        decoder::decode_with(&mut dec, noise_compressed, &mut output).unwrap();
+        dec.reset();
+        assert_eq!(
+            decoder::decode_with(&mut dec, expected, &mut output),
+            Ok(&src[..])
+        );
+    }
+
+    #[cfg(feature = "heatshrink-use-index")]
+    #[test]
+    fn index_two_byte_chains() {
+        // few distinct bytes, which made long chains of single bytes
+        let mut seed: u32 = 11;
+        let src: [u8; 4000] = core::array::from_fn(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            [0, 0, 0, 1, 2][(seed >> 16) as usize % 5]
+        });
+
+        for params in [Params::new(8, 4).unwrap(), Params::new(10, 3).unwrap()] {
+            let mut indexed = [0u8; 5000];
+            let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+            let indexed = encoder::encode_with(&mut enc, &src, &mut indexed).unwrap();
+
+            let mut linear = [0u8; 5000];
+            let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+            enc.set_use_index(false);
+            assert_eq!(
+                encoder::encode_with(&mut enc, &src, &mut linear),
+                Ok(indexed)
+            );
+
+            let mut output = [0u8; 4001];
+            let mut dec = decoder::HeatshrinkDecoder::<1024>::with_params(params).unwrap();
+            assert_eq!(
+                decoder::decode_with(&mut dec, indexed, &mut output),
+                Ok(&src[..])
+            );
+        }
+    }
+
+    #[test]
+    fn lazy_matching() {
+        // text-like data
+        let src: &[u8; 3000] = include_bytes!("decoder.rs")[..3000].try_into().unwrap();
+
+        let mut greedy = [0u8; 4000];
+        let greedy = encoder::encode(src, &mut greedy).unwrap();
+        let mut lazy = [0u8; 4000];
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .lazy_matching(true)
+            .build()
+            .unwrap();
+        let lazy = encoder::encode_with(&mut enc, src, &mut lazy).unwrap();
+        assert!(lazy.len() < greedy.len());
+        let mut output = [0u8; 3001];
+        assert_eq!(decoder::decode(lazy, &mut output), Ok(&src[..]));
+
+        // in place, and through a saved state
+        let mut compressed = [0u8; 4000];
+        enc.reset();
+        let size = enc.encode_slice(src, &mut compressed).unwrap();
+        assert!(size < greedy.len());
+        assert_eq!(
+            decoder::decode(&compressed[..size], &mut output),
+            Ok(&src[..])
+        );
+
+        let mut state = [0u8; encoder::HeatshrinkEncoder::<256>::STATE_MAX_SIZE];
+        enc.reset();
+        assert_eq!(enc.sink(&src[..100]), (HSsinkRes::SinkOK, 100));
+        enc.save_state(&mut state).unwrap();
+        let mut resumed = encoder::HeatshrinkEncoder::<256>::new();
+        resumed.restore_state(&state).unwrap();
+        let mut size = 0;
+        resumed
+            .sink_all(&src[100..], &mut |data: &[u8]| {
+                compressed[size..][..data.len()].copy_from_slice(data);
+                size += data.len();
+            })
+            .unwrap();
+        size += resumed.finish_into(&mut compressed[size..]).unwrap();
+        assert_eq!(compressed[..size], *lazy);
+    }
+
+    #[test]
+    fn compression_levels() {
+        let src: &[u8; 3000] = include_bytes!("encoder.rs")[..3000].try_into().unwrap();
+        let mut default = [0u8; 4000];
+        let default = encoder::encode(src, &mut default).unwrap();
+
+        let mut sizes = [0; encoder::MAX_LEVEL as usize + 1];
+        for level in 0..=encoder::MAX_LEVEL + 1 {
+            let mut compressed = [0u8; 4000];
+            let mut enc = encoder::HeatshrinkEncoder::new();
+            enc.set_level(level);
+            let compressed = encoder::encode_with(&mut enc, src, &mut compressed).unwrap();
+            let clamped = level.clamp(encoder::MIN_LEVEL, encoder::MAX_LEVEL);
+            sizes[usize::from(clamped)] = compressed.len();
+            if clamped == encoder::DEFAULT_LEVEL {
+                assert_eq!(compressed, default);
+            }
+
+            let mut output = [0u8; 3001];
+            assert_eq!(decoder::decode(compressed, &mut output), Ok(&src[..]));
+
+            let mut built = [0u8; 4000];
+            let mut enc = encoder::EncoderBuilder::<256>::new()
+                .level(level)
+                .build()
+                .unwrap();
+            assert_eq!(
+                encoder::encode_with(&mut enc, src, &mut built),
+                Ok(compressed)
+            );
+        }
+
+        // more effort, better ratio
+        let levels = &sizes[usize::from(encoder::MIN_LEVEL)..];
+        assert!(levels.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(levels[0] > levels[levels.len() - 1]);
+    }
+
+    #[cfg(feature = "heatshrink-use-index")]
+    #[test]
+    fn linear_search_words() {
+        // the window is scanned 8 bytes at a time, whatever its alignment
+        let text = include_bytes!("decoder.rs");
+        for (size, params) in [(3000, Params::new(8, 4)), (2995, Params::new(10, 5))] {
+            let params = params.unwrap();
+            let src = &text[..size];
+            let mut indexed = [0u8; 4000];
+            let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+            let indexed = encoder::encode_with(&mut enc, src, &mut indexed).unwrap();
+
+            let mut linear = [0u8; 4000];
+            let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+            enc.set_use_index(false);
+            assert_eq!(
+                encoder::encode_with(&mut enc, src, &mut linear),
+                Ok(indexed)
+            );
+            enc.reset();
+            enc.set_max_search_depth(3);
+            let size = encoder::encode_with(&mut enc, src, &mut linear)
+                .unwrap()
+                .len();
+
+            let mut output = [0u8; 3001];
+            let mut dec = decoder::HeatshrinkDecoder::<1024>::with_params(params).unwrap();
+            assert_eq!(
+                decoder::decode_with(&mut dec, &linear[..size], &mut output),
+                Ok(src)
+            );
+        }
+    }
+
+    #[test]
+    fn bit_writer_output_sizes() {
+        let src: [u8; 3000] = core::array::from_fn(|i| (i % 37) as u8 ^ (i / 200) as u8);
+
+        for params in [Params::new(4, 3), Params::new(8, 4), Params::new(12, 11)] {
+            let params = params.unwrap();
+            let mut expected = [0u8; 4000];
+            let mut enc = encoder::HeatshrinkEncoder::<4096>::with_params(params).unwrap();
+            let expected = encoder::encode_with(&mut enc, &src, &mut expected).unwrap();
+
+            // whole tokens are written when they fit, bits by bits otherwise
+            for poll_size in [1, 2, 3, 5] {
+                let mut compressed = [0u8; 4000];
+                let mut size = 0;
+                let mut enc = encoder::HeatshrinkEncoder::<4096>::with_params(params).unwrap();
+                let mut input = &src[..];
+                while !input.is_empty() {
+                    let (res, n) = enc.sink(&input[..input.len().min(100)]);
+                    assert_eq!(res, HSsinkRes::SinkOK);
+                    input = &input[n..];
+                    while let (HSpollRes::PollMore, n) =
+                        enc.poll(&mut compressed[size..size + poll_size])
+                    {
+                        size += n;
+                    }
+                    size = enc.total_out() as usize;
+                }
+                while enc.finish() == HSfinishRes::FinishMore {
+                    size += enc.poll(&mut compressed[size..size + poll_size]).1;
+                }
+                assert_eq!(compressed[..size], *expected);
+            }
+
+            let mut output = [0u8; 3001];
+            let mut dec = decoder::HeatshrinkDecoder::<4096>::with_params(params).unwrap();
+            assert_eq!(
+                decoder::decode_with(&mut dec, expected, &mut output),
+                Ok(&src[..])
+            );
+        }
+    }
+
+    #[test]
+    fn block_container() {
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut compressed = [0u8; block::block_bound(3000, 512)];
+        let mut output = [0u8; 3000];
+
+        let size = block::encode_blocks(src, &mut compressed, 512)
+            .unwrap()
+            .len();
+        assert!(size < src.len());
+        assert_eq!(
+            block::decode_blocks(&compressed[..size], &mut output),
+            Ok(src)
+        );
+        assert_eq!(block::encode_blocks(&[], &mut compressed, 512), Ok(&[][..]));
+
+        // each block is a stream of its own
+        let mut single = [0u8; 1024];
+        let single = encoder::encode(&src[512..1024], &mut single).unwrap();
+        let first_size = u32::from_le_bytes(compressed[4..8].try_into().unwrap());
+        let second = &compressed[block::BLOCK_HEADER_SIZE + first_size as usize..];
+        assert_eq!(&second[..4], &512u32.to_le_bytes());
+        assert_eq!(&second[block::BLOCK_HEADER_SIZE..][..single.len()], single);
+
+        assert_eq!(
+            block::encode_blocks(src, &mut compressed[..size - 1], 512),
+            Err(HSError::OutputFull {
+                needed_hint: Some(block::block_bound(3000, 512))
+            })
+        );
+        assert_eq!(
+            block::decode_blocks(&compressed[..size - 1], &mut output),
+            Err(HSError::Truncated)
+        );
+        assert_eq!(
+            block::decode_blocks(&compressed[..size], &mut output[..2999]),
+            Err(HSError::OutputFull { needed_hint: None })
+        );
+        let mut corrupted = compressed;
+        corrupted[1] -= 1;
+        assert_eq!(
+            block::decode_blocks(&corrupted[..size], &mut output),
+            Err(HSError::TooLong)
+        );
+
+        // blocks that don't compress are stored verbatim
+        let mut seed = 7u32;
+        let mut random = [0u8; 3000];
+        for byte in random.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *byte = (seed >> 16) as u8;
+        }
+        let size = block::encode_blocks(&random, &mut compressed, 512)
+            .unwrap()
+            .len();
+        assert_eq!(size, block::block_bound(3000, 512));
+        assert_eq!(
+            u32::from_le_bytes(compressed[4..8].try_into().unwrap()),
+            512 | block::STORED_BLOCK_FLAG
+        );
+        assert_eq!(
+            &compressed[block::BLOCK_HEADER_SIZE..][..512],
+            &random[..512]
+        );
+        assert_eq!(
+            block::decode_blocks(&compressed[..size], &mut output),
+            Ok(&random[..])
+        );
+        let mut corrupted = compressed;
+        corrupted[0] += 1;
+        assert_eq!(
+            block::decode_blocks(&corrupted[..size], &mut output),
+            Err(HSError::TooShort)
+        );
+
+        #[cfg(feature = "parallel")]
+        for block_size in [1, 100, 512, 4096] {
+            let mut sequential = [0u8; block::block_bound(3000, 1)];
+            let mut parallel = [0u8; block::block_bound(3000, 1)];
+            let mut enc =
+                encoder::HeatshrinkEncoder::<1024>::with_params(Params::new(10, 5).unwrap())
+                    .unwrap();
+            enc.set_level(encoder::MAX_LEVEL);
+            let parallel =
+                block::encode_parallel_with(&enc, src, &mut parallel, block_size).unwrap();
+            assert_eq!(
+                block::encode_blocks_with(&mut enc, src, &mut sequential, block_size),
+                Ok(parallel)
+            );
+            let mut parallel = [0u8; block::block_bound(3000, 1)];
+            let parallel =
+                block::encode_parallel_with(&enc, &random, &mut parallel, block_size).unwrap();
+            assert_eq!(
+                block::encode_blocks_with(&mut enc, &random, &mut sequential, block_size),
+                Ok(parallel)
+            );
+        }
+    }
+
+    #[test]
+    fn skip_ahead() {
+        // text around an incompressible region
+        let mut seed = 5u32;
+        let mut src = [0u8; 3000];
+        src[..1000].copy_from_slice(&include_bytes!("encoder.rs")[..1000]);
+        for byte in src[1000..2000].iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *byte = (seed >> 16) as u8;
+        }
+        src[2000..].copy_from_slice(&include_bytes!("encoder.rs")[..1000]);
+
+        let mut searched = [0u8; 4000];
+        let searched = encoder::encode(&src, &mut searched).unwrap();
+        let mut skipped = [0u8; 4000];
+        let mut enc = encoder::EncoderBuilder::<256>::new()
+            .skip_ahead(true)
+            .build()
+            .unwrap();
+        let skipped = encoder::encode_with(&mut enc, &src, &mut skipped).unwrap();
+        assert_ne!(skipped, searched);
+        assert!(skipped.len() < searched.len() + 8);
+        let mut output = [0u8; 3001];
+        assert_eq!(decoder::decode(skipped, &mut output), Ok(&src[..]));
+
+        let mut compressed = [0u8; 4000];
+        enc.reset();
+        let size = enc.encode_slice(&src, &mut compressed).unwrap();
+        assert!(size < searched.len() + 8);
+        assert_eq!(
+            decoder::decode(&compressed[..size], &mut output),
+            Ok(&src[..])
+        );
+
+        // the lower levels skip ahead, the default one doesn't
+        let mut leveled = [0u8; 4000];
+        enc = encoder::EncoderBuilder::<256>::new()
+            .level(7)
+            .build()
+            .unwrap();
+        let size = encoder::encode_with(&mut enc, &src, &mut leveled)
+            .unwrap()
+            .len();
+        assert_eq!(decoder::decode(&leveled[..size], &mut output), Ok(&src[..]));
+        enc.set_level(encoder::DEFAULT_LEVEL);
+        enc.reset();
+        assert_eq!(
+            encoder::encode_with(&mut enc, &src, &mut leveled),
+            Ok(searched)
+        );
+    }
+
+    #[test]
+    fn backref_runs() {
+        // runs of several periods, starting with zeros read before the
+        // start of the stream
+        let mut src = [0u8; 3000];
+        for (i, byte) in src.iter_mut().enumerate().skip(100) {
+            let period = 1 + (i / 300) * 7;
+            *byte = (i % period) as u8 ^ (i / 300) as u8;
+        }
+
+        for params in [Params::new(6, 3), Params::new(8, 7), Params::new(11, 8)] {
+            let params = params.unwrap();
+            let mut compressed = [0u8; 4000];
+            let mut enc = encoder::HeatshrinkEncoder::<2048>::with_params(params).unwrap();
+            let compressed = encoder::encode_with(&mut enc, &src, &mut compressed).unwrap();
+
+            // the runs are split by the window end and by the poll buffer
+            for poll_size in [1, 5, 64, 3000] {
+                let mut dec = decoder::HeatshrinkDecoder::<2048>::with_params(params).unwrap();
+                let mut output = [0u8; 3000];
+                let mut size = 0;
+                let mut input = compressed;
+                while !input.is_empty() || size < output.len() {
+                    let (_, sunk) = dec.sink(input);
+                    input = &input[sunk..];
+                    let end = (size + poll_size).min(output.len());
+                    size += dec.poll(&mut output[size..end]).1;
+                }
+                // only the padding bits are left
+                assert_eq!(dec.poll(&mut [0u8; 1]), (HSpollRes::PollEmpty, 0));
+                assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+                assert_eq!(size, src.len());
+                assert_eq!(output, src);
+            }
+        }
+    }
+
+    #[test]
+    fn reset_and_zeroize() {
+        fn compress(enc: &mut encoder::HeatshrinkEncoder, src: &[u8], dst: &mut [u8]) -> usize {
+            let mut size = 0;
+            let mut output = |data: &[u8]| {
+                dst[size..size + data.len()].copy_from_slice(data);
+                size += data.len();
+            };
+            enc.sink_all(src, &mut output).unwrap();
+            enc.finish();
+            enc.poll_all(&mut output).unwrap();
+            size
+        }
+
+        let short: [u8; 100] = core::array::from_fn(|i| (i % 7) as u8);
+        let long: [u8; 1000] = core::array::from_fn(|i| (i % 37) as u8 ^ (i / 200) as u8);
+        let mut expected = [0u8; 200];
+        let expected = encoder::encode(&short, &mut expected).unwrap();
+
+        // after short streams, streams filling the window and primed ones
+        let mut compressed = [0u8; 2000];
+        let mut enc = encoder::HeatshrinkEncoder::new();
+        for previous in [&short[..], &long[..], &short[..]] {
+            enc.reset();
+            compress(&mut enc, previous, &mut compressed);
+            enc.reset();
+            let size = compress(&mut enc, &short, &mut compressed);
+            assert_eq!(&compressed[..size], expected);
+        }
+        enc.reset();
+        enc.prime(&long);
+        compress(&mut enc, &short, &mut compressed);
+        enc.reset();
+        let size = compress(&mut enc, &short, &mut compressed);
+        assert_eq!(&compressed[..size], expected);
+
+        enc.reset();
+        compress(&mut enc, &long, &mut compressed);
+        enc.reset_and_zeroize();
+        let size = compress(&mut enc, &short, &mut compressed);
+        assert_eq!(&compressed[..size], expected);
+
+        let mut output = [0u8; 1000];
+        let mut dec = decoder::HeatshrinkDecoder::new();
+        assert_eq!(
+            decoder::decode_with(&mut dec, &compressed[..size], &mut output),
+            Ok(&short[..])
+        );
+        dec.reset_and_zeroize();
+        assert_eq!(
+            decoder::decode_with(&mut dec, expected, &mut output),
+            Ok(&short[..])
+        );
+    }
+
+    #[test]
+    fn container_header() {
+        use super::container::{read_header, write_header, Header, HEADER_SIZE};
+
+        let src: [u8; 1000] = core::array::from_fn(|i| (i % 37) as u8 ^ (i / 200) as u8);
+        let params = Params::new(10, 5).unwrap();
+        let mut compressed = [0u8; 1500];
+        let size = write_header(&Header::new(params, Some(1000)), &mut compressed).unwrap();
+        assert_eq!(size, HEADER_SIZE);
+        let mut enc = encoder::HeatshrinkEncoder::<1024>::with_params(params).unwrap();
+        let stream_size = encoder::encode_with(&mut enc, &src, &mut compressed[size..])
+            .unwrap()
+            .len();
+
+        let (header, offset) = read_header(&compressed).unwrap();
+        assert_eq!(header, Header::new(params, Some(1000)));
+        let mut output = [0u8; 1000];
+        let mut dec = decoder::HeatshrinkDecoder::<1024>::with_params(header.params).unwrap();
+        assert_eq!(
+            decoder::decode_exact_with(
+                &mut dec,
+                &compressed[offset..offset + stream_size],
+                &mut output,
+                header.original_len.unwrap() as usize
+            ),
+            Ok(&src[..])
+        );
+
+        let mut header = [0u8; HEADER_SIZE];
+        write_header(&Header::new(params, None), &mut header).unwrap();
+        assert_eq!(
+            read_header(&header),
+            Ok((Header::new(params, None), HEADER_SIZE))
+        );
+        assert_eq!(
+            write_header(&Header::new(params, Some(u64::MAX)), &mut header),
+            Err(HSError::InvalidHeader)
+        );
+        assert_eq!(
+            write_header(&Header::new(params, None), &mut header[1..]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(HEADER_SIZE)
+            })
+        );
+        assert_eq!(
+            read_header(&header[..HEADER_SIZE - 1]),
+            Err(HSError::Truncated)
+        );
+        // bad magic, version and parameters
+        for (offset, value) in [(0, b'h'), (4, 3), (5, 3), (6, 10)] {
+            let mut corrupted = header;
+            corrupted[offset] = value;
+            assert_eq!(read_header(&corrupted), Err(HSError::InvalidHeader));
+        }
+    }
+
+    #[test]
+    fn container_metadata() {
+        use super::container::{
+            read_header, read_header_with_metadata, write_header, write_header_with_metadata,
+            Header, Metadata, HEADER_SIZE, METADATA_FIXED_SIZE,
+        };
+
+        let params = Params::new(10, 5).unwrap();
+        let header = Header::new(params, Some(1000));
+        let metadata = Metadata {
+            name: Some("firmware.bin"),
+            mtime: Some(1_700_000_000),
+            comment: Some("release 1.2"),
+        };
+        let size = HEADER_SIZE + METADATA_FIXED_SIZE + 12 + 11;
+        assert_eq!(HEADER_SIZE + metadata.size(), size);
+        let mut buffer = [0u8; 100];
+        assert_eq!(
+            write_header_with_metadata(&header, &metadata, &mut buffer),
+            Ok(size)
+        );
+        assert_eq!(
+            read_header_with_metadata(&buffer),
+            Ok((header, Some(metadata), size))
+        );
+        // skipped by read_header
+        assert_eq!(read_header(&buffer), Ok((header, size)));
+        assert_eq!(
+            read_header_with_metadata(&buffer[..size - 1]),
+            Err(HSError::Truncated)
+        );
+        assert_eq!(
+            read_header_with_metadata(&buffer[..HEADER_SIZE + 1]),
+            Err(HSError::Truncated)
+        );
+        assert_eq!(
+            write_header_with_metadata(&header, &metadata, &mut buffer[..size - 1]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(size)
+            })
+        );
+
+        // no metadata in a plain header, an empty section
+        write_header(&header, &mut buffer).unwrap();
+        assert_eq!(
+            read_header_with_metadata(&buffer),
+            Ok((header, None, HEADER_SIZE))
+        );
+        assert_eq!(
+            write_header_with_metadata(&header, &Metadata::new(), &mut buffer),
+            Ok(HEADER_SIZE + METADATA_FIXED_SIZE)
+        );
+        assert_eq!(
+            read_header_with_metadata(&buffer),
+            Ok((
+                header,
+                Some(Metadata::new()),
+                HEADER_SIZE + METADATA_FIXED_SIZE
+            ))
+        );
+
+        let invalid = Metadata {
+            mtime: Some(u64::MAX),
+            ..metadata
+        };
+        assert_eq!(
+            write_header_with_metadata(&header, &invalid, &mut buffer),
+            Err(HSError::InvalidHeader)
+        );
+        write_header_with_metadata(&header, &metadata, &mut buffer).unwrap();
+        buffer[HEADER_SIZE + METADATA_FIXED_SIZE] = 0xff;
+        assert_eq!(
+            read_header_with_metadata(&buffer),
+            Err(HSError::InvalidHeader)
+        );
+    }
+
+    #[cfg(feature = "crc")]
+    #[test]
+    fn crc_trailer() {
+        use super::crc::{self, Checksum, Crc16, Crc32, CrcDecoder, CrcEncoder};
+
+        let mut check = Crc32::default();
+        check.update(b"123456789");
+        assert_eq!(check.value(), 0xcbf4_3926);
+        let mut check = Crc16::default();
+        check.update(b"123456789");
+        assert_eq!(check.value(), 0x29b1);
+
+        let src = &include_bytes!("decoder.rs")[..3000];
+        let mut compressed = [0u8; 4096];
+        let mut output = [0u8; 4096];
+        let size = crc::encode::<Crc32>(src, &mut compressed).unwrap().len();
+        assert_eq!(
+            crc::decode::<Crc32>(&compressed[..size], &mut output),
+            Ok(src)
+        );
+        for corrupted in [size - 1, size / 2] {
+            compressed[corrupted] ^= 0x10;
+            assert_ne!(
+                crc::decode::<Crc32>(&compressed[..size], &mut output),
+                Ok(src)
+            );
+            compressed[corrupted] ^= 0x10;
+        }
+        assert_eq!(
+            crc::decode::<Crc32>(&compressed[..3], &mut output),
+            Err(HSError::Truncated)
+        );
+
+        // the wrappers produce the same stream, polled and sunk in any size
+        for (chunk, poll) in [(1, 1), (7, 3), (1000, 64)] {
+            let mut enc = CrcEncoder::<Crc16>::new(encoder::HeatshrinkEncoder::new());
+            let mut input = src;
+            let mut stream_size = 0;
+            while !input.is_empty() {
+                let (_, sunk) = enc.sink(&input[..chunk.min(input.len())]);
+                input = &input[sunk..];
+                stream_size += enc.poll(&mut compressed[stream_size..][..poll]).1;
+            }
+            while enc.finish() == HSfinishRes::FinishMore {
+                stream_size += enc.poll(&mut compressed[stream_size..][..poll]).1;
+            }
+            let mut expected = [0u8; 4096];
+            assert_eq!(
+                crc::encode::<Crc16>(src, &mut expected),
+                Ok(&compressed[..stream_size])
+            );
+
+            let mut dec = CrcDecoder::<Crc16>::new(decoder::HeatshrinkDecoder::new());
+            let mut input = &compressed[..stream_size];
+            let mut size = 0;
+            while !input.is_empty() {
+                let (_, sunk) = dec.sink(&input[..chunk.min(input.len())]);
+                input = &input[sunk..];
+                size += dec.poll(&mut output[size..][..poll]).1;
+            }
+            while dec.finish() == Ok(HSfinishRes::FinishMore) {
+                size += dec.poll(&mut output[size..][..poll]).1;
+            }
+            assert_eq!(dec.finish(), Ok(HSfinishRes::FinishDone));
+            assert_eq!(&output[..size], src);
+        }
+
+        let mut dec = CrcDecoder::<Crc32>::new(decoder::HeatshrinkDecoder::new());
+        assert_eq!(dec.sink(&compressed[..2]), (HSsinkRes::SinkOK, 2));
+        assert_eq!(dec.finish(), Err(HSError::Truncated));
+    }
+
+    #[cfg(feature = "digest")]
+    #[test]
+    fn digest_while_streaming() {
+        use super::digest::{DigestDecoder, DigestEncoder};
+        use sha2::{Digest, Sha256};
+
+        let src = &include_bytes!("encoder.rs")[..5000];
+        let expected_hash = Sha256::digest(src);
+        let mut compressed = [0u8; 6000];
+        let mut output = [0u8; 5100];
+
+        let mut enc = DigestEncoder::new(encoder::HeatshrinkEncoder::new(), Sha256::new());
+        let mut input = src;
+        let mut compressed_size = 0;
+        while !input.is_empty() {
+            let (_, sunk) = enc.sink(&input[..100.min(input.len())]);
+            input = &input[sunk..];
+            compressed_size += enc.poll(&mut compressed[compressed_size..][..7]).1;
+        }
+        while enc.finish() == HSfinishRes::FinishMore {
+            compressed_size += enc.poll(&mut compressed[compressed_size..][..7]).1;
+        }
+        assert_eq!(enc.finalize(), expected_hash);
+        let mut expected = [0u8; 6000];
+        assert_eq!(
+            encoder::encode(src, &mut expected),
+            Ok(&compressed[..compressed_size])
+        );
+
+        let mut dec = DigestDecoder::new(decoder::HeatshrinkDecoder::new(), Sha256::new());
+        let mut input = &compressed[..compressed_size];
+        let mut size = 0;
+        while !input.is_empty() {
+            let (_, sunk) = dec.sink(&input[..13.min(input.len())]);
+            input = &input[sunk..];
+            size += dec.poll(&mut output[size..][..50]).1;
+        }
+        while dec.finish() == HSfinishRes::FinishMore {
+            size += dec.poll(&mut output[size..][..50]).1;
+        }
+        assert_eq!(&output[..size], src);
+        assert_eq!(dec.finalize(), expected_hash);
+    }
+
+    #[test]
+    fn length_prefixed_frames() {
+        use super::frame::{FrameDecoder, FrameEncoder, FRAME_HEADER_SIZE};
+
+        let src = &include_bytes!("encoder.rs")[..10000];
+        let mut stream = [0u8; 40000];
+        let mut output = [0u8; 10000];
+
+        for frame_size in [4, 64, 1500] {
+            let mut enc = FrameEncoder::new(encoder::HeatshrinkEncoder::new());
+            let mut input_size = 0;
+            let mut stream_size = 0;
+            while input_size < src.len() {
+                let mut frame = [0u8; 1500];
+                let (consumed, size) = enc
+                    .encode_frame(&src[input_size..], &mut frame[..frame_size])
+                    .unwrap();
+                assert!(consumed > 0 && size <= frame_size);
+                assert_eq!(
+                    usize::from(u16::from_le_bytes([frame[0], frame[1]])),
+                    size - FRAME_HEADER_SIZE
+                );
+                stream[stream_size..stream_size + size].copy_from_slice(&frame[..size]);
+                input_size += consumed;
+                stream_size += size;
+            }
+
+            // the bytes are received in pieces unrelated to the frames
+            for chunk in [1, 7, 1000] {
+                let mut dec = FrameDecoder::new(decoder::HeatshrinkDecoder::new(), frame_size);
+                let mut size = 0;
+                for piece in stream[..stream_size].chunks(chunk) {
+                    dec.sink_all(piece, &mut |data: &[u8]| {
+                        output[size..size + data.len()].copy_from_slice(data);
+                        size += data.len();
+                    })
+                    .unwrap();
+                }
+                assert_eq!(dec.finish(), Ok(()));
+                assert_eq!(&output[..size], src);
+            }
+
+            let mut dec = FrameDecoder::new(decoder::HeatshrinkDecoder::new(), frame_size);
+            assert_eq!(dec.sink_all(&stream[..3], &mut |_| {}), Ok(()));
+            assert_eq!(dec.finish(), Err(HSError::Truncated));
+        }
+
+        let mut enc = FrameEncoder::new(encoder::HeatshrinkEncoder::new());
+        assert_eq!(
+            enc.encode_frame(src, &mut [0u8; 3]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(FRAME_HEADER_SIZE + 2)
+            })
+        );
+        let mut dec = FrameDecoder::new(decoder::HeatshrinkDecoder::new(), 64);
+        assert_eq!(
+            dec.sink_all(&[0xff, 0x00], &mut |_| {}),
+            Err(HSError::InvalidFrame)
+        );
+    }
+
+    #[cfg(feature = "armor")]
+    #[test]
+    fn armored_text() {
+        use super::armor::{self, ArmorReader, ArmorWriter};
+
+        let mut text = [0u8; 100];
+        let mut output = [0u8; 100];
+        // RFC 4648 test vectors
+        for (src, expected) in [
+            (&b""[..], &b""[..]),
+            (b"f", b"Zg==\n"),
+            (b"fo", b"Zm8=\n"),
+            (b"foo", b"Zm9v\n"),
+            (b"foobar", b"Zm9vYmFy\n"),
+        ] {
+            assert_eq!(armor::armor(src, &mut text, 76), Ok(expected));
+            assert_eq!(armor::dearmor(expected, &mut output), Ok(src));
+        }
+        assert_eq!(
+            armor::armor(b"foobar", &mut text, 3),
+            Ok(&b"Zm9\nvYm\nFy\n"[..])
+        );
+        assert_eq!(armor::armor(b"foobar", &mut text, 0), Ok(&b"Zm9vYmFy"[..]));
+        assert_eq!(
+            armor::dearmor(b"  Zm9v\r\n  YmE=\n", &mut output),
+            Ok(&b"fooba"[..])
+        );
+
+        let src = &include_bytes!("encoder.rs")[..3000];
+        let mut text = [0u8; armor::armor_bound(3000, armor::LINE_LENGTH)];
+        let mut output = [0u8; 3000];
+        let size = armor::armor(src, &mut text, armor::LINE_LENGTH)
+            .unwrap()
+            .len();
+        assert!(text[..size]
+            .split(|&char| char == b'\n')
+            .all(|line| line.len() <= armor::LINE_LENGTH));
+        assert_eq!(armor::dearmor(&text[..size], &mut output), Ok(src));
+
+        // streamed in pieces of any size
+        for chunk in [1, 7, 1000] {
+            let mut writer = ArmorWriter::default();
+            let mut streamed = [0u8; armor::armor_bound(3000, armor::LINE_LENGTH)];
+            let mut streamed_size = 0;
+            let mut write = |data: &[u8]| {
+                streamed[streamed_size..streamed_size + data.len()].copy_from_slice(data);
+                streamed_size += data.len();
+            };
+            for piece in src.chunks(chunk) {
+                writer.sink_all(piece, &mut write);
+            }
+            writer.finish(&mut write);
+            assert_eq!(&streamed[..streamed_size], &text[..size]);
+
+            let mut reader = ArmorReader::new();
+            let mut output_size = 0;
+            for piece in text[..size].chunks(chunk) {
+                reader
+                    .sink_all(piece, &mut |data: &[u8]| {
+                        output[output_size..output_size + data.len()].copy_from_slice(data);
+                        output_size += data.len();
+                    })
+                    .unwrap();
+            }
+            assert_eq!(reader.finish(), Ok(()));
+            assert_eq!(&output[..output_size], src);
+        }
+
+        assert_eq!(
+            armor::armor(src, &mut text[..size - 1], armor::LINE_LENGTH),
+            Err(HSError::OutputFull {
+                needed_hint: Some(size)
+            })
+        );
+        assert_eq!(
+            armor::dearmor(&text[..size], &mut output[..2999]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(3000)
+            })
+        );
+        assert_eq!(armor::dearmor(b"Zm9", &mut output), Err(HSError::Truncated));
+        for invalid in [&b"Zm9v!"[..], b"Z===", b"Zg==Zm9v", b"Zm=v"] {
+            assert_eq!(
+                armor::dearmor(invalid, &mut output),
+                Err(HSError::InvalidFrame)
+            );
+        }
+    }
+
+    #[cfg(feature = "cobs")]
+    #[test]
+    fn cobs_packets() {
+        use super::cobs::{CobsFrameReader, CobsFrameWriter, PACKET_DELIMITER};
+
+        // zeros, text and noise, compressed to runs of more than 254 non
+        // zero bytes
+        let mut seed: u32 = 5;
+        let mut src = [0u8; 12000];
+        for (i, byte) in src.iter_mut().enumerate() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *byte = match i / 3000 {
+                0 => 0,
+                1 => include_bytes!("encoder.rs")[i],
+                _ => (seed >> 16) as u8,
+            };
+        }
+        let mut stream = [0u8; 50000];
+        let mut output = [0u8; 12000];
+
+        for packet_size in [4, 64, 300, 1024] {
+            let mut writer = CobsFrameWriter::new(encoder::HeatshrinkEncoder::new());
+            let mut input_size = 0;
+            let mut stream_size = 0;
+            while input_size < src.len() {
+                let mut packet = [0xaau8; 1024];
+                let (consumed, size) = writer
+                    .write_packet(&src[input_size..], &mut packet[..packet_size])
+                    .unwrap();
+                assert!(consumed > 0 && size <= packet_size);
+                assert_eq!(packet[size - 1], PACKET_DELIMITER);
+                assert!(!packet[..size - 1].contains(&PACKET_DELIMITER));
+                stream[stream_size..stream_size + size].copy_from_slice(&packet[..size]);
+                input_size += consumed;
+                stream_size += size;
+            }
+
+            for chunk in [1, 5, 4000] {
+                let mut reader = CobsFrameReader::new(decoder::HeatshrinkDecoder::new());
+                let mut size = 0;
+                for piece in stream[..stream_size].chunks(chunk) {
+                    reader
+                        .sink_all(piece, &mut |data: &[u8]| {
+                            output[size..size + data.len()].copy_from_slice(data);
+                            size += data.len();
+                        })
+                        .unwrap();
+                }
+                assert_eq!(reader.finish(), Ok(()));
+                assert_eq!(output[..size], src);
+            }
+
+            let mut reader = CobsFrameReader::new(decoder::HeatshrinkDecoder::new());
+            assert_eq!(reader.sink_all(&stream[..1], &mut |_| {}), Ok(()));
+            assert_eq!(reader.finish(), Err(HSError::Truncated));
+        }
+
+        let mut writer = CobsFrameWriter::new(encoder::HeatshrinkEncoder::new());
+        assert_eq!(
+            writer.write_packet(&src, &mut [0u8; 3]),
+            Err(HSError::OutputFull {
+                needed_hint: Some(4)
+            })
+        );
+        let mut reader = CobsFrameReader::new(decoder::HeatshrinkDecoder::new());
+        assert_eq!(
+            reader.sink_all(&[3, 1, PACKET_DELIMITER], &mut |_| {}),
+            Err(HSError::InvalidFrame)
+        );
+    }
+
+    #[test]
+    fn seekable_blocks() {
+        use super::block::{self, SeekableDecoder, SEEK_FOOTER_SIZE};
+
+        let src = &include_bytes!("decoder.rs")[..20000];
+        let mut compressed = [0u8; 70000];
+        let mut output = [0u8; 70000];
+
+        for block_size in [50, 100, 4096, 100000] {
+            let size = block::encode_seekable(src, &mut compressed, block_size)
+                .unwrap()
+                .len();
+            assert!(size <= block::seekable_bound(src.len(), block_size));
+            let seekable = SeekableDecoder::new(&compressed[..size]).unwrap();
+            assert_eq!(seekable.len(), Ok(src.len()));
+            assert_eq!(seekable.block_count(), src.len().div_ceil(block_size));
+
+            // the container before the seek table is a block container
+            let container_size = size - 4 * seekable.block_count() - SEEK_FOOTER_SIZE;
+            assert_eq!(
+                block::decode_blocks(&compressed[..container_size], &mut output),
+                Ok(src)
+            );
+
+            for (offset, len) in [(0, 10), (99, 2), (1000, 5000), (src.len() - 3, 10)] {
+                let read = seekable.read_at(offset, &mut output[..len]).unwrap();
+                assert_eq!(read, len.min(src.len() - offset));
+                assert_eq!(output[..read], src[offset..offset + read]);
+            }
+            assert_eq!(seekable.read_at(src.len() + 1, &mut output[..10]), Ok(0));
+        }
+
+        assert_eq!(
+            SeekableDecoder::new(&compressed[..SEEK_FOOTER_SIZE - 1]).err(),
+            Some(HSError::Truncated)
+        );
+        assert_eq!(
+            SeekableDecoder::new(&src[..100]).err(),
+            Some(HSError::InvalidHeader)
+        );
+    }
+
+    /// Poll with output buffers sized by the schedule until the instance
+    /// is empty
+    fn poll_scheduled(
+        poll: &mut impl FnMut(&mut [u8]) -> (HSpollRes, usize),
+        poll_sizes: &mut impl Iterator<Item = usize>,
+        output: &mut std::vec::Vec<u8>,
+    ) {
+        let mut buffer = [0u8; 64];
+
+        loop {
+            let size = poll_sizes.next().unwrap();
+            let (res, output_size) = poll(&mut buffer[..size]);
+            output.extend_from_slice(&buffer[..output_size]);
+            match res {
+                HSpollRes::PollMore => {}
+                HSpollRes::PollEmpty => return,
+                res => panic!("poll failed: {:?}", res),
+            }
+        }
+    }
+
+    /// Compress data sinking and polling it in chunks sized by the
+    /// schedules, so that the encoder suspends and resumes anywhere
+    fn encode_scheduled(
+        enc: &mut encoder::HeatshrinkEncoder<2048>,
+        mut data: &[u8],
+        sink_sizes: &[usize],
+        poll_sizes: &[usize],
+    ) -> std::vec::Vec<u8> {
+        let mut sink_sizes = sink_sizes.iter().copied().cycle();
+        let mut poll_sizes = poll_sizes.iter().copied().cycle();
+        let mut output = std::vec::Vec::new();
+
+        while !data.is_empty() {
+            let size = sink_sizes.next().unwrap().min(data.len());
+            match enc.sink(&data[..size]) {
+                (HSsinkRes::SinkOK, input_size) => data = &data[input_size..],
+                (HSsinkRes::SinkFull, _) => {}
+                res => panic!("sink failed: {:?}", res),
+            }
+            poll_scheduled(&mut |buffer| enc.poll(buffer), &mut poll_sizes, &mut output);
+        }
+        while enc.finish() == HSfinishRes::FinishMore {
+            poll_scheduled(&mut |buffer| enc.poll(buffer), &mut poll_sizes, &mut output);
+        }
+        output
+    }
+
+    /// Uncompress data sinking and polling it in chunks sized by the
+    /// schedules, so that the decoder suspends and resumes anywhere
+    fn decode_scheduled(
+        dec: &mut decoder::HeatshrinkDecoder<2048, 16>,
+        mut data: &[u8],
+        sink_sizes: &[usize],
+        poll_sizes: &[usize],
+    ) -> std::vec::Vec<u8> {
+        let mut sink_sizes = sink_sizes.iter().copied().cycle();
+        let mut poll_sizes = poll_sizes.iter().copied().cycle();
+        let mut output = std::vec::Vec::new();
+
+        while !data.is_empty() {
+            let size = sink_sizes.next().unwrap().min(data.len());
+            match dec.sink(&data[..size]) {
+                (HSsinkRes::SinkOK, input_size) => data = &data[input_size..],
+                (HSsinkRes::SinkFull, _) => {}
+                res => panic!("sink failed: {:?}", res),
+            }
+            poll_scheduled(&mut |buffer| dec.poll(buffer), &mut poll_sizes, &mut output);
+        }
+        assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        output
+    }
+
+    /// Data to compress: arbitrary bytes, which are mostly literals, or
+    /// bytes of a small alphabet, which are mostly back-references
+    fn data_strategy() -> impl Strategy<Value = std::vec::Vec<u8>> {
+        prop_oneof![
+            vec(any::<u8>(), 0..1024),
+            vec(0u8..4, 0..4096),
+            vec(vec(any::<u8>(), 1..20), 1..8).prop_flat_map(|words| {
+                vec(0..words.len(), 0..512)
+                    .prop_map(move |picks| picks.iter().flat_map(|&i| words[i].clone()).collect())
+            }),
+        ]
+    }
+
+    /// Window and lookahead sizes fitting in a window of 2048 bytes
+    fn params_strategy() -> impl Strategy<Value = Params> {
+        (4u8..=11)
+            .prop_flat_map(|window_bits| (Just(window_bits), 3..window_bits))
+            .prop_map(|(window_bits, lookahead_bits)| {
+                Params::new(window_bits, lookahead_bits).unwrap()
+            })
+    }
+
+    /// Chunk sizes of a schedule, including single bytes
+    fn schedule_strategy() -> impl Strategy<Value = std::vec::Vec<usize>> {
+        prop_oneof![Just(std::vec![1]), vec(1usize..=64, 1..16)]
+    }
+
+    proptest! {
+        #[test]
+        fn scheduled_roundtrip(
+            data in data_strategy(),
+            params in params_strategy(),
+            sink_sizes in schedule_strategy(),
+            poll_sizes in schedule_strategy(),
+        ) {
+            let mut enc = encoder::HeatshrinkEncoder::<2048>::with_params(params).unwrap();
+            let mut expected = std::vec![0u8; encoder::encode_bound(data.len())];
+            let expected = encoder::encode_with(&mut enc, &data, &mut expected).unwrap();
+
+            // the compressed stream doesn't depend on the schedule
+            enc.reset();
+            let compressed = encode_scheduled(&mut enc, &data, &sink_sizes, &poll_sizes);
+            prop_assert_eq!(&compressed[..], expected);
+
+            let mut dec = decoder::HeatshrinkDecoder::<2048, 16>::with_params(params).unwrap();
+            let output = decode_scheduled(&mut dec, &compressed, &sink_sizes, &poll_sizes);
+            prop_assert_eq!(output, data);
+        }
+    }
 }
@@ -0,0 +1,311 @@
+#[cfg(feature = "crc")]
+use super::crc::{Checksum, Crc32};
+use super::encoder::HeatshrinkEncoder;
+use super::frame::{FrameEncoder, FRAME_HEADER_SIZE};
+use super::HSError;
+use super::HSfinishRes;
+use super::HSpollRes;
+use super::HSsinkRes;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+use core::fmt;
+
+/// Default size of the compressed frames handed to the output callback by
+/// `CompressingFmtWriter`
+pub const DEFAULT_LOG_FRAME_SIZE: usize = 64;
+
+/// Default size of the frames emitted by `LogCompressor`, e.g. a flash page
+pub const DEFAULT_LOG_COMPRESSOR_FRAME_SIZE: usize = 256;
+
+#[cfg(feature = "crc")]
+/// Size in bytes of the CRC-32 trailer of the frames of `LogCompressor`
+pub const LOG_FRAME_CRC_SIZE: usize = 4;
+
+/// `core::fmt::Write` sink compressing the formatted text on the fly, so
+/// that `write!` logging is compressed without an intermediate string
+/// buffer
+///
+/// The compressed stream is gathered in frames of FRAME_SIZE bytes, each
+/// handed to the output callback (e.g. to send it over a radio or store it
+/// in flash) once full. `flush` hands the data written so far as a last,
+/// shorter frame, and `finish` ends the compressed stream.
+pub struct CompressingFmtWriter<
+    F,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const FRAME_SIZE: usize = DEFAULT_LOG_FRAME_SIZE,
+> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    output: F,
+    frame: [u8; FRAME_SIZE],
+    frame_size: usize,
+}
+
+impl<F: FnMut(&[u8])> CompressingFmtWriter<F> {
+    /// Create a writer compressing text with the default parameters
+    pub fn new(output: F) -> Self {
+        CompressingFmtWriter::with_encoder(HeatshrinkEncoder::new(), output)
+    }
+}
+
+impl<F: FnMut(&[u8]), const WINDOW_SIZE: usize, const FRAME_SIZE: usize>
+    CompressingFmtWriter<F, WINDOW_SIZE, FRAME_SIZE>
+{
+    /// Create a writer compressing text with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(encoder: HeatshrinkEncoder<WINDOW_SIZE>, output: F) -> Self {
+        CompressingFmtWriter {
+            encoder,
+            output,
+            frame: [0; FRAME_SIZE],
+            frame_size: 0,
+        }
+    }
+
+    /// Hand the frame gathered so far to the output callback
+    fn emit_frame(&mut self) {
+        if let Some(frame) = self.frame.get(..self.frame_size).filter(|f| !f.is_empty()) {
+            (self.output)(frame);
+        }
+        self.frame_size = 0;
+    }
+
+    /// Poll the compressed data available into the frames
+    fn poll_frames(&mut self) -> Result<(), HSError> {
+        loop {
+            let (res, output_size) = self.encoder.poll(
+                self.frame
+                    .get_mut(self.frame_size..)
+                    .ok_or(HSError::Internal)?,
+            );
+            self.frame_size += output_size;
+            if self.frame_size == FRAME_SIZE {
+                self.emit_frame();
+            }
+            match res {
+                HSpollRes::PollEmpty => return Ok(()),
+                HSpollRes::PollMore => {}
+                _ => return Err(HSError::Internal),
+            }
+        }
+    }
+
+    /// Compress the text written so far and hand it to the output callback,
+    /// without ending the compressed stream (see
+    /// `HeatshrinkEncoder::flush`): the frames handed so far are a decodable
+    /// prefix of the stream.
+    pub fn flush(&mut self) -> Result<(), HSError> {
+        while let HSfinishRes::FinishMore = self.encoder.flush() {
+            self.poll_frames()?;
+        }
+        self.emit_frame();
+        Ok(())
+    }
+
+    /// End the compressed stream and hand its end to the output callback,
+    /// returning the encoder
+    pub fn finish(mut self) -> Result<HeatshrinkEncoder<WINDOW_SIZE>, HSError> {
+        while let HSfinishRes::FinishMore = self.encoder.finish() {
+            self.poll_frames()?;
+        }
+        self.emit_frame();
+        Ok(self.encoder)
+    }
+}
+
+impl<F: FnMut(&[u8]), const WINDOW_SIZE: usize, const FRAME_SIZE: usize> fmt::Write
+    for CompressingFmtWriter<F, WINDOW_SIZE, FRAME_SIZE>
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = s.as_bytes();
+
+        while !data.is_empty() {
+            match self.encoder.sink(data) {
+                (HSsinkRes::SinkOK, input_size) => {
+                    data = data.get(input_size..).ok_or(fmt::Error)?;
+                }
+                (HSsinkRes::SinkFull, _) => {}
+                (HSsinkRes::SinkErrorMisuse, _) => return Err(fmt::Error),
+            }
+            self.poll_frames().map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compressor of log records emitting length-prefixed compressed frames, as
+/// read by `frame::FrameDecoder`
+///
+/// The records appended are compressed into a frame of at most FRAME_SIZE
+/// bytes, emitted when it can't take more data (the compressed size of the
+/// data is bounded by the size of its literals) or when `flush` is called,
+/// e.g. on a timer. Each frame is flushed to a byte boundary, so that it is
+/// uncompressed as soon as it is received or read back, while the window is
+/// kept across the frames.
+///
+/// With the `crc` feature and `set_crc`, a CRC-32 of the frame (header and
+/// payload) is appended to it as a little-endian trailer, not counted in its
+/// header: check and strip it before sinking the frame into the decoder.
+pub struct LogCompressor<
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const FRAME_SIZE: usize = DEFAULT_LOG_COMPRESSOR_FRAME_SIZE,
+> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    frame: [u8; FRAME_SIZE],
+    frame_size: usize,
+    input_size: usize,
+    #[cfg(feature = "crc")]
+    crc: bool,
+}
+
+impl LogCompressor {
+    /// Create a compressor with the default parameters
+    pub fn new() -> Self {
+        LogCompressor::with_encoder(HeatshrinkEncoder::new())
+    }
+}
+
+impl Default for LogCompressor {
+    fn default() -> Self {
+        LogCompressor::new()
+    }
+}
+
+impl<const WINDOW_SIZE: usize, const FRAME_SIZE: usize> LogCompressor<WINDOW_SIZE, FRAME_SIZE> {
+    /// Create a compressor with the provided encoder (and therefore its
+    /// window and lookahead parameters), which must not have been sunk yet
+    pub fn with_encoder(encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        LogCompressor {
+            encoder,
+            frame: [0; FRAME_SIZE],
+            frame_size: FRAME_HEADER_SIZE,
+            input_size: 0,
+            #[cfg(feature = "crc")]
+            crc: false,
+        }
+    }
+
+    #[cfg(feature = "crc")]
+    /// Append a CRC-32 trailer to the frames, to be called before appending
+    /// any record
+    pub fn set_crc(&mut self, crc: bool) {
+        self.crc = crc;
+    }
+
+    /// Size in bytes of the trailer of the frames
+    fn trailer_size(&self) -> usize {
+        #[cfg(feature = "crc")]
+        if self.crc {
+            return LOG_FRAME_CRC_SIZE;
+        }
+        0
+    }
+
+    /// Largest uncompressed data of a frame
+    fn frame_input_size(&self) -> usize {
+        FrameEncoder::<WINDOW_SIZE>::frame_input_size(
+            FRAME_SIZE.saturating_sub(self.trailer_size()),
+        )
+    }
+
+    /// Number of bytes of the records appended since the last frame
+    pub fn pending(&self) -> usize {
+        self.input_size
+    }
+
+    /// Compress a record, handing each frame filled up to the output
+    /// callback.
+    ///
+    /// A record may span several frames. Fail with `OutputFull` if FRAME_SIZE
+    /// can't hold a single byte of data.
+    pub fn append(
+        &mut self,
+        mut record: &[u8],
+        output: &mut impl FnMut(&[u8]),
+    ) -> Result<(), HSError> {
+        let frame_input_size = self.frame_input_size();
+        if frame_input_size == 0 {
+            return Err(HSError::OutputFull {
+                needed_hint: Some(FRAME_HEADER_SIZE + 2 + self.trailer_size()),
+            });
+        }
+
+        while !record.is_empty() {
+            if self.input_size >= frame_input_size {
+                self.flush(output)?;
+            }
+            let input = record
+                .get(..(frame_input_size - self.input_size).min(record.len()))
+                .ok_or(HSError::Internal)?;
+            match self.encoder.sink(input) {
+                (HSsinkRes::SinkOK, input_size) => {
+                    self.input_size += input_size;
+                    record = record.get(input_size..).ok_or(HSError::Internal)?;
+                }
+                (HSsinkRes::SinkFull, _) => {}
+                (HSsinkRes::SinkErrorMisuse, _) => return Err(HSError::InvalidState),
+            }
+            self.poll_payload()?;
+        }
+
+        Ok(())
+    }
+
+    /// Poll the compressed data available into the payload of the frame,
+    /// which was sized for the worst case
+    fn poll_payload(&mut self) -> Result<(), HSError> {
+        let payload_end = FRAME_SIZE - self.trailer_size();
+
+        loop {
+            let payload = self
+                .frame
+                .get_mut(self.frame_size..payload_end)
+                .ok_or(HSError::Internal)?;
+            match self.encoder.poll(payload) {
+                (HSpollRes::PollEmpty, output_size) => {
+                    self.frame_size += output_size;
+                    return Ok(());
+                }
+                (HSpollRes::PollMore, output_size) => self.frame_size += output_size,
+                _ => return Err(HSError::Internal),
+            }
+        }
+    }
+
+    /// Emit the records appended since the last frame as a frame, if any
+    pub fn flush(&mut self, output: &mut impl FnMut(&[u8])) -> Result<(), HSError> {
+        if self.input_size == 0 {
+            return Ok(());
+        }
+        while let HSfinishRes::FinishMore = self.encoder.flush() {
+            self.poll_payload()?;
+        }
+
+        let payload_size =
+            u16::try_from(self.frame_size - FRAME_HEADER_SIZE).map_err(|_| HSError::Internal)?;
+        self.frame
+            .get_mut(..FRAME_HEADER_SIZE)
+            .ok_or(HSError::Internal)?
+            .copy_from_slice(&payload_size.to_le_bytes());
+        #[cfg(feature = "crc")]
+        if self.crc {
+            let mut crc = Crc32::default();
+            crc.update(self.frame.get(..self.frame_size).ok_or(HSError::Internal)?);
+            self.frame
+                .get_mut(self.frame_size..self.frame_size + LOG_FRAME_CRC_SIZE)
+                .ok_or(HSError::Internal)?
+                .copy_from_slice(&crc.value().to_le_bytes());
+            self.frame_size += LOG_FRAME_CRC_SIZE;
+        }
+        output(self.frame.get(..self.frame_size).ok_or(HSError::Internal)?);
+
+        self.frame_size = FRAME_HEADER_SIZE;
+        self.input_size = 0;
+        Ok(())
+    }
+
+    /// Unwrap the underlying encoder, dropping the records appended since
+    /// the last frame
+    pub fn into_inner(self) -> HeatshrinkEncoder<WINDOW_SIZE> {
+        self.encoder
+    }
+}
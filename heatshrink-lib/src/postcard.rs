@@ -0,0 +1,151 @@
+use super::decoder::{self, HeatshrinkDecoder};
+use super::encoder::{self, HeatshrinkEncoder};
+use super::HSError;
+use super::HSfinishRes;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+use ::postcard::ser_flavors::Flavor;
+use serde::{Deserialize, Serialize};
+
+/// Error of the compressed serialization: an error of postcard, or an error
+/// of the compressed stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The value couldn't be (de)serialized, or the serialization buffer is
+    /// too small for the compressed data
+    Postcard(::postcard::Error),
+    /// The compressed data is invalid or truncated, or the scratch buffer is
+    /// too small for the serialized data
+    Heatshrink(HSError),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Postcard(error) => write!(f, "postcard error: {}", error),
+            Error::Heatshrink(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// postcard serialization flavor compressing the serialized data into a
+/// slice as it is produced, without buffering the uncompressed data
+///
+/// Use it with `postcard::serialize_with_flavor`, or through `to_slice`.
+/// The flavor resolves into the sub-slice holding the compressed data, and
+/// fails with `SerializeBufferFull` if the slice is too small.
+pub struct Compress<'a, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    dst: &'a mut [u8],
+    size: usize,
+}
+
+impl<'a> Compress<'a> {
+    /// Compress the serialized data to dst with the default parameters
+    pub fn new(dst: &'a mut [u8]) -> Self {
+        Compress::with_encoder(dst, HeatshrinkEncoder::new())
+    }
+}
+
+impl<'a, const WINDOW_SIZE: usize> Compress<'a, WINDOW_SIZE> {
+    /// Compress the serialized data to dst with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(dst: &'a mut [u8], encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        Compress {
+            encoder,
+            dst,
+            size: 0,
+        }
+    }
+}
+
+impl<'a, const WINDOW_SIZE: usize> Flavor for Compress<'a, WINDOW_SIZE> {
+    type Output = &'a mut [u8];
+
+    fn try_extend(&mut self, mut data: &[u8]) -> ::postcard::Result<()> {
+        while !data.is_empty() {
+            let dst = self
+                .dst
+                .get_mut(self.size..)
+                .ok_or(::postcard::Error::SerializeBufferFull)?;
+            let (input_size, output_size) = encoder::encode_step(&mut self.encoder, data, dst)
+                .map_err(|_| ::postcard::Error::SerializeBufferFull)?;
+            if input_size == 0 && output_size == 0 {
+                return Err(::postcard::Error::SerializeBufferFull);
+            }
+            data = data.get(input_size..).unwrap_or_default();
+            self.size += output_size;
+        }
+
+        Ok(())
+    }
+
+    fn try_push(&mut self, data: u8) -> ::postcard::Result<()> {
+        self.try_extend(&[data])
+    }
+
+    fn finalize(mut self) -> ::postcard::Result<Self::Output> {
+        // flush the end of the compressed stream
+        loop {
+            let dst = self
+                .dst
+                .get_mut(self.size..)
+                .ok_or(::postcard::Error::SerializeBufferFull)?;
+            match encoder::encode_partial(&mut self.encoder, &[], dst) {
+                Ok((HSfinishRes::FinishDone, _, output_size)) => {
+                    self.size += output_size;
+                    break;
+                }
+                Ok((HSfinishRes::FinishMore, _, output_size)) if output_size > 0 => {
+                    self.size += output_size;
+                }
+                Ok((HSfinishRes::FinishMore, _, _)) | Err(_) => {
+                    return Err(::postcard::Error::SerializeBufferFull);
+                }
+            }
+        }
+
+        self.dst
+            .get_mut(..self.size)
+            .ok_or(::postcard::Error::SerializeBufferFull)
+    }
+}
+
+/// serialize the value with postcard, compressed to the destination buffer
+pub fn to_slice<'a, T: Serialize + ?Sized>(
+    value: &T,
+    dst: &'a mut [u8],
+) -> Result<&'a mut [u8], Error> {
+    ::postcard::serialize_with_flavor(value, Compress::new(dst)).map_err(Error::Postcard)
+}
+
+/// uncompress the src buffer to the scratch buffer and deserialize the value
+/// with postcard. The value may borrow from the scratch buffer.
+///
+/// Fail with `OutputFull` if the scratch buffer isn't larger than the
+/// serialized data.
+pub fn from_bytes<'a, T: Deserialize<'a>>(src: &[u8], scratch: &'a mut [u8]) -> Result<T, Error> {
+    let mut dec: HeatshrinkDecoder = Default::default();
+
+    from_bytes_with(&mut dec, src, scratch)
+}
+
+/// uncompress the src buffer to the scratch buffer using the provided
+/// decoder (and therefore its window and lookahead parameters) and
+/// deserialize the value with postcard
+pub fn from_bytes_with<
+    'a,
+    T: Deserialize<'a>,
+    const WINDOW_SIZE: usize,
+    const INPUT_SIZE: usize,
+>(
+    dec: &mut HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    src: &[u8],
+    scratch: &'a mut [u8],
+) -> Result<T, Error> {
+    let serialized = decoder::decode_with(dec, src, scratch).map_err(Error::Heatshrink)?;
+
+    ::postcard::from_bytes(serialized).map_err(Error::Postcard)
+}
@@ -0,0 +1,194 @@
+#[cfg(feature = "decoder")]
+use super::decoder::HeatshrinkDecoder;
+#[cfg(feature = "encoder")]
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+#[cfg(feature = "decoder")]
+use super::HSfinishRes;
+#[cfg(feature = "decoder")]
+use super::HEATSHRINK_INPUT_BUFFER_SIZE;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+use bytes::{Bytes, BytesMut};
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+use futures_core::Stream;
+
+#[cfg(feature = "encoder")]
+/// Stream of the compressed chunks of the data yielded by the wrapped stream
+///
+/// Each chunk of data (e.g. a `Bytes` of a hyper/axum body stream) is sunk
+/// into the encoder and the compressed data available is yielded as one
+/// chunk. The end of the compressed stream is yielded once the wrapped
+/// stream ends.
+pub struct EncodeStream<S, const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE> {
+    inner: S,
+    encoder: HeatshrinkEncoder<WINDOW_SIZE>,
+    done: bool,
+}
+
+#[cfg(feature = "encoder")]
+impl<S> EncodeStream<S> {
+    /// Compress the data of the stream with the default parameters
+    pub fn new(inner: S) -> Self {
+        EncodeStream::with_encoder(inner, HeatshrinkEncoder::new())
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<S, const WINDOW_SIZE: usize> EncodeStream<S, WINDOW_SIZE> {
+    /// Compress the data of the stream with the provided encoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_encoder(inner: S, encoder: HeatshrinkEncoder<WINDOW_SIZE>) -> Self {
+        EncodeStream {
+            inner,
+            encoder,
+            done: false,
+        }
+    }
+
+    /// Get a reference to the wrapped stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap this stream, returning the wrapped stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[cfg(feature = "encoder")]
+impl<S, B, const WINDOW_SIZE: usize> Stream for EncodeStream<S, WINDOW_SIZE>
+where
+    S: Stream<Item = B> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Bytes, HSError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while !this.done {
+            let mut output = BytesMut::new();
+            let mut write = |data: &[u8]| output.extend_from_slice(data);
+            let result = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(data) => this.encoder.sink_all(data.as_ref(), &mut write),
+                None => {
+                    // flush the end of the compressed stream
+                    this.done = true;
+                    this.encoder.finish();
+                    this.encoder.poll_all(&mut write)
+                }
+            };
+            if let Err(error) = result {
+                this.done = true;
+                return Poll::Ready(Some(Err(error)));
+            }
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(output.freeze())));
+            }
+        }
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(feature = "decoder")]
+/// Stream of the uncompressed chunks of the compressed data yielded by the
+/// wrapped stream
+///
+/// The stream ends after an error: `InvalidBackref` or `OutputLimit` if the
+/// compressed data is corrupted, or `Truncated` if the wrapped stream ends
+/// in the middle of a compressed token.
+pub struct DecodeStream<
+    S,
+    const WINDOW_SIZE: usize = HEATSHRINK_WINDOW_SIZE,
+    const INPUT_SIZE: usize = HEATSHRINK_INPUT_BUFFER_SIZE,
+> {
+    inner: S,
+    decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>,
+    done: bool,
+}
+
+#[cfg(feature = "decoder")]
+impl<S> DecodeStream<S> {
+    /// Uncompress the data of the stream with the default parameters
+    pub fn new(inner: S) -> Self {
+        DecodeStream::with_decoder(inner, HeatshrinkDecoder::new())
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<S, const WINDOW_SIZE: usize, const INPUT_SIZE: usize>
+    DecodeStream<S, WINDOW_SIZE, INPUT_SIZE>
+{
+    /// Uncompress the data of the stream with the provided decoder (and
+    /// therefore its window and lookahead parameters)
+    pub fn with_decoder(inner: S, decoder: HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>) -> Self {
+        DecodeStream {
+            inner,
+            decoder,
+            done: false,
+        }
+    }
+
+    /// Get a reference to the wrapped stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the wrapped stream
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Unwrap this stream, returning the wrapped stream
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+#[cfg(feature = "decoder")]
+impl<S, B, const WINDOW_SIZE: usize, const INPUT_SIZE: usize> Stream
+    for DecodeStream<S, WINDOW_SIZE, INPUT_SIZE>
+where
+    S: Stream<Item = B> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = Result<Bytes, HSError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        while !this.done {
+            let data = match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(data) => data,
+                None => {
+                    // all the compressed data has been processed
+                    this.done = true;
+                    return Poll::Ready(match this.decoder.finish() {
+                        HSfinishRes::FinishDone => None,
+                        HSfinishRes::FinishMore => Some(Err(HSError::Truncated)),
+                    });
+                }
+            };
+            let mut output = BytesMut::new();
+            if let Err(error) = this
+                .decoder
+                .sink_all(data.as_ref(), &mut |data| output.extend_from_slice(data))
+            {
+                this.done = true;
+                return Poll::Ready(Some(Err(error)));
+            }
+            if !output.is_empty() {
+                return Poll::Ready(Some(Ok(output.freeze())));
+            }
+        }
+        Poll::Ready(None)
+    }
+}
@@ -0,0 +1,85 @@
+use super::HEATSHRINK_LOOKAHEAD_BITS;
+use super::HEATSHRINK_WINDOWS_BITS;
+use super::HEATSHRINK_WINDOW_SIZE;
+
+/// Number of entries of the 2-gram table of `estimate_ratio`
+const ESTIMATE_TABLE_SIZE: usize = 256;
+
+/// Estimate the ratio of the compressed size to the size of data with the
+/// default parameters, without running the encoder: below 1.0 the data is
+/// worth compressing, above it the data would expand.
+///
+/// The estimate only looks for the last occurrence of each 2-gram in the
+/// window (where the encoder searches them all) and counts the bits of the
+/// resulting literals and back-references. It is linear in the size of data
+/// and uses 512 bytes of stack.
+pub fn estimate_ratio(data: &[u8]) -> f32 {
+    let literal_bits = 1 + 8;
+    let backref_bits = 1 + usize::from(HEATSHRINK_WINDOWS_BITS + HEATSHRINK_LOOKAHEAD_BITS);
+    let max_match_length = 1 << HEATSHRINK_LOOKAHEAD_BITS;
+
+    // last position (modulo 2^16) of the 2-grams, by hash
+    let mut table = [0u16; ESTIMATE_TABLE_SIZE];
+    let mut compressed_bits = 0;
+    let mut index = 0;
+
+    while index < data.len() {
+        let candidate = update_table(&mut table, data, index);
+        let match_length = candidate
+            .map(|candidate| match_length_at(data, candidate, index, max_match_length))
+            .unwrap_or(0);
+
+        // like the encoder, only use back-references shorter than the
+        // literals they replace
+        if match_length > backref_bits / 8 {
+            compressed_bits += backref_bits;
+            for skipped in index + 1..index + match_length {
+                update_table(&mut table, data, skipped);
+            }
+            index += match_length;
+        } else {
+            compressed_bits += literal_bits;
+            index += 1;
+        }
+    }
+
+    if data.is_empty() {
+        1.0
+    } else {
+        compressed_bits.div_ceil(8) as f32 / data.len() as f32
+    }
+}
+
+/// Record the 2-gram at index in the table, returning the position of its
+/// previous occurrence if it is in the window
+fn update_table(
+    table: &mut [u16; ESTIMATE_TABLE_SIZE],
+    data: &[u8],
+    index: usize,
+) -> Option<usize> {
+    let (&first, &second) = (data.get(index)?, data.get(index + 1)?);
+    let hash = u16::from_le_bytes([first, second]).wrapping_mul(0x9e37) >> 8;
+    let entry = table.get_mut(usize::from(hash) % ESTIMATE_TABLE_SIZE)?;
+    let distance = usize::from((index as u16).wrapping_sub(*entry));
+    *entry = index as u16;
+
+    if (1..=HEATSHRINK_WINDOW_SIZE).contains(&distance) {
+        index.checked_sub(distance)
+    } else {
+        None
+    }
+}
+
+/// Length of the match of the data at index with the data at candidate
+fn match_length_at(data: &[u8], candidate: usize, index: usize, maxlen: usize) -> usize {
+    let (Some(earlier), Some(current)) = (data.get(candidate..), data.get(index..)) else {
+        return 0;
+    };
+
+    earlier
+        .iter()
+        .zip(current)
+        .take(maxlen)
+        .take_while(|(a, b)| a == b)
+        .count()
+}
@@ -0,0 +1,148 @@
+#![allow(unsafe_code)]
+
+use super::decoder::HeatshrinkDecoder;
+use super::encoder::HeatshrinkEncoder;
+use super::HSError;
+use super::HSfinishRes;
+use super::Params;
+use super::{HEATSHRINK_LOOKAHEAD_BITS, HEATSHRINK_MAX_WINDOWS_BITS, HEATSHRINK_WINDOWS_BITS};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use wasm_bindgen::prelude::*;
+
+/// Window capacity of the instances, the largest window so that any stream
+/// can be handled (the memory of a browser isn't scarce)
+const WINDOW_SIZE: usize = 1 << HEATSHRINK_MAX_WINDOWS_BITS;
+
+/// Input buffer size of the decoders, large as the data comes in big chunks
+const INPUT_SIZE: usize = 1024;
+
+/// Parameters of the optional windowBits and lookaheadBits arguments,
+/// defaulting to the default parameters of the library
+fn params(window_bits: Option<u8>, lookahead_bits: Option<u8>) -> Result<Params, JsError> {
+    Ok(Params::new(
+        window_bits.unwrap_or(HEATSHRINK_WINDOWS_BITS),
+        lookahead_bits.unwrap_or(HEATSHRINK_LOOKAHEAD_BITS),
+    )?)
+}
+
+/// Compress data, returned as a new Uint8Array
+#[wasm_bindgen]
+pub fn encode(
+    data: &[u8],
+    window_bits: Option<u8>,
+    lookahead_bits: Option<u8>,
+) -> Result<Vec<u8>, JsError> {
+    let mut encoder = Encoder::new(window_bits, lookahead_bits)?;
+    let mut compressed = encoder.push(data)?;
+
+    compressed.extend(encoder.finish()?);
+    Ok(compressed)
+}
+
+/// Uncompress data, returned as a new Uint8Array
+///
+/// Throw if the data is corrupted or truncated.
+#[wasm_bindgen]
+pub fn decode(
+    data: &[u8],
+    window_bits: Option<u8>,
+    lookahead_bits: Option<u8>,
+) -> Result<Vec<u8>, JsError> {
+    let mut decoder = Decoder::new(window_bits, lookahead_bits)?;
+    let mut uncompressed = decoder.push(data)?;
+
+    uncompressed.extend(decoder.finish()?);
+    Ok(uncompressed)
+}
+
+/// Streaming compression: push the chunks of data as they come and get the
+/// compressed data available, then get the end of the compressed stream
+/// with finish
+#[wasm_bindgen]
+pub struct Encoder {
+    encoder: Box<HeatshrinkEncoder<WINDOW_SIZE>>,
+}
+
+#[wasm_bindgen]
+impl Encoder {
+    /// Create an encoder, with the default parameters unless given
+    #[wasm_bindgen(constructor)]
+    pub fn new(window_bits: Option<u8>, lookahead_bits: Option<u8>) -> Result<Encoder, JsError> {
+        let params = params(window_bits, lookahead_bits)?;
+
+        Ok(Encoder {
+            encoder: HeatshrinkEncoder::boxed_with_params(params)?,
+        })
+    }
+
+    /// Compress a chunk of data and return the compressed data available
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        let mut output = Vec::new();
+
+        self.encoder
+            .sink_all(chunk, &mut |data| output.extend_from_slice(data))?;
+        Ok(output)
+    }
+
+    /// Return the end of the compressed stream, the encoder is then reset
+    /// for a new stream
+    pub fn finish(&mut self) -> Result<Vec<u8>, JsError> {
+        let mut output = Vec::new();
+
+        self.encoder.finish();
+        self.encoder
+            .poll_all(&mut |data| output.extend_from_slice(data))?;
+        self.encoder.reset();
+        Ok(output)
+    }
+}
+
+/// Streaming decompression: push the chunks of compressed data as they come
+/// (e.g. from a WebSocket) and get the uncompressed data available, then
+/// check the stream is complete with finish
+#[wasm_bindgen]
+pub struct Decoder {
+    decoder: Box<HeatshrinkDecoder<WINDOW_SIZE, INPUT_SIZE>>,
+}
+
+#[wasm_bindgen]
+impl Decoder {
+    /// Create a decoder, with the default parameters unless given
+    #[wasm_bindgen(constructor)]
+    pub fn new(window_bits: Option<u8>, lookahead_bits: Option<u8>) -> Result<Decoder, JsError> {
+        let params = params(window_bits, lookahead_bits)?;
+
+        Ok(Decoder {
+            decoder: HeatshrinkDecoder::boxed_with_params(params)?,
+        })
+    }
+
+    /// Uncompress a chunk of compressed data and return the uncompressed
+    /// data available
+    ///
+    /// Throw if the compressed data is corrupted.
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<u8>, JsError> {
+        let mut output = Vec::new();
+
+        self.decoder
+            .sink_all(chunk, &mut |data| output.extend_from_slice(data))?;
+        Ok(output)
+    }
+
+    /// Check the compressed stream is complete, the decoder is then reset
+    /// for a new stream. Return an empty Uint8Array, for symmetry with the
+    /// encoder.
+    ///
+    /// Throw if the compressed stream is truncated.
+    pub fn finish(&mut self) -> Result<Vec<u8>, JsError> {
+        let result = self.decoder.finish();
+
+        self.decoder.reset();
+        match result {
+            HSfinishRes::FinishDone => Ok(Vec::new()),
+            HSfinishRes::FinishMore => Err(HSError::Truncated.into()),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Build with `cargo build --release`: linking fails with an undefined
+//! `heatshrink_panic_is_reachable` symbol if any function below can panic.
+#![no_std]
+
+use core::fmt::{self, Write};
+
+use heatshrink::{crc, decoder, encoder, HSError};
+
+#[no_mangle]
+pub fn check_encode(src: &[u8], dst: &mut [u8]) -> Result<usize, HSError> {
+    encoder::encode(src, dst).map(<[u8]>::len)
+}
+
+#[no_mangle]
+pub fn check_decode(src: &[u8], dst: &mut [u8]) -> Result<usize, HSError> {
+    decoder::decode(src, dst).map(<[u8]>::len)
+}
+
+#[no_mangle]
+pub fn check_step(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut enc = encoder::HeatshrinkEncoder::new();
+    let (consumed, size) = encoder::encode_step(&mut enc, src, dst).unwrap_or_default();
+    let mut dec = decoder::HeatshrinkDecoder::new();
+    let (_, output_size) = decoder::decode_step(&mut dec, src, dst).unwrap_or_default();
+
+    consumed + size + output_size
+}
+
+#[no_mangle]
+pub fn check_self_test() -> Result<(), HSError> {
+    heatshrink::self_test()
+}
+
+#[no_mangle]
+pub fn check_estimate_ratio(src: &[u8]) -> f32 {
+    heatshrink::util::estimate_ratio(src)
+}
+
+#[no_mangle]
+pub fn check_container(src: &[u8], dst: &mut [u8]) -> Result<usize, HSError> {
+    let (header, size) = heatshrink::container::read_header(src)?;
+    heatshrink::container::write_header(&header, dst).map(|written| written + size)
+}
+
+#[no_mangle]
+pub fn check_seekable(src: &[u8], dst: &mut [u8]) -> usize {
+    let size = heatshrink::block::encode_seekable(src, dst, 64).map_or(0, <[u8]>::len);
+    let read = heatshrink::block::SeekableDecoder::new(src)
+        .and_then(|seekable| seekable.read_at(100, dst))
+        .unwrap_or(0);
+    size + read
+}
+
+#[no_mangle]
+pub fn check_armor(src: &[u8], dst: &mut [u8]) -> usize {
+    let size = heatshrink::armor::armor(src, dst, 76).map_or(0, <[u8]>::len);
+    size + heatshrink::armor::dearmor(src, dst).map_or(0, <[u8]>::len)
+}
+
+#[no_mangle]
+pub fn check_cobs(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut writer = heatshrink::cobs::CobsFrameWriter::new(encoder::HeatshrinkEncoder::new());
+    let (consumed, size) = writer.write_packet(src, dst).unwrap_or_default();
+
+    let mut reader = heatshrink::cobs::CobsFrameReader::new(decoder::HeatshrinkDecoder::new());
+    let mut output_size = 0;
+    let _ = reader.sink_all(src, &mut |data| output_size += data.len());
+    let _ = reader.finish();
+    consumed + size + output_size
+}
+
+#[no_mangle]
+pub fn check_crc(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut enc = crc::CrcEncoder::<crc::Crc16>::new(encoder::HeatshrinkEncoder::new());
+    let mut output_size = enc.sink(src).1;
+    enc.finish();
+    output_size += enc.poll(dst).1;
+
+    let mut dec = crc::CrcDecoder::<crc::Crc32>::new(decoder::HeatshrinkDecoder::new());
+    output_size += dec.sink(src).1;
+    output_size += dec.poll(dst).1;
+    let _ = dec.finish();
+    output_size + crc::decode::<crc::Crc32>(src, dst).map_or(0, <[u8]>::len)
+}
+
+#[no_mangle]
+pub fn check_frame(src: &[u8], dst: &mut [u8]) -> usize {
+    let mut enc = heatshrink::frame::FrameEncoder::new(encoder::HeatshrinkEncoder::new());
+    let (consumed, size) = enc.encode_frame(src, dst).unwrap_or_default();
+
+    let mut dec = heatshrink::frame::FrameDecoder::new(decoder::HeatshrinkDecoder::new(), 64);
+    let mut output_size = 0;
+    let _ = dec.sink_all(src, &mut |data| output_size += data.len());
+    let _ = dec.finish();
+    consumed + size + output_size
+}
+
+#[no_mangle]
+pub fn check_encoder(dictionary: &[u8], src: &[u8], dst: &mut [u8]) -> usize {
+    let mut enc = encoder::HeatshrinkEncoder::<2048>::default();
+    let mut output_size = 0;
+
+    enc.prime(dictionary);
+    enc.sink(src);
+    output_size += enc.poll(dst).1;
+    output_size += enc.poll_with_budget(dst, 16).1;
+    output_size += usize::from(enc.is_expanding(16));
+    output_size += enc.sink_capacity();
+    output_size += enc.flush_into(dst).unwrap_or(0);
+    output_size += enc.finish_into(dst).unwrap_or(0);
+    output_size += enc.save_state(dst).unwrap_or(0);
+    let _ = enc.restore_state(src);
+    enc.reset();
+    enc.sink(src);
+    enc.reset_and_zeroize();
+    enc.set_lazy_matching(true);
+    enc.set_skip_ahead(true);
+    output_size + enc.encode_slice(src, dst).unwrap_or(0)
+}
+
+#[no_mangle]
+pub fn check_decoder(dictionary: &[u8], src: &[u8], dst: &mut [u8]) -> usize {
+    let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+    let mut output_size = 0;
+
+    dec.set_strict(true);
+    dec.prime(dictionary);
+    dec.sink(src);
+    output_size += dec.poll(dst).1;
+    output_size += dec.poll_with_budget(dst, 16).1;
+    let _ = dec.sync();
+    output_size += dec.sink_capacity();
+    output_size += dec.save_state(dst).unwrap_or(0);
+    let _ = dec.restore_state(src);
+    dec.finish();
+    dec.reset_and_zeroize();
+    dec.sink(src);
+    dec.abort();
+    output_size + dec.poll_from(src, dst).2
+}
+
+/// Formatter output counting the bytes written to it
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+#[no_mangle]
+pub fn check_debug(src: &[u8]) -> usize {
+    let mut enc = encoder::HeatshrinkEncoder::<2048>::default();
+    let mut dec = decoder::HeatshrinkDecoder::<2048>::default();
+    let _ = enc.restore_state(src);
+    let _ = dec.restore_state(src);
+    enc.sink(src);
+    dec.sink(src);
+
+    let mut counter = Counter(0);
+    let _ = write!(counter, "{enc:?} {dec:?}");
+    counter.0
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    extern "Rust" {
+        fn heatshrink_panic_is_reachable() -> !;
+    }
+    // SAFETY: never called, the symbol does not exist
+    unsafe { heatshrink_panic_is_reachable() }
+}
@@ -0,0 +1,18 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    // put the memory.x of the target where the link.x script of
+    // cortex-m-rt finds it
+    let memory = if env::var_os("CARGO_FEATURE_STM32F411").is_some() {
+        &include_bytes!("memory-stm32f411.x")[..]
+    } else {
+        &include_bytes!("memory.x")[..]
+    };
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("memory.x"), memory).unwrap();
+    println!("cargo::rustc-link-search={}", out.display());
+    println!("cargo::rerun-if-changed=memory.x");
+    println!("cargo::rerun-if-changed=memory-stm32f411.x");
+}
@@ -0,0 +1,17 @@
+#!/bin/sh
+# Cargo runner: run the ELF given on QEMU and decode its defmt output, the
+# exit status being the one of the firmware (semihosting exit)
+set -e
+
+fifo=$(mktemp -u)
+mkfifo "$fifo"
+trap 'rm -f "$fifo"' EXIT
+
+defmt-print -e "$1" < "$fifo" &
+printer=$!
+
+status=0
+qemu-system-arm -cpu cortex-m3 -machine lm3s6965evb -nographic \
+    -semihosting-config enable=on,target=native -kernel "$1" > "$fifo" || status=$?
+wait "$printer"
+exit "$status"
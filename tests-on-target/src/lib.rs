@@ -0,0 +1,31 @@
+//! Runtime of the on-target tests: defmt logger, panic handler and, on
+//! QEMU, the exit of the emulator with the result of the tests.
+
+#![no_std]
+
+#[cfg(all(feature = "qemu", feature = "stm32f411"))]
+compile_error!("the `qemu` and `stm32f411` features are exclusive");
+
+#[cfg(feature = "qemu")]
+use defmt_semihosting as _;
+#[cfg(feature = "stm32f411")]
+use defmt_rtt as _;
+use panic_probe as _;
+
+/// defmt-test ends with a breakpoint when all the tests pass, and panic-probe
+/// with an undefined instruction when one fails: without a debugger to catch
+/// them both escalate to a hard fault, whose instruction tells them apart.
+#[cfg(feature = "qemu")]
+#[cortex_m_rt::exception]
+unsafe fn HardFault(frame: &cortex_m_rt::ExceptionFrame) -> ! {
+    use cortex_m_semihosting::debug;
+
+    // SAFETY: the stacked PC points to the Thumb instruction which faulted
+    let instruction = unsafe { core::ptr::read_volatile(frame.pc() as *const u16) };
+    if instruction & 0xff00 == 0xbe00 {
+        debug::exit(debug::EXIT_SUCCESS);
+    } else {
+        debug::exit(debug::EXIT_FAILURE);
+    }
+    loop {}
+}
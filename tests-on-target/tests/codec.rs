@@ -0,0 +1,195 @@
+//! Known-answer vectors and roundtrips of the encoder and of the decoder,
+//! run on the target with defmt-test.
+
+#![no_std]
+#![no_main]
+
+use heatshrink_tests_on_target as _;
+
+#[defmt_test::tests]
+mod tests {
+    use heatshrink::decoder::{self, HeatshrinkDecoder};
+    use heatshrink::encoder::{self, HeatshrinkEncoder};
+    use heatshrink::{HSError, HSfinishRes, HSpollRes, HSsinkRes, Params};
+
+    /// Pseudo-random bytes from a linear congruential generator
+    fn noise(seed: u32, dst: &mut [u8]) {
+        let mut seed = seed;
+        for b in dst.iter_mut() {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            *b = (seed >> 16) as u8;
+        }
+    }
+
+    /// Compressible data, the one of the `window_sizes` test on the host
+    fn pattern(dst: &mut [u8]) {
+        for (i, b) in dst.iter_mut().enumerate() {
+            *b = (i % 251) as u8 ^ (i / 300) as u8;
+        }
+    }
+
+    fn compare_with<const WINDOW_SIZE: usize>(src: &[u8], window_bits: u8, lookahead_bits: u8) {
+        let mut compressed_buffer = [0u8; 2048];
+        let mut uncompressed_buffer = [0u8; 1024];
+
+        let params = Params::new(window_bits, lookahead_bits).unwrap();
+
+        let mut enc = HeatshrinkEncoder::<WINDOW_SIZE>::with_params(params).unwrap();
+        let out1 = encoder::encode_with(&mut enc, src, &mut compressed_buffer).unwrap();
+        defmt::assert!(out1.len() <= encoder::encode_bound(src.len()));
+
+        let mut dec = HeatshrinkDecoder::<WINDOW_SIZE>::with_params(params).unwrap();
+        let out2 = decoder::decode_with(&mut dec, out1, &mut uncompressed_buffer).unwrap();
+
+        defmt::assert_eq!(src, out2);
+    }
+
+    #[test]
+    fn self_test() {
+        defmt::assert_eq!(heatshrink::self_test(), Ok(()));
+    }
+
+    #[test]
+    fn clib_compatibility() {
+        let src = hex_literal::hex!("90D4B2B549A4082BE00F000E4C46DF2817C605F005B4BE0825F00280");
+        let expected = hex_literal::hex!("215295543402000000000000000000000000000000000000000000000000000000000000000000009302000000000000F202F102F0020000000000002F0400000000000000000000000000000000000000000000");
+        let mut dst = [0u8; 100];
+
+        let out = decoder::decode(&src, &mut dst).unwrap();
+        defmt::assert_eq!(&expected[..], out);
+
+        let out = encoder::encode(&expected, &mut dst).unwrap();
+        defmt::assert_eq!(&src[..], out);
+    }
+
+    #[test]
+    fn clib_compatibility_w11() {
+        // `heatshrink -e -w 11 -l 4` output for "abc" repeated 6 times
+        let src = hex_literal::hex!("b0d8ac6005c0");
+        let expected = b"abcabcabcabcabcabc";
+        let mut dst = [0u8; 100];
+
+        let mut dec = HeatshrinkDecoder::<2048>::default();
+        let out = decoder::decode_with(&mut dec, &src, &mut dst).unwrap();
+        defmt::assert_eq!(&expected[..], out);
+
+        let mut enc = HeatshrinkEncoder::<2048>::default();
+        let out = encoder::encode_with(&mut enc, expected, &mut dst).unwrap();
+        defmt::assert_eq!(&src[..], out);
+    }
+
+    #[test]
+    fn multi_byte_backref() {
+        // "abc" repeated 6 times with -w 12 -l 10: both the index and the
+        // count of the back-reference are wider than a byte
+        let src = hex_literal::hex!("b0d8ac60020380");
+        let expected = b"abcabcabcabcabcabc";
+        let mut dst = [0u8; 100];
+
+        let params = Params::new(12, 10).unwrap();
+        let mut dec = HeatshrinkDecoder::<4096>::with_params(params).unwrap();
+        let out = decoder::decode_with(&mut dec, &src, &mut dst).unwrap();
+        defmt::assert_eq!(&expected[..], out);
+
+        let mut enc = HeatshrinkEncoder::<4096>::with_params(params).unwrap();
+        let out = encoder::encode_with(&mut enc, expected, &mut dst).unwrap();
+        defmt::assert_eq!(&src[..], out);
+    }
+
+    #[test]
+    fn window_sizes() {
+        let mut src = [0u8; 1000];
+        pattern(&mut src);
+
+        compare_with::<16>(&src, 4, 3);
+        compare_with::<256>(&src, 5, 4);
+        compare_with::<256>(&src, 8, 4);
+        compare_with::<512>(&src, 9, 8);
+        compare_with::<2048>(&src, 11, 4);
+        compare_with::<4096>(&src, 10, 6);
+        compare_with::<4096>(&src, 12, 8);
+        compare_with::<4096>(&src, 12, 11);
+    }
+
+    #[test]
+    fn incompressible() {
+        let mut src = [0u8; 1000];
+        noise(42, &mut src);
+
+        compare_with::<256>(&src, 8, 4);
+        compare_with::<2048>(&src, 11, 4);
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let mut src = [0u8; 1000];
+        pattern(&mut src);
+        let mut expected = [0u8; 1024];
+        let expected = encoder::encode(&src, &mut expected).unwrap();
+
+        // sink one byte and poll one byte at a time, so that every state is
+        // suspended and resumed
+        let mut enc = HeatshrinkEncoder::<256>::default();
+        let mut compressed = [0u8; 1024];
+        let mut compressed_size = 0;
+        let mut poll = |enc: &mut HeatshrinkEncoder<256>| loop {
+            let (res, size) = enc.poll(&mut compressed[compressed_size..][..1]);
+            compressed_size += size;
+            if res != HSpollRes::PollMore {
+                defmt::assert_eq!(res, HSpollRes::PollEmpty);
+                break;
+            }
+        };
+        let mut sunk = 0;
+        while sunk < src.len() {
+            let (res, size) = enc.sink(&src[sunk..][..1]);
+            defmt::assert_eq!(res, HSsinkRes::SinkOK);
+            sunk += size;
+            poll(&mut enc);
+        }
+        while enc.finish() == HSfinishRes::FinishMore {
+            poll(&mut enc);
+        }
+        defmt::assert_eq!(expected, &compressed[..compressed_size]);
+
+        let mut dec = HeatshrinkDecoder::<256, 1>::default();
+        let mut uncompressed = [0u8; 1024];
+        let mut uncompressed_size = 0;
+        for byte in expected.chunks(1) {
+            let (res, size) = dec.sink(byte);
+            defmt::assert_eq!((res, size), (HSsinkRes::SinkOK, 1));
+            loop {
+                let (res, size) = dec.poll(&mut uncompressed[uncompressed_size..][..1]);
+                uncompressed_size += size;
+                if res != HSpollRes::PollMore {
+                    defmt::assert_eq!(res, HSpollRes::PollEmpty);
+                    break;
+                }
+            }
+        }
+        defmt::assert_eq!(dec.finish(), HSfinishRes::FinishDone);
+        defmt::assert_eq!(&src[..], &uncompressed[..uncompressed_size]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut src = [0u8; 1000];
+        pattern(&mut src);
+        let mut compressed = [0u8; 1024];
+        let compressed = encoder::encode(&src, &mut compressed).unwrap();
+        let mut uncompressed = [0u8; 1024];
+
+        defmt::assert_eq!(
+            decoder::decode_exact(compressed, &mut uncompressed, src.len() + 1),
+            Err(HSError::TooShort)
+        );
+        defmt::assert_eq!(
+            decoder::decode_exact(compressed, &mut uncompressed, src.len() - 1),
+            Err(HSError::TooLong)
+        );
+        defmt::assert!(matches!(
+            encoder::encode(&src, &mut [0u8; 8]),
+            Err(HSError::OutputFull { .. })
+        ));
+    }
+}